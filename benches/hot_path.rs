@@ -0,0 +1,115 @@
+//! Benchmarks for the per-request hot path.
+//!
+//! Run with `cargo bench`; compare against a saved baseline with
+//! `cargo bench -- --save-baseline main` / `--baseline main`.
+
+use std::{
+    hint::black_box,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use axum::{body::Body, extract::ConnectInfo, http::Request};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use ipnet::IpNet;
+use tezcatlipoca_auth::{cache::IpSet, testing::TestApp};
+use tokio::runtime::Runtime;
+
+/// Banned list sizes used for lookup benchmarks.
+const LIST_SIZES: [usize; 3] = [1_000, 100_000, 1_000_000];
+
+/// `count` distinct addresses starting at 10.0.0.0.
+fn hosts(count: usize) -> impl Iterator<Item = IpNet> {
+    (0..count as u32).map(|i| IpNet::from(IpAddr::from(Ipv4Addr::from(0x0a00_0000 + i))))
+}
+
+/// `count` distinct /24 networks starting at 20.0.0.0/24.
+fn networks(count: usize) -> impl Iterator<Item = IpNet> {
+    (0..count as u32)
+        .map(|i| IpNet::new(IpAddr::from(Ipv4Addr::from(0x1400_0000 + (i << 8))), 24).unwrap())
+}
+
+fn ip_set_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ip_set_lookup");
+    group.throughput(Throughput::Elements(1));
+    let miss: IpAddr = "192.0.2.1".parse().unwrap();
+    let host_hit: IpAddr = "10.0.0.99".parse().unwrap();
+    let net_hit: IpAddr = "20.0.3.7".parse().unwrap();
+
+    for size in LIST_SIZES {
+        let set: IpSet = hosts(size / 2).chain(networks(size / 2)).collect();
+        group.bench_with_input(BenchmarkId::new("miss", size), &set, |b, set| {
+            b.iter(|| set.contains(black_box(miss)))
+        });
+        group.bench_with_input(BenchmarkId::new("host_hit", size), &set, |b, set| {
+            b.iter(|| set.contains(black_box(host_hit)))
+        });
+        group.bench_with_input(BenchmarkId::new("network_hit", size), &set, |b, set| {
+            b.iter(|| set.contains(black_box(net_hit)))
+        });
+    }
+    group.finish();
+}
+
+/// Middleware round trips, one per client IP source.
+fn client_ip_extraction(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let app = rt.block_on(TestApp::new(&[]));
+    let mut group = c.benchmark_group("client_ip");
+
+    group.bench_function("cf_connecting_ip", |b| {
+        b.to_async(&rt).iter(|| {
+            let req = Request::get("/")
+                .header("cf-connecting-ip", "198.51.100.7")
+                .body(Body::empty())
+                .unwrap();
+            app.send(req)
+        })
+    });
+    group.bench_function("x_forwarded_for", |b| {
+        b.to_async(&rt).iter(|| {
+            let req = Request::get("/")
+                .header("x-forwarded-for", "198.51.100.7, 10.0.0.1, 10.0.0.2")
+                .body(Body::empty())
+                .unwrap();
+            app.send(req)
+        })
+    });
+    group.bench_function("peer_address", |b| {
+        let peer: SocketAddr = "198.51.100.7:50000".parse().unwrap();
+        b.to_async(&rt).iter(|| {
+            let mut req = Request::get("/").body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            app.send(req)
+        })
+    });
+    group.finish();
+}
+
+/// Full allow and block decisions against banned lists of various sizes.
+fn middleware_round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("middleware");
+    group.sample_size(50);
+
+    for size in LIST_SIZES {
+        let entries: Vec<String> = hosts(size).map(|net| net.addr().to_string()).collect();
+        let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+        let app = rt.block_on(TestApp::new(&entries));
+
+        group.bench_with_input(BenchmarkId::new("allowed", size), &app, |b, app| {
+            b.to_async(&rt).iter(|| app.get_from("192.0.2.1", "/"))
+        });
+        group.bench_with_input(BenchmarkId::new("blocked", size), &app, |b, app| {
+            b.to_async(&rt).iter(|| app.get_from("10.0.0.99", "/"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    ip_set_lookup,
+    client_ip_extraction,
+    middleware_round_trip
+);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+//! Client IP resolution from arbitrary proxy headers and peers, with the
+//! default `CLIENT_IP_SOURCES`.
+
+#![no_main]
+
+use std::net::{IpAddr, SocketAddr};
+
+use http::{HeaderMap, HeaderValue};
+use ipnet::IpNet;
+use libfuzzer_sys::fuzz_target;
+use tezcatlipoca_auth::{
+    client_ip::{CLOUDFLARE_RANGES, ClientIp, Trust},
+    config::IpSource,
+};
+
+const HEADERS: &[&str] = &[
+    "cf-connecting-ip",
+    "x-forwarded-for",
+    "x-real-ip",
+    "true-client-ip",
+];
+
+/// Headers as (name index, value), the peer's address and port, whether
+/// `CLOUDFLARE_VERIFY` is on, and `TRUSTED_HOPS`.
+type Input = (Vec<(u8, Vec<u8>)>, Option<([u8; 16], u16)>, bool, u8);
+
+fuzz_target!(|input: Input| {
+    let (headers, peer, verify, hops) = input;
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let Ok(value) = HeaderValue::from_bytes(&value) else {
+            continue;
+        };
+        map.append(HEADERS[usize::from(name) % HEADERS.len()], value);
+    }
+    let peer =
+        peer.map(|(octets, port)| SocketAddr::new(IpAddr::from(octets).to_canonical(), port));
+    let ranges: Vec<IpNet> = CLOUDFLARE_RANGES
+        .iter()
+        .map(|range| range.parse().unwrap())
+        .collect();
+    let trust = Trust {
+        cloudflare: verify.then_some(ranges.as_slice()),
+        proxies: &[],
+        hops: usize::from(hops),
+    };
+
+    let resolved = ClientIp::resolve(&map, peer, &IpSource::defaults(), trust);
+    // The socket comes last, so a connection always yields an address
+    if peer.is_some() {
+        assert!(resolved.is_some());
+    }
+    if let Some(ClientIp(ip)) = resolved {
+        assert_eq!(ip, ip.to_canonical());
+    }
+});
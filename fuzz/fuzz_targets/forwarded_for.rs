@@ -0,0 +1,46 @@
+//! `x-forwarded-for` chains against arbitrary `TRUSTED_PROXIES` and
+//! `TRUSTED_HOPS`.
+
+#![no_main]
+
+use std::net::IpAddr;
+
+use http::{HeaderMap, HeaderValue};
+use ipnet::{IpNet, Ipv4Net};
+use libfuzzer_sys::fuzz_target;
+use tezcatlipoca_auth::{
+    client_ip::{ClientIp, FORWARDED_FOR, Trust},
+    config::IpSource,
+};
+
+fuzz_target!(|input: (String, Vec<([u8; 4], u8)>, u8)| {
+    let (value, proxies, hops) = input;
+    let Ok(header) = HeaderValue::from_str(&value) else {
+        return;
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(FORWARDED_FOR, header);
+    let proxies: Vec<IpNet> = proxies
+        .into_iter()
+        .filter_map(|(octets, len)| Ipv4Net::new(octets.into(), len % 33).ok())
+        .map(IpNet::from)
+        .collect();
+    let trust = Trust {
+        cloudflare: None,
+        proxies: &proxies,
+        hops: usize::from(hops),
+    };
+
+    let sources = [IpSource::Header(FORWARDED_FOR.to_string())];
+    if let Some(ClientIp(ip)) = ClientIp::resolve(&headers, None, &sources, trust) {
+        // Whichever entry is picked, it must be one of the chain's
+        let entries = value
+            .split(',')
+            .filter_map(|entry| entry.trim().parse::<IpAddr>().ok());
+        assert!(
+            entries
+                .map(|entry| entry.to_canonical())
+                .any(|entry| entry == ip)
+        );
+    }
+});
@@ -0,0 +1,29 @@
+//! The blocklist parser shared by banned IPs files, feeds, object storage
+//! and KV bans.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tezcatlipoca_auth::lists::{parse_entry, parse_list, parse_sections};
+
+fuzz_target!(|data: &[u8]| {
+    let content = String::from_utf8_lossy(data);
+    let lines = content.lines().count();
+
+    let (entries, rejected) = parse_list(&content);
+    assert!(entries.len() + rejected.len() <= lines);
+    assert!(
+        rejected
+            .windows(2)
+            .all(|pair| pair[0].number < pair[1].number)
+    );
+    assert!(rejected.last().is_none_or(|line| line.number <= lines));
+    // Entries read back the same when written out as a list
+    for net in &entries {
+        assert_eq!(parse_entry(&net.to_string()), Some(*net));
+    }
+
+    // Headers only sort entries into categories, never add or drop one
+    let (sections, _) = parse_sections(&content);
+    assert_eq!(sections.len(), entries.len());
+});
@@ -0,0 +1,251 @@
+//! AbuseIPDB reputation lookups.
+//!
+//! When `ABUSEIPDB_API_KEY` is set, client IPs not on any ban list are checked
+//! against AbuseIPDB and blocked if their abuse confidence score reaches
+//! `ABUSEIPDB_THRESHOLD`. Lookups never run on the request path: an unknown IP
+//! is queued for the background [`lookup_task`] and allowed through
+//! (fail-open) until its score is cached. Scores are cached for
+//! `ABUSEIPDB_CACHE_TTL_SECS`; failed lookups are cached briefly as unknown so
+//! an API outage doesn't turn into a retry storm, and block the IP meanwhile
+//! with `FAIL_MODE=closed`.
+
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::{sync::mpsc, time::sleep};
+use tracing::{debug, warn};
+
+use crate::{config::AbuseIpDbConfig, metrics::Metrics, shard::ShardedMap, AppState};
+
+const API_URL: &str = "https://api.abuseipdb.com/api/v2/check";
+
+/// Maximum lookups waiting for the background task; extra IPs are dropped
+/// and retried on their next request.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How long a failed lookup is remembered before the IP is retried.
+const FAILURE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy)]
+struct CachedScore {
+    /// `None` when the lookup failed
+    score: Option<u8>,
+    expires: Instant,
+}
+
+/// Score cache plus the queue feeding the lookup task.
+#[derive(Debug)]
+pub struct AbuseIpDb {
+    config: AbuseIpDbConfig,
+    scores: ShardedMap<String, CachedScore>,
+    /// IPs queued or being looked up, to avoid duplicate queries
+    pending: Mutex<HashSet<String>>,
+    queue: mpsc::Sender<String>,
+    receiver: Mutex<Option<mpsc::Receiver<String>>>,
+}
+
+impl AbuseIpDb {
+    pub fn new(config: AbuseIpDbConfig) -> Self {
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            config,
+            scores: ShardedMap::new(),
+            pending: Mutex::new(HashSet::new()),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Cached score for `ip`, queuing a lookup when it isn't known yet.
+    ///
+    /// Returns `None` for unknown, failed or non-public addresses, which the
+    /// caller treats as "allow".
+    pub fn score(&self, ip: &str) -> Option<u8> {
+        let cached = self.scores.get(ip);
+        if let Some(cached) = cached
+            && cached.expires > Instant::now()
+        {
+            return cached.score;
+        }
+
+        if is_public(ip) {
+            self.enqueue(ip);
+        }
+        None
+    }
+
+    /// Whether the last lookup of `ip` failed and isn't due for a retry yet.
+    pub fn failed(&self, ip: &str) -> bool {
+        self.scores
+            .get(ip)
+            .is_some_and(|cached| cached.score.is_none() && cached.expires > Instant::now())
+    }
+
+    /// Whether `score` is at or above the blocking threshold.
+    pub fn is_abusive(&self, score: u8) -> bool {
+        score >= self.config.threshold
+    }
+
+    fn enqueue(&self, ip: &str) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.contains(ip) {
+            return;
+        }
+        if self.queue.try_send(ip.to_string()).is_ok() {
+            pending.insert(ip.to_string());
+        }
+    }
+
+    fn store(&self, ip: String, score: Option<u8>) {
+        let ttl = if score.is_some() {
+            self.config.cache_ttl
+        } else {
+            FAILURE_TTL
+        };
+        let now = Instant::now();
+        // The cache limit is split evenly across shards
+        let shard_limit = self.config.max_cached.div_ceil(self.scores.shard_count());
+        let mut scores = self.scores.write(&ip);
+        if scores.len() >= shard_limit {
+            scores.retain(|_, cached| cached.expires > now);
+            if scores.len() >= shard_limit
+                && let Some(victim) = scores.keys().next().cloned()
+            {
+                scores.remove(&victim);
+            }
+        }
+        scores.insert(
+            ip.clone(),
+            CachedScore {
+                score,
+                expires: now + ttl,
+            },
+        );
+        drop(scores);
+
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&ip);
+    }
+
+    /// Number of cached scores, including expired ones not yet evicted.
+    pub fn cached_count(&self) -> usize {
+        self.scores.len()
+    }
+}
+
+/// Private, loopback and other non-routable addresses are never looked up.
+pub(crate) fn is_public(ip: &str) -> bool {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation())
+        }
+        Ok(IpAddr::V6(v6)) => {
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_unique_local()
+                || v6.is_unicast_link_local())
+        }
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckResponse {
+    data: CheckData,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckData {
+    abuse_confidence_score: u8,
+}
+
+/// Works through queued lookups one at a time.
+///
+/// Runs until the state is dropped. Does nothing if AbuseIPDB is disabled or
+/// the task was already started.
+pub async fn lookup_task(state: AppState) {
+    let Some(reputation) = state.abuseipdb.clone() else {
+        return;
+    };
+    let Some(mut receiver) = reputation
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    else {
+        return;
+    };
+
+    while let Some(ip) = receiver.recv().await {
+        Metrics::incr(&state.metrics.reputation_lookups);
+        match check(&state.http, &reputation.config, &ip).await {
+            Ok(score) => {
+                debug!("AbuseIPDB score for {}: {}", ip, score);
+                reputation.store(ip, Some(score));
+            }
+            Err(LookupError::RateLimited(retry_after)) => {
+                warn!("AbuseIPDB rate limit reached, pausing lookups for {:?}", retry_after);
+                reputation.store(ip, None);
+                sleep(retry_after).await;
+            }
+            Err(LookupError::Http(e)) => {
+                warn!("AbuseIPDB lookup for {} failed: {}", ip, e);
+                reputation.store(ip, None);
+            }
+        }
+    }
+}
+
+enum LookupError {
+    RateLimited(Duration),
+    Http(reqwest::Error),
+}
+
+async fn check(
+    http: &reqwest::Client,
+    config: &AbuseIpDbConfig,
+    ip: &str,
+) -> Result<u8, LookupError> {
+    let res = http
+        .get(API_URL)
+        .query(&[
+            ("ipAddress", ip),
+            ("maxAgeInDays", &config.max_age_days.to_string()),
+        ])
+        .header("Key", &config.api_key)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(LookupError::Http)?;
+
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = res
+            .headers()
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(3600);
+        return Err(LookupError::RateLimited(Duration::from_secs(retry_after)));
+    }
+
+    let body: CheckResponse = res
+        .error_for_status()
+        .map_err(LookupError::Http)?
+        .json()
+        .await
+        .map_err(LookupError::Http)?;
+    Ok(body.data.abuse_confidence_score)
+}
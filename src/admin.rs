@@ -0,0 +1,725 @@
+//! Operational admin API.
+//!
+//! Enabled with `ADMIN_API=true`. These routes are mounted outside the
+//! ForwardAuth middleware, so watching or managing the service doesn't show
+//! up as (or get blocked like) proxied traffic.
+//!
+//! Every request must carry `Authorization: Bearer <ADMIN_TOKEN>`, checked
+//! regardless of the client IP; without a token configured, every request
+//! is refused. The token is compared in constant time;
+//! refused attempts are logged with the client IP and counted in
+//! `admin_auth_failures` on `/health`.
+//!
+//! # Endpoints
+//! - `GET /admin/events`: Server-Sent Events stream of live decisions and
+//!   security events. `?filter=blocked` hides allowed requests.
+//! - `GET /admin/stats/ip/{ip}`: hit/block counters and first/last seen for
+//!   one client IP, plus whether it is currently banned.
+//! - `GET /admin/stats/top?window=1h&limit=10`: most-blocked IPs, most-hit
+//!   paths and most frequent user agents over a recent window.
+//! - `GET /admin/bans?prefix=203.0.&source=crowdsec&page=2`: banned entries
+//!   with their source, reason and expiry, filtered and paginated.
+//! - `GET /admin/bans/{ip}`: the dynamic ban of an IP with its source,
+//!   reason, expiry and seconds left.
+//! - `PATCH /admin/bans/{ip}` with `{"duration": "6h"}`: makes the dynamic
+//!   ban of an IP end that long from now, shortening or extending it;
+//!   `{"duration": "permanent"}` makes it permanent.
+//! - `DELETE /admin/bans/{ip}`: lifts the dynamic ban of an IP (list
+//!   entries stay banned until removed from their source).
+//! - `GET /admin/bans/export?format=csv`: the dynamic bans as CSV or JSON
+//!   (the default), with reasons and expiries.
+//! - `POST /admin/bans/import?format=csv`: adds bans from a CSV or JSON
+//!   export (see [`crate::bans`]); the format defaults to the body's
+//!   `Content-Type`. Reports the bans applied and the records rejected.
+//! - `POST /admin/refresh`: reloads the banned IPs files and downloads every
+//!   feed now instead of waiting for `CACHE_TTL`, reporting the entries
+//!   loaded and any error per source.
+//! - `GET /admin/maintenance`: whether maintenance mode is on and what set it
+//!   (`admin`, `kv` or `config`).
+//! - `PUT /admin/maintenance` with `{"enabled": true}`: switches maintenance
+//!   mode on or off on this replica; `DELETE /admin/maintenance` returns
+//!   the decision to the KV store and config.
+//! - `GET /admin/lockdown`: whether a lockdown is on, until when and what
+//!   started it (`admin` or `kv`).
+//! - `POST /admin/lockdown?duration=30m`: locks every replica reachable
+//!   through Redis down to `LOCKDOWN_ALLOWLIST` for `duration` (default
+//!   `LOCKDOWN_DURATION`); `DELETE /admin/lockdown` lifts it again. See
+//!   [`crate::lockdown`].
+
+use std::{convert::Infallible, net::IpAddr, time::Duration};
+
+use axum::{
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+        HeaderMap, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tracing::{info, warn};
+
+use crate::{
+    bans::{self, BanFormat, ImportError},
+    cache::{reload_banned_ips, IpSet, FILES_SOURCE},
+    client_ip::ClientIp,
+    config::parse_duration,
+    error::{Error, Result},
+    events::{SecurityEvent, Verdict},
+    feeds, lockdown,
+    maintenance::{self, Origin},
+    metrics::Metrics,
+    stats::IpStats,
+    AppState,
+};
+
+/// Builds the `/admin` routes.
+pub fn router(state: AppState) -> Router {
+    if state.config.admin_token.is_none() {
+        warn!("Admin API enabled without ADMIN_TOKEN; every admin request is refused");
+    }
+    Router::new()
+        .route("/admin/events", get(events))
+        .route("/admin/stats/ip/{ip}", get(ip_stats))
+        .route("/admin/stats/top", get(top_stats))
+        .route("/admin/bans", get(bans))
+        .route("/admin/bans/{ip}", get(ban).patch(update_ban).delete(unban))
+        .route("/admin/bans/export", get(export_bans))
+        .route(
+            "/admin/bans/import",
+            post(import_bans).layer(DefaultBodyLimit::max(IMPORT_MAX_BYTES)),
+        )
+        .route("/admin/refresh", post(refresh))
+        .route(
+            "/admin/maintenance",
+            get(maintenance_status).put(set_maintenance).delete(reset_maintenance),
+        )
+        .route(
+            "/admin/lockdown",
+            get(lockdown_status).post(start_lockdown).delete(lift_lockdown),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+/// Middleware refusing admin requests without the `ADMIN_TOKEN` bearer token.
+///
+/// Without a token (only possible with a `Config` built in code, `from_env`
+/// requires one) every request is refused with 503: the API stays closed
+/// rather than open to anyone.
+async fn require_token(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = &state.config.admin_token else {
+        Metrics::incr(&state.metrics.admin_auth_failures);
+        return (StatusCode::SERVICE_UNAVAILABLE, "ADMIN_TOKEN is not set").into_response();
+    };
+    let presented = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(token) if token_matches(token.trim(), expected) => next.run(req).await,
+        _ => {
+            Metrics::incr(&state.metrics.admin_auth_failures);
+            warn!(
+                "🔑 ADMIN AUTH FAILED: {} token from IP {} for {} {}",
+                if presented.is_some() { "wrong" } else { "missing" },
+                ip,
+                req.method(),
+                req.uri().path()
+            );
+            (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+        }
+    }
+}
+
+/// Compares digests rather than the tokens, so neither the content nor the
+/// length of the expected token shows in the timing.
+fn token_matches(presented: &str, expected: &str) -> bool {
+    let presented = Sha256::digest(presented.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    presented.ct_eq(&expected).into()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EventFilter {
+    /// Every decision plus security events
+    #[default]
+    All,
+    /// Only blocked / would-block decisions plus security events
+    Blocked,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    filter: EventFilter,
+}
+
+/// Streams decisions (`event: decision`) and security events
+/// (`event: security`) as JSON.
+///
+/// Clients that can't keep up receive an `event: lagged` message with the
+/// number of skipped events instead of stalling the service.
+async fn events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let only_blocked = matches!(query.filter, EventFilter::Blocked);
+
+    let decisions = BroadcastStream::new(state.events.subscribe_decisions()).filter_map(
+        move |item| async move {
+            match item {
+                Ok(decision) if only_blocked && decision.verdict == Verdict::Allowed => None,
+                Ok(decision) => Some(json_event("decision", &decision)),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Some(lagged(missed)),
+            }
+        },
+    );
+    let security = BroadcastStream::new(state.events.subscribe()).map(|item| match item {
+        Ok(event) => json_event("security", &event),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => lagged(missed),
+    });
+
+    let stream = futures_util::stream::select(decisions, security).map(Ok);
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn json_event(name: &str, data: &impl serde::Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|_| Event::default().event("error"))
+}
+
+fn lagged(missed: u64) -> Event {
+    Event::default().event("lagged").data(missed.to_string())
+}
+
+#[derive(Serialize)]
+struct IpStatsResponse {
+    ip: String,
+    #[serde(flatten)]
+    stats: IpStats,
+    currently_banned: bool,
+}
+
+/// Statistics for one client IP; 404 if it hasn't been seen recently (or
+/// tracking is disabled with `IP_STATS_CAPACITY=0`).
+async fn ip_stats(
+    State(state): State<AppState>,
+    Path(ip): Path<String>,
+) -> Result<Json<IpStatsResponse>> {
+    let Some(stats) = state.ip_stats.get(&ip) else {
+        return Err(Error::NotFound("IP not seen recently".to_string()));
+    };
+    let currently_banned =
+        state.dynamic_bans.contains(&ip) || state.banned_ips.read().await.contains(&ip);
+
+    Ok(Json(IpStatsResponse {
+        ip,
+        stats,
+        currently_banned,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct TopQuery {
+    window: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Top offenders over `window` (default 1h, capped at `TOP_STATS_RETENTION`),
+/// `limit` entries per list (default 10, at most 100).
+async fn top_stats(State(state): State<AppState>, Query(query): Query<TopQuery>) -> Result<Response> {
+    let window = match query.window.as_deref().map(parse_duration) {
+        None => Duration::from_secs(3600),
+        Some(Some(window)) => window,
+        Some(None) => {
+            let message = "window must be a duration such as 300, 15m or 1h";
+            return Err(Error::BadRequest(message.to_string()));
+        }
+    };
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+
+    Ok(Json(state.top_stats.report(window, limit)).into_response())
+}
+
+#[derive(Serialize)]
+struct RefreshResponse {
+    /// Entries in the cache after the refresh, across all sources
+    entries: usize,
+    sources: Vec<SourceRefresh>,
+}
+
+#[derive(Serialize)]
+struct SourceRefresh {
+    /// `files` for the banned IPs files, otherwise the feed name
+    source: String,
+    /// Entries now loaded from the source
+    entries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Reloads every source at once. A source that fails keeps its previous
+/// entries; the answer takes the status of the worst failure, e.g. `502 Bad
+/// Gateway` for an unreachable feed.
+async fn refresh(State(state): State<AppState>) -> Response {
+    let mut sources = Vec::new();
+    let mut status = StatusCode::OK;
+    let mut failed = |e: Error| {
+        status = status.max(e.status());
+        Some(e.to_string())
+    };
+
+    let files = reload_banned_ips(&state).await;
+    sources.push(SourceRefresh {
+        source: "files".to_string(),
+        entries: state.banned_ips.read().await.file_entries(),
+        error: files.err().and_then(&mut failed),
+    });
+
+    for feed in &state.config.feeds {
+        let (entries, error) = match feeds::refresh_now(&state, feed).await {
+            Ok(entries) => (entries, None),
+            Err(e) => {
+                let cache = state.banned_ips.read().await;
+                let current = cache.sources.get(&feed.name).map_or(0, |s| s.len());
+                (current, failed(e))
+            }
+        };
+        sources.push(SourceRefresh {
+            source: feed.name.clone(),
+            entries,
+            error,
+        });
+    }
+
+    let entries = state.banned_ips.read().await.len();
+    (status, Json(RefreshResponse { entries, sources })).into_response()
+}
+
+#[derive(Serialize)]
+struct MaintenanceStatus {
+    enabled: bool,
+    /// What decided the current mode
+    origin: Origin,
+}
+
+#[derive(Deserialize)]
+struct MaintenanceUpdate {
+    enabled: bool,
+}
+
+async fn maintenance_status(State(state): State<AppState>) -> Json<MaintenanceStatus> {
+    let (enabled, origin) = maintenance::status(&state);
+    Json(MaintenanceStatus { enabled, origin })
+}
+
+/// Switches maintenance mode on this replica, overriding the KV store and
+/// config until reset.
+async fn set_maintenance(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(update): Json<MaintenanceUpdate>,
+) -> Json<MaintenanceStatus> {
+    state.maintenance.set(Some(update.enabled));
+    warn!(
+        "🚧 Maintenance mode switched {} through the admin API from {}",
+        if update.enabled { "on" } else { "off" },
+        ip
+    );
+    maintenance_status(State(state)).await
+}
+
+async fn reset_maintenance(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+) -> Json<MaintenanceStatus> {
+    state.maintenance.set(None);
+    let (enabled, origin) = maintenance::status(&state);
+    info!(
+        "Maintenance mode reset through the admin API from {}, now {} (from {:?})",
+        ip,
+        if enabled { "on" } else { "off" },
+        origin
+    );
+    Json(MaintenanceStatus { enabled, origin })
+}
+
+#[derive(Serialize)]
+struct LockdownStatus {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<DateTime<Utc>>,
+    /// What started the current lockdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<lockdown::Origin>,
+}
+
+#[derive(Deserialize)]
+struct LockdownQuery {
+    duration: Option<String>,
+}
+
+async fn lockdown_status(State(state): State<AppState>) -> Json<LockdownStatus> {
+    let status = lockdown::status(&state);
+    Json(LockdownStatus {
+        active: status.is_some(),
+        until: status.map(|(until, _)| until),
+        origin: status.map(|(_, origin)| origin),
+    })
+}
+
+/// Locks down this replica and, through Redis, the others; 400 when the
+/// duration is invalid or above `LOCKDOWN_MAX_DURATION`.
+async fn start_lockdown(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<LockdownQuery>,
+) -> Result<Json<LockdownStatus>> {
+    let duration = match query.duration.as_deref().map(parse_duration) {
+        None => None,
+        Some(Some(duration)) => Some(duration),
+        Some(None) => {
+            let message = "duration must be a duration such as 15m or 2h";
+            return Err(Error::BadRequest(message.to_string()));
+        }
+    };
+    let Some(until) = lockdown::end(&state, duration) else {
+        let max = state.config.lockdown_max_duration;
+        let message = format!("duration must be above zero and at most {:?}", max);
+        return Err(Error::BadRequest(message));
+    };
+    state.lockdown.set(Some(until));
+    warn!("🔒 LOCKDOWN until {} started through the admin API from {}", until, ip);
+    state.events.publish(SecurityEvent::Lockdown {
+        until: Some(until),
+        timestamp: Utc::now(),
+    });
+    Ok(lockdown_status(State(state)).await)
+}
+
+/// Lifts the lockdown started through the admin API, here and through
+/// Redis; a lockdown set in the KV store stays until its key is deleted.
+async fn lift_lockdown(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+) -> Json<LockdownStatus> {
+    state.lockdown.set(None);
+    info!("Lockdown lifted through the admin API from {}", ip);
+    state.events.publish(SecurityEvent::Lockdown {
+        until: None,
+        timestamp: Utc::now(),
+    });
+    lockdown_status(State(state)).await
+}
+
+/// Source name of the bans added at runtime.
+const DYNAMIC_SOURCE: &str = bans::DYNAMIC;
+
+#[derive(Debug, Deserialize)]
+struct BansQuery {
+    /// Only entries whose text starts with this, e.g. `203.0.`
+    prefix: Option<String>,
+    /// Only entries of this source: `files`, `dynamic`, or a feed name
+    source: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct BansPage {
+    /// Entries matching the filters, across all pages
+    total: usize,
+    page: usize,
+    per_page: usize,
+    entries: Vec<BannedEntry>,
+}
+
+#[derive(Serialize)]
+struct BannedEntry {
+    entry: String,
+    source: String,
+    /// `[category]` section of a banned IPs file entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    /// Only known for dynamic bans
+    reason: Option<String>,
+    /// `None` for list entries and permanent bans
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Banned entries of every source, sorted by address; `page` starts at 1,
+/// `per_page` defaults to 100 (at most 1000).
+///
+/// An entry listed by several sources appears once per source.
+async fn bans(State(state): State<AppState>, Query(query): Query<BansQuery>) -> Response {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(100).clamp(1, 1000);
+    let prefix = query.prefix.as_deref().unwrap_or("");
+    let wanted = |source: &str| query.source.as_deref().is_none_or(|s| s == source);
+
+    let mut matches: Vec<(IpNet, BannedEntry)> = Vec::new();
+    let mut add_list = |source: &str, category: Option<&str>, set: &IpSet| {
+        for net in set.iter() {
+            let entry = display(net);
+            if entry.starts_with(prefix) {
+                matches.push((
+                    net,
+                    BannedEntry {
+                        entry,
+                        source: source.to_string(),
+                        category: category.map(str::to_string),
+                        reason: None,
+                        expires: None,
+                    },
+                ));
+            }
+        }
+    };
+    {
+        let cache = state.banned_ips.read().await;
+        if wanted(FILES_SOURCE) {
+            add_list(FILES_SOURCE, None, &cache.ips);
+            for (category, set) in &cache.categories {
+                add_list(FILES_SOURCE, Some(category), set);
+            }
+        }
+        for (name, set) in &cache.sources {
+            if wanted(name) {
+                add_list(name, None, set);
+            }
+        }
+    }
+    if wanted(DYNAMIC_SOURCE) {
+        for ban in state.dynamic_bans.active() {
+            let Ok(ip) = ban.ip.parse::<IpAddr>() else {
+                continue;
+            };
+            if ban.ip.starts_with(prefix) {
+                let entry = BannedEntry {
+                    entry: ban.ip,
+                    source: DYNAMIC_SOURCE.to_string(),
+                    category: None,
+                    reason: Some(ban.reason),
+                    expires: ban.expires,
+                };
+                matches.push((IpNet::from(ip), entry));
+            }
+        }
+    }
+
+    matches.sort_unstable_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.source.cmp(&y.source)));
+    let total = matches.len();
+    let entries = matches
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|(_, entry)| entry)
+        .collect();
+
+    Json(BansPage {
+        total,
+        page,
+        per_page,
+        entries,
+    })
+    .into_response()
+}
+
+/// Single addresses without the `/32` or `/128` suffix, as they are written
+/// in the lists.
+fn display(net: IpNet) -> String {
+    if net.prefix_len() == net.max_prefix_len() {
+        net.addr().to_string()
+    } else {
+        net.to_string()
+    }
+}
+
+/// Largest accepted import body; a 100k-ban CSV export is around 8 MiB.
+const IMPORT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+impl FormatQuery {
+    /// The requested format, falling back to `default`.
+    fn parse(&self, default: BanFormat) -> Result<BanFormat> {
+        self.format
+            .as_deref()
+            .map_or(Ok(default), str::parse)
+            .map_err(Error::BadRequest)
+    }
+}
+
+/// Dynamic bans in the requested format, sorted by IP.
+async fn export_bans(
+    State(state): State<AppState>,
+    Query(query): Query<FormatQuery>,
+) -> Result<Response> {
+    let format = query.parse(BanFormat::Json)?;
+    let body = bans::export_bans(&state.dynamic_bans.active(), format);
+    Ok(([(CONTENT_TYPE, format.content_type())], body).into_response())
+}
+
+#[derive(Serialize)]
+struct ImportResponse {
+    /// Bans added or updated
+    imported: usize,
+    /// Valid records skipped because their expiry has passed
+    expired: usize,
+    errors: Vec<ImportError>,
+}
+
+/// Adds the bans of a CSV or JSON document. Valid records are applied even
+/// when others are rejected; a body that can't be read at all is a 400.
+async fn import_bans(
+    State(state): State<AppState>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response> {
+    let csv_body = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("csv"));
+    let default = if csv_body { BanFormat::Csv } else { BanFormat::Json };
+    let format = query.parse(default)?;
+
+    let (bans, errors) = bans::parse_bans(&body, format);
+    if errors.iter().any(|e| e.record == 0) {
+        return Ok((StatusCode::BAD_REQUEST, Json(errors)).into_response());
+    }
+    let imported = state.dynamic_bans.import(&bans);
+    info!(
+        "Imported {} bans ({} expired, {} rejected)",
+        imported,
+        bans.len() - imported,
+        errors.len()
+    );
+    Ok(Json(ImportResponse {
+        imported,
+        expired: bans.len() - imported,
+        errors,
+    })
+    .into_response())
+}
+
+/// Dynamic bans are keyed like request IPs, so `::ffff:203.0.113.7` finds
+/// `203.0.113.7`.
+fn canonical(ip: String) -> String {
+    ip.parse::<IpAddr>()
+        .map_or(ip, |addr| addr.to_canonical().to_string())
+}
+
+#[derive(Serialize)]
+struct BanStatus {
+    #[serde(flatten)]
+    ban: bans::ActiveBan,
+    /// Seconds until the ban ends, `None` when permanent
+    remaining_secs: Option<u64>,
+}
+
+impl From<bans::ActiveBan> for BanStatus {
+    fn from(ban: bans::ActiveBan) -> Self {
+        let remaining_secs = ban
+            .expires
+            .map(|expires| (expires - Utc::now()).num_seconds().max(0) as u64);
+        Self {
+            ban,
+            remaining_secs,
+        }
+    }
+}
+
+/// The dynamic ban of an IP; 404 when it has none.
+async fn ban(State(state): State<AppState>, Path(ip): Path<String>) -> Result<Json<BanStatus>> {
+    match state.dynamic_bans.get(&canonical(ip)) {
+        Some(ban) => Ok(Json(BanStatus::from(ban))),
+        None => Err(no_dynamic_ban()),
+    }
+}
+
+fn no_dynamic_ban() -> Error {
+    Error::NotFound("IP has no dynamic ban".to_string())
+}
+
+#[derive(Deserialize)]
+struct BanUpdate {
+    /// New length from now, or `permanent`
+    duration: String,
+}
+
+/// Changes when a dynamic ban ends, here and through Redis; 404 when the IP
+/// has no ban, 400 for an invalid duration.
+async fn update_ban(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    Path(ip): Path<String>,
+    Json(update): Json<BanUpdate>,
+) -> Result<Json<BanStatus>> {
+    let ip = canonical(ip);
+    let duration = match update.duration.trim() {
+        "permanent" => None,
+        duration => match parse_duration(duration).filter(|d| !d.is_zero()) {
+            Some(duration) => Some(duration),
+            None => {
+                let message = "duration must be a duration such as 30m or 7d, or \"permanent\"";
+                return Err(Error::BadRequest(message.to_string()));
+            }
+        },
+    };
+    let Some(ban) = state.dynamic_bans.set_duration(&ip, duration) else {
+        return Err(no_dynamic_ban());
+    };
+    info!(
+        "Dynamic ban of {} now ends {} through the admin API from {}",
+        ip,
+        ban.expires.map_or("never".to_string(), |t| format!("at {}", t)),
+        client
+    );
+    state.events.publish(SecurityEvent::BanUpdated {
+        ip,
+        reason: ban.reason.clone(),
+        duration_secs: duration.map(|d| d.as_secs()),
+        timestamp: Utc::now(),
+    });
+    Ok(Json(BanStatus::from(ban)))
+}
+
+/// Lifts a dynamic ban; 404 when the IP has none.
+async fn unban(State(state): State<AppState>, Path(ip): Path<String>) -> Result<StatusCode> {
+    let ip = canonical(ip);
+    if !state.dynamic_bans.unban(&ip) {
+        return Err(no_dynamic_ban());
+    }
+    info!("Dynamic ban of {} lifted through the admin API", ip);
+    state.events.publish(SecurityEvent::Unban {
+        ip,
+        timestamp: Utc::now(),
+    });
+    Ok(StatusCode::NO_CONTENT)
+}
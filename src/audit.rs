@@ -0,0 +1,164 @@
+//! Append-only audit trail of request decisions.
+//!
+//! With `AUDIT_LOG` set, every decision is written as one JSON line, apart
+//! from the human-readable logs and unaffected by `RUST_LOG`. Each record
+//! carries a sequence number that increases by one per record, so gaps
+//! reveal lost or removed lines; after a restart the sequence continues from
+//! the last record of the file. `AUDIT_LOG=unix:///path` sends the lines to
+//! a stream socket instead (e.g. a log shipper), numbered from 1.
+//!
+//! Records are written by a dedicated thread. Unlike the live event stream
+//! the trail never drops decisions; while the destination is unavailable they
+//! queue in memory.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::events::DecisionEvent;
+
+/// One line of the audit trail.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub decision: &'a DecisionEvent,
+    /// Whether blocks were enforced (`ENFORCE`)
+    pub enforce: bool,
+    pub host: &'a str,
+}
+
+/// Handle for queueing decisions to the audit writer.
+#[derive(Debug)]
+pub struct AuditTrail {
+    queue: Sender<DecisionEvent>,
+}
+
+impl AuditTrail {
+    /// Opens `destination` (a file path or `unix:///path`) and starts the
+    /// writer thread.
+    pub fn open(destination: &str, enforce: bool) -> io::Result<Self> {
+        let (sink, last_seq) = match destination.strip_prefix("unix://") {
+            Some(path) => (Sink::socket(path)?, 0),
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .read(true)
+                    .open(destination)?;
+                let last_seq = last_sequence(&file)?;
+                info!("Audit trail {} continues after record {}", destination, last_seq);
+                (Sink::File(BufWriter::new(file)), last_seq)
+            }
+        };
+        let (queue, records) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("audit".to_string())
+            .spawn(move || write_records(sink, records, last_seq, enforce))?;
+        Ok(Self { queue })
+    }
+
+    /// Queues a decision; never blocks.
+    pub fn record(&self, decision: DecisionEvent) {
+        // Only fails once the writer thread has died, which it reports itself
+        let _ = self.queue.send(decision);
+    }
+}
+
+/// Sequence number of the last record in `file`, 0 when it is empty.
+fn last_sequence(file: &File) -> io::Result<u64> {
+    #[derive(serde::Deserialize)]
+    struct Seq {
+        seq: u64,
+    }
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+    let mut last = 0;
+    for line in reader.lines() {
+        if let Ok(record) = serde_json::from_str::<Seq>(&line?) {
+            last = record.seq;
+        }
+    }
+    Ok(last)
+}
+
+fn write_records(mut sink: Sink, records: Receiver<DecisionEvent>, mut seq: u64, enforce: bool) {
+    let host = crate::logger::hostname();
+    let mut pending: Vec<u8> = Vec::new();
+    while let Ok(first) = records.recv() {
+        // Write everything queued so far with one flush
+        for decision in std::iter::once(first).chain(records.try_iter()) {
+            seq += 1;
+            let record = AuditRecord {
+                seq,
+                decision: &decision,
+                enforce,
+                host: &host,
+            };
+            if serde_json::to_writer(&mut pending, &record).is_ok() {
+                pending.push(b'\n');
+            }
+        }
+        while let Err(e) = sink.write(&pending) {
+            error!("Failed to write audit trail, retrying: {}", e);
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        pending.clear();
+    }
+}
+
+enum Sink {
+    File(BufWriter<File>),
+    #[cfg(unix)]
+    Socket {
+        path: String,
+        stream: Option<std::os::unix::net::UnixStream>,
+    },
+}
+
+impl Sink {
+    /// Socket sink, connected on first write.
+    #[cfg(unix)]
+    fn socket(path: &str) -> io::Result<Self> {
+        Ok(Self::Socket {
+            path: path.to_string(),
+            stream: None,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn socket(_path: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        ))
+    }
+
+    /// Writes and flushes `lines`; a failed socket is reconnected on retry.
+    fn write(&mut self, lines: &[u8]) -> io::Result<()> {
+        match self {
+            Self::File(file) => {
+                file.write_all(lines)?;
+                file.flush()
+            }
+            #[cfg(unix)]
+            Self::Socket { path, stream } => {
+                let connection = match stream {
+                    Some(connection) => connection,
+                    None => stream.insert(std::os::unix::net::UnixStream::connect(path.as_str())?),
+                };
+                let result = connection.write_all(lines);
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Primary authentication after IP screening.
+//!
+//! With `AUTH_MODE=basic`, requests for paths under `AUTH_PATHS` need HTTP
+//! Basic credentials matching an entry of `AUTH_USERS_FILE`, an htpasswd
+//! file with bcrypt hashes (`htpasswd -nbB user password`). The file is read
+//! at startup. With `AUTH_MODE=ldap` the credentials are checked against a
+//! directory server instead (see [`crate::ldap`]).
+//!
+//! Checking a bcrypt hash or asking the directory is slow, so a successful login is
+//! answered with a signed `tez_session` cookie (see [`session`]). While it is
+//! valid (`SESSION_TTL`) the cookie alone authenticates the user and the
+//! credentials aren't looked at again. Traefik only passes the cookie on to
+//! the browser when it is listed in the middleware's
+//! `addAuthCookiesToResponse`. The user name is returned in `X-Auth-User`
+//! for `authResponseHeaders`.
+//!
+//! Paths under `TOTP_PATHS` also need a one-time code; see [`crate::totp`].
+
+use std::{collections::HashMap, net::IpAddr};
+
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tracing::{info, warn};
+
+use crate::{
+    challenge::{escape, safe_return_path, Passed},
+    config::{AuthConfig, AuthMode, BlockResponse},
+    controllers,
+    session::{self, Signer},
+    totp::{Enrollment, TotpStore},
+    AppState,
+};
+
+/// Name of the session cookie.
+pub const SESSION_COOKIE: &str = "tez_session";
+
+/// Response header carrying the authenticated user name.
+pub const USER_HEADER: &str = "x-auth-user";
+
+/// Prefixes of session token subjects, so other signed cookies (bound to an
+/// IP) are never mistaken for a session.
+const SUBJECT_PREFIX: &str = "user:";
+const SUBJECT_PREFIX_TOTP: &str = "user+totp:";
+
+/// A request that passed authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub user: String,
+    /// Whether the session also passed the TOTP second factor
+    pub totp: bool,
+    /// `Set-Cookie` value when the session was just created
+    pub set_cookie: Option<String>,
+}
+
+/// Why a request wasn't authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+    /// Neither a session cookie nor credentials were sent
+    #[error("AUTH REQUIRED")]
+    Missing,
+    /// Unknown user or wrong password
+    #[error("INVALID CREDENTIALS")]
+    Invalid,
+}
+
+/// Credential checker for the configured [`AuthMode`].
+#[derive(Debug)]
+pub struct Authenticator {
+    config: AuthConfig,
+    /// User name to bcrypt hash
+    users: HashMap<String, String>,
+    totp: Option<TotpStore>,
+}
+
+impl Authenticator {
+    /// Loads the user database.
+    ///
+    /// An unreadable users file leaves the database empty, so every login
+    /// fails rather than the protected paths opening up.
+    pub fn new(config: AuthConfig) -> Self {
+        let users = match &config.mode {
+            AuthMode::Basic { users_file } => match std::fs::read_to_string(users_file) {
+                Ok(text) => {
+                    let users = parse_htpasswd(&text);
+                    info!("Loaded {} user(s) from {}", users.len(), users_file);
+                    users
+                }
+                Err(e) => {
+                    warn!("Failed to read users file {}: {}", users_file, e);
+                    HashMap::new()
+                }
+            },
+            AuthMode::Ldap(_) => HashMap::new(),
+        };
+        let totp = config
+            .totp
+            .clone()
+            .map(|totp| TotpStore::new(totp, &config.realm));
+        Self {
+            config,
+            users,
+            totp,
+        }
+    }
+
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    /// Second factor store, when `TOTP_PATHS` is set.
+    pub fn totp(&self) -> Option<&TotpStore> {
+        self.totp.as_ref()
+    }
+
+    /// Whether requests for `path` must be authenticated.
+    pub fn required(&self, path: &str) -> bool {
+        controllers::path_matches(&self.config.paths, path)
+    }
+
+    /// Authenticates a request by its session cookie or, failing that, its
+    /// credentials; the latter starts a new session.
+    pub async fn authenticate(
+        &self,
+        signer: &Signer,
+        headers: &HeaderMap,
+    ) -> Result<Session, AuthError> {
+        let subject = session::cookie(headers, SESSION_COOKIE).and_then(|token| signer.subject(token));
+        if let Some(subject) = subject {
+            let session = |user: &str, totp| Session {
+                user: user.to_string(),
+                totp,
+                set_cookie: None,
+            };
+            if let Some(user) = subject.strip_prefix(SUBJECT_PREFIX) {
+                return Ok(session(user, false));
+            }
+            if let Some(user) = subject.strip_prefix(SUBJECT_PREFIX_TOTP) {
+                return Ok(session(user, true));
+            }
+        }
+
+        let (user, password) = basic_credentials(headers).ok_or(AuthError::Missing)?;
+        if !self.verify_password(&user, password).await {
+            return Err(AuthError::Invalid);
+        }
+
+        Ok(Session {
+            set_cookie: Some(self.session_cookie(signer, &user, false, headers)),
+            user,
+            totp: false,
+        })
+    }
+
+    async fn verify_password(&self, user: &str, password: String) -> bool {
+        match &self.config.mode {
+            AuthMode::Basic { .. } => {
+                let Some(hash) = self.users.get(user).cloned() else {
+                    return false;
+                };
+                // bcrypt takes tens of milliseconds by design; keep it off the runtime
+                tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+                    .await
+                    .is_ok_and(|result| result.unwrap_or(false))
+            }
+            #[cfg(feature = "ldap")]
+            AuthMode::Ldap(ldap) => crate::ldap::authenticate(ldap, user, &password).await,
+            #[cfg(not(feature = "ldap"))]
+            AuthMode::Ldap(_) => {
+                warn!("AUTH_MODE=ldap but this build lacks the ldap feature; refusing login");
+                false
+            }
+        }
+    }
+
+    /// `Set-Cookie` value for a new session of `user`.
+    fn session_cookie(&self, signer: &Signer, user: &str, totp: bool, headers: &HeaderMap) -> String {
+        let prefix = if totp { SUBJECT_PREFIX_TOTP } else { SUBJECT_PREFIX };
+        let token = signer.issue(&format!("{}{}", prefix, user), self.config.session_ttl);
+        session::set_cookie(
+            SESSION_COOKIE,
+            &token,
+            self.config.session_ttl,
+            session::is_https(headers),
+        )
+    }
+
+    /// Checks a code submitted to `TOTP_PATH`.
+    ///
+    /// The primary login is checked again, so the code can't be submitted
+    /// for someone else. A valid code starts a session that includes the
+    /// second factor; an invalid one shows the code page again.
+    pub async fn verify_totp(
+        &self,
+        signer: &Signer,
+        query: &str,
+        headers: &HeaderMap,
+    ) -> Result<Passed, BlockResponse> {
+        let unauthorized = || BlockResponse::Unauthorized(self.config.realm.clone());
+        let totp = self.totp.as_ref().ok_or_else(unauthorized)?;
+        let session = self
+            .authenticate(signer, headers)
+            .await
+            .map_err(|_| unauthorized())?;
+
+        let mut code = String::new();
+        let mut return_to = "/".to_string();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "code" => code = value.into_owned(),
+                "return" => return_to = safe_return_path(&value).to_string(),
+                _ => {}
+            }
+        }
+
+        if !session.totp && !totp.verify(&session.user, &code) {
+            warn!("🔑 TOTP FAILED: user {} submitted an invalid code", session.user);
+            return Err(BlockResponse::Challenge(self.totp_page(&session.user, &return_to, true)));
+        }
+        Ok(Passed {
+            set_cookie: self.session_cookie(signer, &session.user, true, headers),
+            location: return_to,
+        })
+    }
+
+    /// Page asking `user` for a one-time code, with the provisioning URI
+    /// while the user hasn't enrolled yet.
+    pub fn totp_page(&self, user: &str, return_to: &str, failed: bool) -> String {
+        let Some(totp) = &self.totp else {
+            return String::new();
+        };
+        let enroll = match totp.enrollment(user) {
+            Enrollment::Enrolled => String::new(),
+            Enrollment::Pending { uri } => format!(
+                r#"<p>Add this account to your authenticator app, then enter the code it shows:</p>
+<p><a href="{uri}"><code>{uri}</code></a></p>
+"#,
+                uri = escape(&uri)
+            ),
+        };
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Verification code</title>
+<style>body{{font-family:system-ui,sans-serif;display:flex;justify-content:center;margin-top:15vh}}</style>
+</head>
+<body>
+<form method="get" action="{action}">
+<h1>Verification code</h1>
+{enroll}{error}<input type="hidden" name="return" value="{return_to}">
+<input name="code" inputmode="numeric" autocomplete="one-time-code" pattern="[0-9]{{6}}" required autofocus>
+<button type="submit">Verify</button>
+</form>
+</body>
+</html>
+"#,
+            action = escape(&totp.config().path),
+            error = if failed { "<p>That code wasn't valid, try again.</p>\n" } else { "" },
+            return_to = escape(return_to),
+        )
+    }
+}
+
+/// Answers a request for `TOTP_PATH` once the IP check has passed; `None`
+/// when the path isn't the TOTP endpoint.
+pub async fn totp_endpoint(
+    state: &AppState,
+    ip: IpAddr,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+) -> Option<Result<Passed, BlockResponse>> {
+    let auth = state.auth.as_ref()?;
+    if auth.totp()?.config().path != path {
+        return None;
+    }
+    Some(match controllers::check(state, ip, method, path, query, headers).await {
+        Ok(_) => auth.verify_totp(&state.cookie_signer, query, headers).await,
+        Err(block) => Err(block),
+    })
+}
+
+/// Parses `user:hash` lines; blank lines and `#` comments are skipped.
+pub fn parse_htpasswd(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(user, hash)| (user.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// User and password from an `Authorization: Basic` header.
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
@@ -0,0 +1,649 @@
+//! Dynamic, runtime-added bans.
+//!
+//! Unlike the banned IPs file, these bans are created by the service itself
+//! (for example when a client hits a honeypot path) and expire on their own.
+//!
+//! Automatic bans escalate for repeat offenders: with `BAN_ESCALATION` set
+//! (e.g. `1h,6h,24h,permanent`) each new offense within
+//! `BAN_ESCALATION_RESET` of the previous one moves the IP to the next step.
+//! With `BAN_HISTORY_FILE` set, offense counts and active bans are saved to
+//! disk by [`history_task`] and restored at startup, so a restart neither
+//! resets the escalation clock nor lifts long bans.
+//!
+//! Every ban records a free-text reason and its source: `local` for the
+//! automatic bans of this instance, `gossip` or `redis` for bans received
+//! from other instances, `history` for bans restored at startup and
+//! `import` (unless the record names another) for imported ones. Both are
+//! reported with the requests the ban blocks.
+//!
+//! Bans can be exported and imported in bulk as CSV
+//! (`ip,reason,expires,source`) or JSON (an array of `{"ip", "reason",
+//! "expires", "source"}` objects), e.g. to migrate from fail2ban or another
+//! bouncer. `expires` is an RFC 3339 time or Unix timestamp; empty or
+//! missing means permanent. `source` may be left out too, so three-column
+//! CSV files keep importing.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    net::IpAddr,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, time::sleep};
+use tracing::{debug, info, warn};
+
+use crate::{events::BanDetail, shard::ShardedMap, AppState};
+
+/// Source and category name of the dynamic bans as a whole.
+pub const DYNAMIC: &str = "dynamic";
+
+/// Source of automatic bans made by this instance.
+pub const SOURCE_LOCAL: &str = "local";
+
+/// Source of bans received from a replica over UDP, see [`crate::gossip`].
+pub const SOURCE_GOSSIP: &str = "gossip";
+
+/// Source of bans received through Redis pub/sub, see [`crate::pubsub`].
+pub const SOURCE_REDIS: &str = "redis";
+
+/// Source of bans restored from `BAN_HISTORY_FILE`.
+pub const SOURCE_HISTORY: &str = "history";
+
+/// Source of imported bans that don't name one.
+pub const SOURCE_IMPORT: &str = "import";
+
+/// How often the offense history is written when it has changed.
+const HISTORY_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Offense history of one IP, as persisted in `BAN_HISTORY_FILE`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffenseRecord {
+    /// Offenses counted towards escalation
+    pub offenses: u32,
+    pub last_offense: DateTime<Utc>,
+    /// End of the ban from the last offense (`None` means permanent)
+    pub ban_expires: Option<DateTime<Utc>>,
+    /// What triggered the last offense, e.g. `honeypot`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// An active dynamic ban, as listed by [`DynamicBans::active`] and
+/// exchanged by import and export.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveBan {
+    pub ip: String,
+    pub reason: String,
+    /// Where the ban comes from, e.g. `local` or `import`
+    #[serde(default = "import_source")]
+    pub source: String,
+    /// `None` means permanent
+    pub expires: Option<DateTime<Utc>>,
+}
+
+fn import_source() -> String {
+    SOURCE_IMPORT.to_string()
+}
+
+#[derive(Clone, Debug)]
+struct BanEntry {
+    /// `None` means permanent
+    expires: Option<Instant>,
+    reason: String,
+    source: String,
+}
+
+impl BanEntry {
+    /// The ban as listed, with its expiry in wall-clock time.
+    fn active(self, ip: String, now: Instant, wall_now: DateTime<Utc>) -> ActiveBan {
+        ActiveBan {
+            ip,
+            reason: self.reason,
+            source: self.source,
+            expires: self.expires.and_then(|t| {
+                chrono::Duration::from_std(t - now).ok().map(|left| wall_now + left)
+            }),
+        }
+    }
+}
+
+/// Set of dynamically banned IPs with optional expiry.
+#[derive(Debug, Default)]
+pub struct DynamicBans {
+    entries: ShardedMap<String, BanEntry>,
+    /// Prior automatic bans per IP
+    offenses: ShardedMap<String, OffenseRecord>,
+    /// Ban durations for the 1st, 2nd, ... offense; empty disables escalation
+    escalation: Vec<Option<Duration>>,
+    /// Offenses older than this no longer count
+    escalation_reset: Duration,
+    /// Offense history changed since it was last saved
+    dirty: AtomicBool,
+    /// Bumped on every ban and unban
+    version: AtomicU64,
+}
+
+impl DynamicBans {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the set with an escalation ladder for [`offend`](Self::offend).
+    pub fn with_escalation(steps: Vec<Option<Duration>>, reset: Duration) -> Self {
+        Self {
+            escalation: steps,
+            escalation_reset: reset,
+            ..Self::default()
+        }
+    }
+
+    /// Bans `ip` for `duration`, or permanently when `duration` is `None`.
+    ///
+    /// Re-banning an already banned IP replaces its expiry, reason and
+    /// source.
+    pub fn ban(&self, ip: &str, duration: Option<Duration>, reason: &str, source: &str) {
+        let entry = BanEntry {
+            expires: duration.map(|d| Instant::now() + d),
+            reason: reason.to_string(),
+            source: source.to_string(),
+        };
+        self.entries.insert(ip.to_string(), entry);
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lifts the ban of `ip`, keeping its offense history. Returns whether
+    /// it was banned.
+    pub fn unban(&self, ip: &str) -> bool {
+        let active = self.contains(ip);
+        self.entries.remove(ip);
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        // Ended now rather than `None`, which would restore it as permanent
+        if let Some(record) = self.offenses.write(ip).get_mut(ip) {
+            record.ban_expires = Some(Utc::now());
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        active
+    }
+
+    /// Changes on every ban and unban (not when a ban expires), so cached
+    /// decisions can tell they are outdated.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Records an offense by `ip` and bans it automatically, with the
+    /// `local` source.
+    ///
+    /// Without an escalation ladder the ban lasts `default`. Otherwise the
+    /// n-th offense uses the n-th step, staying on the last one; the count
+    /// starts over once the previous offense is older than the reset period.
+    /// Returns the applied duration (`None` means permanent).
+    pub fn offend(&self, ip: &str, default: Option<Duration>, reason: &str) -> Option<Duration> {
+        let now = Utc::now();
+        let mut offenses = self.offenses.write(ip);
+        let record = offenses.entry(ip.to_string()).or_insert(OffenseRecord {
+            offenses: 0,
+            last_offense: now,
+            ban_expires: None,
+            reason: None,
+        });
+        let expired = (now - record.last_offense)
+            .to_std()
+            .is_ok_and(|age| age > self.escalation_reset);
+        if expired {
+            record.offenses = 0;
+        }
+        record.offenses += 1;
+        record.last_offense = now;
+        record.reason = Some(reason.to_string());
+
+        let duration = match self.escalation.as_slice() {
+            [] => default,
+            steps => steps[(record.offenses as usize - 1).min(steps.len() - 1)],
+        };
+        record.ban_expires = duration
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .map(|d| now + d);
+        drop(offenses);
+
+        self.dirty.store(true, Ordering::Relaxed);
+        self.ban(ip, duration, reason, SOURCE_LOCAL);
+        duration
+    }
+
+    /// Number of recorded offenses for `ip`.
+    pub fn offenses(&self, ip: &str) -> u32 {
+        self.offenses.get(ip).map_or(0, |r| r.offenses)
+    }
+
+    /// Whether `ip` is currently banned. Expired entries count as not banned.
+    pub fn contains(&self, ip: &str) -> bool {
+        self.entries
+            .get(ip)
+            .is_some_and(|ban| ban.expires.is_none_or(|t| t > Instant::now()))
+    }
+
+    /// Reason, source and end of the active ban of `ip`, if any.
+    pub fn detail(&self, ip: &str) -> Option<BanDetail> {
+        let now = Instant::now();
+        self.entries
+            .get(ip)
+            .filter(|ban| ban.expires.is_none_or(|t| t > now))
+            .map(|ban| BanDetail {
+                source: ban.source,
+                reason: Some(ban.reason),
+                expires: ban.expires.and_then(|t| {
+                    chrono::Duration::from_std(t - now).ok().map(|left| Utc::now() + left)
+                }),
+            })
+    }
+
+    /// Number of active (non-expired) bans.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.entries
+            .count(|ban| ban.expires.is_none_or(|t| t > now))
+    }
+
+    /// Snapshot of the active bans, in no particular order.
+    pub fn active(&self) -> Vec<ActiveBan> {
+        let now = Instant::now();
+        let wall_now = Utc::now();
+        self.entries
+            .entries()
+            .into_iter()
+            .filter(|(_, ban)| ban.expires.is_none_or(|t| t > now))
+            .map(|(ip, ban)| ban.active(ip, now, wall_now))
+            .collect()
+    }
+
+    /// The active ban of `ip`, if any.
+    pub fn get(&self, ip: &str) -> Option<ActiveBan> {
+        let now = Instant::now();
+        self.entries
+            .get(ip)
+            .filter(|ban| ban.expires.is_none_or(|t| t > now))
+            .map(|ban| ban.active(ip.to_string(), now, Utc::now()))
+    }
+
+    /// Makes the active ban of `ip` end `duration` from now, or permanent
+    /// when `duration` is `None`, keeping its reason and source.
+    /// Returns the changed ban, `None` when `ip` isn't banned.
+    ///
+    /// The offense history follows, so a restart with `BAN_HISTORY_FILE`
+    /// restores the new end.
+    pub fn set_duration(&self, ip: &str, duration: Option<Duration>) -> Option<ActiveBan> {
+        let now = Instant::now();
+        let mut entries = self.entries.write(ip);
+        let ban = entries
+            .get_mut(ip)
+            .filter(|ban| ban.expires.is_none_or(|t| t > now))?;
+        ban.expires = duration.map(|d| now + d);
+        let ban = ban.clone().active(ip.to_string(), now, Utc::now());
+        drop(entries);
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(record) = self.offenses.write(ip).get_mut(ip) {
+            record.ban_expires = ban.expires;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Some(ban)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Snapshot of the offense history, dropping records that no longer
+    /// count towards escalation and whose ban is over.
+    pub fn history(&self) -> HashMap<String, OffenseRecord> {
+        self.prune_offenses();
+        self.offenses.entries().into_iter().collect()
+    }
+
+    /// Drops offense records that no longer count towards escalation and
+    /// whose ban is over, returning how many.
+    pub fn prune_offenses(&self) -> usize {
+        let now = Utc::now();
+        let reset =
+            chrono::Duration::from_std(self.escalation_reset).unwrap_or(chrono::Duration::MAX);
+        self.offenses.retain(|_, record| {
+            now - record.last_offense <= reset || record.ban_expires.is_none_or(|t| t > now)
+        })
+    }
+
+    /// Drops expired bans, which otherwise stay in memory (inactive) until
+    /// the IP is banned again. Returns how many were dropped.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        self.entries.retain(|_, ban| ban.expires.is_none_or(|t| t > now))
+    }
+
+    /// Restores offense counts and re-applies bans that are still active.
+    pub fn restore(&self, history: HashMap<String, OffenseRecord>) {
+        let now = Utc::now();
+        for (ip, record) in history {
+            let reason = record.reason.as_deref().unwrap_or("restored");
+            match record.ban_expires {
+                None => self.ban(&ip, None, reason, SOURCE_HISTORY),
+                Some(expires) if expires > now => {
+                    self.ban(&ip, (expires - now).to_std().ok(), reason, SOURCE_HISTORY);
+                }
+                Some(_) => {}
+            }
+            self.offenses.insert(ip, record);
+        }
+    }
+
+    /// Applies imported bans, skipping those that have already expired.
+    /// Returns how many were applied.
+    pub fn import(&self, bans: &[ActiveBan]) -> usize {
+        let now = Utc::now();
+        let mut applied = 0;
+        for ban in bans {
+            let duration = match ban.expires {
+                None => None,
+                Some(expires) => match (expires - now).to_std() {
+                    Ok(left) if !left.is_zero() => Some(left),
+                    _ => continue,
+                },
+            };
+            self.ban(&ban.ip, duration, &ban.reason, &ban.source);
+            applied += 1;
+        }
+        applied
+    }
+}
+
+/// Bulk import and export formats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BanFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+impl FromStr for BanFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown ban format '{}', expected csv or json", other)),
+        }
+    }
+}
+
+impl fmt::Display for BanFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        })
+    }
+}
+
+impl BanFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+/// A record of an import that couldn't be used.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ImportError {
+    /// Line number (CSV) or array position (JSON), starting at 1
+    pub record: usize,
+    pub message: String,
+}
+
+/// Renders bans for export, sorted by IP.
+pub fn export_bans(bans: &[ActiveBan], format: BanFormat) -> String {
+    let mut bans = bans.to_vec();
+    bans.sort_by(|a, b| a.ip.cmp(&b.ip));
+    match format {
+        BanFormat::Json => serde_json::to_string_pretty(&bans).unwrap_or_default(),
+        BanFormat::Csv => {
+            let mut out = String::from("ip,reason,expires,source\n");
+            for ban in &bans {
+                let expires = ban.expires.map(|t| t.to_rfc3339()).unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{}",
+                    ban.ip,
+                    csv_field(&ban.reason),
+                    expires,
+                    csv_field(&ban.source)
+                );
+            }
+            out
+        }
+    }
+}
+
+/// Parses an import, keeping the valid records and reporting the others.
+///
+/// A JSON document that isn't an array fails as a whole (record 0). CSV
+/// header lines (starting with `ip`), blank lines and `#` comments are
+/// skipped.
+pub fn parse_bans(text: &str, format: BanFormat) -> (Vec<ActiveBan>, Vec<ImportError>) {
+    let mut bans = Vec::new();
+    let mut errors = Vec::new();
+    match format {
+        BanFormat::Json => match serde_json::from_str::<Vec<serde_json::Value>>(text) {
+            Ok(values) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    let ban = serde_json::from_value::<JsonBan>(value)
+                        .map_err(|e| e.to_string())
+                        .and_then(|ban| {
+                            let expires = ban.expires.as_ref().map(json_expiry).transpose()?;
+                            validated(&ban.ip, ban.reason, ban.source, expires.flatten())
+                        });
+                    match ban {
+                        Ok(ban) => bans.push(ban),
+                        Err(message) => errors.push(ImportError { record: i + 1, message }),
+                    }
+                }
+            }
+            Err(e) => errors.push(ImportError {
+                record: 0,
+                message: format!("expected a JSON array of bans: {}", e),
+            }),
+        },
+        BanFormat::Csv => {
+            for (i, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let fields = match csv_fields(line) {
+                    Ok(fields) => fields,
+                    Err(message) => {
+                        errors.push(ImportError { record: i + 1, message });
+                        continue;
+                    }
+                };
+                let field = |n: usize| fields.get(n).map(|f| f.trim()).filter(|f| !f.is_empty());
+                if field(0).is_some_and(|ip| ip.eq_ignore_ascii_case("ip")) {
+                    continue;
+                }
+                let ban = field(2)
+                    .map(parse_expiry)
+                    .transpose()
+                    .and_then(|expires| {
+                        let reason = field(1).map(str::to_string);
+                        let source = field(3).map(str::to_string);
+                        validated(field(0).unwrap_or(""), reason, source, expires)
+                    });
+                match ban {
+                    Ok(ban) => bans.push(ban),
+                    Err(message) => errors.push(ImportError { record: i + 1, message }),
+                }
+            }
+        }
+    }
+    (bans, errors)
+}
+
+#[derive(Deserialize)]
+struct JsonBan {
+    ip: String,
+    reason: Option<String>,
+    source: Option<String>,
+    /// RFC 3339 string or Unix timestamp
+    expires: Option<serde_json::Value>,
+}
+
+fn json_expiry(value: &serde_json::Value) -> Result<Option<DateTime<Utc>>, String> {
+    match value {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(s) if s.trim().is_empty() => Ok(None),
+        serde_json::Value::String(s) => parse_expiry(s).map(Some),
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(Some)
+            .ok_or_else(|| format!("invalid expiry timestamp {}", n)),
+        other => Err(format!("invalid expiry {}", other)),
+    }
+}
+
+fn parse_expiry(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(secs) = s.parse::<i64>() {
+        return DateTime::from_timestamp(secs, 0)
+            .ok_or_else(|| format!("invalid expiry timestamp {}", s));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| format!("invalid expiry '{}', expected RFC 3339 or a Unix timestamp", s))
+}
+
+/// Normalizes the address the way request IPs are keyed.
+fn validated(
+    ip: &str,
+    reason: Option<String>,
+    source: Option<String>,
+    expires: Option<DateTime<Utc>>,
+) -> Result<ActiveBan, String> {
+    let ip = ip.trim();
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return Err(if ip.contains('/') {
+            format!("'{}' is a network; add networks to BANNED_IPS_FILE", ip)
+        } else {
+            format!("invalid IP address '{}'", ip)
+        });
+    };
+    Ok(ActiveBan {
+        ip: addr.to_canonical().to_string(),
+        reason: reason
+            .filter(|r| !r.trim().is_empty())
+            .unwrap_or_else(|| "imported".to_string()),
+        source: source
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(import_source),
+        expires,
+    })
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits one CSV line, honouring double-quoted fields.
+fn csv_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Loads `BAN_HISTORY_FILE` into the dynamic bans.
+///
+/// A missing file is normal on first start; a corrupt one is logged and
+/// ignored rather than preventing startup.
+pub async fn load_history(state: &AppState) {
+    let Some(path) = &state.config.ban_history_file else {
+        return;
+    };
+    let content = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Failed to read ban history {}: {}", path, e);
+            return;
+        }
+    };
+    match serde_json::from_str::<HashMap<String, OffenseRecord>>(&content) {
+        Ok(history) => {
+            info!("Restored offense history for {} IPs from {}", history.len(), path);
+            state.dynamic_bans.restore(history);
+        }
+        Err(e) => warn!("Ignoring unreadable ban history {}: {}", path, e),
+    }
+}
+
+/// Writes the offense history to `BAN_HISTORY_FILE` whenever it changed.
+pub async fn history_task(state: AppState) {
+    let Some(path) = state.config.ban_history_file.clone() else {
+        return;
+    };
+    loop {
+        sleep(HISTORY_SAVE_INTERVAL).await;
+        if !state.dynamic_bans.dirty.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        if let Err(e) = save_history(&path, &state.dynamic_bans.history()).await {
+            warn!("Failed to save ban history to {}: {}", path, e);
+            state.dynamic_bans.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes `history` through a temporary file so a crash mid-write never
+/// leaves a truncated history behind.
+pub async fn save_history(
+    path: &str,
+    history: &HashMap<String, OffenseRecord>,
+) -> std::io::Result<()> {
+    let json = serde_json::to_vec(history)?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, json).await?;
+    fs::rename(&tmp, path).await?;
+    debug!("Saved offense history for {} IPs", history.len());
+    Ok(())
+}
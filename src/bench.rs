@@ -0,0 +1,284 @@
+//! Load generator behind `tezcatlipoca-auth bench`.
+//!
+//! Sends ForwardAuth-shaped requests (the `x-forwarded-*` headers Traefik
+//! adds) to a running instance at a fixed rate, each from a client IP drawn
+//! from a pool of random public IPv4 addresses, and reports the answers and
+//! latency percentiles. The pool size matters: a few IPs keep every cache
+//! warm, while many distinct ones exercise lookups and per-IP state like
+//! real traffic does.
+//!
+//! The rate is kept regardless of how fast answers come (open loop), and
+//! latency is measured from when a request was due rather than when it was
+//! sent. An instance that can't keep up, or too low a `--concurrency`, shows
+//! up as latency instead of silently lowering the rate.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::Semaphore,
+    time::{Instant, MissedTickBehavior, interval},
+};
+
+use crate::metrics::{LatencyHistogram, LatencySummary, Metrics};
+
+/// Original URIs of the generated requests, picked at random.
+const PATHS: &[&str] = &[
+    "/",
+    "/login",
+    "/api/v1/items?page=2",
+    "/static/app.js",
+    "/images/logo.png",
+    "/account/settings",
+];
+
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+/// What to send and how fast.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Base URL of the instance, without trailing slash
+    pub target: String,
+    /// Requests per second
+    pub rps: u64,
+    pub duration: Duration,
+    /// Requests in flight at most
+    pub concurrency: usize,
+    /// Distinct client IPs
+    pub ips: usize,
+    /// `x-forwarded-host` of the requests
+    pub host: String,
+    pub timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            target: "http://127.0.0.1:8199".to_string(),
+            rps: 1000,
+            duration: Duration::from_secs(10),
+            concurrency: 256,
+            ips: 10_000,
+            host: "app.example.com".to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Outcome of a run.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Requested rate
+    pub rps: u64,
+    pub sent: u64,
+    /// From the first request until the last answer
+    pub elapsed: Duration,
+    /// Answers by status code
+    pub statuses: BTreeMap<u16, u64>,
+    /// Requests without an answer (connection failures, timeouts)
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// Latency of answered requests
+    pub latency: LatencySummary,
+    pub max_latency_us: u64,
+}
+
+impl Report {
+    /// Requests answered or failed per second.
+    pub fn achieved_rps(&self) -> f64 {
+        self.sent as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Percentiles are bucket upper bounds, which can exceed the maximum
+        let ms = |us: u64| us.min(self.max_latency_us) as f64 / 1000.0;
+        writeln!(
+            f,
+            "{} requests in {:.1}s: {:.0} req/s (target {})",
+            self.sent,
+            self.elapsed.as_secs_f64(),
+            self.achieved_rps(),
+            self.rps
+        )?;
+        for (status, count) in &self.statuses {
+            writeln!(f, "  {}: {}", status, count)?;
+        }
+        if self.errors > 0 {
+            writeln!(
+                f,
+                "  errors: {} (last: {})",
+                self.errors,
+                self.last_error.as_deref().unwrap_or_default()
+            )?;
+        }
+        writeln!(
+            f,
+            "latency: p50 {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+            ms(self.latency.p50_us),
+            ms(self.latency.p95_us),
+            ms(self.latency.p99_us),
+            ms(self.max_latency_us)
+        )?;
+        if self.achieved_rps() < self.rps as f64 * 0.95 {
+            writeln!(
+                f,
+                "the target rate was not reached: raise --concurrency or run more clients"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Results {
+    latency: LatencyHistogram,
+    max_latency_us: AtomicU64,
+    statuses: Mutex<BTreeMap<u16, u64>>,
+    errors: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+/// Sends requests as set by `options` and waits for every answer.
+pub async fn run(options: &Options) -> Result<Report, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .timeout(options.timeout)
+        .build()?;
+    let mut rng = Rng::new();
+    let ips: Arc<[IpAddr]> = (0..options.ips.max(1))
+        .map(|_| IpAddr::V4(rng.public_ipv4()))
+        .collect();
+    let results = Arc::new(Results::default());
+    let slots = Arc::new(Semaphore::new(options.concurrency.max(1)));
+
+    // Burst catches up on ticks shorter than the timer resolution
+    let mut ticks = interval(Duration::from_secs_f64(1.0 / options.rps.max(1) as f64));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let started = Instant::now();
+    let end = started + options.duration;
+    let mut sent = 0;
+    loop {
+        let due = ticks.tick().await;
+        if due >= end {
+            break;
+        }
+        let slot = slots.clone().acquire_owned().await.expect("never closed");
+        let request = client
+            .get(&options.target)
+            .header("x-forwarded-for", ips[rng.below(ips.len())].to_string())
+            .header("x-forwarded-method", "GET")
+            .header("x-forwarded-proto", "https")
+            .header("x-forwarded-host", &options.host)
+            .header("x-forwarded-uri", PATHS[rng.below(PATHS.len())])
+            .header("user-agent", USER_AGENT);
+        let results = results.clone();
+        tokio::spawn(async move {
+            let _slot = slot;
+            let answer = match request.send().await {
+                Ok(res) => {
+                    let status = res.status().as_u16();
+                    res.bytes().await.map(|_| status)
+                }
+                Err(e) => Err(e),
+            };
+            match answer {
+                Ok(status) => {
+                    let elapsed = due.elapsed();
+                    results.latency.record(elapsed);
+                    let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+                    results.max_latency_us.fetch_max(us, Ordering::Relaxed);
+                    *results
+                        .statuses
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .entry(status)
+                        .or_default() += 1;
+                }
+                Err(e) => {
+                    Metrics::incr(&results.errors);
+                    *results.last_error.lock().unwrap_or_else(|e| e.into_inner()) =
+                        Some(e.to_string());
+                }
+            }
+        });
+        sent += 1;
+    }
+    // Every slot free again: all answers are in
+    let _ = slots.acquire_many(options.concurrency.max(1) as u32).await;
+    let elapsed = started.elapsed();
+
+    let statuses = results
+        .statuses
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let last_error = results
+        .last_error
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Ok(Report {
+        rps: options.rps,
+        sent,
+        elapsed,
+        statuses,
+        errors: Metrics::get(&results.errors),
+        last_error,
+        latency: results.latency.summary(),
+        max_latency_us: results.max_latency_us.load(Ordering::Relaxed),
+    })
+}
+
+/// xorshift64*, plenty for picking IPs and paths.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let mut seed = [0u8; 8];
+        getrandom::fill(&mut seed).expect("OS random number generator is available");
+        Self(u64::from_le_bytes(seed) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A random address outside the private, loopback, link-local,
+    /// documentation, multicast and reserved ranges, so it is judged like a
+    /// real client's.
+    fn public_ipv4(&mut self) -> Ipv4Addr {
+        loop {
+            let ip = Ipv4Addr::from((self.next() >> 32) as u32);
+            let [first, second, ..] = ip.octets();
+            let reserved = ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || ip.is_broadcast()
+                || first == 0
+                || first >= 240
+                || (first == 100 && (64..128).contains(&second));
+            if !reserved {
+                return ip;
+            }
+        }
+    }
+}
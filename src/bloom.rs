@@ -0,0 +1,78 @@
+//! Bloom filter used as a negative fast path in front of exact IP sets.
+//!
+//! The overwhelming majority of requests come from addresses that are not
+//! banned. A bloom filter answers "definitely not present" with a few bit
+//! probes in one small allocation, so the hash set behind it is only
+//! consulted for the rare hits (and the ~1% false positives).
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Bits allocated per expected entry; with [`HASHES`] probes this gives a
+/// false positive rate of roughly 1%.
+const BITS_PER_ENTRY: usize = 10;
+/// Probes per lookup.
+const HASHES: u64 = 7;
+/// Smallest filter, in bits.
+const MIN_BITS: usize = 1024;
+
+/// Fixed-size bloom filter.
+///
+/// Entries cannot be removed; owners rebuild the filter from their exact set
+/// after removals or once more than [`capacity`](Self::capacity) entries were
+/// inserted.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// `bit count - 1`; the bit count is a power of two
+    mask: u64,
+    capacity: usize,
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl BloomFilter {
+    /// Creates a filter sized for `capacity` entries at about 1% false
+    /// positives.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let bits = (capacity.saturating_mul(BITS_PER_ENTRY))
+            .max(MIN_BITS)
+            .next_power_of_two();
+        Self {
+            bits: vec![0; bits / 64],
+            mask: bits as u64 - 1,
+            capacity: capacity.max(MIN_BITS / BITS_PER_ENTRY),
+        }
+    }
+
+    /// Number of entries the filter was sized for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn insert<T: Hash>(&mut self, item: &T) {
+        for bit in self.probes(item) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// `false` means the item was never inserted; `true` means it probably was.
+    pub fn might_contain<T: Hash>(&self, item: &T) -> bool {
+        self.probes(item)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Bit positions for `item`, derived from one 64-bit hash by double
+    /// hashing (Kirsch-Mitzenmacher).
+    fn probes<T: Hash>(&self, item: &T) -> impl Iterator<Item = u64> + use<T> {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let mask = self.mask;
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & mask)
+    }
+}
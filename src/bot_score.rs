@@ -0,0 +1,94 @@
+//! Heuristic bot score from cheap request signals.
+//!
+//! With `BOT_SCORE_CHALLENGE` or `BOT_SCORE_BAN` set, every request gets a
+//! suspicion score adding up the signals it shows:
+//!
+//! | Signal | Points |
+//! |---|---|
+//! | No `User-Agent` | 40 |
+//! | `User-Agent` of a script or scanner (`BOT_USER_AGENTS`) | 40 |
+//! | No `Accept-Language` | 20 |
+//! | No `Accept` | 10 |
+//! | HTTP/1.0 or older, from `BOT_PROTOCOL_HEADER` | 30 |
+//! | Header order of a script (`BOT_HEADER_ORDERS`), from `BOT_HEADER_ORDER_HEADER` | 30 |
+//!
+//! Browsers score 0. Clients scoring `BOT_SCORE_CHALLENGE` or more get the
+//! challenge (see [`crate::challenge`]) whatever their reputation, and
+//! clients scoring `BOT_SCORE_BAN` or more are banned like a honeypot hit.
+//!
+//! Traefik doesn't forward the client's HTTP version or header order; a
+//! plugin in front of ForwardAuth can pass them in the two hint headers,
+//! whose signals are skipped while unset.
+
+use axum::http::{
+    header::{ACCEPT, ACCEPT_LANGUAGE, USER_AGENT},
+    HeaderMap, HeaderName,
+};
+
+use crate::config::BotScoreConfig;
+
+/// Default `BOT_USER_AGENTS`: HTTP libraries, command-line clients and scanners
+pub const DEFAULT_USER_AGENTS: &str = "(?i)^(curl|wget|python-requests|python-urllib|\
+    go-http-client|libwww-perl|java|okhttp|scrapy|aiohttp|httpie)/;\
+    (?i)(masscan|zgrab|nikto|sqlmap|nmap|nuclei|dirbuster|gobuster)";
+
+pub const NO_USER_AGENT: u32 = 40;
+pub const SUSPICIOUS_USER_AGENT: u32 = 40;
+pub const NO_ACCEPT_LANGUAGE: u32 = 20;
+pub const NO_ACCEPT: u32 = 10;
+pub const OLD_PROTOCOL: u32 = 30;
+pub const HEADER_ORDER: u32 = 30;
+
+/// Suspicion score of a request and the signals behind it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub points: u32,
+    pub signals: Vec<&'static str>,
+}
+
+impl Score {
+    fn add(&mut self, points: u32, signal: &'static str) {
+        self.points += points;
+        self.signals.push(signal);
+    }
+}
+
+/// Scores a request with `headers`.
+pub fn score(config: &BotScoreConfig, headers: &HeaderMap) -> Score {
+    let mut score = Score::default();
+    let header = |name: &HeaderName| {
+        headers
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    match header(&USER_AGENT) {
+        None => score.add(NO_USER_AGENT, "no user agent"),
+        Some(agent) if config.user_agents.is_match(&agent) => {
+            score.add(SUSPICIOUS_USER_AGENT, "suspicious user agent")
+        }
+        Some(_) => {}
+    }
+    if header(&ACCEPT_LANGUAGE).is_none() {
+        score.add(NO_ACCEPT_LANGUAGE, "no accept-language");
+    }
+    if header(&ACCEPT).is_none() {
+        score.add(NO_ACCEPT, "no accept");
+    }
+    let protocol = config.protocol_header.as_ref().and_then(header);
+    if protocol.is_some_and(|protocol| is_old_protocol(&protocol)) {
+        score.add(OLD_PROTOCOL, "old protocol");
+    }
+    let order = config.header_order_header.as_ref().and_then(header);
+    if order.is_some_and(|order| config.header_orders.is_match(&order.to_ascii_lowercase())) {
+        score.add(HEADER_ORDER, "header order");
+    }
+    score
+}
+
+/// `HTTP/1.0`, `1.0`, `HTTP/0.9`, ...
+fn is_old_protocol(protocol: &str) -> bool {
+    let version = protocol.trim_start_matches("HTTP/").trim_start_matches("http/");
+    matches!(version, "1.0" | "0.9")
+}
@@ -1,71 +1,631 @@
 use std::{
-    collections::HashSet,
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
+use ipnet::IpNet;
+use notify::{RecursiveMode, Watcher};
+use serde::Serialize;
 use tokio::{
     fs,
+    sync::mpsc,
     time::sleep,
 };
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use crate::AppState;
+use crate::{
+    bloom::BloomFilter,
+    config::Categories,
+    error::{Error, Result},
+    lists::{log_rejected, parse_sections},
+    supervisor,
+    AppState,
+};
+
+/// Source name of the entries from the banned IPs files.
+pub const FILES_SOURCE: &str = "files";
 
 pub struct BannedIpsCache {
-    pub ips: HashSet<String>,
+    /// Uncategorized entries loaded from the banned IPs files
+    pub ips: IpSet,
+    /// Entries of the banned IPs files under a `[category]` header
+    pub categories: HashMap<String, IpSet>,
+    /// Entry count of each file loaded on the last refresh
+    pub files: Vec<ListFile>,
+    /// Entries pulled from external feeds (e.g. CrowdSec), keyed by source name
+    pub sources: HashMap<String, IpSet>,
+    /// Malformed lines skipped on the last refresh of each source list
+    pub source_rejected: HashMap<String, usize>,
     pub last_read: Instant,
+    /// Whether the banned IPs files were loaded successfully at least once
+    pub loaded: bool,
+    /// Bumped on every change to the entries, see [`version`](Self::version)
+    version: u64,
 }
 
 impl BannedIpsCache {
     pub fn new(cache_ttl: Duration) -> Self {
         Self {
-            ips: HashSet::new(),
+            ips: IpSet::new(),
+            categories: HashMap::new(),
+            files: Vec::new(),
+            sources: HashMap::new(),
+            source_rejected: HashMap::new(),
             last_read: Instant::now() - cache_ttl,
+            loaded: false,
+            version: 0,
         }
     }
 
+    /// Changes whenever the files or a source are replaced or handed out
+    /// for updating, so cached decisions can tell they are outdated.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn is_stale(&self, cache_ttl: Duration) -> bool {
         self.last_read.elapsed() >= cache_ttl
     }
 
-    pub async fn refresh(&mut self, banned_ips_file: &str) -> std::io::Result<()> {
-        let content = read_banned_ips(banned_ips_file).await?;
-        self.ips = content;
-        self.last_read = Instant::now();
-        debug!("Banned IPs cache refreshed with {} entries", self.ips.len());
+    /// Reloads the banned IPs files while holding `&mut self`.
+    ///
+    /// Behind the shared lock, prefer [`reload_banned_ips`], which only locks
+    /// for the swap.
+    pub async fn refresh(&mut self, paths: &[String]) -> Result<()> {
+        let (ips, categories, files) = read_banned_ips(paths).await?;
+        self.set_file_entries(ips, categories, files);
         Ok(())
     }
 
+    /// Replaces the entries loaded from the banned IPs files.
+    pub fn set_file_entries(
+        &mut self,
+        ips: IpSet,
+        categories: HashMap<String, IpSet>,
+        files: Vec<ListFile>,
+    ) {
+        self.ips = ips;
+        self.categories = categories;
+        self.files = files;
+        self.last_read = Instant::now();
+        self.loaded = true;
+        self.version += 1;
+        debug!(
+            "Banned IPs cache refreshed with {} entries from {} files",
+            self.file_entries(),
+            self.files.len()
+        );
+    }
+
+    /// Whether `ip` is covered by the files or any external source.
+    ///
+    /// Strings that aren't valid IP addresses are never banned.
     pub fn contains(&self, ip: &str) -> bool {
-        self.ips.contains(ip)
+        ip.parse().is_ok_and(|ip| self.contains_ip(ip))
+    }
+
+    /// Like [`contains`](Self::contains), for an already parsed address.
+    pub fn contains_ip(&self, ip: IpAddr) -> bool {
+        self.source_of(ip, &Categories::All).is_some()
+    }
+
+    /// Name of the first source covering `ip` with an entry of one of
+    /// `categories`: [`FILES_SOURCE`] or an external source, whose name is
+    /// its category.
+    pub fn source_of(&self, ip: IpAddr, categories: &Categories) -> Option<&str> {
+        let in_files = self.ips.contains(ip)
+            || self
+                .categories
+                .iter()
+                .any(|(name, set)| categories.enforces(name) && set.contains(ip));
+        if in_files {
+            return Some(FILES_SOURCE);
+        }
+        self.sources
+            .iter()
+            .find(|(name, set)| categories.enforces(name) && set.contains(ip))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Entries of an external source, created empty on first use.
+    ///
+    /// File refreshes never touch these; each source task owns its own set.
+    pub fn source_mut(&mut self, name: &str) -> &mut IpSet {
+        self.version += 1;
+        self.sources.entry(name.to_string()).or_default()
+    }
+
+    /// Replaces a source parsed from a text list, recording how many of its
+    /// lines were rejected.
+    pub fn set_source(&mut self, name: &str, ips: IpSet, rejected: usize) {
+        *self.source_mut(name) = ips;
+        self.source_rejected.insert(name.to_string(), rejected);
+    }
+
+    /// Malformed lines skipped across the banned IPs files and source lists.
+    pub fn rejected_lines(&self) -> usize {
+        self.files.iter().map(|f| f.rejected).sum::<usize>()
+            + self.source_rejected.values().sum::<usize>()
+    }
+
+    /// Number of entries loaded from the banned IPs files.
+    pub fn file_entries(&self) -> usize {
+        self.ips.len() + self.categories.values().map(IpSet::len).sum::<usize>()
+    }
+
+    /// Total number of entries across the files and all external sources.
+    pub fn len(&self) -> usize {
+        self.file_entries() + self.sources.values().map(IpSet::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Set of banned addresses and networks.
+///
+/// Single addresses are kept in a hash set. Networks live in one
+/// [`PrefixTrie`] per address family, so matching walks at most 32 (IPv4) or
+/// 128 (IPv6) trie levels no matter how many prefixes a list contains
+/// (FireHOL and Spamhaus lists run to hundreds of thousands). A bloom filter
+/// over the single addresses answers most misses without touching the hash
+/// set.
+#[derive(Debug, Default, Clone)]
+pub struct IpSet {
+    addrs: HashSet<IpAddr>,
+    /// Negative fast path for `addrs`, rebuilt after removals and on growth
+    addrs_filter: BloomFilter,
+    /// Networks as inserted, kept so the tries can be rebuilt after removals
+    nets: HashSet<IpNet>,
+    v4: PrefixTrie,
+    v6: PrefixTrie,
+}
+
+impl IpSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a single address or network.
+    pub fn insert(&mut self, net: IpNet) {
+        self.update([net], []);
+    }
+
+    /// Adds and removes entries.
+    ///
+    /// Insertions go straight into the trie; removing a network rebuilds the
+    /// affected structure once at the end of the batch.
+    pub fn update(
+        &mut self,
+        add: impl IntoIterator<Item = IpNet>,
+        remove: impl IntoIterator<Item = IpNet>,
+    ) {
+        let mut addrs_removed = false;
+        let mut nets_removed = false;
+        for net in remove {
+            if is_host(&net) {
+                addrs_removed |= self.addrs.remove(&net.addr());
+            } else {
+                nets_removed |= self.nets.remove(&net.trunc());
+            }
+        }
+        if nets_removed {
+            self.rebuild_tries();
+        }
+        let mut addrs_added = false;
+        for net in add {
+            if is_host(&net) {
+                if self.addrs.insert(net.addr()) {
+                    addrs_added = true;
+                    self.addrs_filter.insert(&net.addr());
+                }
+            } else if self.nets.insert(net.trunc()) {
+                self.insert_into_trie(net.trunc());
+            }
+        }
+        if addrs_removed || (addrs_added && self.addrs.len() > self.addrs_filter.capacity()) {
+            self.rebuild_filter();
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // `::ffff:203.0.113.7` must match a ban on `203.0.113.7`
+        let ip = ip.to_canonical();
+        self.contains_addr(&ip) || self.matching_net(ip).is_some()
+    }
+
+    /// Most specific entry covering `ip`: the address itself if it was added
+    /// as a single host, otherwise the longest matching network.
+    pub fn longest_match(&self, ip: IpAddr) -> Option<IpNet> {
+        let ip = ip.to_canonical();
+        if self.contains_addr(&ip) {
+            return Some(IpNet::from(ip));
+        }
+        self.matching_net(ip)
+    }
+
+    fn contains_addr(&self, ip: &IpAddr) -> bool {
+        self.addrs_filter.might_contain(ip) && self.addrs.contains(ip)
+    }
+
+    fn matching_net(&self, ip: IpAddr) -> Option<IpNet> {
+        let (trie, key, max_len) = match ip {
+            IpAddr::V4(v4) => (&self.v4, (u32::from(v4) as u128) << 96, 32),
+            IpAddr::V6(v6) => (&self.v6, u128::from(v6), 128),
+        };
+        let len = trie.longest_match(key, max_len)?;
+        IpNet::new(ip, len).ok().map(|net| net.trunc())
+    }
+
+    /// Entries as inserted, single addresses as host networks, in no
+    /// particular order.
+    pub fn iter(&self) -> impl Iterator<Item = IpNet> + '_ {
+        self.addrs
+            .iter()
+            .map(|&addr| IpNet::from(addr))
+            .chain(self.nets.iter().copied())
+    }
+
+    /// Number of entries (addresses plus networks) as inserted.
+    pub fn len(&self) -> usize {
+        self.addrs.len() + self.nets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn insert_into_trie(&mut self, net: IpNet) {
+        match net {
+            IpNet::V4(n) => self
+                .v4
+                .insert((u32::from(n.network()) as u128) << 96, n.prefix_len()),
+            IpNet::V6(n) => self.v6.insert(u128::from(n.network()), n.prefix_len()),
+        }
+    }
+
+    fn rebuild_filter(&mut self) {
+        // Headroom so a steadily growing set isn't rebuilt on every batch
+        self.addrs_filter = BloomFilter::with_capacity(self.addrs.len() * 2);
+        for addr in &self.addrs {
+            self.addrs_filter.insert(addr);
+        }
+    }
+
+    fn rebuild_tries(&mut self) {
+        self.v4 = PrefixTrie::default();
+        self.v6 = PrefixTrie::default();
+        for net in self.nets.clone() {
+            self.insert_into_trie(net);
+        }
     }
 }
 
-async fn read_banned_ips(banned_ips_file: &str) -> std::io::Result<HashSet<String>> {
-    match fs::read_to_string(banned_ips_file).await {
-        Ok(content) => {
-            let ips: HashSet<String> = content
-                .lines()
-                .map(|line| line.trim().to_string())
-                .collect();
-            Ok(ips)
+impl FromIterator<IpNet> for IpSet {
+    fn from_iter<I: IntoIterator<Item = IpNet>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.update(iter, []);
+        set
+    }
+}
+
+fn is_host(net: &IpNet) -> bool {
+    net.prefix_len() == net.max_prefix_len()
+}
+
+/// Binary trie for longest-prefix matching.
+///
+/// Keys are left-aligned in a `u128` (IPv4 addresses occupy the top 32 bits)
+/// and walked one bit per level, so a lookup costs at most one step per
+/// prefix bit regardless of how many prefixes are stored. Nodes live in a
+/// flat arena indexed by `u32`, which keeps them compact and cache friendly.
+#[derive(Debug, Clone)]
+pub struct PrefixTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TrieNode {
+    /// Arena index of the 0 and 1 child; 0 means none (the root is never a child)
+    children: [u32; 2],
+    /// A stored prefix ends at this node
+    terminal: bool,
+}
+
+impl Default for PrefixTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![TrieNode::default()],
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            warn!("Banned IPs file not found: {}", banned_ips_file);
-            Ok(HashSet::new())
+    }
+}
+
+impl PrefixTrie {
+    /// Stores the first `len` bits of `key`.
+    pub fn insert(&mut self, key: u128, len: u8) {
+        let mut node = 0;
+        for depth in 0..len {
+            let bit = bit_at(key, depth);
+            let child = self.nodes[node].children[bit];
+            node = if child == 0 {
+                let next = self.nodes.len();
+                self.nodes.push(TrieNode::default());
+                self.nodes[node].children[bit] = next as u32;
+                next
+            } else {
+                child as usize
+            };
         }
-        Err(e) => Err(e),
+        self.nodes[node].terminal = true;
     }
+
+    /// Length of the longest stored prefix of `key`, looking at no more than
+    /// `max_len` bits.
+    pub fn longest_match(&self, key: u128, max_len: u8) -> Option<u8> {
+        let mut node = 0;
+        let mut best = self.nodes[0].terminal.then_some(0);
+        for depth in 0..max_len {
+            let child = self.nodes[node].children[bit_at(key, depth)];
+            if child == 0 {
+                break;
+            }
+            node = child as usize;
+            if self.nodes[node].terminal {
+                best = Some(depth + 1);
+            }
+        }
+        best
+    }
+}
+
+fn bit_at(key: u128, depth: u8) -> usize {
+    ((key >> (127 - depth as u32)) & 1) as usize
+}
+
+/// A banned IPs file and the number of entries it contributed.
+#[derive(Clone, Debug, Serialize)]
+pub struct ListFile {
+    pub path: String,
+    pub entries: usize,
+    /// Malformed lines that were skipped
+    pub rejected: usize,
+    #[serde(skip)]
+    fingerprint: Fingerprint,
+}
+
+/// Cheap change detection for a list file: modification time plus size,
+/// and the file a symlinked path resolves to.
+///
+/// A Kubernetes ConfigMap update writes new files to a fresh directory and
+/// swaps the `..data` symlink over to it; the resolved path changes even
+/// when the new file has the same size and timestamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Fingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+    target: Option<PathBuf>,
 }
 
+impl Fingerprint {
+    async fn of(path: &str) -> std::io::Result<Self> {
+        let meta = fs::metadata(path).await?;
+        Ok(Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            target: fs::canonicalize(path).await.ok(),
+        })
+    }
+}
+
+/// Uncategorized entries, entries by category and the files they came from.
+type FileEntries = (IpSet, HashMap<String, IpSet>, Vec<ListFile>);
+
+/// Loads and merges every file named in `BANNED_IPS_FILE`, keeping the
+/// entries of each `[category]` section apart.
+///
+/// Directories contribute each regular, non-hidden file they contain, in
+/// name order. A missing path is logged and treated as empty; any other read
+/// error fails the whole refresh so a partial list never replaces a good one.
+async fn read_banned_ips(paths: &[String]) -> Result<FileEntries> {
+    let mut ips = IpSet::new();
+    let mut categories: HashMap<String, IpSet> = HashMap::new();
+    let mut files = Vec::new();
+    for path in paths {
+        let listed = list_files(path).await.map_err(|e| Error::cache(path, e))?;
+        for file in listed {
+            // Taken before reading, so a write racing with the read is seen
+            // as a change on the next check
+            let fingerprint = match Fingerprint::of(&file).await {
+                Ok(fingerprint) => fingerprint,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    warn!("Banned IPs file not found: {}", file);
+                    continue;
+                }
+                Err(e) => return Err(Error::cache(file, e)),
+            };
+            let content = fs::read_to_string(&file)
+                .await
+                .map_err(|e| Error::cache(&file, e))?;
+            let (sections, rejected) = parse_sections(&content);
+            log_rejected(&file, &rejected);
+            debug!("Loaded {} banned entries from {}", sections.len(), file);
+            files.push(ListFile {
+                path: file,
+                entries: sections.len(),
+                rejected: rejected.len(),
+                fingerprint,
+            });
+            ips.update(sections.uncategorized, []);
+            for (name, entries) in sections.categories {
+                categories.entry(name).or_default().update(entries, []);
+            }
+        }
+    }
+    Ok((ips, categories, files))
+}
+
+/// Current fingerprints of the files `paths` resolve to; missing files are
+/// left out, matching what [`read_banned_ips`] would load.
+async fn fingerprints(paths: &[String]) -> Result<Vec<(String, Fingerprint)>> {
+    let mut result = Vec::new();
+    for path in paths {
+        let listed = list_files(path).await.map_err(|e| Error::cache(path, e))?;
+        for file in listed {
+            match Fingerprint::of(&file).await {
+                Ok(fingerprint) => result.push((file, fingerprint)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::cache(file, e)),
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Files to load for one `BANNED_IPS_FILE` entry: the path itself, or the
+/// files inside it when it is a directory.
+///
+/// Symlinks to files count as files, as in a mounted ConfigMap where each
+/// key is a link into the hidden `..data` directory.
+async fn list_files(path: &str) -> std::io::Result<Vec<String>> {
+    match fs::metadata(path).await {
+        Ok(meta) if meta.is_dir() => {}
+        _ => return Ok(vec![path.to_string()]),
+    }
+
+    let mut files = Vec::new();
+    let mut dir = fs::read_dir(path).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && fs::metadata(entry.path()).await.is_ok_and(|meta| meta.is_file()) {
+            files.push(entry.path().to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the banned IPs files and swaps them into the shared cache.
+///
+/// Files are read and parsed before the write lock is taken, so requests
+/// keep being answered from the previous list while I/O is in progress.
+pub async fn reload_banned_ips(state: &AppState) -> Result<()> {
+    let (ips, categories, files) = read_banned_ips(&state.config.banned_ips_files).await?;
+    state.banned_ips.write().await.set_file_entries(ips, categories, files);
+    Ok(())
+}
+
+/// Reloads the banned IPs files only if one was added, removed or changed.
+///
+/// Files are compared by modification time and size, which costs one
+/// `stat` per file instead of re-reading and re-parsing multi-megabyte lists
+/// on every tick. Returns whether a reload happened; either way the cache is
+/// marked fresh.
+pub async fn reload_banned_ips_if_changed(state: &AppState) -> Result<bool> {
+    let current = fingerprints(&state.config.banned_ips_files).await?;
+    {
+        let mut cache = state.banned_ips.write().await;
+        let unchanged = cache.files.len() == current.len()
+            && cache
+                .files
+                .iter()
+                .zip(&current)
+                .all(|(file, (path, fingerprint))| {
+                    file.path == *path && file.fingerprint == *fingerprint
+                });
+        if unchanged {
+            cache.last_read = Instant::now();
+            return Ok(false);
+        }
+    }
+    reload_banned_ips(state).await?;
+    Ok(true)
+}
+
+/// Keeps the banned IPs file entries fresh.
+///
+/// This task owns refreshes: the request path only ever takes the read lock.
+/// It wakes when the cache reaches `CACHE_TTL`, so a reload triggered
+/// elsewhere (e.g. at startup) pushes the next one back.
 pub async fn cache_refresh_task(state: AppState) {
+    let ttl = state.config.cache_ttl;
     loop {
-        sleep(state.config.cache_ttl).await;
+        let age = state.banned_ips.read().await.last_read.elapsed();
+        sleep(ttl.saturating_sub(age)).await;
 
-        let mut cache = state.banned_ips.write().await;
-        if cache.is_stale(state.config.cache_ttl) {
-            if let Err(e) = cache.refresh(&state.config.banned_ips_file).await {
-                warn!("Failed to refresh banned IPs cache: {}", e);
+        if state.banned_ips.read().await.is_stale(ttl)
+            && let Err(e) = reload_banned_ips_if_changed(&state).await
+        {
+            warn!("Failed to refresh banned IPs cache: {}", e);
+            supervisor::failed(&e);
+            // Retry on the next tick instead of spinning on the stale cache
+            sleep(ttl).await;
+        } else {
+            supervisor::ran();
+        }
+    }
+}
+
+/// Time to let a burst of filesystem events settle before reloading.
+const WATCH_SETTLE: Duration = Duration::from_millis(100);
+
+/// Reloads the banned IPs files as soon as they change on disk
+/// (`BANNED_IPS_WATCH=true`), on top of the `CACHE_TTL` polling.
+///
+/// The directory holding each file (or each listed directory) is watched
+/// rather than the file itself: an update that swaps a symlink, like a
+/// ConfigMap update, replaces the file without ever writing to the watched
+/// inode. Every event triggers [`reload_banned_ips_if_changed`], so
+/// unrelated files in the directory cost a few `stat` calls.
+pub fn watch_banned_ips(state: AppState) -> notify::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok() {
+            let _ = tx.send(());
+        }
+    })?;
+    for dir in watched_dirs(&state.config.banned_ips_files) {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        debug!("Watching {} for banned IPs file changes", dir.display());
+    }
+
+    tokio::spawn(async move {
+        // Dropping the watcher stops the events
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            sleep(WATCH_SETTLE).await;
+            while rx.try_recv().is_ok() {}
+            match reload_banned_ips_if_changed(&state).await {
+                Ok(true) => info!("Banned IPs files changed on disk, cache reloaded"),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to reload changed banned IPs files: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Directories to watch for the `BANNED_IPS_FILE` entries, without duplicates.
+fn watched_dirs(paths: &[String]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        let dir = if path.is_dir() {
+            path
+        } else {
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
             }
+        };
+        if !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_path_buf());
         }
     }
+    dirs
 }
@@ -0,0 +1,342 @@
+//! Browser challenge for suspicious client IPs.
+//!
+//! With `CHALLENGE` set, clients that aren't banned but look suspicious (an
+//! AbuseIPDB score of at least `CHALLENGE_THRESHOLD`, a bot score of at least
+//! `BOT_SCORE_CHALLENGE`, or every client when neither is available) get a
+//! `403` challenge page instead of the upstream response. Two kinds of
+//! challenge are available:
+//!
+//! - `turnstile` / `hcaptcha`: the provider's captcha widget; the response
+//!   token is checked with the provider's `siteverify` API.
+//! - `pow`: a JavaScript proof of work. The page carries a signed, IP-bound
+//!   challenge string valid for a few minutes; the browser searches for a
+//!   nonce whose `SHA-256(challenge + nonce)` starts with
+//!   `CHALLENGE_DIFFICULTY` zero bits. Checking a solution is one hash, so
+//!   nothing leaves the service. `crypto.subtle` only exists in secure
+//!   contexts, so the protected site must be served over HTTPS.
+//!
+//! Either way the page submits to `CHALLENGE_PATH` on the protected site.
+//! That request reaches this service through ForwardAuth like any other; a
+//! valid solution redirects the client back with a signed `tez_challenge`
+//! cookie bound to its IP. While the cookie is valid (`CHALLENGE_TTL`) the
+//! challenge is skipped. Bans are never bypassed by the cookie.
+
+use std::{net::IpAddr, time::Duration};
+
+use axum::{
+    http::{
+        header::{LOCATION, SET_COOKIE},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{BlockResponse, CaptchaProvider, ChallengeMode},
+    metrics::Metrics,
+    session, AppState,
+};
+
+/// Name of the bypass cookie.
+pub const COOKIE_NAME: &str = "tez_challenge";
+
+/// How long a proof-of-work challenge can be solved.
+const POW_CHALLENGE_TTL: Duration = Duration::from_secs(300);
+
+/// Successful verification: send the client back with the bypass cookie.
+#[derive(Debug)]
+pub struct Passed {
+    /// Relative URL the client came from
+    pub location: String,
+    /// `Set-Cookie` header value
+    pub set_cookie: String,
+}
+
+impl IntoResponse for Passed {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::FOUND,
+            [(LOCATION, self.location), (SET_COOKIE, self.set_cookie)],
+        )
+            .into_response()
+    }
+}
+
+/// Whether `ip` has to solve the challenge before being let through, given
+/// the request's bot score.
+///
+/// Clients with a valid bypass cookie for their IP never do.
+pub fn required(
+    state: &AppState,
+    ip: &str,
+    headers: &HeaderMap,
+    bot_points: Option<u32>,
+) -> bool {
+    let Some(challenge) = &state.config.challenge else {
+        return false;
+    };
+    let bot_threshold = state.config.bot_score.as_ref().and_then(|bot| bot.challenge_threshold);
+    let suspicious = match challenge.threshold {
+        Some(threshold) => state
+            .abuseipdb
+            .as_ref()
+            .and_then(|reputation| reputation.score(ip))
+            .is_some_and(|score| score >= threshold),
+        None => bot_threshold.is_none(),
+    };
+    let bot = bot_threshold.zip(bot_points).is_some_and(|(threshold, points)| points >= threshold);
+    (suspicious || bot) && !has_valid_cookie(state, ip, headers)
+}
+
+fn has_valid_cookie(state: &AppState, ip: &str, headers: &HeaderMap) -> bool {
+    session::cookie(headers, COOKIE_NAME)
+        .is_some_and(|token| state.cookie_signer.validate(token, ip))
+}
+
+/// Checks a submitted captcha response or proof-of-work solution.
+///
+/// `query` is the query string of the request to `CHALLENGE_PATH`. Failed or
+/// missing solutions get the challenge page again.
+pub async fn verify(
+    state: &AppState,
+    ip: IpAddr,
+    query: &str,
+    headers: &HeaderMap,
+) -> Result<Passed, BlockResponse> {
+    let challenge = state
+        .config
+        .challenge
+        .as_ref()
+        .expect("verify is only routed when the challenge is enabled");
+
+    let params: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    };
+    let return_to = param("return").map_or("/", safe_return_path).to_string();
+    let client_ip = ip.to_string();
+
+    let solved = match &challenge.mode {
+        ChallengeMode::Captcha {
+            provider,
+            secret_key,
+            verify_url,
+            ..
+        } => match param(response_field(*provider)) {
+            Some(token) => siteverify(state, verify_url, secret_key, token, &client_ip)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Captcha verification failed: {}", e);
+                    false
+                }),
+            None => false,
+        },
+        ChallengeMode::ProofOfWork { difficulty } => {
+            match (param("challenge"), param("nonce")) {
+                (Some(challenge), Some(nonce)) => {
+                    state
+                        .cookie_signer
+                        .validate(challenge, &pow_subject(&client_ip))
+                        && nonce.parse::<u64>().is_ok()
+                        && pow_solves(challenge, nonce, *difficulty)
+                }
+                _ => false,
+            }
+        }
+    };
+    if !solved {
+        debug!("🧩 CHALLENGE FAILED: IP {} submitted an invalid solution", client_ip);
+        return Err(BlockResponse::Challenge(page(state, &client_ip, &return_to)));
+    }
+
+    debug!("🧩 CHALLENGE PASSED: IP {}", client_ip);
+    Metrics::incr(&state.metrics.challenges_passed);
+    let token = state.cookie_signer.issue(&client_ip, challenge.cookie_ttl);
+    Ok(Passed {
+        location: return_to,
+        set_cookie: session::set_cookie(
+            COOKIE_NAME,
+            &token,
+            challenge.cookie_ttl,
+            session::is_https(headers),
+        ),
+    })
+}
+
+#[derive(Deserialize)]
+struct SiteverifyResponse {
+    success: bool,
+}
+
+/// Asks the provider whether `token` is a valid, unused captcha response.
+async fn siteverify(
+    state: &AppState,
+    verify_url: &str,
+    secret_key: &str,
+    token: &str,
+    remote_ip: &str,
+) -> Result<bool, reqwest::Error> {
+    let response: SiteverifyResponse = state
+        .http
+        .post(verify_url)
+        .form(&[
+            ("secret", secret_key),
+            ("response", token),
+            ("remoteip", remote_ip),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.success)
+}
+
+/// Form field the provider's widget fills with the captcha response.
+fn response_field(provider: CaptchaProvider) -> &'static str {
+    match provider {
+        CaptchaProvider::Turnstile => "cf-turnstile-response",
+        CaptchaProvider::HCaptcha => "h-captcha-response",
+    }
+}
+
+/// Subject of proof-of-work challenges, kept apart from bypass cookies
+/// (which are bound to the bare IP) so a challenge can't be used as one.
+fn pow_subject(ip: &str) -> String {
+    format!("pow:{}", ip)
+}
+
+/// Whether `SHA-256(challenge + nonce)` starts with `difficulty` zero bits.
+pub fn pow_solves(challenge: &str, nonce: &str, difficulty: u8) -> bool {
+    let hash = Sha256::new()
+        .chain_update(challenge)
+        .chain_update(nonce)
+        .finalize();
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros >= u32::from(difficulty)
+}
+
+/// Keeps redirects on the protected site: only plain absolute paths are
+/// accepted, anything else (`//host`, `https://...`) returns to `/`.
+pub(crate) fn safe_return_path(path: &str) -> &str {
+    let safe = path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control);
+    if safe { path } else { "/" }
+}
+
+/// Challenge page for `ip`, which wanted `return_to`.
+pub fn page(state: &AppState, ip: &str, return_to: &str) -> String {
+    let Some(challenge) = &state.config.challenge else {
+        return String::new();
+    };
+    let (head, widget) = match &challenge.mode {
+        // The widget submits the form once solved
+        ChallengeMode::Captcha {
+            provider, site_key, ..
+        } => {
+            let (script, class) = match provider {
+                CaptchaProvider::Turnstile => (
+                    "https://challenges.cloudflare.com/turnstile/v0/api.js",
+                    "cf-turnstile",
+                ),
+                CaptchaProvider::HCaptcha => ("https://js.hcaptcha.com/1/api.js", "h-captcha"),
+            };
+            (
+                format!(r#"<script src="{}" async defer></script>"#, script),
+                format!(
+                    r#"<div class="{}" data-sitekey="{}" data-callback="solved"></div>
+<script>function solved(){{document.getElementById("challenge").submit()}}</script>"#,
+                    class,
+                    escape(site_key)
+                ),
+            )
+        }
+        ChallengeMode::ProofOfWork { difficulty } => {
+            let token = state
+                .cookie_signer
+                .issue(&pow_subject(ip), POW_CHALLENGE_TTL);
+            (
+                String::new(),
+                format!(
+                    r#"<input type="hidden" name="challenge" value="{}">
+<input type="hidden" name="nonce">
+<script>
+(async () => {{
+  const form = document.getElementById("challenge");
+  const data = new TextEncoder().encode(form.challenge.value);
+  for (let n = 0; ; n++) {{
+    const nonce = new TextEncoder().encode(String(n));
+    const input = new Uint8Array(data.length + nonce.length);
+    input.set(data);
+    input.set(nonce, data.length);
+    const hash = new Uint8Array(await crypto.subtle.digest("SHA-256", input));
+    let zeros = 0;
+    for (const b of hash) {{
+      zeros += b === 0 ? 8 : Math.clz32(b) - 24;
+      if (b !== 0) break;
+    }}
+    if (zeros >= {}) {{
+      form.nonce.value = n;
+      form.submit();
+      return;
+    }}
+  }}
+}})();
+</script>"#,
+                    escape(&token),
+                    difficulty
+                ),
+            )
+        }
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Checking your browser</title>
+{head}
+<style>body{{font-family:system-ui,sans-serif;display:flex;justify-content:center;margin-top:15vh}}</style>
+</head>
+<body>
+<form id="challenge" method="get" action="{action}">
+<h1>Checking your browser</h1>
+<p>This only takes a moment.</p>
+<input type="hidden" name="return" value="{return_to}">
+{widget}
+<noscript><p>JavaScript is required to complete the check.</p></noscript>
+</form>
+</body>
+</html>
+"#,
+        action = escape(&challenge.path),
+        return_to = escape(return_to),
+    )
+}
+
+/// Minimal HTML escaping for attribute values.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
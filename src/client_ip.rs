@@ -0,0 +1,259 @@
+//! Client IP extraction.
+//!
+//! [`ClientIp`] is an axum extractor resolving the address a request should
+//! be judged by. It yields a plain [`IpAddr`], so nothing allocated while
+//! resolving it outlives the request.
+//!
+//! Anyone can send `cf-connecting-ip`, so with `CLOUDFLARE_VERIFY` (the
+//! default) it is only trusted on requests that came through Cloudflare: the
+//! connection's peer, or the edge address a proxy on loopback or in
+//! `TRUSTED_PROXIES` appended last to `x-forwarded-for`, must be within
+//! Cloudflare's ranges (`CLOUDFLARE_IPS`, refreshed by [`crate::cloudflare`]).
+//! Otherwise the header is skipped like an invalid one and the next source
+//! is used. Behind a proxy in another container or host, such as Traefik in
+//! Docker, `TRUSTED_PROXIES` must therefore name the proxy's network, or the
+//! header is always skipped; this is warned about at startup and the first
+//! time it happens.
+//!
+//! Each proxy appends the address it got the request from to
+//! `x-forwarded-for`, after whatever the client sent, so only the entries
+//! on the right can be trusted. By default the first (leftmost) entry is
+//! used, which is only safe when the edge proxy discards the client's value.
+//! Behind a chain of proxies, set either:
+//!
+//! - `TRUSTED_PROXIES`: the proxies' networks; entries are read from the
+//!   right and the first one outside them is the client (rightmost
+//!   untrusted).
+//! - `TRUSTED_HOPS`: the number of proxies appending entries; the client is
+//!   that many entries from the right (1 is the last entry).
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+use ipnet::IpNet;
+use tracing::{debug, warn};
+
+use crate::{
+    config::{Config, IpSource},
+    AppState,
+};
+
+/// Cloudflare's real IP header
+pub const CLOUDFLARE_HEADER: &str = "cf-connecting-ip";
+
+/// The proxy chain header
+pub const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Cloudflare's published ranges (<https://www.cloudflare.com/ips/>), the
+/// default `CLOUDFLARE_IPS`
+pub const CLOUDFLARE_RANGES: &[&str] = &[
+    "173.245.48.0/20",
+    "103.21.244.0/22",
+    "103.22.200.0/22",
+    "103.31.4.0/22",
+    "141.101.64.0/18",
+    "108.162.192.0/18",
+    "190.93.240.0/20",
+    "188.114.96.0/20",
+    "197.234.240.0/22",
+    "198.41.128.0/17",
+    "162.158.0.0/15",
+    "104.16.0.0/13",
+    "104.24.0.0/14",
+    "172.64.0.0/13",
+    "131.0.72.0/22",
+    "2400:cb00::/32",
+    "2606:4700::/32",
+    "2803:f800::/32",
+    "2405:b500::/32",
+    "2405:8100::/32",
+    "2a06:98c0::/29",
+    "2c0f:f248::/32",
+];
+
+/// Address of the client behind the proxy chain.
+///
+/// Sources are tried in the order of `CLIENT_IP_SOURCES`, by default:
+/// 1. `cf-connecting-ip` - Cloudflare's real IP header
+/// 2. `x-forwarded-for` - Standard proxy header (the first entry if several,
+///    or the one picked by `TRUSTED_PROXIES` / `TRUSTED_HOPS`)
+/// 3. `x-real-ip` - nginx's `proxy_set_header X-Real-IP $remote_addr`
+/// 4. `true-client-ip` - Akamai / Cloudflare Enterprise
+/// 5. Socket address from connection info (direct connection)
+///
+/// A header that is present but doesn't hold a valid IP is skipped, so a
+/// garbage value can't be used to dodge a ban on the real address; so is
+/// `cf-connecting-ip` on a request that didn't come through Cloudflare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientIp(pub IpAddr);
+
+/// How far the proxy headers are trusted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trust<'a> {
+    /// Cloudflare's ranges, when `cf-connecting-ip` has to come from them
+    pub cloudflare: Option<&'a [IpNet]>,
+    /// Proxies whose `x-forwarded-for` entries are skipped from the right
+    pub proxies: &'a [IpNet],
+    /// Number of `x-forwarded-for` entries appended by trusted proxies
+    pub hops: usize,
+}
+
+impl<'a> Trust<'a> {
+    /// The trust configured in `config`, with the current Cloudflare ranges.
+    pub fn new(config: &'a Config, cloudflare_ips: &'a [IpNet]) -> Self {
+        Self {
+            cloudflare: config.cloudflare_verify.then_some(cloudflare_ips),
+            proxies: &config.trusted_proxies,
+            hops: config.trusted_hops,
+        }
+    }
+}
+
+impl ClientIp {
+    /// Resolves the client IP from the first of `sources` that yields one.
+    pub fn resolve(
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+        sources: &[IpSource],
+        trust: Trust,
+    ) -> Option<Self> {
+        sources
+            .iter()
+            .find_map(|source| match source {
+                IpSource::Header(name) if name == CLOUDFLARE_HEADER => {
+                    let ip = header_ip(headers, name)?;
+                    if !from_cloudflare(headers, peer, &trust) {
+                        let behind_proxy = headers.contains_key(FORWARDED_FOR)
+                            && !peer.is_some_and(|addr| proxied(addr.ip(), &trust));
+                        if behind_proxy && !UNTRUSTED_PROXY_WARNED.swap(true, Ordering::Relaxed) {
+                            warn!(
+                                "Ignoring {} {} forwarded by {:?}, which isn't in TRUSTED_PROXIES; set it to the network of the proxy in front (logged once)",
+                                name, ip, peer
+                            );
+                        } else {
+                            debug!("Ignoring {} {} not sent by Cloudflare", name, ip);
+                        }
+                        return None;
+                    }
+                    Some(ip)
+                }
+                IpSource::Header(name) if name == FORWARDED_FOR => {
+                    let value = headers.get(name)?.to_str().ok()?;
+                    forwarded_for_ip(value, &trust)
+                }
+                IpSource::Header(name) => header_ip(headers, name),
+                IpSource::Socket => peer.map(|addr| addr.ip()),
+            })
+            // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+            .map(|ip| Self(ip.to_canonical()))
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequestParts<AppState> for ClientIp {
+    /// Only returned when none of the configured sources yields an address,
+    /// e.g. `socket` only while the router was served without connect info.
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let config = &state.config;
+        let ranges = state.cloudflare_ips.read().unwrap_or_else(|e| e.into_inner());
+        let trust = Trust::new(config, &ranges);
+        Self::resolve(&parts.headers, peer, &config.client_ip_sources, trust)
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// The client's entry of an `x-forwarded-for` value, if it parses.
+fn forwarded_for_ip(value: &str, trust: &Trust) -> Option<IpAddr> {
+    let entries: Vec<&str> = value.split(',').map(str::trim).collect();
+    if !trust.proxies.is_empty() {
+        // Rightmost untrusted; the leftmost entry when every one is trusted
+        let mut client = None;
+        for entry in entries.iter().rev() {
+            let ip: IpAddr = entry.parse().ok()?;
+            client = Some(ip);
+            if !trust.proxies.iter().any(|net| net.contains(&ip.to_canonical())) {
+                break;
+            }
+        }
+        return client;
+    }
+    // Chains shorter than the hops only hold entries of trusted proxies, the
+    // first of which saw the client
+    let index = match trust.hops {
+        0 => 0,
+        hops => entries.len().saturating_sub(hops),
+    };
+    entries.get(index)?.parse().ok()
+}
+
+/// First address in header `name`, if it parses.
+fn header_ip(headers: &HeaderMap, name: &str) -> Option<IpAddr> {
+    let value = headers.get(name)?.to_str().ok()?;
+    value.split(',').next()?.trim().parse().ok()
+}
+
+/// Set once the first `cf-connecting-ip` forwarded by an untrusted proxy has
+/// been warned about.
+static UNTRUSTED_PROXY_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Whether `config` reads `cf-connecting-ip` but can only trust it from
+/// Cloudflare or loopback peers, as no `TRUSTED_PROXIES` are set. Behind a
+/// proxy in another container or host the header is then always ignored.
+pub fn cloudflare_proxy_untrusted(config: &Config) -> bool {
+    config.cloudflare_verify
+        && config.trusted_proxies.is_empty()
+        && config
+            .client_ip_sources
+            .iter()
+            .any(|source| matches!(source, IpSource::Header(name) if name == CLOUDFLARE_HEADER))
+}
+
+/// Whether `peer` is a proxy whose `x-forwarded-for` entries are trusted: on
+/// loopback or in `TRUSTED_PROXIES`.
+fn proxied(peer: IpAddr, trust: &Trust) -> bool {
+    let peer = peer.to_canonical();
+    peer.is_loopback() || trust.proxies.iter().any(|net| net.contains(&peer))
+}
+
+/// Whether the peer, or the last `x-forwarded-for` entry the proxy in front
+/// appended for its own peer, is a Cloudflare edge.
+///
+/// The entry is only taken from a peer that is such a proxy: on loopback or
+/// in `TRUSTED_PROXIES`. Anyone else could have written it.
+fn from_cloudflare(headers: &HeaderMap, peer: Option<SocketAddr>, trust: &Trust) -> bool {
+    let Some(ranges) = trust.cloudflare else {
+        return true;
+    };
+    let peer = peer.map(|addr| addr.ip().to_canonical());
+    let edge = headers
+        .get(FORWARDED_FOR)
+        .filter(|_| peer.is_some_and(|ip| proxied(ip, trust)))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    [peer, edge]
+        .into_iter()
+        .flatten()
+        .any(|ip| ranges.iter().any(|range| range.contains(&ip.to_canonical())))
+}
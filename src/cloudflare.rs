@@ -0,0 +1,82 @@
+//! Cloudflare's IP ranges, refreshed from its published lists.
+//!
+//! `cf-connecting-ip` is only trusted on requests from Cloudflare (see
+//! [`crate::client_ip`]), so the ranges have to follow Cloudflare's. With
+//! `CLOUDFLARE_IPS_REFRESH` set (24 hours by default), a background task
+//! downloads `https://www.cloudflare.com/ips-v4` and `ips-v6`
+//! (`CLOUDFLARE_IPS_URLS`) at startup and on that schedule, and replaces the
+//! ranges in use. `CLOUDFLARE_IPS` is the list until the first download
+//! succeeds. A failed download, or one without a single valid range, keeps
+//! the current ranges.
+
+use std::time::Duration;
+
+use ipnet::IpNet;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::{supervisor, AppState};
+
+/// Cloudflare's published lists, the default `CLOUDFLARE_IPS_URLS`
+pub const DEFAULT_URLS: &[&str] = &[
+    "https://www.cloudflare.com/ips-v4",
+    "https://www.cloudflare.com/ips-v6",
+];
+
+/// Why the ranges couldn't be refreshed
+#[derive(Debug, thiserror::Error)]
+pub enum RefreshError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    /// A list held no valid range
+    #[error("{0} holds no valid range")]
+    Empty(String),
+}
+
+/// Refreshes the ranges every `every`.
+pub async fn refresh_task(state: AppState, every: Duration) {
+    loop {
+        match refresh(&state).await {
+            Ok(count) => {
+                info!("Cloudflare IP ranges refreshed: {} ranges", count);
+                supervisor::ran();
+            }
+            Err(e) => {
+                warn!("Failed to refresh Cloudflare IP ranges, keeping current ones: {}", e);
+                supervisor::failed(&e);
+            }
+        }
+        sleep(every).await;
+    }
+}
+
+/// Downloads every list of `CLOUDFLARE_IPS_URLS` and replaces the ranges in
+/// use with them. Returns the number of ranges.
+pub async fn refresh(state: &AppState) -> Result<usize, RefreshError> {
+    let mut ranges = Vec::new();
+    for url in &state.config.cloudflare_ips_urls {
+        let body = state
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let parsed = parse_ranges(&body);
+        if parsed.is_empty() {
+            return Err(RefreshError::Empty(url.clone()));
+        }
+        ranges.extend(parsed);
+    }
+    let count = ranges.len();
+    *state.cloudflare_ips.write().unwrap_or_else(|e| e.into_inner()) = ranges;
+    Ok(count)
+}
+
+/// CIDRs one per line; anything else is skipped.
+pub fn parse_ranges(body: &str) -> Vec<IpNet> {
+    body.lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
@@ -1,16 +1,249 @@
-use std::{env, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fmt, net::SocketAddr, str::FromStr, time::Duration};
+
+use axum::http::HeaderName;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ipnet::IpNet;
+
+use crate::{
+    bot_score, client_ip, cloudflare, controllers, error, fingerprint,
+    header_rules::HeaderRules,
+    lists,
+    policies::{self, Policy, PolicyAuth},
+    patterns::PatternSet,
+    query_rules::QueryRules,
+    rate_limit::{Algorithm, Rate},
+    schedule::Schedule,
+};
 
 /// Application configuration loaded from environment variables
 #[derive(Clone, Debug)]
 pub struct Config {
-    pub banned_ips_file: String,
+    /// Banned IPs files or directories of list files (`BANNED_IPS_FILE`)
+    pub banned_ips_files: Vec<String>,
+    /// Where the client IP is taken from, in order of priority
+    pub client_ip_sources: Vec<IpSource>,
+    /// Proxies whose `x-forwarded-for` entries are skipped from the right
+    /// (`TRUSTED_PROXIES`), see [`crate::client_ip`]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Number of `x-forwarded-for` entries appended by trusted proxies
+    /// (`TRUSTED_HOPS`); 0 uses the first entry
+    pub trusted_hops: usize,
+    /// Only trust `cf-connecting-ip` on requests coming from Cloudflare
+    /// (`CLOUDFLARE_VERIFY`), see [`crate::client_ip`]
+    pub cloudflare_verify: bool,
+    /// Cloudflare's ranges until they are first refreshed (`CLOUDFLARE_IPS`)
+    pub cloudflare_ips: Vec<IpNet>,
+    /// Lists Cloudflare's ranges are refreshed from (`CLOUDFLARE_IPS_URLS`),
+    /// see [`crate::cloudflare`]
+    pub cloudflare_ips_urls: Vec<String>,
+    /// How often the ranges are refreshed (`CLOUDFLARE_IPS_REFRESH`, `None`
+    /// keeps `CLOUDFLARE_IPS`)
+    pub cloudflare_ips_refresh: Option<Duration>,
     pub cache_ttl: Duration,
+    /// Reload the banned IPs files as soon as they change on disk
+    pub banned_ips_watch: bool,
     pub log_file: String,
     pub log_dir: String,
     pub log_rotation: LogRotation,
     pub log_max_files: usize,
+    /// Where logs go besides stdout
+    pub log_target: LogTarget,
     pub port: u16,
     pub hostname: String,
+    /// Addresses served, `APP_HOSTNAME:PORT` with every route unless `LISTEN` is set
+    pub listen: Vec<Listen>,
+    /// Expect a PROXY protocol header on every connection
+    pub proxy_protocol: bool,
+    /// Balancers whose PROXY headers are honoured (`PROXY_PROTOCOL_TRUSTED`,
+    /// `TRUSTED_PROXIES` by default); connections from other peers are dropped
+    pub proxy_protocol_trusted: Vec<IpNet>,
+    /// HTTP versions the listener speaks
+    pub http_version: HttpVersion,
+    /// How long idle keep-alive connections are kept (`None` disables keep-alive)
+    pub keep_alive_timeout: Option<Duration>,
+    /// Maximum concurrent streams per HTTP/2 connection
+    pub http2_max_concurrent_streams: u32,
+    /// Length of the listen queue for pending TCP connections
+    pub tcp_backlog: u32,
+    /// How IPv6 listeners treat IPv4 clients
+    pub ipv6_mode: Ipv6Mode,
+    /// Requests handled at the same time across all clients (`None`: unlimited)
+    pub max_inflight: Option<usize>,
+    /// Requests handled at the same time for one client IP (`None`: unlimited)
+    pub max_inflight_per_ip: Option<usize>,
+    /// Deadline for handling a request (`None`: no deadline)
+    pub request_timeout: Option<Duration>,
+    /// Largest request body accepted (`None`: unlimited)
+    pub max_body_bytes: Option<usize>,
+    /// Block banned IPs; when false they are only logged and counted
+    pub enforce: bool,
+    /// Whether requests pass or are blocked when a check can't be made
+    /// (`FAIL_MODE`)
+    pub fail_mode: FailMode,
+    /// What blocked requests are answered with
+    pub block_response: BlockResponse,
+    /// Add an `X-Block-Reason` header to blocked responses
+    /// (`BLOCK_REASON_HEADER`), for internal debugging
+    pub block_reason_header: bool,
+    /// Ban categories enforced per host and path, first match wins
+    /// (`CATEGORY_RULES`); requests matching none enforce every category
+    pub category_rules: Vec<CategoryRule>,
+    /// Opening hours per host and path, first match wins (`SCHEDULE_RULES`);
+    /// requests matching none are never blocked for the time
+    pub schedule_rules: Vec<ScheduleRule>,
+    /// Time zone of the schedules (`SCHEDULE_TIMEZONE`)
+    pub schedule_timezone: Tz,
+    /// Requests one client IP may send (`RATE_LIMIT`, `None`: unlimited),
+    /// see [`crate::rate_limit`]
+    pub rate_limit: Option<Rate>,
+    /// Rate limits per host and path, first match wins (`RATE_LIMIT_RULES`);
+    /// requests matching none are limited by `RATE_LIMIT`
+    pub rate_limit_rules: Vec<RateLimitRule>,
+    /// How requests are counted against the rate limits
+    /// (`RATE_LIMIT_ALGORITHM`)
+    pub rate_limit_algorithm: Algorithm,
+    /// Time to wait for Redis when rate limits are counted there
+    /// (`RATE_LIMIT_REDIS`, `RATE_LIMIT_REDIS_TIMEOUT_MS`), see
+    /// [`crate::rate_limit_redis`]; `None` counts on each replica
+    pub rate_limit_redis: Option<Duration>,
+    /// Header conditions blocking a request, first match wins
+    /// (`HEADER_RULES`), see [`crate::header_rules`]
+    pub header_rules: HeaderRules,
+    /// Headers carrying the client's TLS fingerprints
+    /// (`TLS_FINGERPRINT_HEADERS`), see [`crate::fingerprint`]
+    pub tls_fingerprint_headers: Vec<HeaderName>,
+    /// Lower-case JA3/JA4 fingerprints blocked wherever they come from
+    /// (`BLOCKED_TLS_FINGERPRINTS`)
+    pub blocked_tls_fingerprints: HashSet<String>,
+    /// Named policies by lower-case name (`POLICIES`), see [`policies`]
+    pub policies: BTreeMap<String, Policy>,
+    /// Lower-case header naming the policy of a request (`POLICY_HEADER`)
+    pub policy_header: String,
+    /// Start in maintenance mode (`MAINTENANCE`), see [`crate::maintenance`]
+    pub maintenance: bool,
+    /// Clients let through during maintenance (`MAINTENANCE_ALLOWLIST`)
+    pub maintenance_allowlist: Vec<IpNet>,
+    /// HTML answered during maintenance (`MAINTENANCE_PAGE`); a default
+    /// page when `None`
+    pub maintenance_page: Option<String>,
+    /// `Retry-After` of maintenance answers (`MAINTENANCE_RETRY_AFTER`)
+    pub maintenance_retry_after: Duration,
+    /// Clients let through during a lockdown (`LOCKDOWN_ALLOWLIST`), see
+    /// [`crate::lockdown`]
+    pub lockdown_allowlist: Vec<IpNet>,
+    /// Lockdown length when none is given (`LOCKDOWN_DURATION`)
+    pub lockdown_duration: Duration,
+    /// Longest lockdown accepted (`LOCKDOWN_MAX_DURATION`)
+    pub lockdown_max_duration: Duration,
+    /// Delay before answering banned IPs (`None` disables the tarpit)
+    pub tarpit_delay: Option<DelayRange>,
+    /// Maximum number of banned connections held at the same time
+    pub tarpit_max_concurrent: usize,
+    /// Trap paths that ban any client requesting them
+    pub honeypot_paths: Vec<String>,
+    /// How long honeypot bans last (`None` means permanently)
+    pub honeypot_ban_duration: Option<Duration>,
+    /// Query-string rules that ban exploit probes like honeypot paths do
+    pub query_rules: QueryRules,
+    /// Ban durations for repeat offenders, by offense (`None` is permanent);
+    /// empty uses the fixed honeypot duration
+    pub ban_escalation: Vec<Option<Duration>>,
+    /// Quiet period after which an IP's offense count starts over
+    pub ban_escalation_reset: Duration,
+    /// Where offense history is persisted across restarts
+    pub ban_history_file: Option<String>,
+    /// Snapshot of all learned runtime state, restored at startup
+    /// (`STATE_SNAPSHOT_FILE`)
+    pub state_snapshot_file: Option<String>,
+    /// How often the runtime state snapshot is written
+    pub state_snapshot_interval: Duration,
+    /// How often expired bans and stale per-IP counters are removed
+    /// (`SWEEP_INTERVAL`), see [`crate::sweeper`]
+    pub sweep_interval: Duration,
+    /// Ban propagation between replicas (`None` when `GOSSIP_BIND` is unset)
+    pub gossip: Option<GossipConfig>,
+    /// Ban propagation through Redis pub/sub (`None` when `REDIS_URL` is unset)
+    pub redis: Option<RedisConfig>,
+    /// Bans and config overrides watched in etcd or Consul (`None` when
+    /// `KV_BACKEND` is unset)
+    pub kv: Option<KvConfig>,
+    /// Defer the first request of unknown IPs with a 429
+    pub greylist: bool,
+    /// How long a greylisted IP must wait before retrying
+    pub greylist_delay: Duration,
+    /// How long an IP that passed the greylist stays trusted
+    pub greylist_ttl: Duration,
+    /// CrowdSec bouncer settings (`None` when `CROWDSEC_LAPI_URL` is unset)
+    pub crowdsec: Option<CrowdSecConfig>,
+    /// AbuseIPDB reputation lookups (`None` when `ABUSEIPDB_API_KEY` is unset)
+    pub abuseipdb: Option<AbuseIpDbConfig>,
+    /// DNS blocklist lookups (`None` when `DNSBL_ZONES` is empty or the
+    /// `dns` feature is disabled)
+    pub dnsbl: Option<DnsblConfig>,
+    /// Reverse DNS names of blocked clients (`None` unless `RDNS=true` in a
+    /// build with the `dns` feature)
+    pub rdns: Option<RdnsConfig>,
+    /// MaxMind Country or City database annotating decisions (`GEOIP_COUNTRY_DB`)
+    pub geoip_country_db: Option<String>,
+    /// MaxMind ASN database annotating decisions (`GEOIP_ASN_DB`)
+    pub geoip_asn_db: Option<String>,
+    /// Browser challenge for suspicious IPs (`None` when `CHALLENGE` is unset)
+    pub challenge: Option<ChallengeConfig>,
+    /// Heuristic bot score (`None` unless `BOT_SCORE_CHALLENGE` or
+    /// `BOT_SCORE_BAN` is set), see [`crate::bot_score`]
+    pub bot_score: Option<BotScoreConfig>,
+    /// Keys for signing cookies, newest first; a random key per process
+    /// when empty
+    pub cookie_secrets: Vec<String>,
+    /// Encrypt cookie payloads instead of only signing them
+    pub cookie_encrypt: bool,
+    /// Credential check after IP screening (`None` when `AUTH_MODE=none`)
+    pub auth: Option<AuthConfig>,
+    /// HMAC request signatures (`None` when `SIGNATURE_SECRET` is unset)
+    pub signature: Option<SignatureConfig>,
+    /// Second ForwardAuth service asked after the local checks (`None` when
+    /// `UPSTREAM_AUTH_URL` is unset)
+    pub upstream_auth: Option<UpstreamAuthConfig>,
+    /// Policy decisions from Open Policy Agent (`None` when `OPA_URL` is unset)
+    pub opa: Option<OpaConfig>,
+    /// Rhai script that may override decisions (`scripting` feature)
+    pub script_file: Option<String>,
+    /// Directory of WebAssembly decision plugins (`plugins` feature)
+    pub plugin_dir: Option<String>,
+    /// Memory limit of one plugin instance in MiB
+    pub plugin_memory_mb: usize,
+    /// Remote blocklists fetched on a schedule (Tor exit nodes, `REMOTE_LISTS`)
+    pub feeds: Vec<FeedConfig>,
+    /// Block event notifications (`None` when `WEBHOOK_URL` is unset)
+    pub webhook: Option<WebhookConfig>,
+    /// Decision log push to Loki (`None` when `LOKI_URL` is unset)
+    pub loki: Option<LokiConfig>,
+    /// JSON Lines audit trail: a file path or `unix:///path` (`AUDIT_LOG`)
+    pub audit_log: Option<String>,
+    /// Mount the `/admin` API
+    pub admin_api: bool,
+    /// Bearer token required on every `/admin` request (`ADMIN_TOKEN` or the
+    /// contents of `ADMIN_TOKEN_FILE`); `None` leaves the API open
+    pub admin_token: Option<String>,
+    /// Port for the Envoy ext_authz gRPC server (`None` disables it)
+    pub ext_authz_port: Option<u16>,
+    /// Number of client IPs tracked for per-IP statistics (0 disables)
+    pub ip_stats_capacity: usize,
+    /// Longest window available for top-offender reports
+    pub top_stats_retention: Duration,
+    /// Decisions kept per client IP and request target
+    /// (`DECISION_CACHE_SIZE`, 0 disables), see [`crate::decision_cache`]
+    pub decision_cache_size: usize,
+    /// How long a cached decision is reused (`DECISION_CACHE_TTL`)
+    pub decision_cache_ttl: Duration,
+    pub validation_mode: ValidationMode,
+    /// Problems found while loading in `warn` mode (empty in `strict` mode)
+    pub validation_warnings: Vec<ConfigIssue>,
+    /// `TEZ_*` variables that aren't configuration variables
+    pub unknown_vars: Vec<String>,
 }
 
 /// Log rotation strategy
@@ -21,68 +254,2594 @@ pub enum LogRotation {
     Never,
 }
 
-impl Config {
-    /// Load configuration from environment variables with defaults
-    pub fn from_env() -> Self {
-        let banned_ips_file =
-            env::var("BANNED_IPS_FILE").unwrap_or_else(|_| "./banned-ips.txt".to_string());
+/// Log destination besides stdout (`LOG_TARGET`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogTarget {
+    /// Rotated files under `LOG_DIR`
+    File,
+    /// A syslog daemon or collector, RFC 5424 formatted
+    Syslog(SyslogConfig),
+    /// The systemd journal, through its socket; replaces stdout
+    Journald { socket: String },
+    /// The Windows Application event log; replaces stdout
+    EventLog,
+}
+
+/// Syslog destination settings
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyslogConfig {
+    pub transport: SyslogTransport,
+    /// `host:port`, or the socket path for [`SyslogTransport::Unix`]
+    pub address: String,
+    /// Facility code, e.g. 3 for `daemon`
+    pub facility: u8,
+}
+
+/// How syslog messages are sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyslogTransport {
+    /// One datagram per message
+    Udp,
+    /// Octet-counted frames (RFC 6587) on a persistent connection
+    Tcp,
+    /// Datagrams to a local socket such as `/dev/log`
+    Unix,
+}
+
+impl SyslogConfig {
+    /// Parses `udp://host:port`, `tcp://host:port`, `unix:///path` or a bare
+    /// socket path.
+    pub fn parse_address(s: &str) -> Option<(SyslogTransport, String)> {
+        let (transport, address) = match s.split_once("://") {
+            Some(("udp", address)) => (SyslogTransport::Udp, address),
+            Some(("tcp", address)) => (SyslogTransport::Tcp, address),
+            Some(("unix", address)) => (SyslogTransport::Unix, address),
+            Some(_) => return None,
+            None if s.starts_with('/') => (SyslogTransport::Unix, s),
+            None => return None,
+        };
+        let valid = match transport {
+            SyslogTransport::Unix => address.starts_with('/'),
+            _ => address
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()),
+        };
+        valid.then(|| (transport, address.to_string()))
+    }
+
+    /// Facility code for a name such as `daemon` or `local0`.
+    pub fn parse_facility(name: &str) -> Option<u8> {
+        const NAMES: [&str; 12] = [
+            "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+            "authpriv", "ftp",
+        ];
+        let name = name.to_lowercase();
+        if let Some(code) = NAMES.iter().position(|&n| n == name) {
+            return Some(code as u8);
+        }
+        let local = name.strip_prefix("local")?.parse::<u8>().ok().filter(|&n| n <= 7)?;
+        Some(16 + local)
+    }
+}
+
+/// CrowdSec Local API connection settings
+#[derive(Clone, Debug)]
+pub struct CrowdSecConfig {
+    /// Base URL of the Local API, without trailing slash
+    pub lapi_url: String,
+    /// Bouncer API key (`cscli bouncers add`)
+    pub api_key: String,
+    /// How often to pull the decision stream
+    pub poll_interval: Duration,
+    /// Machine login used to push alerts (`cscli machines add`)
+    pub machine_id: Option<String>,
+    pub machine_password: Option<String>,
+}
+
+impl CrowdSecConfig {
+    /// Whether machine credentials are set, enabling alert pushes.
+    pub fn can_push_alerts(&self) -> bool {
+        self.machine_id.is_some() && self.machine_password.is_some()
+    }
+}
+
+/// DNS blocklist lookup settings
+#[derive(Clone, Debug)]
+pub struct DnsblConfig {
+    /// Zones queried for every unknown IP, e.g. `zen.spamhaus.org`
+    pub zones: Vec<String>,
+    /// Name server to query instead of the system resolver
+    pub resolver: Option<SocketAddr>,
+    /// Time allowed for one query
+    pub timeout: Duration,
+    /// How long a lookup result is trusted
+    pub cache_ttl: Duration,
+    /// Upper bound on cached results
+    pub max_cached: usize,
+}
+
+/// Reverse DNS lookup settings
+#[derive(Clone, Debug)]
+pub struct RdnsConfig {
+    /// Name server to query instead of the system resolver
+    pub resolver: Option<SocketAddr>,
+    /// Lookups started per second at most
+    pub rate: u32,
+    /// How long a hostname (or its absence) is trusted
+    pub cache_ttl: Duration,
+    /// Upper bound on cached hostnames
+    pub max_cached: usize,
+}
+
+/// AbuseIPDB reputation lookup settings
+#[derive(Clone, Debug)]
+pub struct AbuseIpDbConfig {
+    pub api_key: String,
+    /// Block IPs whose abuse confidence score (0-100) is at least this
+    pub threshold: u8,
+    /// How long a looked-up score is trusted
+    pub cache_ttl: Duration,
+    /// Only consider reports from the last N days (1-365)
+    pub max_age_days: u16,
+    /// Upper bound on cached scores
+    pub max_cached: usize,
+}
+
+/// Browser challenge settings
+#[derive(Clone, Debug)]
+pub struct ChallengeConfig {
+    pub mode: ChallengeMode,
+    /// Challenge IPs whose AbuseIPDB score is at least this; `None`
+    /// challenges every client without a valid bypass cookie, unless
+    /// `BOT_SCORE_CHALLENGE` picks them
+    pub threshold: Option<u8>,
+    /// How long a solved challenge is remembered by the bypass cookie
+    pub cookie_ttl: Duration,
+    /// Path the challenge page submits the captcha response to
+    pub path: String,
+}
+
+/// Signals and thresholds of the heuristic bot score
+#[derive(Clone, Debug, Default)]
+pub struct BotScoreConfig {
+    /// Challenge clients scoring at least this (`BOT_SCORE_CHALLENGE`)
+    pub challenge_threshold: Option<u32>,
+    /// Ban clients scoring at least this (`BOT_SCORE_BAN`)
+    pub ban_threshold: Option<u32>,
+    /// User agents of scripts and scanners (`BOT_USER_AGENTS`)
+    pub user_agents: PatternSet,
+    /// Header carrying the client's HTTP version (`BOT_PROTOCOL_HEADER`)
+    pub protocol_header: Option<HeaderName>,
+    /// Header listing the client's header names in order
+    /// (`BOT_HEADER_ORDER_HEADER`)
+    pub header_order_header: Option<HeaderName>,
+    /// Lower-case header orders of scripts (`BOT_HEADER_ORDERS`)
+    pub header_orders: PatternSet,
+}
+
+/// What a challenged client has to solve
+#[derive(Clone, Debug)]
+pub enum ChallengeMode {
+    /// Third-party captcha widget
+    Captcha {
+        provider: CaptchaProvider,
+        site_key: String,
+        secret_key: String,
+        /// Verification endpoint, the provider's `siteverify` API by default
+        verify_url: String,
+    },
+    /// JavaScript proof of work, verified locally
+    ProofOfWork {
+        /// Leading zero bits required in the solution hash
+        difficulty: u8,
+    },
+}
+
+/// Captcha service rendering the challenge widget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptchaProvider {
+    /// Cloudflare Turnstile
+    Turnstile,
+    HCaptcha,
+}
+
+impl CaptchaProvider {
+    /// Server-side token verification endpoint.
+    pub fn verify_url(self) -> &'static str {
+        match self {
+            Self::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            Self::HCaptcha => "https://api.hcaptcha.com/siteverify",
+        }
+    }
+}
+
+/// Default path of the challenge verification endpoint
+pub const CHALLENGE_PATH: &str = "/.tezcatlipoca/challenge";
+
+/// Primary authentication settings
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    pub mode: AuthMode,
+    /// Path prefixes that require authentication
+    pub paths: Vec<String>,
+    /// Realm announced in `WWW-Authenticate`
+    pub realm: String,
+    /// How long the session cookie issued after a successful login is valid
+    pub session_ttl: Duration,
+    /// Second factor for sensitive paths (`None` when `TOTP_PATHS` is empty)
+    pub totp: Option<TotpConfig>,
+}
+
+/// TOTP second factor settings
+#[derive(Clone, Debug)]
+pub struct TotpConfig {
+    /// Path prefixes that require a one-time code
+    pub paths: Vec<String>,
+    /// Path the code page submits to
+    pub path: String,
+    /// Where user secrets are persisted (in memory only when `None`)
+    pub store_file: Option<String>,
+}
+
+/// Default path of the TOTP code submission endpoint
+pub const TOTP_PATH: &str = "/.tezcatlipoca/totp";
+
+/// How credentials are checked
+#[derive(Clone, Debug)]
+pub enum AuthMode {
+    /// HTTP Basic against an htpasswd file with bcrypt hashes
+    Basic { users_file: String },
+    /// HTTP Basic checked by binding to an LDAP / Active Directory server
+    Ldap(LdapConfig),
+}
+
+/// LDAP authentication settings
+#[derive(Clone, Debug)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` server URL
+    pub url: String,
+    /// Upgrade plain `ldap://` connections with StartTLS
+    pub starttls: bool,
+    /// Name to bind as, with `{user}` replaced by the login: a DN template
+    /// such as `uid={user},ou=people,dc=example,dc=org`, or `{user}@corp.example`
+    /// for Active Directory
+    pub bind_dn: String,
+    /// Where the user's entry is looked up for group checks
+    pub base_dn: Option<String>,
+    /// Filter finding the user's entry, with `{user}` replaced by the login
+    pub user_filter: String,
+    /// Group names (CN) of which the user must be a member of at least one;
+    /// empty accepts any user who can bind
+    pub groups: Vec<String>,
+    /// Limit for the whole exchange with the server
+    pub timeout: Duration,
+}
+
+/// HMAC request signature settings
+#[derive(Clone, Debug)]
+pub struct SignatureConfig {
+    /// Shared secret clients sign requests with
+    pub secret: String,
+    /// Path prefixes that require a signature
+    pub paths: Vec<String>,
+    /// Maximum clock difference between signing and checking
+    pub window: Duration,
+}
+
+/// Chained upstream authentication settings
+#[derive(Clone, Debug)]
+pub struct UpstreamAuthConfig {
+    /// ForwardAuth endpoint of the upstream service
+    pub url: String,
+    /// Limit for one upstream auth request
+    pub timeout: Duration,
+}
+
+/// Open Policy Agent settings
+#[derive(Clone, Debug)]
+pub struct OpaConfig {
+    /// Data API URL of the rule deciding `allow`
+    pub url: String,
+    /// Limit for one policy query
+    pub timeout: Duration,
+    /// Allow requests when OPA can't be reached (`OPA_FAIL_OPEN`, following
+    /// `FAIL_MODE` by default)
+    pub fail_open: bool,
+}
+
+/// A remote blocklist merged into the cache under its own source name
+#[derive(Clone, Debug)]
+pub struct FeedConfig {
+    pub name: String,
+    pub url: String,
+    pub refresh: Duration,
+}
+
+/// Webhook delivery settings
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+    /// Maximum events per POST
+    pub batch_size: usize,
+    /// How long to wait for more events after the first one of a batch
+    pub batch_window: Duration,
+    /// Retries after the first failed attempt
+    pub max_retries: u32,
+}
+
+/// Peer-to-peer ban propagation settings
+#[derive(Clone, Debug)]
+pub struct GossipConfig {
+    /// UDP address to receive ban messages on, e.g. `0.0.0.0:7946`
+    pub bind: SocketAddr,
+    /// Other replicas as `host:port`, resolved on every send
+    pub peers: Vec<String>,
+    /// Shared key signing every message (`GOSSIP_SECRET`)
+    pub secret: String,
+}
+
+/// Redis connection settings
+#[derive(Clone, Debug)]
+pub struct RedisConfig {
+    /// Connection URL, e.g. `redis://:password@redis:6379/0`
+    pub url: String,
+    /// Pub/sub channel carrying ban and unban events
+    pub channel: String,
+}
+
+/// Key-value store holding bans and config overrides
+#[derive(Clone, Debug)]
+pub struct KvConfig {
+    pub backend: KvBackend,
+    /// HTTP API address, e.g. `http://consul:8500` or `http://etcd:2379`
+    pub url: String,
+    /// Key prefix watched, e.g. `tezcatlipoca/`
+    pub prefix: String,
+    /// Consul ACL token or etcd auth token
+    pub token: Option<String>,
+}
+
+/// Supported key-value stores
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KvBackend {
+    /// Consul KV through blocking queries
+    Consul,
+    /// etcd v3 through its JSON gateway
+    Etcd,
+}
+
+impl KvBackend {
+    /// Local agent address used when `KV_URL` is unset
+    pub fn default_url(self) -> &'static str {
+        match self {
+            KvBackend::Consul => "http://127.0.0.1:8500",
+            KvBackend::Etcd => "http://127.0.0.1:2379",
+        }
+    }
+}
+
+impl fmt::Display for KvBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KvBackend::Consul => "consul",
+            KvBackend::Etcd => "etcd",
+        })
+    }
+}
+
+/// HashiCorp Vault settings, read before the rest of the configuration
+#[derive(Clone, Debug)]
+pub struct VaultConfig {
+    /// Server address, e.g. `https://vault:8200`
+    pub addr: String,
+    pub auth: VaultAuth,
+    /// Enterprise namespace (`X-Vault-Namespace`)
+    pub namespace: Option<String>,
+    /// Variables to fill from Vault
+    pub secrets: Vec<VaultSecret>,
+}
+
+/// How the service logs in to Vault
+#[derive(Clone, Debug)]
+pub enum VaultAuth {
+    /// A token given in `VAULT_TOKEN`
+    Token(String),
+    /// Kubernetes auth method with the pod's service account token
+    Kubernetes {
+        role: String,
+        /// Mount path of the auth method
+        mount: String,
+        /// Service account token file
+        jwt_file: String,
+    },
+}
 
-        let cache_ttl_secs = env::var("CACHE_TTL_SECS")
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(5);
+/// One variable whose value is a field of a Vault secret
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VaultSecret {
+    /// Variable name, e.g. `ADMIN_TOKEN`
+    pub var: String,
+    /// Secret path, e.g. `secret/data/tezcatlipoca` for KV version 2
+    pub path: String,
+    pub field: String,
+}
 
-        let log_file = env::var("LOG_FILE").unwrap_or_else(|_| "./traefik-auth.log".to_string());
+impl VaultSecret {
+    /// Parses `VAR=path#field`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (var, location) = s.split_once('=')?;
+        let (path, field) = location.rsplit_once('#')?;
+        let (var, path, field) = (var.trim(), path.trim().trim_matches('/'), field.trim());
+        if var.is_empty() || path.is_empty() || field.is_empty() {
+            return None;
+        }
+        Some(Self {
+            var: var.to_string(),
+            path: path.to_string(),
+            field: field.to_string(),
+        })
+    }
+}
 
-        let log_dir = env::var("LOG_DIR").unwrap_or_else(|_| ".".to_string());
+impl VaultConfig {
+    /// Reads the `VAULT_*` variables; `None` when `VAULT_ADDR` is unset.
+    ///
+    /// Checked on their own and always strictly, since the rest of the
+    /// configuration may depend on the secrets.
+    pub fn from_env() -> error::Result<Option<Self>> {
+        let secrets = HashMap::new();
+        let mut env = EnvReader::new(&secrets);
+        let Some(addr) = env.optional("VAULT_ADDR") else {
+            return Ok(None);
+        };
+        let addr = addr.trim().trim_end_matches('/').to_string();
+        if !addr.starts_with("http://") && !addr.starts_with("https://") {
+            env.invalid("VAULT_ADDR", &addr, "an http:// or https:// URL");
+        }
 
-        let log_rotation = match env::var("LOG_ROTATION")
-            .unwrap_or_else(|_| "daily".to_string())
-            .to_lowercase()
-            .as_str()
-        {
-            "hourly" => LogRotation::Hourly,
-            "daily" => LogRotation::Daily,
-            "never" => LogRotation::Never,
-            _ => LogRotation::Daily,
+        let auth = match (env.optional("VAULT_TOKEN"), env.optional("VAULT_K8S_ROLE")) {
+            (Some(token), None) => Some(VaultAuth::Token(token.trim().to_string())),
+            (None, Some(role)) => Some(VaultAuth::Kubernetes {
+                role: role.trim().to_string(),
+                mount: env.string("VAULT_K8S_MOUNT", "kubernetes").trim_matches('/').to_string(),
+                jwt_file: env.string(
+                    "VAULT_K8S_TOKEN_FILE",
+                    "/var/run/secrets/kubernetes.io/serviceaccount/token",
+                ),
+            }),
+            (Some(_), Some(role)) => {
+                env.invalid("VAULT_K8S_ROLE", &role, "unset when VAULT_TOKEN is set");
+                None
+            }
+            (None, None) => {
+                env.invalid("VAULT_TOKEN", "", "a token, or VAULT_K8S_ROLE for Kubernetes auth");
+                None
+            }
         };
 
-        let log_max_files = env::var("LOG_MAX_FILES")
-            .ok()
-            .and_then(|s| s.parse::<usize>().ok())
-            .unwrap_or(7);
+        let mut secrets = Vec::new();
+        for item in env.list("VAULT_SECRETS", &[]) {
+            match VaultSecret::parse(&item) {
+                Some(secret) => secrets.push(secret),
+                None => env.invalid("VAULT_SECRETS", &item, "comma-separated VAR=path#field"),
+            }
+        }
+
+        match auth {
+            Some(auth) if env.issues.is_empty() => Ok(Some(Self {
+                addr,
+                auth,
+                namespace: env.optional("VAULT_NAMESPACE"),
+                secrets,
+            })),
+            _ => Err(ConfigError { issues: env.issues }.into()),
+        }
+    }
+}
 
-        let hostname = env::var("APP_HOSTNAME").unwrap_or_else(|_| "0.0.0.0".to_string());
+/// Grafana Loki push settings
+#[derive(Clone, Debug)]
+pub struct LokiConfig {
+    /// Push API URL, e.g. `http://loki:3100/loki/api/v1/push`
+    pub url: String,
+    /// Static stream labels added to `service`, `host` and `decision`
+    pub labels: Vec<(String, String)>,
+    /// Tenant sent as `X-Scope-OrgID` for multi-tenant Loki
+    pub tenant: Option<String>,
+    /// Maximum decisions per push
+    pub batch_size: usize,
+    /// How long to wait for more decisions after the first one of a batch
+    pub batch_window: Duration,
+}
 
-        let port = env::var("PORT")
-            .ok()
-            .and_then(|s| s.parse::<u16>().ok())
-            .unwrap_or(8199);
+impl LokiConfig {
+    /// Parses `name=value` pairs separated by commas. Label names follow
+    /// Prometheus rules (`[a-zA-Z_][a-zA-Z0-9_]*`).
+    pub fn parse_labels(s: &str) -> Option<Vec<(String, String)>> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                valid.then(|| (name.to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+}
 
+/// Payload shape sent to the webhook
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookFormat {
+    /// `{"events": [...]}` with the full event objects
+    Json,
+    /// Discord `{"content": "..."}` message
+    Discord,
+    /// Slack `{"text": "..."}` message
+    Slack,
+}
+
+/// Official list of Tor exit node addresses
+pub const TOR_EXIT_LIST_URL: &str = "https://check.torproject.org/torbulkexitlist";
+
+/// Source of the client IP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpSource {
+    /// First address in this request header (lower-case name)
+    Header(String),
+    /// Address of the connection
+    Socket,
+}
+
+impl IpSource {
+    /// Cloudflare's header, the proxy chain, the single-address headers of
+    /// nginx and Akamai, then the connection.
+    ///
+    /// `x-real-ip` and `true-client-ip` come after `x-forwarded-for` so that
+    /// behind a proxy setting the latter, a client can't pick its address by
+    /// sending one of them itself.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::Header(client_ip::CLOUDFLARE_HEADER.to_string()),
+            Self::Header("x-forwarded-for".to_string()),
+            Self::Header("x-real-ip".to_string()),
+            Self::Header("true-client-ip".to_string()),
+            Self::Socket,
+        ]
+    }
+
+    /// Parses `cf-connecting-ip,x-forwarded-for,socket`.
+    fn parse_list(s: &str) -> Option<Vec<Self>> {
+        let sources = s
+            .split(',')
+            .map(|item| item.trim().to_ascii_lowercase())
+            .filter(|item| !item.is_empty())
+            .map(|item| match item.as_str() {
+                "socket" => Some(Self::Socket),
+                name if name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') => {
+                    Some(Self::Header(item))
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        (!sources.is_empty()).then_some(sources)
+    }
+}
+
+impl fmt::Display for IpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(name) => f.write_str(name),
+            Self::Socket => f.write_str("socket"),
+        }
+    }
+}
+
+/// Answer sent to blocked requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockResponse {
+    /// Plain status: 403, 404 (hide the service) or 429
+    Status(u16),
+    /// `302 Found` to a block or appeal page
+    Redirect(String),
+    /// `429 Too Many Requests` with `Retry-After`; used by greylisting, not
+    /// selectable through `BLOCK_STATUS`
+    RetryAfter(Duration),
+    /// `403 Forbidden` with the rendered challenge page as the body
+    Challenge(String),
+    /// `401 Unauthorized` asking for Basic credentials in the given realm
+    Unauthorized(String),
+    /// Denial from the upstream auth service, passed on unchanged
+    Upstream(Box<UpstreamResponse>),
+    /// Another response with an `X-Block-Reason` header explaining the
+    /// block (`BLOCK_REASON_HEADER`)
+    WithReason(Box<BlockResponse>, String),
+    /// Another response with `Retry-After` and an `X-Ban-Expires` time
+    /// telling when a temporary ban or lockdown ends
+    Expiring(Box<BlockResponse>, DateTime<Utc>),
+    /// `503 Service Unavailable` with `Retry-After` and the maintenance page
+    Maintenance(Duration, String),
+}
+
+impl BlockResponse {
+    /// Status codes accepted for `BLOCK_STATUS`.
+    pub const STATUSES: [u16; 4] = [403, 404, 429, 302];
+
+    /// HTTP status code of the response.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Status(code) => *code,
+            Self::Redirect(_) => 302,
+            Self::RetryAfter(_) => 429,
+            Self::Challenge(_) => 403,
+            Self::Unauthorized(_) => 401,
+            Self::Upstream(response) => response.status,
+            Self::WithReason(response, _) | Self::Expiring(response, _) => response.status(),
+            Self::Maintenance(..) => 503,
+        }
+    }
+}
+
+/// Answer of the upstream auth service to a denied request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamResponse {
+    pub status: u16,
+    /// Headers to return, e.g. `Location` of a login page
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// Inclusive range of durations, parsed from `"10"` or `"10-30"` (seconds)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DelayRange {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl DelayRange {
+    fn parse_secs(s: &str) -> Option<Self> {
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (min.trim().parse::<u64>().ok()?, max.trim().parse::<u64>().ok()?),
+            None => {
+                let secs = s.parse::<u64>().ok()?;
+                (secs, secs)
+            }
+        };
+        (min <= max).then(|| Self {
+            min: Duration::from_secs(min),
+            max: Duration::from_secs(max),
+        })
+    }
+}
+
+/// Parses a boolean written as `true`/`false`, `1`/`0`, `yes`/`no` or `on`/`off`.
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a duration written as plain seconds (`"90"`) or with a unit
+/// suffix: `s`, `m`, `h` or `d` (`"15m"`, `"1h"`, `"7d"`).
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+    let value = number.parse::<u64>().ok()?;
+    let secs = match unit.trim() {
+        "s" => value,
+        "m" => value.checked_mul(60)?,
+        "h" => value.checked_mul(3600)?,
+        "d" => value.checked_mul(86400)?,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+/// One address served and the routes on it (`LISTEN`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listen {
+    /// `host:port`, with brackets around an IPv6 host
+    pub addr: String,
+    pub routes: Routes,
+}
+
+/// Routes served on a listener
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routes {
+    /// ForwardAuth and, with `ADMIN_API=true`, the admin API
+    All,
+    /// ForwardAuth and `/health`
+    Auth,
+    /// The admin API and `/health`
+    Admin,
+}
+
+impl Listen {
+    /// Parses `host:port` or `host:port=routes`, routes being `all`, `auth`
+    /// or `admin`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, routes) = match s.rsplit_once('=') {
+            Some((addr, routes)) => (addr.trim(), routes.trim()),
+            None => (s.trim(), "all"),
+        };
+        let routes = match routes.to_lowercase().as_str() {
+            "all" => Routes::All,
+            "auth" => Routes::Auth,
+            "admin" => Routes::Admin,
+            _ => return None,
+        };
+        let (host, port) = addr.rsplit_once(':')?;
+        if host.is_empty() || port.parse::<u16>().ok().filter(|&p| p != 0).is_none() {
+            return None;
+        }
+        Some(Self {
+            addr: addr.to_string(),
+            routes,
+        })
+    }
+}
+
+impl fmt::Display for Routes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Routes::All => "all",
+            Routes::Auth => "auth",
+            Routes::Admin => "admin",
+        })
+    }
+}
+
+/// Ban categories enforced for the requests of a host or path
+/// (`CATEGORY_RULES`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CategoryRule {
+    /// Lower-case host without port; `None` matches every host
+    pub host: Option<String>,
+    /// Path prefix, see [`path_matches`](crate::controllers::path_matches)
+    pub path: String,
+    pub categories: Categories,
+}
+
+/// Which ban categories a request is checked against
+///
+/// A category is the `[name]` section of an entry in the banned IPs files,
+/// the name of an external source (`tor`, a `REMOTE_LISTS` name,
+/// `crowdsec`, ...) or `dynamic` for bans added at runtime. Uncategorized
+/// entries of the banned IPs files are always enforced.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Categories {
+    /// `*`
+    #[default]
+    All,
+    /// `a,b`: only these
+    Only(Vec<String>),
+    /// `-a,-b`: all but these
+    Except(Vec<String>),
+}
+
+impl Categories {
+    /// Whether entries of `category` block the request.
+    pub fn enforces(&self, category: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(names) => names.iter().any(|n| n.eq_ignore_ascii_case(category)),
+            Self::Except(names) => !names.iter().any(|n| n.eq_ignore_ascii_case(category)),
+        }
+    }
+}
+
+impl fmt::Display for Categories {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => f.write_str("*"),
+            Self::Only(names) => f.write_str(&names.join(",")),
+            Self::Except(names) => {
+                let names: Vec<String> = names.iter().map(|n| format!("-{}", n)).collect();
+                f.write_str(&names.join(","))
+            }
+        }
+    }
+}
+
+impl CategoryRule {
+    /// Parses `target=categories`, the target being `host`, `/path` or
+    /// `host/path` and the categories `*`, `a,b` or `-a,-b`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (target, categories) = s.split_once('=')?;
+        let (host, path) = parse_target(target)?;
+
+        let names: Vec<&str> = categories.split(',').map(str::trim).collect();
+        let categories = if names == ["*"] {
+            Categories::All
+        } else if names.iter().all(|n| n.starts_with('-')) {
+            let names = names.iter().map(|n| lists::category_name(&n[1..]));
+            Categories::Except(names.collect::<Option<_>>()?)
+        } else if names.iter().any(|n| n.starts_with('-')) {
+            return None;
+        } else {
+            let names = names.iter().map(|n| lists::category_name(n));
+            Categories::Only(names.collect::<Option<_>>()?)
+        };
+        Some(Self {
+            host,
+            path,
+            categories,
+        })
+    }
+
+    /// Parses rules separated by `;`.
+    fn parse_list(s: &str) -> Option<Vec<Self>> {
+        parse_rules(s, Self::parse)
+    }
+
+    /// Whether the rule applies to a request for `path` on `host`.
+    pub fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        target_matches(self.host.as_deref(), std::slice::from_ref(&self.path), host, path)
+    }
+}
+
+/// Splits a rule target, `host`, `/path` or `host/path`, into its
+/// lower-case host and path prefix.
+fn parse_target(target: &str) -> Option<(Option<String>, String)> {
+    let target = target.trim();
+    let (host, path) = match target.find('/') {
+        Some(0) => (None, target),
+        Some(i) => (Some(&target[..i]), &target[i..]),
+        None => (Some(target), "/"),
+    };
+    if host.is_some_and(|h| h.is_empty() || h.contains(char::is_whitespace)) {
+        return None;
+    }
+    Some((host.map(str::to_ascii_lowercase), path.to_string()))
+}
+
+/// Parses rules separated by `;`, failing if any rule is invalid.
+pub(crate) fn parse_rules<T>(s: &str, parse: fn(&str) -> Option<T>) -> Option<Vec<T>> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(parse)
+        .collect()
+}
+
+fn target_matches(
+    rule_host: Option<&str>,
+    rule_paths: &[String],
+    host: Option<&str>,
+    path: &str,
+) -> bool {
+    let host_matches = match (rule_host, host) {
+        (None, _) => true,
+        (Some(rule), Some(host)) => rule.eq_ignore_ascii_case(host),
+        (Some(_), None) => false,
+    };
+    host_matches && controllers::path_matches(rule_paths, path)
+}
+
+/// Categories enforced for a request: those of the first matching rule, or
+/// all of them.
+pub fn enforced_categories<'a>(
+    rules: &'a [CategoryRule],
+    host: Option<&str>,
+    path: &str,
+) -> &'a Categories {
+    static ALL: Categories = Categories::All;
+    rules
+        .iter()
+        .find(|rule| rule.matches(host, path))
+        .map_or(&ALL, |rule| &rule.categories)
+}
+
+/// Opening hours of a host or path (`SCHEDULE_RULES`), see [`schedule`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleRule {
+    /// Lower-case host without port; `None` matches every host
+    pub host: Option<String>,
+    /// Path prefix, see [`path_matches`](crate::controllers::path_matches)
+    pub path: String,
+    pub schedule: Schedule,
+}
+
+impl ScheduleRule {
+    /// Parses `target=schedule`, the target being `host`, `/path` or
+    /// `host/path`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (target, schedule) = s.split_once('=')?;
+        let (host, path) = parse_target(target)?;
+        Some(Self {
+            host,
+            path,
+            schedule: Schedule::parse(schedule)?,
+        })
+    }
+
+    /// Whether the rule applies to a request for `path` on `host`.
+    pub fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        target_matches(self.host.as_deref(), std::slice::from_ref(&self.path), host, path)
+    }
+}
+
+/// Rate limit of a host or path (`RATE_LIMIT_RULES`), see [`crate::rate_limit`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitRule {
+    /// Lower-case host without port; `None` matches every host
+    pub host: Option<String>,
+    /// Path prefix, see [`path_matches`](crate::controllers::path_matches)
+    pub path: String,
+    /// `None` (`off`) exempts the target from rate limits
+    pub rate: Option<Rate>,
+}
+
+impl RateLimitRule {
+    /// Parses `target=rate` or `target=off`, the target being `host`,
+    /// `/path` or `host/path`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (target, rate) = s.split_once('=')?;
+        let (host, path) = parse_target(target)?;
+        let rate = match rate.trim() {
+            "off" => None,
+            rate => Some(Rate::parse(rate)?),
+        };
+        Some(Self { host, path, rate })
+    }
+
+    /// Whether the rule applies to a request for `path` on `host`.
+    pub fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        target_matches(self.host.as_deref(), std::slice::from_ref(&self.path), host, path)
+    }
+}
+
+/// How IPv6 listeners treat IPv4 clients (`IPV6_MODE`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ipv6Mode {
+    /// One socket for both families: `[::]` also accepts IPv4 clients
+    Dual,
+    /// IPv6 clients only
+    V6Only,
+    /// `[::]` is IPv6 only, and `0.0.0.0` is bound on the same port for
+    /// IPv4, where dual-stack sockets are disabled (e.g. OpenBSD)
+    Separate,
+}
+
+/// HTTP versions accepted by the listener
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/1.1, and HTTP/2 when the client starts with its preface (h2c)
+    Auto,
+    Http1,
+    Http2,
+}
+
+/// What happens to requests when a check can't be made: the banned IPs
+/// files were never loaded, or a reputation lookup (AbuseIPDB, DNSBL)
+/// failed or timed out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailMode {
+    /// Let them through, as if the check had passed
+    Open,
+    /// Block them, for deployments where an outage must not open the door
+    Closed,
+}
+
+impl fmt::Display for FailMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailMode::Open => "open",
+            FailMode::Closed => "closed",
+        })
+    }
+}
+
+/// How invalid configuration values are handled at startup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Refuse to start when any variable is invalid
+    Strict,
+    /// Fall back to the default and log a warning
+    Warn,
+}
+
+/// A single invalid environment variable
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    pub var: String,
+    pub value: String,
+    pub expected: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={:?} is invalid: expected {}",
+            self.var, self.value, self.expected
+        )
+    }
+}
+
+/// Why [`Config::from_env`] failed in strict mode (as [`error::Error::Config`])
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "invalid configuration ({} problem(s)):{}\nFix the variables above or set CONFIG_VALIDATION=warn to fall back to defaults",
+    .issues.len(),
+    issue_list(.issues)
+)]
+pub struct ConfigError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+/// One `\n  - ` line per issue.
+fn issue_list(issues: &[ConfigIssue]) -> String {
+    issues.iter().map(|issue| format!("\n  - {}", issue)).collect()
+}
+
+/// Prefix namespacing the variables: `TEZ_CACHE_TTL_SECS` is read before
+/// `CACHE_TTL_SECS`, which keeps working for existing deployments.
+pub const PREFIX: &str = "TEZ_";
+
+/// Every variable read by [`Config::from_env`] and [`VaultConfig::from_env`],
+/// without [`PREFIX`]. A `TEZ_*` variable not in this list is reported as
+/// unknown.
+pub const VARS: &[&str] = &[
+    "ABUSEIPDB_API_KEY", "ABUSEIPDB_CACHE_TTL_SECS", "ABUSEIPDB_MAX_AGE_DAYS",
+    "ABUSEIPDB_MAX_CACHED", "ABUSEIPDB_THRESHOLD", "ADMIN_API", "ADMIN_TOKEN", "APP_HOSTNAME",
+    "AUDIT_LOG", "AUTH_MODE", "AUTH_PATHS", "AUTH_REALM", "AUTH_USERS_FILE", "BANNED_IPS_FILE",
+    "BANNED_IPS_WATCH", "BAN_ESCALATION", "BAN_ESCALATION_RESET", "BAN_HISTORY_FILE",
+    "BLOCKED_TLS_FINGERPRINTS", "BLOCK_REASON_HEADER", "BLOCK_REDIRECT_URL", "BLOCK_STATUS",
+    "BLOCK_TOR", "BOT_HEADER_ORDERS", "BOT_HEADER_ORDER_HEADER", "BOT_PROTOCOL_HEADER",
+    "BOT_SCORE_BAN", "BOT_SCORE_CHALLENGE", "BOT_USER_AGENTS", "CACHE_TTL_SECS", "CATEGORY_RULES",
+    "CHALLENGE", "CHALLENGE_DIFFICULTY", "CHALLENGE_PATH", "CHALLENGE_SECRET_KEY",
+    "CHALLENGE_SITE_KEY", "CHALLENGE_THRESHOLD", "CHALLENGE_TTL", "CHALLENGE_VERIFY_URL",
+    "CLIENT_IP_SOURCES", "CLOUDFLARE_IPS", "CLOUDFLARE_IPS_REFRESH", "CLOUDFLARE_IPS_URLS",
+    "CLOUDFLARE_VERIFY", "CONFIG_VALIDATION", "COOKIE_ENCRYPT", "COOKIE_SECRET", "CROWDSEC_API_KEY",
+    "CROWDSEC_LAPI_URL", "CROWDSEC_MACHINE_ID", "CROWDSEC_MACHINE_PASSWORD", "CROWDSEC_POLL_SECS",
+    "DECISION_CACHE_SIZE", "DECISION_CACHE_TTL", "DNSBL_CACHE_TTL", "DNSBL_MAX_CACHED",
+    "DNSBL_RESOLVER", "DNSBL_TIMEOUT_MS", "DNSBL_ZONES", "ENFORCE", "EXT_AUTHZ_PORT", "FAIL_MODE",
+    "GEOIP_ASN_DB", "GEOIP_COUNTRY_DB", "GOSSIP_BIND", "GOSSIP_PEERS", "GOSSIP_SECRET", "GREYLIST",
+    "GREYLIST_DELAY", "GREYLIST_TTL", "HEADER_RULES", "HONEYPOT_BAN_SECS", "HONEYPOT_PATHS",
+    "HTTP2_MAX_CONCURRENT_STREAMS", "HTTP_KEEPALIVE_TIMEOUT", "HTTP_VERSION", "IPV6_MODE",
+    "IP_STATS_CAPACITY", "JOURNALD_SOCKET", "KV_BACKEND", "KV_PREFIX", "KV_TOKEN", "KV_URL",
+    "LDAP_BASE_DN", "LDAP_GROUPS", "LDAP_STARTTLS", "LDAP_TIMEOUT", "LDAP_URL", "LDAP_USER_DN",
+    "LDAP_USER_FILTER", "LISTEN", "LOCKDOWN_ALLOWLIST", "LOCKDOWN_DURATION",
+    "LOCKDOWN_MAX_DURATION", "LOG_DIR", "LOG_FILE", "LOG_MAX_FILES", "LOG_ROTATION", "LOG_TARGET",
+    "LOKI_BATCH_SECS", "LOKI_BATCH_SIZE", "LOKI_LABELS", "LOKI_TENANT", "LOKI_URL", "MAINTENANCE",
+    "MAINTENANCE_ALLOWLIST", "MAINTENANCE_PAGE", "MAINTENANCE_RETRY_AFTER", "MAX_BODY_BYTES",
+    "MAX_INFLIGHT", "MAX_INFLIGHT_PER_IP", "OPA_FAIL_OPEN", "OPA_TIMEOUT", "OPA_URL", "PLUGIN_DIR",
+    "PLUGIN_MEMORY_MB", "POLICIES", "POLICY_HEADER", "PORT", "PROXY_PROTOCOL",
+    "PROXY_PROTOCOL_TRUSTED", "QUERY_RULES",
+    "RATE_LIMIT", "RATE_LIMIT_ALGORITHM", "RATE_LIMIT_REDIS", "RATE_LIMIT_REDIS_TIMEOUT_MS",
+    "RATE_LIMIT_RULES", "RDNS", "RDNS_CACHE_TTL", "RDNS_MAX_CACHED", "RDNS_RATE", "RDNS_RESOLVER",
+    "REDIS_CHANNEL", "REDIS_PASSWORD", "REDIS_URL", "REMOTE_LISTS", "REMOTE_LISTS_REFRESH_SECS",
+    "REQUEST_TIMEOUT_MS", "SCHEDULE_RULES", "SCHEDULE_TIMEZONE", "SCRIPT_FILE", "SESSION_TTL",
+    "SIGNATURE_PATHS", "SIGNATURE_SECRET", "SIGNATURE_WINDOW", "STATE_SNAPSHOT_FILE",
+    "STATE_SNAPSHOT_INTERVAL", "SWEEP_INTERVAL", "SYSLOG_ADDR", "SYSLOG_FACILITY",
+    "TARPIT_DELAY_SECS", "TARPIT_MAX_CONCURRENT", "TCP_BACKLOG", "TLS_FINGERPRINT_HEADERS",
+    "TOP_STATS_RETENTION", "TOR_EXIT_LIST_URL", "TOR_REFRESH_SECS", "TOTP_PATH", "TOTP_PATHS",
+    "TOTP_STORE_FILE", "TRUSTED_HOPS", "TRUSTED_PROXIES", "UPSTREAM_AUTH_TIMEOUT",
+    "UPSTREAM_AUTH_URL", "VAULT_ADDR", "VAULT_K8S_MOUNT", "VAULT_K8S_ROLE", "VAULT_K8S_TOKEN_FILE",
+    "VAULT_NAMESPACE", "VAULT_SECRETS", "VAULT_TOKEN", "WEBHOOK_BATCH_SECS", "WEBHOOK_BATCH_SIZE",
+    "WEBHOOK_FORMAT", "WEBHOOK_MAX_RETRIES", "WEBHOOK_URL",
+];
+
+/// `TEZ_*` variables set in the environment that aren't configuration
+/// variables (or their `_FILE` variants), e.g. a misspelled
+/// `TEZ_CACHE_TTL_SEC`.
+pub fn unknown_vars() -> Vec<String> {
+    let known = |name: &str| {
+        VARS.contains(&name) || name.strip_suffix("_FILE").is_some_and(|n| VARS.contains(&n))
+    };
+    let mut unknown: Vec<String> = env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| name.strip_prefix(PREFIX).is_some_and(|name| !known(name)))
+        .collect();
+    unknown.sort();
+    unknown
+}
+
+/// The known variable closest to a misspelled one, if any is close enough.
+pub fn suggest(name: &str) -> Option<String> {
+    let name = name.strip_prefix(PREFIX).unwrap_or(name);
+    let (name, suffix) = match name.strip_suffix("_FILE") {
+        Some(stem) => (stem, "_FILE"),
+        None => (name, ""),
+    };
+    VARS.iter()
+        .map(|known| (edit_distance(name, known), known))
+        .filter(|(distance, known)| *distance <= (known.len() / 4).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| format!("{}{}{}", PREFIX, known, suffix))
+}
+
+/// Levenshtein distance between two ASCII names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.bytes().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Reads environment variables and records every value that fails to parse,
+/// so all problems can be reported at once instead of one per restart.
+///
+/// Secrets fetched from Vault take precedence over the environment, and
+/// prefixed names over unprefixed ones.
+struct EnvReader<'a> {
+    issues: Vec<ConfigIssue>,
+    secrets: &'a HashMap<String, String>,
+}
+
+impl<'a> EnvReader<'a> {
+    fn new(secrets: &'a HashMap<String, String>) -> Self {
         Self {
-            banned_ips_file,
+            issues: Vec::new(),
+            secrets,
+        }
+    }
+
+    /// Raw value of a variable, `None` when unset.
+    ///
+    /// When the variable itself is unset, `<NAME>_FILE` names a file holding
+    /// the value (e.g. a Docker or Kubernetes secret); a trailing newline is
+    /// dropped. Setting both is a problem.
+    fn var(&mut self, name: &str) -> Option<String> {
+        debug_assert!(VARS.contains(&name), "{} is missing from config::VARS", name);
+        let prefixed = format!("{}{}", PREFIX, name);
+        if let Some(secret) = self.secrets.get(&prefixed).or_else(|| self.secrets.get(name)) {
+            return Some(secret.clone());
+        }
+        let value = env::var(&prefixed).ok().or_else(|| env::var(name).ok());
+        let file = [format!("{}_FILE", prefixed), format!("{}_FILE", name)]
+            .into_iter()
+            .find_map(|var| {
+                let path = env::var(&var).ok().filter(|p| !p.trim().is_empty())?;
+                Some((var, path))
+            });
+        match (value, file) {
+            (value, None) => value,
+            (Some(value), Some((var, path))) => {
+                self.invalid(&var, &path, &format!("unset when {} is set", name));
+                Some(value)
+            }
+            (None, Some((var, path))) => match std::fs::read_to_string(path.trim()) {
+                Ok(content) => Some(content.trim_end_matches(['\r', '\n']).to_string()),
+                Err(e) => {
+                    self.invalid(&var, &path, &format!("a readable file ({})", e));
+                    None
+                }
+            },
+        }
+    }
+
+    fn string(&mut self, name: &str, default: &str) -> String {
+        self.var(name).unwrap_or_else(|| default.to_string())
+    }
+
+    /// Variable that is unset or empty yields `None`.
+    fn optional(&mut self, name: &str) -> Option<String> {
+        self.var(name).filter(|s| !s.trim().is_empty())
+    }
+
+    /// Records a problem that isn't tied to parsing a single value.
+    fn invalid(&mut self, name: &str, value: &str, expected: &str) {
+        self.issues.push(ConfigIssue {
+            var: name.to_string(),
+            value: value.to_string(),
+            expected: expected.to_string(),
+        });
+    }
+
+    /// Comma-separated list; empty items are dropped.
+    fn list(&mut self, name: &str, default: &[&str]) -> Vec<String> {
+        match self.var(name) {
+            Some(raw) => raw
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => default.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn bool(&mut self, name: &str, default: bool) -> bool {
+        self.parse_with(name, default, "a boolean (true/false)", parse_bool)
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, default: T, expected: &str) -> T {
+        self.parse_with(name, default, expected, |s| s.parse().ok())
+    }
+
+    /// Parse a variable with a custom parser; `None` marks the value as invalid.
+    fn parse_with<T>(
+        &mut self,
+        name: &str,
+        default: T,
+        expected: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> T {
+        let Some(raw) = self.var(name) else {
+            return default;
+        };
+        match parse(raw.trim()) {
+            Some(value) => value,
+            None => {
+                self.issues.push(ConfigIssue {
+                    var: name.to_string(),
+                    value: raw,
+                    expected: expected.to_string(),
+                });
+                default
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from environment variables with defaults.
+    ///
+    /// Every variable is checked before returning. With `CONFIG_VALIDATION=strict`
+    /// (the default) any invalid value is an error listing all problems; with
+    /// `CONFIG_VALIDATION=warn` invalid values fall back to their defaults and
+    /// are kept in `validation_warnings` so they can be logged once logging is up.
+    pub fn from_env() -> error::Result<Self> {
+        Self::from_env_with_secrets(&HashMap::new())
+    }
+
+    /// Like [`Config::from_env`], with `secrets` (variable name to value,
+    /// e.g. fetched from Vault) taking precedence over the environment.
+    pub fn from_env_with_secrets(secrets: &HashMap<String, String>) -> error::Result<Self> {
+        let mut env = EnvReader::new(secrets);
+
+        let validation_mode = env.parse_with(
+            "CONFIG_VALIDATION",
+            ValidationMode::Strict,
+            "one of: strict, warn",
+            |s| match s.to_lowercase().as_str() {
+                "strict" => Some(ValidationMode::Strict),
+                "warn" => Some(ValidationMode::Warn),
+                _ => None,
+            },
+        );
+
+        let banned_ips_files = env.list("BANNED_IPS_FILE", &["./banned-ips.txt"]);
+
+        let client_ip_sources = env.parse_with(
+            "CLIENT_IP_SOURCES",
+            IpSource::defaults(),
+            "a comma-separated list of header names and `socket`",
+            IpSource::parse_list,
+        );
+
+        let mut trusted_proxies = Vec::new();
+        for item in env.list("TRUSTED_PROXIES", &[]) {
+            match lists::parse_entry(&item) {
+                Some(net) => trusted_proxies.push(net),
+                None => env.invalid("TRUSTED_PROXIES", &item, "comma-separated IPs or CIDRs"),
+            }
+        }
+        let trusted_hops = env.parse("TRUSTED_HOPS", 0usize, "a whole number of proxies");
+        if trusted_hops > 0 && !trusted_proxies.is_empty() {
+            env.invalid("TRUSTED_HOPS", &trusted_hops.to_string(), "0 with TRUSTED_PROXIES set");
+        }
+
+        let cloudflare_verify = env.bool("CLOUDFLARE_VERIFY", true);
+        let mut cloudflare_ips = Vec::new();
+        for item in env.list("CLOUDFLARE_IPS", client_ip::CLOUDFLARE_RANGES) {
+            match item.parse() {
+                Ok(net) => cloudflare_ips.push(net),
+                Err(_) => env.invalid("CLOUDFLARE_IPS", &item, "comma-separated CIDRs"),
+            }
+        }
+
+        let cloudflare_ips_urls = env.list("CLOUDFLARE_IPS_URLS", cloudflare::DEFAULT_URLS);
+        let cloudflare_ips_refresh = env.parse_with(
+            "CLOUDFLARE_IPS_REFRESH",
+            Some(Duration::from_secs(24 * 3600)),
+            "a duration such as 12h or 1d (0 disables)",
+            |s| parse_duration(s).map(|d| Some(d).filter(|d| !d.is_zero())),
+        );
+
+        let cache_ttl_secs = env.parse_with(
+            "CACHE_TTL_SECS",
+            5,
+            "a whole number of seconds greater than 0",
+            |s| s.parse::<u64>().ok().filter(|&n| n > 0),
+        );
+
+        let banned_ips_watch = env.bool("BANNED_IPS_WATCH", false);
+
+        let log_file = env.string("LOG_FILE", "./traefik-auth.log");
+
+        let log_dir = env.string("LOG_DIR", ".");
+
+        let log_rotation = env.parse_with(
+            "LOG_ROTATION",
+            LogRotation::Daily,
+            "one of: hourly, daily, never",
+            |s| match s.to_lowercase().as_str() {
+                "hourly" => Some(LogRotation::Hourly),
+                "daily" => Some(LogRotation::Daily),
+                "never" => Some(LogRotation::Never),
+                _ => None,
+            },
+        );
+
+        let log_max_files = env.parse("LOG_MAX_FILES", 7usize, "a non-negative whole number");
+
+        let log_target = match env.string("LOG_TARGET", "file").to_lowercase().as_str() {
+            "file" => LogTarget::File,
+            "syslog" => {
+                let (transport, address) = env.parse_with(
+                    "SYSLOG_ADDR",
+                    (SyslogTransport::Unix, "/dev/log".to_string()),
+                    "udp://host:port, tcp://host:port or unix:///path",
+                    SyslogConfig::parse_address,
+                );
+                let facility = env.parse_with(
+                    "SYSLOG_FACILITY",
+                    3,
+                    "a facility name such as daemon, auth or local0-local7",
+                    SyslogConfig::parse_facility,
+                );
+                LogTarget::Syslog(SyslogConfig {
+                    transport,
+                    address,
+                    facility,
+                })
+            }
+            "journald" => LogTarget::Journald {
+                socket: env.string("JOURNALD_SOCKET", "/run/systemd/journal/socket"),
+            },
+            "eventlog" => LogTarget::EventLog,
+            other => {
+                env.invalid("LOG_TARGET", other, "one of: file, syslog, journald, eventlog");
+                LogTarget::File
+            }
+        };
+
+        let hostname = env.string("APP_HOSTNAME", "0.0.0.0");
+
+        let port = env.parse_with("PORT", 8199, "a port number between 1 and 65535", |s| {
+            s.parse::<u16>().ok().filter(|&p| p != 0)
+        });
+
+        let mut listen = Vec::new();
+        for item in env.list("LISTEN", &[]) {
+            match Listen::parse(&item) {
+                Some(entry) => listen.push(entry),
+                None => env.invalid("LISTEN", &item, "comma-separated host:port[=all|auth|admin]"),
+            }
+        }
+        if listen.is_empty() {
+            let host = if hostname.contains(':') && !hostname.starts_with('[') {
+                format!("[{}]", hostname)
+            } else {
+                hostname.clone()
+            };
+            listen.push(Listen {
+                addr: format!("{}:{}", host, port),
+                routes: Routes::All,
+            });
+        }
+
+        let proxy_protocol = env.bool("PROXY_PROTOCOL", false);
+        let mut proxy_protocol_trusted = Vec::new();
+        for item in env.list("PROXY_PROTOCOL_TRUSTED", &[]) {
+            match lists::parse_entry(&item) {
+                Some(net) => proxy_protocol_trusted.push(net),
+                None => env.invalid("PROXY_PROTOCOL_TRUSTED", &item, "comma-separated IPs or CIDRs"),
+            }
+        }
+        if proxy_protocol_trusted.is_empty() {
+            proxy_protocol_trusted = trusted_proxies.clone();
+        }
+        if proxy_protocol && proxy_protocol_trusted.is_empty() {
+            env.invalid(
+                "PROXY_PROTOCOL_TRUSTED",
+                "",
+                "the load balancer's IPs or CIDRs (or TRUSTED_PROXIES) with PROXY_PROTOCOL=true",
+            );
+        }
+
+        let http_version = env.parse_with(
+            "HTTP_VERSION",
+            HttpVersion::Auto,
+            "one of: auto, http1, http2",
+            |s| match s.to_lowercase().as_str() {
+                "auto" => Some(HttpVersion::Auto),
+                "http1" => Some(HttpVersion::Http1),
+                "http2" => Some(HttpVersion::Http2),
+                _ => None,
+            },
+        );
+
+        let keep_alive_timeout = env.parse_with(
+            "HTTP_KEEPALIVE_TIMEOUT",
+            Some(Duration::from_secs(120)),
+            "a duration such as 120, 90s or 5m (0 disables keep-alive)",
+            |s| parse_duration(s).map(|d| Some(d).filter(|d| !d.is_zero())),
+        );
+
+        let http2_max_concurrent_streams = env.parse_with(
+            "HTTP2_MAX_CONCURRENT_STREAMS",
+            200,
+            "a whole number greater than 0",
+            |s| s.parse::<u32>().ok().filter(|&n| n > 0),
+        );
+
+        let tcp_backlog = env.parse_with(
+            "TCP_BACKLOG",
+            1024,
+            "a whole number greater than 0",
+            |s| s.parse::<u32>().ok().filter(|&n| n > 0),
+        );
+
+        let ipv6_mode = env.parse_with(
+            "IPV6_MODE",
+            Ipv6Mode::Dual,
+            "one of: dual, v6only, separate",
+            |s| match s.to_lowercase().as_str() {
+                "dual" => Some(Ipv6Mode::Dual),
+                "v6only" => Some(Ipv6Mode::V6Only),
+                "separate" => Some(Ipv6Mode::Separate),
+                _ => None,
+            },
+        );
+
+        let max_body_bytes = env.parse_with(
+            "MAX_BODY_BYTES",
+            Some(65_536),
+            "a non-negative whole number of bytes (0 disables)",
+            |s| s.parse::<usize>().ok().map(|n| Some(n).filter(|&n| n > 0)),
+        );
+
+        let max_inflight = env.parse_with(
+            "MAX_INFLIGHT",
+            Some(10_000),
+            "a non-negative whole number (0 disables)",
+            |s| s.parse::<usize>().ok().map(|n| Some(n).filter(|&n| n > 0)),
+        );
+
+        let max_inflight_per_ip = env.parse_with(
+            "MAX_INFLIGHT_PER_IP",
+            None,
+            "a non-negative whole number (0 disables)",
+            |s| s.parse::<usize>().ok().map(|n| Some(n).filter(|&n| n > 0)),
+        );
+
+        let request_timeout = env.parse_with(
+            "REQUEST_TIMEOUT_MS",
+            Some(Duration::from_secs(30)),
+            "a whole number of milliseconds (0 disables)",
+            |s| {
+                s.parse::<u64>()
+                    .ok()
+                    .map(|ms| Some(Duration::from_millis(ms)).filter(|d| !d.is_zero()))
+            },
+        );
+
+        let enforce = env.bool("ENFORCE", true);
+        let fail_mode = env.parse_with(
+            "FAIL_MODE",
+            FailMode::Open,
+            "one of: open, closed",
+            |s| match s.to_lowercase().as_str() {
+                "open" => Some(FailMode::Open),
+                "closed" => Some(FailMode::Closed),
+                _ => None,
+            },
+        );
+
+        let block_redirect = env.optional("BLOCK_REDIRECT_URL").and_then(|url| {
+            if url.starts_with("https://") || url.starts_with("http://") || url.starts_with('/') {
+                Some(url)
+            } else {
+                env.invalid("BLOCK_REDIRECT_URL", &url, "an http(s) URL or an absolute path");
+                None
+            }
+        });
+        let block_status = env.parse_with(
+            "BLOCK_STATUS",
+            if block_redirect.is_some() { 302 } else { 403 },
+            "one of: 403, 404, 429, 302",
+            |s| s.parse::<u16>().ok().filter(|c| BlockResponse::STATUSES.contains(c)),
+        );
+        let block_response = match (block_status, block_redirect) {
+            (302, Some(url)) => BlockResponse::Redirect(url),
+            (302, None) => {
+                env.invalid("BLOCK_STATUS", "302", "BLOCK_REDIRECT_URL to be set for 302");
+                BlockResponse::Status(403)
+            }
+            (status, _) => BlockResponse::Status(status),
+        };
+        let block_reason_header = env.bool("BLOCK_REASON_HEADER", false);
+        let category_rules = env.parse_with(
+            "CATEGORY_RULES",
+            Vec::new(),
+            "rules such as \"blog.example.com=-tor;/admin=*\" separated by ;",
+            CategoryRule::parse_list,
+        );
+
+        let schedule_rules = env.parse_with(
+            "SCHEDULE_RULES",
+            Vec::new(),
+            "rules such as \"staging.example.com=mon-fri 08:00-18:00\" separated by ;",
+            |s| parse_rules(s, ScheduleRule::parse),
+        );
+        let schedule_timezone = env.parse_with(
+            "SCHEDULE_TIMEZONE",
+            Tz::UTC,
+            "an IANA time zone name such as Europe/Berlin",
+            |s| s.parse::<Tz>().ok(),
+        );
+        let rate_limit = env.optional("RATE_LIMIT").and_then(|raw| {
+            let rate = Rate::parse(&raw);
+            if rate.is_none() {
+                env.invalid("RATE_LIMIT", &raw, "requests per period such as 100/s or 5/min");
+            }
+            rate
+        });
+        let rate_limit_rules = env.parse_with(
+            "RATE_LIMIT_RULES",
+            Vec::new(),
+            "rules such as \"/login=5/min;/health=off\" separated by ;",
+            |s| parse_rules(s, RateLimitRule::parse),
+        );
+        let rate_limit_algorithm = env.parse_with(
+            "RATE_LIMIT_ALGORITHM",
+            Algorithm::TokenBucket,
+            "one of: token-bucket, fixed-window, sliding-log",
+            Algorithm::parse,
+        );
+        let header_rules = match env.optional("HEADER_RULES") {
+            Some(raw) => HeaderRules::parse(&raw).unwrap_or_else(|e| {
+                let expected = format!(
+                    "rules such as {:?} separated by ; ({})",
+                    "!x-internal-token;x-requested-with=BotNet", e
+                );
+                env.invalid("HEADER_RULES", &raw, &expected);
+                HeaderRules::default()
+            }),
+            None => HeaderRules::default(),
+        };
+        let tls_fingerprint_headers = env
+            .list("TLS_FINGERPRINT_HEADERS", fingerprint::DEFAULT_HEADERS)
+            .into_iter()
+            .filter_map(|name| match HeaderName::try_from(name.to_ascii_lowercase()) {
+                Ok(name) => Some(name),
+                Err(_) => {
+                    env.invalid("TLS_FINGERPRINT_HEADERS", &name, "HTTP header names");
+                    None
+                }
+            })
+            .collect();
+        let blocked_tls_fingerprints = env
+            .optional("BLOCKED_TLS_FINGERPRINTS")
+            .map(|list| fingerprint::parse_list(&list))
+            .unwrap_or_default();
+
+        let policies = match env.optional("POLICIES") {
+            Some(json) => match policies::parse(&json) {
+                Ok(policies) => policies,
+                Err(e) => {
+                    env.invalid("POLICIES", &json, &format!("a JSON object of policies ({})", e));
+                    BTreeMap::new()
+                }
+            },
+            None => BTreeMap::new(),
+        };
+        let policy_header = env.parse_with(
+            "POLICY_HEADER",
+            policies::DEFAULT_HEADER.to_string(),
+            "an HTTP header name such as X-Auth-Policy",
+            |s| HeaderName::from_bytes(s.as_bytes()).ok().map(|h| h.to_string()),
+        );
+
+        let maintenance = env.bool("MAINTENANCE", false);
+        let mut maintenance_allowlist = Vec::new();
+        for item in env.list("MAINTENANCE_ALLOWLIST", &[]) {
+            match lists::parse_entry(&item) {
+                Some(net) => maintenance_allowlist.push(net),
+                None => env.invalid("MAINTENANCE_ALLOWLIST", &item, "comma-separated IPs or CIDRs"),
+            }
+        }
+        let maintenance_page = env.optional("MAINTENANCE_PAGE");
+        let maintenance_retry_after = env.parse_with(
+            "MAINTENANCE_RETRY_AFTER",
+            Duration::from_secs(300),
+            "a duration such as 300, 5m or 1h",
+            parse_duration,
+        );
+
+        let mut lockdown_allowlist = Vec::new();
+        for item in env.list("LOCKDOWN_ALLOWLIST", &[]) {
+            match lists::parse_entry(&item) {
+                Some(net) => lockdown_allowlist.push(net),
+                None => env.invalid("LOCKDOWN_ALLOWLIST", &item, "comma-separated IPs or CIDRs"),
+            }
+        }
+        let lockdown_max_duration = env.parse_with(
+            "LOCKDOWN_MAX_DURATION",
+            Duration::from_secs(24 * 3600),
+            "a non-zero duration such as 30m or 24h",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+        let lockdown_duration = env.parse_with(
+            "LOCKDOWN_DURATION",
+            Duration::from_secs(3600).min(lockdown_max_duration),
+            "a non-zero duration no longer than LOCKDOWN_MAX_DURATION",
+            |s| parse_duration(s).filter(|d| !d.is_zero() && *d <= lockdown_max_duration),
+        );
+
+        let tarpit_delay = env.parse_with(
+            "TARPIT_DELAY_SECS",
+            None,
+            "seconds as \"N\" or a range \"MIN-MAX\" (0 disables)",
+            |s| DelayRange::parse_secs(s).map(|d| Some(d).filter(|d| !d.max.is_zero())),
+        );
+
+        let tarpit_max_concurrent = env.parse_with(
+            "TARPIT_MAX_CONCURRENT",
+            1000,
+            "a whole number greater than 0",
+            |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+        );
+
+        let honeypot_paths = env.list("HONEYPOT_PATHS", &[]);
+
+        let honeypot_ban_secs = env.parse(
+            "HONEYPOT_BAN_SECS",
+            86400u64,
+            "a whole number of seconds (0 bans permanently)",
+        );
+
+        let query_rules = match env.optional("QUERY_RULES") {
+            Some(raw) => QueryRules::parse(&raw).unwrap_or_else(|e| {
+                let expected = format!(
+                    "rules such as {:?} separated by ; ({})",
+                    "cmd;action=shell;~(?i)union select", e
+                );
+                env.invalid("QUERY_RULES", &raw, &expected);
+                QueryRules::default()
+            }),
+            None => QueryRules::default(),
+        };
+
+        let ban_escalation = env.optional("BAN_ESCALATION").map_or_else(Vec::new, |raw| {
+            let steps: Option<Vec<_>> = raw
+                .split(',')
+                .map(|step| match step.trim().to_lowercase().as_str() {
+                    "permanent" => Some(None),
+                    step => parse_duration(step).filter(|d| !d.is_zero()).map(Some),
+                })
+                .collect();
+            steps.unwrap_or_else(|| {
+                env.invalid(
+                    "BAN_ESCALATION",
+                    &raw,
+                    "comma-separated durations or \"permanent\", e.g. 1h,6h,24h,permanent",
+                );
+                Vec::new()
+            })
+        });
+
+        let ban_escalation_reset = env.parse_with(
+            "BAN_ESCALATION_RESET",
+            Duration::from_secs(30 * 86400),
+            "a duration such as 86400, 12h or 30d",
+            parse_duration,
+        );
+
+        let ban_history_file = env.optional("BAN_HISTORY_FILE");
+
+        let state_snapshot_file = env.optional("STATE_SNAPSHOT_FILE");
+        let state_snapshot_interval = env.parse_with(
+            "STATE_SNAPSHOT_INTERVAL",
+            Duration::from_secs(60),
+            "a positive duration such as 30, 5m or 1h",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+
+        let sweep_interval = env.parse_with(
+            "SWEEP_INTERVAL",
+            Duration::from_secs(60),
+            "a positive duration such as 30, 5m or 1h",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+
+        let redis = env.optional("REDIS_URL").and_then(|url| {
+            let mut url = url.trim().to_string();
+            if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+                env.invalid("REDIS_URL", &url, "a redis:// or rediss:// URL");
+                return None;
+            }
+            // Kept out of the URL so it can come from REDIS_PASSWORD_FILE
+            if let Some(password) = env.optional("REDIS_PASSWORD") {
+                let with_password = reqwest::Url::parse(&url).ok().and_then(|mut parsed| {
+                    parsed.set_password(Some(password.trim())).ok()?;
+                    Some(parsed.to_string())
+                });
+                match with_password {
+                    Some(with_password) => url = with_password,
+                    None => env.invalid("REDIS_URL", &url, "a URL with a host to use REDIS_PASSWORD"),
+                }
+            }
+            Some(RedisConfig {
+                url,
+                channel: env.string("REDIS_CHANNEL", "tezcatlipoca:bans"),
+            })
+        });
+
+        let rate_limit_redis = if !env.bool("RATE_LIMIT_REDIS", false) {
+            None
+        } else if !cfg!(feature = "redis") {
+            env.invalid("RATE_LIMIT_REDIS", "true", "false in a build without the redis feature");
+            None
+        } else if redis.is_none() {
+            env.invalid("RATE_LIMIT_REDIS", "true", "false without REDIS_URL");
+            None
+        } else {
+            Some(Duration::from_millis(env.parse_with(
+                "RATE_LIMIT_REDIS_TIMEOUT_MS",
+                50,
+                "a number of milliseconds greater than 0",
+                |s| s.parse().ok().filter(|&ms| ms > 0),
+            )))
+        };
+
+        let kv = env.optional("KV_BACKEND").and_then(|raw| {
+            let backend = match raw.trim().to_lowercase().as_str() {
+                "consul" => KvBackend::Consul,
+                "etcd" => KvBackend::Etcd,
+                _ => {
+                    env.invalid("KV_BACKEND", &raw, "consul or etcd");
+                    return None;
+                }
+            };
+            let url = env.optional("KV_URL");
+            let url = url.as_deref().unwrap_or(backend.default_url());
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                env.invalid("KV_URL", url, "an http:// or https:// URL");
+                return None;
+            }
+            Some(KvConfig {
+                backend,
+                url: url.trim().trim_end_matches('/').to_string(),
+                prefix: env.string("KV_PREFIX", "tezcatlipoca/"),
+                token: env.optional("KV_TOKEN"),
+            })
+        });
+
+        let gossip_bind = env.parse_with(
+            "GOSSIP_BIND",
+            None,
+            "a socket address such as 0.0.0.0:7946",
+            |s| match s {
+                "" => Some(None),
+                s => s.parse::<SocketAddr>().ok().map(Some),
+            },
+        );
+        let gossip = gossip_bind.and_then(|bind| {
+            let peers = env.list("GOSSIP_PEERS", &[]);
+            if peers.is_empty() {
+                env.invalid("GOSSIP_PEERS", "", "at least one host:port when GOSSIP_BIND is set");
+            }
+            match env.optional("GOSSIP_SECRET") {
+                Some(secret) if secret.len() >= 16 => Some(GossipConfig {
+                    bind,
+                    peers,
+                    secret,
+                }),
+                secret => {
+                    env.invalid(
+                        "GOSSIP_SECRET",
+                        &"*".repeat(secret.map_or(0, |s| s.len())),
+                        "a shared secret of at least 16 characters when GOSSIP_BIND is set",
+                    );
+                    None
+                }
+            }
+        });
+
+        let greylist = env.bool("GREYLIST", false);
+
+        let greylist_delay = env.parse_with(
+            "GREYLIST_DELAY",
+            Duration::from_secs(10),
+            "a duration such as 10, 30s or 2m (greater than 0)",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+
+        let greylist_ttl = env.parse_with(
+            "GREYLIST_TTL",
+            Duration::from_secs(86400),
+            "a duration such as 3600, 12h or 7d (greater than 0)",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+
+        let crowdsec = env.optional("CROWDSEC_LAPI_URL").and_then(|lapi_url| {
+            let api_key = env.optional("CROWDSEC_API_KEY");
+            if api_key.is_none() {
+                env.invalid(
+                    "CROWDSEC_API_KEY",
+                    "",
+                    "a bouncer API key when CROWDSEC_LAPI_URL is set",
+                );
+            }
+            let poll_secs = env.parse_with(
+                "CROWDSEC_POLL_SECS",
+                10,
+                "a whole number of seconds greater than 0",
+                |s| s.parse::<u64>().ok().filter(|&n| n > 0),
+            );
+            Some(CrowdSecConfig {
+                lapi_url: lapi_url.trim().trim_end_matches('/').to_string(),
+                api_key: api_key?,
+                poll_interval: Duration::from_secs(poll_secs),
+                machine_id: env.optional("CROWDSEC_MACHINE_ID"),
+                machine_password: env.optional("CROWDSEC_MACHINE_PASSWORD"),
+            })
+        });
+
+        let abuseipdb = env.optional("ABUSEIPDB_API_KEY").map(|api_key| AbuseIpDbConfig {
+            api_key,
+            threshold: env.parse_with(
+                "ABUSEIPDB_THRESHOLD",
+                75,
+                "a confidence score between 1 and 100",
+                |s| s.parse::<u8>().ok().filter(|n| (1..=100).contains(n)),
+            ),
+            cache_ttl: Duration::from_secs(env.parse(
+                "ABUSEIPDB_CACHE_TTL_SECS",
+                86400u64,
+                "a whole number of seconds",
+            )),
+            max_age_days: env.parse_with(
+                "ABUSEIPDB_MAX_AGE_DAYS",
+                90,
+                "a number of days between 1 and 365",
+                |s| s.parse::<u16>().ok().filter(|n| (1..=365).contains(n)),
+            ),
+            max_cached: env.parse_with(
+                "ABUSEIPDB_MAX_CACHED",
+                100_000,
+                "a whole number greater than 0",
+                |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+            ),
+        });
+
+        let dnsbl_zones: Vec<String> = env
+            .list("DNSBL_ZONES", &[])
+            .into_iter()
+            .map(|zone| zone.trim_matches('.').to_ascii_lowercase())
+            .collect();
+        let dnsbl = if dnsbl_zones.is_empty() {
+            None
+        } else if !cfg!(feature = "dns") {
+            let value = dnsbl_zones.join(",");
+            env.invalid("DNSBL_ZONES", &value, "empty in a build without the dns feature");
+            None
+        } else {
+            Some(DnsblConfig {
+                zones: dnsbl_zones,
+                resolver: env.parse_with(
+                    "DNSBL_RESOLVER",
+                    None,
+                    "an ip:port address such as 127.0.0.1:53",
+                    |s| s.parse::<SocketAddr>().ok().map(Some),
+                ),
+                timeout: Duration::from_millis(env.parse_with(
+                    "DNSBL_TIMEOUT_MS",
+                    2000,
+                    "a whole number of milliseconds greater than 0",
+                    |s| s.parse::<u64>().ok().filter(|&n| n > 0),
+                )),
+                cache_ttl: env.parse_with(
+                    "DNSBL_CACHE_TTL",
+                    Duration::from_secs(3600),
+                    "a duration such as 3600, 1h or 1d",
+                    parse_duration,
+                ),
+                max_cached: env.parse_with(
+                    "DNSBL_MAX_CACHED",
+                    100_000,
+                    "a whole number greater than 0",
+                    |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+                ),
+            })
+        };
+
+        let geoip_country_db = env.optional("GEOIP_COUNTRY_DB");
+        let geoip_asn_db = env.optional("GEOIP_ASN_DB");
+
+        let rdns = if !env.bool("RDNS", false) {
+            None
+        } else if !cfg!(feature = "dns") {
+            env.invalid("RDNS", "true", "false in a build without the dns feature");
+            None
+        } else {
+            Some(RdnsConfig {
+                resolver: env.parse_with(
+                    "RDNS_RESOLVER",
+                    None,
+                    "an ip:port address such as 127.0.0.1:53",
+                    |s| s.parse::<SocketAddr>().ok().map(Some),
+                ),
+                rate: env.parse_with(
+                    "RDNS_RATE",
+                    10,
+                    "a number of lookups per second between 1 and 1000",
+                    |s| s.parse::<u32>().ok().filter(|n| (1..=1000).contains(n)),
+                ),
+                cache_ttl: env.parse_with(
+                    "RDNS_CACHE_TTL",
+                    Duration::from_secs(3600),
+                    "a duration such as 3600, 1h or 1d",
+                    parse_duration,
+                ),
+                max_cached: env.parse_with(
+                    "RDNS_MAX_CACHED",
+                    10_000,
+                    "a whole number greater than 0",
+                    |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+                ),
+            })
+        };
+
+        let challenge = env.optional("CHALLENGE").and_then(|raw| {
+            let provider = match raw.trim().to_lowercase().as_str() {
+                "turnstile" => Some(CaptchaProvider::Turnstile),
+                "hcaptcha" => Some(CaptchaProvider::HCaptcha),
+                "pow" => None,
+                _ => {
+                    env.invalid("CHALLENGE", &raw, "one of: turnstile, hcaptcha, pow");
+                    return None;
+                }
+            };
+            let mode = match provider {
+                Some(provider) => {
+                    let site_key = env.optional("CHALLENGE_SITE_KEY");
+                    let secret_key = env.optional("CHALLENGE_SECRET_KEY");
+                    for (var, value) in [
+                        ("CHALLENGE_SITE_KEY", &site_key),
+                        ("CHALLENGE_SECRET_KEY", &secret_key),
+                    ] {
+                        if value.is_none() {
+                            env.invalid(var, "", "the captcha provider's key when CHALLENGE is set");
+                        }
+                    }
+                    ChallengeMode::Captcha {
+                        provider,
+                        site_key: site_key?,
+                        secret_key: secret_key?,
+                        verify_url: env.string("CHALLENGE_VERIFY_URL", provider.verify_url()),
+                    }
+                }
+                None => ChallengeMode::ProofOfWork {
+                    difficulty: env.parse_with(
+                        "CHALLENGE_DIFFICULTY",
+                        16,
+                        "a number of leading zero bits between 1 and 28",
+                        |s| s.parse::<u8>().ok().filter(|n| (1..=28).contains(n)),
+                    ),
+                },
+            };
+            // Without reputation scores there is nothing to rank clients by,
+            // so every client is challenged (unless bot scores rank them)
+            let threshold = env.parse_with(
+                "CHALLENGE_THRESHOLD",
+                abuseipdb.is_some().then_some(25),
+                "an AbuseIPDB score between 1 and 100",
+                |s| s.parse::<u8>().ok().filter(|n| (1..=100).contains(n)).map(Some),
+            );
+            if let Some(threshold) = threshold
+                && abuseipdb.is_none()
+            {
+                env.invalid(
+                    "CHALLENGE_THRESHOLD",
+                    &threshold.to_string(),
+                    "ABUSEIPDB_API_KEY to be set",
+                );
+            }
+            let path = env.parse_with(
+                "CHALLENGE_PATH",
+                CHALLENGE_PATH.to_string(),
+                "an absolute path",
+                |s| s.starts_with('/').then(|| s.to_string()),
+            );
+            Some(ChallengeConfig {
+                mode,
+                threshold: threshold.filter(|_| abuseipdb.is_some()),
+                cookie_ttl: env.parse_with(
+                    "CHALLENGE_TTL",
+                    Duration::from_secs(86400),
+                    "a duration such as 3600, 12h or 7d (greater than 0)",
+                    |s| parse_duration(s).filter(|d| !d.is_zero()),
+                ),
+                path,
+            })
+        });
+
+        let bot_score_challenge = env.parse_with(
+            "BOT_SCORE_CHALLENGE",
+            None,
+            "a score greater than 0",
+            |s| s.parse::<u32>().ok().filter(|&n| n > 0).map(Some),
+        );
+        if bot_score_challenge.is_some() && challenge.is_none() {
+            env.invalid("BOT_SCORE_CHALLENGE", "", "CHALLENGE to be set");
+        }
+        let bot_score_ban = env.parse_with(
+            "BOT_SCORE_BAN",
+            None,
+            "a score greater than 0",
+            |s| s.parse::<u32>().ok().filter(|&n| n > 0).map(Some),
+        );
+        let raw = env.string("BOT_USER_AGENTS", bot_score::DEFAULT_USER_AGENTS);
+        let user_agents = PatternSet::parse(&raw).unwrap_or_else(|e| {
+            let expected = format!("regular expressions separated by ; ({})", e);
+            env.invalid("BOT_USER_AGENTS", &raw, &expected);
+            PatternSet::default()
+        });
+        let header_name = |s: &str| HeaderName::try_from(s.to_ascii_lowercase()).ok().map(Some);
+        let protocol_header =
+            env.parse_with("BOT_PROTOCOL_HEADER", None, "an HTTP header name", header_name);
+        let header_order_header =
+            env.parse_with("BOT_HEADER_ORDER_HEADER", None, "an HTTP header name", header_name);
+        let raw = env.string("BOT_HEADER_ORDERS", "");
+        let header_orders = PatternSet::parse(&raw).unwrap_or_else(|e| {
+            let expected = format!("regular expressions separated by ; ({})", e);
+            env.invalid("BOT_HEADER_ORDERS", &raw, &expected);
+            PatternSet::default()
+        });
+        let bot_score = (bot_score_challenge.is_some() || bot_score_ban.is_some()).then(|| {
+            BotScoreConfig {
+                challenge_threshold: bot_score_challenge,
+                ban_threshold: bot_score_ban,
+                user_agents,
+                protocol_header,
+                header_order_header,
+                header_orders,
+            }
+        });
+
+        let cookie_secrets = env.list("COOKIE_SECRET", &[]);
+
+        let cookie_encrypt = env.bool("COOKIE_ENCRYPT", false);
+
+        let auth_mode = env.string("AUTH_MODE", "none").trim().to_lowercase();
+        let auth_mode = match auth_mode.as_str() {
+            "none" | "" => None,
+            "basic" => match env.optional("AUTH_USERS_FILE") {
+                Some(users_file) => Some(AuthMode::Basic { users_file }),
+                None => {
+                    env.invalid("AUTH_USERS_FILE", "", "an htpasswd file when AUTH_MODE=basic");
+                    None
+                }
+            },
+            "ldap" => {
+                let url = env
+                    .optional("LDAP_URL")
+                    .map(|url| url.trim().to_string())
+                    .filter(|url| url.starts_with("ldap://") || url.starts_with("ldaps://"));
+                if url.is_none() {
+                    let value = env.string("LDAP_URL", "");
+                    env.invalid("LDAP_URL", &value, "an ldap:// or ldaps:// URL when AUTH_MODE=ldap");
+                }
+                let bind_dn = env
+                    .optional("LDAP_USER_DN")
+                    .filter(|dn| dn.contains("{user}"));
+                if bind_dn.is_none() {
+                    let value = env.string("LDAP_USER_DN", "");
+                    env.invalid("LDAP_USER_DN", &value, "a bind name containing {user} when AUTH_MODE=ldap");
+                }
+                let base_dn = env.optional("LDAP_BASE_DN");
+                let groups = env.list("LDAP_GROUPS", &[]);
+                if !groups.is_empty() && base_dn.is_none() {
+                    env.invalid("LDAP_BASE_DN", "", "a search base when LDAP_GROUPS is set");
+                }
+                let starttls = env.bool("LDAP_STARTTLS", false);
+                let user_filter = env.string("LDAP_USER_FILTER", "(|(uid={user})(sAMAccountName={user}))");
+                let timeout = env.parse_with(
+                    "LDAP_TIMEOUT",
+                    Duration::from_secs(5),
+                    "a duration such as 5 or 10s (greater than 0)",
+                    |s| parse_duration(s).filter(|d| !d.is_zero()),
+                );
+                url.zip(bind_dn).map(|(url, bind_dn)| {
+                    AuthMode::Ldap(LdapConfig {
+                        url,
+                        starttls,
+                        bind_dn,
+                        base_dn,
+                        user_filter,
+                        groups,
+                        timeout,
+                    })
+                })
+            }
+            other => {
+                env.invalid("AUTH_MODE", other, "one of: none, basic, ldap");
+                None
+            }
+        };
+        let totp_paths = env.list("TOTP_PATHS", &[]);
+        let totp = (!totp_paths.is_empty()).then(|| TotpConfig {
+            paths: totp_paths,
+            path: env.parse_with(
+                "TOTP_PATH",
+                TOTP_PATH.to_string(),
+                "an absolute path",
+                |s| s.starts_with('/').then(|| s.to_string()),
+            ),
+            store_file: env.optional("TOTP_STORE_FILE"),
+        });
+        if totp.is_some() && auth_mode.is_none() {
+            let value = env.string("TOTP_PATHS", "");
+            env.invalid("TOTP_PATHS", &value, "AUTH_MODE to be set");
+        }
+        let auth = auth_mode.map(|mode| AuthConfig {
+            mode,
+            paths: env.list("AUTH_PATHS", &["/"]),
+            realm: env.string("AUTH_REALM", "tezcatlipoca"),
+            session_ttl: env.parse_with(
+                "SESSION_TTL",
+                Duration::from_secs(12 * 3600),
+                "a duration such as 3600, 12h or 7d (greater than 0)",
+                |s| parse_duration(s).filter(|d| !d.is_zero()),
+            ),
+            totp,
+        });
+        if auth.is_none() && policies.values().any(|p| p.auth == PolicyAuth::Required) {
+            let value = env.string("POLICIES", "");
+            env.invalid("POLICIES", &value, "AUTH_MODE to be set for \"auth\": \"required\"");
+        }
+
+        let signature = env.optional("SIGNATURE_SECRET").map(|secret| SignatureConfig {
+            secret,
+            paths: env.list("SIGNATURE_PATHS", &["/"]),
+            window: env.parse_with(
+                "SIGNATURE_WINDOW",
+                Duration::from_secs(300),
+                "a duration such as 300, 30s or 5m (greater than 0)",
+                |s| parse_duration(s).filter(|d| !d.is_zero()),
+            ),
+        });
+
+        let upstream_auth = env.optional("UPSTREAM_AUTH_URL").map(|url| UpstreamAuthConfig {
+            url,
+            timeout: env.parse_with(
+                "UPSTREAM_AUTH_TIMEOUT",
+                Duration::from_secs(10),
+                "a duration such as 10 or 10s (greater than 0)",
+                |s| parse_duration(s).filter(|d| !d.is_zero()),
+            ),
+        });
+
+        let opa = env.optional("OPA_URL").map(|url| OpaConfig {
+            url,
+            timeout: env.parse_with(
+                "OPA_TIMEOUT",
+                Duration::from_secs(2),
+                "a duration such as 2 or 5s (greater than 0)",
+                |s| parse_duration(s).filter(|d| !d.is_zero()),
+            ),
+            fail_open: env.bool("OPA_FAIL_OPEN", fail_mode == FailMode::Open),
+        });
+
+        let script_file = env.optional("SCRIPT_FILE");
+        let plugin_dir = env.optional("PLUGIN_DIR");
+        let plugin_memory_mb = env.parse_with(
+            "PLUGIN_MEMORY_MB",
+            16,
+            "a number of MiB between 1 and 4096",
+            |s| s.parse::<usize>().ok().filter(|n| (1..=4096).contains(n)),
+        );
+
+        let mut feeds = Vec::new();
+        if env.bool("BLOCK_TOR", false) {
+            feeds.push(FeedConfig {
+                name: "tor".to_string(),
+                url: env.string("TOR_EXIT_LIST_URL", TOR_EXIT_LIST_URL),
+                refresh: Duration::from_secs(env.parse_with(
+                    "TOR_REFRESH_SECS",
+                    3600,
+                    "a whole number of seconds, at least 60",
+                    |s| s.parse::<u64>().ok().filter(|&n| n >= 60),
+                )),
+            });
+        }
+
+        let remote_refresh = env.parse_with(
+            "REMOTE_LISTS_REFRESH_SECS",
+            3600,
+            "a whole number of seconds, at least 60",
+            |s| s.parse::<u64>().ok().filter(|&n| n >= 60),
+        );
+        for item in env.list("REMOTE_LISTS", &[]) {
+            // Either "name=url" or a bare URL, which then doubles as the name
+            let (name, url) = match item.split_once('=') {
+                Some((name, url)) if !name.contains("://") => (name.trim(), url.trim()),
+                _ => (item.as_str(), item.as_str()),
+            };
+            let object = url.starts_with("s3://") || url.starts_with("gs://");
+            if object && !cfg!(feature = "object-store") {
+                env.invalid(
+                    "REMOTE_LISTS",
+                    &item,
+                    "s3:// or gs:// URLs only in a build with the object-store feature",
+                );
+                continue;
+            }
+            if !(object || url.starts_with("http://") || url.starts_with("https://")) {
+                env.invalid(
+                    "REMOTE_LISTS",
+                    &item,
+                    "comma-separated http(s), s3 or gs URLs or name=URL pairs",
+                );
+                continue;
+            }
+            feeds.push(FeedConfig {
+                name: name.to_string(),
+                url: url.to_string(),
+                refresh: Duration::from_secs(remote_refresh),
+            });
+        }
+
+        let webhook = env.optional("WEBHOOK_URL").map(|url| WebhookConfig {
+            url: url.trim().to_string(),
+            format: env.parse_with(
+                "WEBHOOK_FORMAT",
+                WebhookFormat::Json,
+                "one of: json, discord, slack",
+                |s| match s.to_lowercase().as_str() {
+                    "json" => Some(WebhookFormat::Json),
+                    "discord" => Some(WebhookFormat::Discord),
+                    "slack" => Some(WebhookFormat::Slack),
+                    _ => None,
+                },
+            ),
+            batch_size: env.parse_with(
+                "WEBHOOK_BATCH_SIZE",
+                50,
+                "a whole number greater than 0",
+                |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+            ),
+            batch_window: Duration::from_secs(env.parse(
+                "WEBHOOK_BATCH_SECS",
+                5u64,
+                "a whole number of seconds",
+            )),
+            max_retries: env.parse("WEBHOOK_MAX_RETRIES", 3u32, "a whole number"),
+        });
+
+        let loki = env.optional("LOKI_URL").map(|url| {
+            let url = url.trim().trim_end_matches('/').to_string();
+            LokiConfig {
+                // A bare base URL gets the push API path
+                url: if url.ends_with("/loki/api/v1/push") {
+                    url
+                } else {
+                    format!("{}/loki/api/v1/push", url)
+                },
+                labels: env.parse_with(
+                    "LOKI_LABELS",
+                    Vec::new(),
+                    "comma-separated name=value pairs",
+                    LokiConfig::parse_labels,
+                ),
+                tenant: env.optional("LOKI_TENANT"),
+                batch_size: env.parse_with(
+                    "LOKI_BATCH_SIZE",
+                    500,
+                    "a whole number greater than 0",
+                    |s| s.parse::<usize>().ok().filter(|&n| n > 0),
+                ),
+                batch_window: env.parse_with(
+                    "LOKI_BATCH_SECS",
+                    Duration::from_secs(2),
+                    "a duration such as 2, 10s or 1m (greater than 0)",
+                    |s| parse_duration(s).filter(|d| !d.is_zero()),
+                ),
+            }
+        });
+
+        let audit_log = env.optional("AUDIT_LOG").and_then(|destination| {
+            let destination = destination.trim().to_string();
+            match destination.strip_prefix("unix://") {
+                Some(path) if !path.starts_with('/') => {
+                    env.invalid("AUDIT_LOG", &destination, "a file path or unix:///path");
+                    None
+                }
+                _ => Some(destination),
+            }
+        });
+
+        let mut admin_api = env.bool("ADMIN_API", false);
+        let admin_token = env.optional("ADMIN_TOKEN").map(|token| token.trim().to_string());
+        if admin_api && admin_token.is_none() {
+            env.invalid(
+                "ADMIN_API",
+                "true",
+                "ADMIN_TOKEN or ADMIN_TOKEN_FILE to be set when the admin API is enabled",
+            );
+            admin_api = false;
+        }
+
+        let ext_authz_port = env.parse_with(
+            "EXT_AUTHZ_PORT",
+            None,
+            "a port number between 1 and 65535",
+            |s| s.parse::<u16>().ok().filter(|&p| p != 0).map(Some),
+        );
+
+        let ip_stats_capacity = env.parse(
+            "IP_STATS_CAPACITY",
+            10_000usize,
+            "a whole number of IPs (0 disables)",
+        );
+
+        let top_stats_retention = env.parse_with(
+            "TOP_STATS_RETENTION",
+            Duration::from_secs(3600),
+            "a duration such as 3600, 90m or 24h (at least 60s)",
+            |s| parse_duration(s).filter(|d| d.as_secs() >= 60),
+        );
+
+        let decision_cache_size = env.parse(
+            "DECISION_CACHE_SIZE",
+            0usize,
+            "a whole number of decisions (0 disables)",
+        );
+        let decision_cache_ttl = env.parse_with(
+            "DECISION_CACHE_TTL",
+            Duration::from_secs(1),
+            "a duration such as 1, 5s or 1m (greater than 0)",
+            |s| parse_duration(s).filter(|d| !d.is_zero()),
+        );
+
+        let issues = env.issues;
+        if validation_mode == ValidationMode::Strict && !issues.is_empty() {
+            return Err(ConfigError { issues }.into());
+        }
+
+        Ok(Self {
+            banned_ips_files,
+            client_ip_sources,
+            trusted_proxies,
+            trusted_hops,
+            cloudflare_verify,
+            cloudflare_ips,
+            cloudflare_ips_urls,
+            cloudflare_ips_refresh: cloudflare_ips_refresh.filter(|_| cloudflare_verify),
             cache_ttl: Duration::from_secs(cache_ttl_secs),
+            banned_ips_watch,
             log_file,
             log_dir,
             log_rotation,
             log_max_files,
+            log_target,
             port,
             hostname,
-        }
+            listen,
+            proxy_protocol,
+            proxy_protocol_trusted,
+            http_version,
+            keep_alive_timeout,
+            http2_max_concurrent_streams,
+            tcp_backlog,
+            ipv6_mode,
+            max_inflight,
+            max_inflight_per_ip,
+            request_timeout,
+            max_body_bytes,
+            enforce,
+            fail_mode,
+            block_response,
+            block_reason_header,
+            category_rules,
+            schedule_rules,
+            rate_limit,
+            rate_limit_rules,
+            rate_limit_algorithm,
+            rate_limit_redis,
+            schedule_timezone,
+            header_rules,
+            tls_fingerprint_headers,
+            blocked_tls_fingerprints,
+            policies,
+            policy_header,
+            maintenance,
+            maintenance_allowlist,
+            maintenance_page,
+            maintenance_retry_after,
+            lockdown_allowlist,
+            lockdown_duration,
+            lockdown_max_duration,
+            tarpit_delay,
+            tarpit_max_concurrent,
+            honeypot_paths,
+            honeypot_ban_duration: Some(Duration::from_secs(honeypot_ban_secs))
+                .filter(|d| !d.is_zero()),
+            query_rules,
+            ban_escalation,
+            ban_escalation_reset,
+            ban_history_file,
+            state_snapshot_file,
+            state_snapshot_interval,
+            sweep_interval,
+            gossip,
+            redis,
+            kv,
+            greylist,
+            greylist_delay,
+            greylist_ttl,
+            crowdsec,
+            abuseipdb,
+            dnsbl,
+            rdns,
+            geoip_country_db,
+            geoip_asn_db,
+            challenge,
+            bot_score,
+            cookie_secrets,
+            cookie_encrypt,
+            auth,
+            signature,
+            upstream_auth,
+            opa,
+            script_file,
+            plugin_dir,
+            plugin_memory_mb,
+            feeds,
+            webhook,
+            loki,
+            audit_log,
+            admin_api,
+            admin_token,
+            ext_authz_port,
+            ip_stats_capacity,
+            decision_cache_size,
+            decision_cache_ttl,
+            top_stats_retention,
+            validation_mode,
+            validation_warnings: issues,
+            unknown_vars: unknown_vars(),
+        })
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            banned_ips_file: "./banned-ips.txt".to_string(),
+            banned_ips_files: vec!["./banned-ips.txt".to_string()],
+            client_ip_sources: IpSource::defaults(),
+            trusted_proxies: Vec::new(),
+            trusted_hops: 0,
+            cloudflare_verify: true,
+            cloudflare_ips: client_ip::CLOUDFLARE_RANGES
+                .iter()
+                .map(|range| range.parse().unwrap())
+                .collect(),
+            cloudflare_ips_urls: cloudflare::DEFAULT_URLS
+                .iter()
+                .map(|url| url.to_string())
+                .collect(),
+            cloudflare_ips_refresh: None,
             cache_ttl: Duration::from_secs(5),
+            banned_ips_watch: false,
             log_file: "./traefik-auth.log".to_string(),
             log_dir: ".".to_string(),
             log_rotation: LogRotation::Daily,
             log_max_files: 7,
+            log_target: LogTarget::File,
             port: 8199,
             hostname: "0.0.0.0".to_string(),
+            listen: vec![Listen {
+                addr: "0.0.0.0:8199".to_string(),
+                routes: Routes::All,
+            }],
+            proxy_protocol: false,
+            proxy_protocol_trusted: Vec::new(),
+            http_version: HttpVersion::Auto,
+            keep_alive_timeout: Some(Duration::from_secs(120)),
+            http2_max_concurrent_streams: 200,
+            tcp_backlog: 1024,
+            ipv6_mode: Ipv6Mode::Dual,
+            max_inflight: Some(10_000),
+            max_inflight_per_ip: None,
+            request_timeout: Some(Duration::from_secs(30)),
+            max_body_bytes: Some(65_536),
+            enforce: true,
+            fail_mode: FailMode::Open,
+            block_response: BlockResponse::Status(403),
+            block_reason_header: false,
+            category_rules: Vec::new(),
+            schedule_rules: Vec::new(),
+            rate_limit: None,
+            rate_limit_rules: Vec::new(),
+            rate_limit_algorithm: Algorithm::TokenBucket,
+            rate_limit_redis: None,
+            schedule_timezone: Tz::UTC,
+            header_rules: HeaderRules::default(),
+            tls_fingerprint_headers: fingerprint::DEFAULT_HEADERS
+                .iter()
+                .map(|name| HeaderName::from_static(name))
+                .collect(),
+            blocked_tls_fingerprints: HashSet::new(),
+            policies: BTreeMap::new(),
+            policy_header: policies::DEFAULT_HEADER.to_string(),
+            maintenance: false,
+            maintenance_allowlist: Vec::new(),
+            maintenance_page: None,
+            maintenance_retry_after: Duration::from_secs(300),
+            lockdown_allowlist: Vec::new(),
+            lockdown_duration: Duration::from_secs(3600),
+            lockdown_max_duration: Duration::from_secs(24 * 3600),
+            tarpit_delay: None,
+            tarpit_max_concurrent: 1000,
+            honeypot_paths: Vec::new(),
+            honeypot_ban_duration: Some(Duration::from_secs(86400)),
+            query_rules: QueryRules::default(),
+            ban_escalation: Vec::new(),
+            ban_escalation_reset: Duration::from_secs(30 * 86400),
+            ban_history_file: None,
+            state_snapshot_file: None,
+            state_snapshot_interval: Duration::from_secs(60),
+            sweep_interval: Duration::from_secs(60),
+            gossip: None,
+            redis: None,
+            kv: None,
+            greylist: false,
+            greylist_delay: Duration::from_secs(10),
+            greylist_ttl: Duration::from_secs(86400),
+            crowdsec: None,
+            abuseipdb: None,
+            dnsbl: None,
+            rdns: None,
+            geoip_country_db: None,
+            geoip_asn_db: None,
+            challenge: None,
+            bot_score: None,
+            cookie_secrets: Vec::new(),
+            cookie_encrypt: false,
+            auth: None,
+            signature: None,
+            upstream_auth: None,
+            opa: None,
+            script_file: None,
+            plugin_dir: None,
+            plugin_memory_mb: 16,
+            feeds: Vec::new(),
+            webhook: None,
+            loki: None,
+            audit_log: None,
+            admin_api: false,
+            admin_token: None,
+            ext_authz_port: None,
+            ip_stats_capacity: 10_000,
+            decision_cache_size: 0,
+            decision_cache_ttl: Duration::from_secs(1),
+            top_stats_retention: Duration::from_secs(3600),
+            validation_mode: ValidationMode::Strict,
+            validation_warnings: Vec::new(),
+            unknown_vars: Vec::new(),
         }
     }
 }
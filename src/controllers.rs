@@ -3,19 +3,52 @@
 //! This module contains the core HTTP handlers and authentication middleware
 //! that integrates with Traefik's ForwardAuth system.
 
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::IpAddr, time::Duration};
 
 use axum::{
-    extract::{ConnectInfo, Request, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Request, State},
+    http::{
+        header::{
+            CACHE_CONTROL, CONTENT_TYPE, LOCATION, RETRY_AFTER, SET_COOKIE, USER_AGENT,
+            WWW_AUTHENTICATE,
+        },
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
 use serde::Serialize;
+use tokio::time::Instant;
 use tracing::{debug, warn};
 
-use crate::AppState;
+use crate::{
+    auth::{self, AuthError},
+    bot_score,
+    cache::ListFile,
+    challenge,
+    client_ip::ClientIp,
+    bans,
+    config::{enforced_categories, BlockResponse, FailMode},
+    events::{describe_block, BanDetail, DecisionEvent, SecurityEvent, Verdict},
+    fingerprint,
+    geoip::Geo,
+    honeypot::is_trap,
+    decision_cache::Decision,
+    lockdown, maintenance,
+    policies::{self, Policy},
+    metrics::{LatencySummary, Metrics},
+    opa, rate_limit, schedule, signature,
+    supervisor::{TaskState, TaskStatus},
+    AppState,
+};
+
+/// Response header explaining a block, with `BLOCK_REASON_HEADER` set
+pub const BLOCK_REASON: &str = "x-block-reason";
+
+/// Response header holding the end of a temporary ban or lockdown
+pub const BAN_EXPIRES: &str = "x-ban-expires";
 
 /// Authentication middleware that checks if client IP is banned.
 ///
@@ -29,68 +62,974 @@ use crate::AppState;
 /// - This keeps production logs focused on security events while allowing detailed
 ///   debugging when needed
 ///
-/// # IP Detection Priority
-/// 1. `cf-connecting-ip` - Cloudflare's real IP header
-/// 2. `x-forwarded-for` - Standard proxy header (uses first IP if multiple)
-/// 3. Socket address from connection info (direct connection)
+/// # IP Detection
+/// See [`ClientIp`]: by default `cf-connecting-ip`, then the client's
+/// `x-forwarded-for` entry, `x-real-ip`, `true-client-ip` and finally the
+/// socket address of the connection (`CLIENT_IP_SOURCES`).
 ///
 /// # Cache Behavior
-/// - Automatically refreshes the banned IPs cache if stale
+/// - Only reads the banned IPs cache; refreshes happen in
+///   [`cache_refresh_task`](crate::cache::cache_refresh_task)
 /// - Blocks request with 403 FORBIDDEN if IP is banned
+/// - With `ENFORCE=false`, banned IPs are logged as "would block" and allowed
+/// - With `TARPIT_DELAY_SECS` set, banned requests are held before the 403
+/// - With `ABUSEIPDB_API_KEY` set, IPs scoring at or above
+///   `ABUSEIPDB_THRESHOLD` are blocked once their score has been fetched
+/// - With `DNSBL_ZONES` set, IPs listed in one of the zones are blocked once
+///   the lookup has finished (`dns` feature)
 /// - Logs all access attempts based on log level configuration
 ///
+/// # Honeypots
+/// Requests for a path listed in `HONEYPOT_PATHS` dynamically ban the client
+/// IP for `HONEYPOT_BAN_SECS`. The path is taken from Traefik's
+/// `X-Forwarded-Uri` header, falling back to the request path.
+///
+/// # Challenge
+/// With `CHALLENGE` set, suspicious IPs get the captcha page, and requests
+/// for `CHALLENGE_PATH` are answered by [`challenge::verify`] instead of
+/// going through the IP check.
+///
+/// # Block reasons
+/// Block log lines, audit records and events carry the reason and, for bans,
+/// the ban's source and free text. With `BLOCK_REASON_HEADER` set they are
+/// also sent to the client in `X-Block-Reason`.
+///
+/// # Policy
+/// With `OPA_URL` set, requests that aren't banned are also checked against
+/// an Open Policy Agent rule; see [`crate::opa`].
+///
+/// # Script
+/// With `SCRIPT_FILE` set, a Rhai script may override the verdict of the
+/// checks above; see `script` (`scripting` feature).
+///
+/// # Plugins
+/// With `PLUGIN_DIR` set, WebAssembly plugins may do the same after the
+/// script; see `plugins` (`plugins` feature).
+///
+/// # Upstream auth
+/// With `UPSTREAM_AUTH_URL` set, requests passing the local checks are also
+/// checked by that service; see [`crate::upstream`].
+///
 /// # Arguments
 /// * `State(state)` - Application state containing banned IPs cache and config
-/// * `ConnectInfo(addr)` - Socket address of the client connection
+/// * `ClientIp(ip)` - Client address resolved from proxy headers or the socket
 /// * `headers` - HTTP request headers containing client IP information
 /// * `req` - The incoming HTTP request
 /// * `next` - Next middleware/handler in the chain
 ///
 /// # Returns
-/// * `Ok(Response)` - Request is allowed, continues to next handler
-/// * `Err(StatusCode::FORBIDDEN)` - Request is blocked due to banned IP
+/// * the next handler's response - Request is allowed
+/// * the configured block response (`BLOCK_STATUS`, `BLOCK_REDIRECT_URL`,
+///   403 by default) - Request is blocked due to banned IP
 pub async fn auth_middleware(
     State(state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ClientIp(ip): ClientIp,
     headers: HeaderMap,
     req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-
-    // Extract client IP from headers (Cloudflare, X-Forwarded-For, or direct)
-    let client_ip = headers
-        .get("cf-connecting-ip")
-        .or_else(|| headers.get("x-forwarded-for"))
+) -> Response {
+    let uri = forwarded_uri(&headers, &req);
+    let method = headers
+        .get("x-forwarded-method")
         .and_then(|h| h.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or(s).trim())
-        .unwrap_or_else(|| {
-            // Fall back to socket address if no proxy headers
-            addr.ip().to_string().leak()
-        });
+        .unwrap_or_else(|| req.method().as_str())
+        .to_string();
+    let (path, query) = split_uri(&uri);
+
+    // The service's own health checks keep answering during maintenance
+    // and lockdowns
+    if !matches!(req.uri().path(), "/health" | "/readyz") {
+        if let Some(block) = maintenance::block(&state, ip, path) {
+            return block_response(&block);
+        }
+        if let Some(block) = locked_out(&state, ip, path, &headers).await {
+            return block_response(&block);
+        }
+    }
+
+    if let Some(challenge) = &state.config.challenge
+        && path == challenge.path
+    {
+        return match challenge::verify(&state, ip, query, &headers).await {
+            Ok(passed) => passed.into_response(),
+            Err(block) => block_response(&block),
+        };
+    }
+    if let Some(result) = auth::totp_endpoint(&state, ip, &method, path, query, &headers).await {
+        return match result {
+            Ok(passed) => passed.into_response(),
+            Err(block) => block_response(&block),
+        };
+    }
 
-    let path = req.uri().path();
+    let started = Instant::now();
+    let decision = check(&state, ip, &method, path, query, &headers).await;
+    match &decision {
+        Ok(_) => state.metrics.latency_allowed.record(started.elapsed()),
+        Err(_) => state.metrics.latency_blocked.record(started.elapsed()),
+    }
+    match decision {
+        Ok(allowed) => {
+            let mut response = next.run(req).await;
+            allowed.apply(response.headers_mut());
+            response
+        }
+        Err(block) => block_response(&block),
+    }
+}
+
+/// Extra answer for an allowed request.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Allowed {
+    /// Authenticated user, returned in `X-Auth-User`
+    pub user: Option<String>,
+    /// Session cookie for a fresh login
+    pub set_cookie: Option<String>,
+    /// Headers of the upstream auth service's answer
+    pub upstream_headers: Vec<(String, String)>,
+}
+
+impl Allowed {
+    /// Adds the `X-Auth-User`, `Set-Cookie` and upstream auth headers to a
+    /// response.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let values = [
+            (HeaderName::from_static(auth::USER_HEADER), &self.user),
+            (SET_COOKIE, &self.set_cookie),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.append(name, value);
+            }
+        }
+        append_headers(headers, &self.upstream_headers);
+    }
+}
 
-    // Check if IP is banned
-    let mut cache = state.banned_ips.write().await;
+fn append_headers(headers: &mut HeaderMap, values: &[(String, String)]) {
+    for (name, value) in values {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::from_str(value)) {
+            headers.append(name, value);
+        }
+    }
+}
+
+/// Decides whether `ip` may access `path`.
+///
+/// This is the decision engine shared by every front end (ForwardAuth
+/// middleware, Envoy ext_authz): honeypot and query rule handling, header
+/// rules, TLS fingerprints, bot scores, ban and reputation checks, rate
+/// limits, metrics, stats and events. `method`, `path`, `query` and `headers` describe the
+/// original request. Returns the configured block response when the request
+/// is blocked; in observe-only mode requests are always allowed unless a
+/// required signature or login is missing or invalid.
+pub async fn check(
+    state: &AppState,
+    ip: IpAddr,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+) -> Result<Allowed, BlockResponse> {
+    // Per-IP tables are keyed by the textual address
+    let client_ip = ip.to_string();
+    let client_ip = client_ip.as_str();
+    let user_agent = headers.get(USER_AGENT).and_then(|h| h.to_str().ok());
+
+    // A named policy replaces the global settings; naming an unknown one is
+    // a router misconfiguration, blocked whatever ENFORCE says
+    let policy = match policies::select(state, headers) {
+        Ok(policy) => policy,
+        Err(name) => {
+            let reason = format!("UNKNOWN POLICY {}", name);
+            return reject(state, client_ip, path, user_agent, &reason, None, true)
+                .await
+                .map(|()| Allowed::default());
+        }
+    };
+    let enforced = policies::enforce(state, policy);
+
+    // Trap paths ban the client before the regular checks run
+    let trapped = {
+        let overrides = state.overrides.read().unwrap_or_else(|e| e.into_inner());
+        let traps = policy
+            .and_then(|policy| policy.honeypot_paths.as_deref())
+            .or(overrides.honeypot_paths.as_deref());
+        is_trap(traps.unwrap_or(&state.config.honeypot_paths), path)
+    };
+    if trapped {
+        let duration = auto_ban(state, client_ip, path, "honeypot");
+        Metrics::incr(&state.metrics.honeypot_hits);
+        warn!(
+            "🍯 HONEYPOT: IP {} requested trap path {}, banned {} (offense #{})",
+            client_ip,
+            path,
+            duration.map_or("permanently".to_string(), |d| format!("for {:?}", d)),
+            state.dynamic_bans.offenses(client_ip)
+        );
+    }
+    // So do exploit probes in the query string
+    if let Some(rule) = state.config.query_rules.matching(query) {
+        let duration = auto_ban(state, client_ip, path, &format!("query rule {}", rule));
+        Metrics::incr(&state.metrics.query_rule_hits);
+        warn!(
+            "🧨 QUERY RULE: IP {} requested {}?{} matching {}, banned {} (offense #{})",
+            client_ip,
+            path,
+            query,
+            rule,
+            duration.map_or("permanently".to_string(), |d| format!("for {:?}", d)),
+            state.dynamic_bans.offenses(client_ip)
+        );
+    }
 
-    // Refresh cache if needed
-    if cache.is_stale(state.config.cache_ttl) {
-        if let Err(e) = cache.refresh(&state.config.banned_ips_file).await {
-            warn!("Failed to refresh banned IPs cache: {}", e);
+    // So do requests scoring as a bot, which may otherwise be challenged
+    let bot_score = state.config.bot_score.as_ref().map(|config| {
+        (config, bot_score::score(config, headers))
+    });
+    if let Some((config, score)) = &bot_score {
+        debug!("🤖 BOT SCORE: IP {} scored {} {:?}", client_ip, score.points, score.signals);
+        if config.ban_threshold.is_some_and(|threshold| score.points >= threshold) {
+            let reason = format!("bot score {}", score.points);
+            let duration = auto_ban(state, client_ip, path, &reason);
+            Metrics::incr(&state.metrics.bot_bans);
+            warn!(
+                "🤖 BOT: IP {} scored {} ({}), banned {} (offense #{})",
+                client_ip,
+                score.points,
+                score.signals.join(", "),
+                duration.map_or("permanently".to_string(), |d| format!("for {:?}", d)),
+                state.dynamic_bans.offenses(client_ip)
+            );
         }
     }
+    let bot_points = bot_score.map(|(_, score)| score.points);
 
-    if cache.contains(client_ip) {
-        warn!("🚫 BLOCKED: IP {} attempted to access {} [BANNED]", client_ip, path);
-        return Err(StatusCode::FORBIDDEN);
+    // Header rules and TLS fingerprints look at headers the cached decisions
+    // don't cover
+    let config = &state.config;
+    let header_block = config
+        .header_rules
+        .matching(headers)
+        .map(|rule| format!("HEADER RULE {}", rule))
+        .or_else(|| {
+            fingerprint::blocked(
+                &config.blocked_tls_fingerprints,
+                &config.tls_fingerprint_headers,
+                headers,
+            )
+            .map(|fingerprint| format!("TLS FINGERPRINT {}", fingerprint))
+        });
+    let decision = match (header_block, &state.decision_cache) {
+        (Some(reason), _) => Decision {
+            reason: Some(reason),
+            ban: None,
+        },
+        (None, Some(cache)) => {
+            let versions = (state.banned_ips.read().await.version(), state.dynamic_bans.version());
+            let target = decision_target(state, method, path, headers);
+            match cache.get(ip, &target, versions) {
+                Some(decision) => {
+                    Metrics::incr(&state.metrics.decision_cache_hits);
+                    decision
+                }
+                None => {
+                    let decision =
+                        evaluate(state, ip, client_ip, method, path, headers, policy).await;
+                    cache.insert(ip, &target, versions, decision.clone());
+                    decision
+                }
+            }
+        }
+        (None, None) => evaluate(state, ip, client_ip, method, path, headers, policy).await,
+    };
+    let Decision { reason, ban } = decision;
+
+    let mut allowed = Allowed::default();
+    // Only returns in observe mode, where the block is recorded and the
+    // signature and login checks below still apply
+    let observed = reason.is_some();
+    if let Some(reason) = reason {
+        reject(state, client_ip, path, user_agent, &reason, ban, enforced).await?;
+    } else if let Some(retry_after) =
+        rate_limited(state, ip, path, user_agent, headers, enforced).await
+    {
+        // Ahead of the credential checks, to slow down password guessing
+        return Err(BlockResponse::RetryAfter(retry_after));
+    }
+    if let Some(error) = bad_signature(state, method, path, headers) {
+        unauthorized(state, client_ip, path, user_agent, &error.to_string());
+        return Err(BlockResponse::Status(401));
+    }
+    if let Some(auth) = state
+        .auth
+        .as_ref()
+        .filter(|auth| policies::auth_required(policy, auth, path))
+    {
+        // Logged-in users skip the challenge and greylist
+        let session = match auth.authenticate(&state.cookie_signer, headers).await {
+            Ok(session) => session,
+            Err(error) => {
+                if error == AuthError::Invalid {
+                    unauthorized(state, client_ip, path, user_agent, &error.to_string());
+                }
+                return Err(BlockResponse::Unauthorized(auth.config().realm.clone()));
+            }
+        };
+        // The session cookie is only issued once the second factor passed
+        if !session.totp
+            && let Some(totp) = auth.totp()
+            && totp.required(path)
+        {
+            return Err(BlockResponse::Challenge(auth.totp_page(&session.user, path, false)));
+        }
+        allowed.user = Some(session.user);
+        allowed.set_cookie = session.set_cookie;
+    } else if !observed {
+        if let Some(page) =
+            challenged(state, client_ip, path, user_agent, headers, bot_points, enforced)
+        {
+            return Err(BlockResponse::Challenge(page));
+        }
+        if let Some(retry_after) = greylisted(state, client_ip, path, user_agent, enforced) {
+            return Err(BlockResponse::RetryAfter(retry_after));
+        }
+    }
+    // An observed block was already recorded as one that would have been made
+    if !observed {
+        record_allowed(state, client_ip, path, user_agent);
     }
 
-    drop(cache); // Release the lock before continuing
+    // Enforced regardless of ENFORCE, like local authentication
+    if let Some(upstream) = &state.upstream_auth {
+        allowed.upstream_headers = upstream.forward(ip, method, path, headers).await?;
+    }
 
     // Log allowed connections at DEBUG level (won't show in production with RUST_LOG=info)
     debug!("✅ ALLOWED: IP {} accessed {}", client_ip, path);
 
-    Ok(next.run(req).await)
+    Ok(allowed)
+}
+
+/// Block response for a request a lockdown holds back, recorded like any
+/// block but regardless of `ENFORCE`.
+///
+/// Checked by the front ends before anything else, like maintenance mode.
+pub async fn locked_out(
+    state: &AppState,
+    ip: IpAddr,
+    path: &str,
+    headers: &HeaderMap,
+) -> Option<BlockResponse> {
+    if !lockdown::denies(state, ip) {
+        return None;
+    }
+    Metrics::incr(&state.metrics.locked_out);
+    let user_agent = headers.get(USER_AGENT).and_then(|h| h.to_str().ok());
+    let block = reject(state, &ip.to_string(), path, user_agent, "LOCKDOWN", None, true)
+        .await
+        .err()?;
+    match lockdown::status(state) {
+        Some((until, _)) => Some(expiring(block, until)),
+        None => Some(block),
+    }
+}
+
+/// Bans `client_ip` for an offense on `path` and publishes the ban; returns
+/// its duration (`None` is permanent).
+fn auto_ban(state: &AppState, client_ip: &str, path: &str, reason: &str) -> Option<Duration> {
+    let duration =
+        state.dynamic_bans.offend(client_ip, state.config.honeypot_ban_duration, reason);
+    state.events.publish(SecurityEvent::AutoBan {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        reason: reason.to_string(),
+        duration_secs: duration.map(|d| d.as_secs()),
+        timestamp: Utc::now(),
+    });
+    duration
+}
+
+/// Runs the ban, reputation, schedule, policy, script and plugin checks of
+/// a request: the part of [`check`] the decision cache can skip.
+async fn evaluate(
+    state: &AppState,
+    ip: IpAddr,
+    client_ip: &str,
+    method: &str,
+    path: &str,
+    headers: &HeaderMap,
+    policy: Option<&Policy>,
+) -> Decision {
+    // Path and host rules may leave some ban categories unenforced
+    let rules = policy.and_then(|policy| policy.category_rules.as_deref());
+    let rules = rules.unwrap_or(&state.config.category_rules);
+    let categories = enforced_categories(rules, forwarded_host(headers), path);
+    let dynamic_ban = categories
+        .enforces(bans::DYNAMIC)
+        .then(|| state.dynamic_bans.detail(client_ip))
+        .flatten();
+    let ban = match dynamic_ban {
+        Some(ban) => Some(("DYNAMIC BAN", ban)),
+        None => {
+            // Read-only: the background refresh task owns reloads
+            let cache = state.banned_ips.read().await;
+            cache.source_of(ip, categories).map(|source| {
+                let ban = BanDetail {
+                    source: source.to_string(),
+                    reason: None,
+                    expires: None,
+                };
+                ("BANNED", ban)
+            })
+            // Cache lock is released here, before any tarpit delay
+        }
+    };
+    let reason = ban.as_ref().map(|(reason, _)| reason.to_string());
+    // Without the files, nothing tells whether the IP is banned
+    let fail_closed = state.config.fail_mode == FailMode::Closed;
+    let reason = match reason {
+        None if fail_closed && !state.banned_ips.read().await.loaded => {
+            Some("BAN LIST UNAVAILABLE".to_string())
+        }
+        reason => reason,
+    };
+
+    // Reputation is only consulted for IPs that aren't banned outright; the
+    // lookup itself happens in the background, so unknown IPs pass for now
+    let reason = reason.or_else(|| {
+        let reputation = state.abuseipdb.as_ref()?;
+        match reputation.score(client_ip) {
+            Some(score) => reputation
+                .is_abusive(score)
+                .then(|| format!("ABUSEIPDB SCORE {}", score)),
+            None => (fail_closed && reputation.failed(client_ip))
+                .then(|| "ABUSEIPDB UNAVAILABLE".to_string()),
+        }
+    });
+    #[cfg(feature = "dns")]
+    let reason = reason.or_else(|| {
+        let dnsbl = state.dnsbl.as_ref()?;
+        match dnsbl.listing(client_ip) {
+            Some(zone) => Some(format!("DNSBL {}", zone)),
+            None => (fail_closed && dnsbl.failed(client_ip))
+                .then(|| "DNSBL UNAVAILABLE".to_string()),
+        }
+    });
+    let reason = reason.or_else(|| {
+        let config = &state.config;
+        let rules = policy.and_then(|policy| policy.schedule_rules.as_deref());
+        let rules = rules.unwrap_or(&config.schedule_rules);
+        let host = forwarded_host(headers);
+        schedule::closed(rules, config.schedule_timezone, host, path, Utc::now())
+            .then(|| "SCHEDULE".to_string())
+    });
+
+    // The policy is only asked about requests nothing else blocks
+    let reason = match reason {
+        Some(reason) => Some(reason),
+        None => {
+            let geo = locate(state, client_ip);
+            policy_denial(state, ip, method, path, headers, &geo).await
+        }
+    };
+    #[cfg(feature = "scripting")]
+    let reason = match &state.script {
+        Some(script) => script.decide(ip, method, path, headers, reason),
+        None => reason,
+    };
+    #[cfg(feature = "plugins")]
+    let reason = match &state.plugins {
+        Some(plugins) => {
+            let geo = locate(state, client_ip);
+            plugins.clone().decide(ip, method, path, headers, &geo, reason).await
+        }
+        None => reason,
+    };
+
+    // Scripts and plugins may have replaced the ban with another reason
+    let ban = ban.and_then(|(banned, ban)| (reason.as_deref() == Some(banned)).then_some(ban));
+
+    Decision { reason, ban }
+}
+
+/// What a cached decision is valid for besides the client IP: the method,
+/// host, path and policy of the request.
+fn decision_target(state: &AppState, method: &str, path: &str, headers: &HeaderMap) -> String {
+    let policy = headers.get(&state.config.policy_header).map(|v| v.as_bytes());
+    format!(
+        "{} {}{} {}",
+        method,
+        forwarded_host(headers).unwrap_or(""),
+        path,
+        String::from_utf8_lossy(policy.unwrap_or_default()).to_ascii_lowercase()
+    )
+}
+
+/// Reason to block a request the OPA policy doesn't allow.
+async fn policy_denial(
+    state: &AppState,
+    ip: IpAddr,
+    method: &str,
+    path: &str,
+    headers: &HeaderMap,
+    geo: &Geo,
+) -> Option<String> {
+    let config = state.config.opa.as_ref()?;
+    match opa::allowed(state, config, opa::input(ip, method, path, headers, geo)).await {
+        Ok(true) => None,
+        Ok(false) => Some("OPA POLICY".to_string()),
+        Err(e) => {
+            warn!("OPA policy query to {} failed: {}", config.url, e);
+            (!config.fail_open).then(|| "OPA UNAVAILABLE".to_string())
+        }
+    }
+}
+
+/// Hands a decision to the audit trail and to live subscribers.
+fn publish_decision(state: &AppState, event: impl FnOnce() -> DecisionEvent) {
+    match &state.audit {
+        Some(audit) => {
+            let event = event();
+            state.events.publish_decision(|| event.clone());
+            audit.record(event);
+        }
+        None => state.events.publish_decision(event),
+    }
+}
+
+/// Country and ASN of `client_ip`; empty without GeoIP databases.
+fn locate(state: &AppState, client_ip: &str) -> Geo {
+    state
+        .geoip
+        .as_ref()
+        .map_or_else(Geo::default, |geoip| geoip.lookup(client_ip))
+}
+
+fn record_allowed(state: &AppState, client_ip: &str, path: &str, user_agent: Option<&str>) {
+    Metrics::incr(&state.metrics.allowed);
+    state.ip_stats.record(client_ip, Verdict::Allowed);
+    state
+        .top_stats
+        .record(client_ip, path, user_agent, Verdict::Allowed);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        verdict: Verdict::Allowed,
+        reason: None,
+        ban: None,
+        hostname: None,
+        geo: locate(state, client_ip),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+}
+
+/// Signature problem of a request that has to be signed.
+///
+/// Signed requests skip the challenge and greylist: they come from
+/// machines, which can solve neither.
+fn bad_signature(
+    state: &AppState,
+    method: &str,
+    path: &str,
+    headers: &HeaderMap,
+) -> Option<signature::SignatureError> {
+    let config = state.config.signature.as_ref()?;
+    if !signature::required(config, path) {
+        return None;
+    }
+    signature::verify(config, method, path, headers).err()
+}
+
+/// Records a request refused for its signature or credentials.
+///
+/// Authentication is enforced even with `ENFORCE=false`. Requests that
+/// simply didn't send credentials yet aren't recorded: browsers only send
+/// them after the first `401`.
+fn unauthorized(
+    state: &AppState,
+    client_ip: &str,
+    path: &str,
+    user_agent: Option<&str>,
+    reason: &str,
+) {
+    warn!("🔏 UNAUTHORIZED: IP {} attempted to access {} [{}]", client_ip, path, reason);
+    Metrics::incr(&state.metrics.unauthorized);
+    state.ip_stats.record(client_ip, Verdict::Unauthorized);
+    state
+        .top_stats
+        .record(client_ip, path, user_agent, Verdict::Unauthorized);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        verdict: Verdict::Unauthorized,
+        reason: Some(reason.to_string()),
+        ban: None,
+        hostname: None,
+        geo: locate(state, client_ip),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+}
+
+/// Challenge page for a suspicious IP that hasn't solved the challenge yet.
+///
+/// Like greylisting, skipped in observe-only mode.
+fn challenged(
+    state: &AppState,
+    client_ip: &str,
+    path: &str,
+    user_agent: Option<&str>,
+    headers: &HeaderMap,
+    bot_points: Option<u32>,
+    enforced: bool,
+) -> Option<String> {
+    if !enforced || !challenge::required(state, client_ip, headers, bot_points) {
+        return None;
+    }
+
+    debug!("🧩 CHALLENGED: IP {} accessed {}", client_ip, path);
+    Metrics::incr(&state.metrics.challenged);
+    state.ip_stats.record(client_ip, Verdict::Challenged);
+    state
+        .top_stats
+        .record(client_ip, path, user_agent, Verdict::Challenged);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        verdict: Verdict::Challenged,
+        reason: Some("CHALLENGE".to_string()),
+        ban: None,
+        hostname: None,
+        geo: locate(state, client_ip),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+    Some(challenge::page(state, client_ip, path))
+}
+
+/// Defers a request from an IP the greylist hasn't seen pass yet.
+///
+/// Greylisting is skipped in observe-only mode, like every other block.
+fn greylisted(
+    state: &AppState,
+    client_ip: &str,
+    path: &str,
+    user_agent: Option<&str>,
+    enforced: bool,
+) -> Option<Duration> {
+    if !enforced {
+        return None;
+    }
+    let retry_after = state.greylist.as_ref()?.check(client_ip)?;
+
+    debug!("⏳ GREYLISTED: IP {} accessed {}, retry in {:?}", client_ip, path, retry_after);
+    Metrics::incr(&state.metrics.greylisted);
+    state.ip_stats.record(client_ip, Verdict::Greylisted);
+    state
+        .top_stats
+        .record(client_ip, path, user_agent, Verdict::Greylisted);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        verdict: Verdict::Greylisted,
+        reason: Some("GREYLIST".to_string()),
+        ban: None,
+        hostname: None,
+        geo: locate(state, client_ip),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+    Some(retry_after)
+}
+
+/// Defers a request over the rate limit of its path, see
+/// [`crate::rate_limit`].
+///
+/// Counted in Redis when `RATE_LIMIT_REDIS` is set, falling back to the
+/// local buckets while Redis can't be reached. Like greylisting, skipped in
+/// observe-only mode.
+async fn rate_limited(
+    state: &AppState,
+    ip: IpAddr,
+    path: &str,
+    user_agent: Option<&str>,
+    headers: &HeaderMap,
+    enforced: bool,
+) -> Option<Duration> {
+    if !enforced {
+        return None;
+    }
+    let limiter = state.rate_limiter.as_ref()?;
+    let config = &state.config;
+    let host = forwarded_host(headers);
+    let (scope, rate) =
+        rate_limit::applicable(&config.rate_limit_rules, config.rate_limit, host, path)?;
+    #[cfg(feature = "redis")]
+    let retry_after = match &state.redis_rate_limiter {
+        Some(shared) => match shared.check(ip, scope, rate).await {
+            Ok(retry_after) => retry_after,
+            Err(e) if config.fail_mode == FailMode::Closed => {
+                debug!("Rate limit for {} can't be counted, refused: {}", ip, e);
+                Metrics::incr(&state.metrics.rate_limit_fallbacks);
+                Some(crate::pubsub::RECONNECT_DELAY)
+            }
+            Err(e) => {
+                debug!("Rate limit for {} counted locally: {}", ip, e);
+                Metrics::incr(&state.metrics.rate_limit_fallbacks);
+                limiter.check(ip, scope, rate)
+            }
+        },
+        None => limiter.check(ip, scope, rate),
+    };
+    #[cfg(not(feature = "redis"))]
+    let retry_after = limiter.check(ip, scope, rate);
+    let retry_after = retry_after?;
+
+    let client_ip = ip.to_string();
+    debug!(
+        "🚦 RATE LIMITED: IP {} over {} on {}, retry in {:?}",
+        client_ip, rate, path, retry_after
+    );
+    Metrics::incr(&state.metrics.rate_limited);
+    state.ip_stats.record(&client_ip, Verdict::RateLimited);
+    state
+        .top_stats
+        .record(&client_ip, path, user_agent, Verdict::RateLimited);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.clone(),
+        path: path.to_string(),
+        verdict: Verdict::RateLimited,
+        reason: Some(format!("RATE LIMIT {}", rate)),
+        ban: None,
+        hostname: None,
+        geo: locate(state, &client_ip),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+    Some(retry_after)
+}
+
+/// Blocks a banned client, or only records it when `enforced` is false.
+///
+/// Returns `Ok(())` in observe-only mode so the request continues. `ban` is
+/// the ban behind the block, reported along with `reason`, as is the
+/// client's hostname once its reverse DNS lookup is done (`RDNS`).
+async fn reject(
+    state: &AppState,
+    client_ip: &str,
+    path: &str,
+    user_agent: Option<&str>,
+    reason: &str,
+    ban: Option<BanDetail>,
+    enforced: bool,
+) -> Result<(), BlockResponse> {
+    #[cfg(feature = "dns")]
+    let hostname = state.rdns.as_ref().and_then(|rdns| rdns.hostname(client_ip));
+    #[cfg(not(feature = "dns"))]
+    let hostname: Option<String> = None;
+    let geo = locate(state, client_ip);
+    if let Some(country) = &geo.country {
+        state.metrics.blocked_by_country.incr(country);
+    }
+    state.events.publish(SecurityEvent::Blocked {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        reason: reason.to_string(),
+        ban: ban.clone(),
+        hostname: hostname.clone(),
+        enforced,
+        timestamp: Utc::now(),
+    });
+    let verdict = if enforced {
+        Verdict::Blocked
+    } else {
+        Verdict::WouldBlock
+    };
+    state.ip_stats.record(client_ip, verdict);
+    state.top_stats.record(client_ip, path, user_agent, verdict);
+    publish_decision(state, || DecisionEvent {
+        ip: client_ip.to_string(),
+        path: path.to_string(),
+        verdict,
+        reason: Some(reason.to_string()),
+        ban: ban.clone(),
+        hostname: hostname.clone(),
+        geo: geo.clone(),
+        user_agent: user_agent.map(str::to_string),
+        timestamp: Utc::now(),
+    });
+
+    let reason = describe_block(reason, ban.as_ref());
+    let mut client = match &hostname {
+        Some(hostname) => format!("{} ({})", client_ip, hostname),
+        None => client_ip.to_string(),
+    };
+    if let Some(geo) = geo.describe() {
+        client = format!("{} [{}]", client, geo);
+    }
+    if !enforced {
+        // Observe-only mode: record the decision but let the request through
+        warn!("👀 WOULD BLOCK: IP {} accessed {} [{}, ENFORCE=false]", client, path, reason);
+        Metrics::incr(&state.metrics.would_block);
+        return Ok(());
+    }
+
+    warn!("🚫 BLOCKED: IP {} attempted to access {} [{}]", client, path, reason);
+    Metrics::incr(&state.metrics.blocked);
+    if state.tarpit.hold().await {
+        Metrics::incr(&state.metrics.tarpitted);
+    }
+    let mut block = state.config.block_response.clone();
+    if let Some(expires) = ban.as_ref().and_then(|ban| ban.expires) {
+        block = expiring(block, expires);
+    }
+    if state.config.block_reason_header {
+        return Err(BlockResponse::WithReason(Box::new(block), reason));
+    }
+    Err(block)
+}
+
+/// Tells a client blocked until `expires` when to come back, see
+/// [`BlockResponse::Expiring`].
+///
+/// Only on 403 and 429 answers: a 404 hides the service and a redirect
+/// leads elsewhere.
+fn expiring(block: BlockResponse, expires: DateTime<Utc>) -> BlockResponse {
+    match block.status() {
+        403 | 429 => BlockResponse::Expiring(Box::new(block), expires),
+        _ => block,
+    }
+}
+
+/// Converts the configured block response into an HTTP response.
+pub fn block_response(block: &BlockResponse) -> Response {
+    match block {
+        BlockResponse::Status(code) => StatusCode::from_u16(*code)
+            .unwrap_or(StatusCode::FORBIDDEN)
+            .into_response(),
+        BlockResponse::Redirect(url) => {
+            (StatusCode::FOUND, [(LOCATION, url.as_str())]).into_response()
+        }
+        BlockResponse::RetryAfter(delay) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after_secs(*delay).to_string())],
+        )
+            .into_response(),
+        BlockResponse::Unauthorized(realm) => (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, basic_challenge(realm))],
+        )
+            .into_response(),
+        BlockResponse::Challenge(page) => (
+            StatusCode::FORBIDDEN,
+            [
+                (CONTENT_TYPE, "text/html; charset=utf-8"),
+                (CACHE_CONTROL, "no-store"),
+            ],
+            page.clone(),
+        )
+            .into_response(),
+        BlockResponse::Upstream(upstream) => {
+            let mut response = upstream.body.clone().into_response();
+            *response.status_mut() =
+                StatusCode::from_u16(upstream.status).unwrap_or(StatusCode::FORBIDDEN);
+            response.headers_mut().remove(CONTENT_TYPE);
+            append_headers(response.headers_mut(), &upstream.headers);
+            response
+        }
+        BlockResponse::Maintenance(retry_after, page) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [
+                (RETRY_AFTER, retry_after_secs(*retry_after).to_string()),
+                (CONTENT_TYPE, "text/html; charset=utf-8".to_string()),
+                (CACHE_CONTROL, "no-store".to_string()),
+            ],
+            page.clone(),
+        )
+            .into_response(),
+        BlockResponse::WithReason(block, reason) => {
+            let mut response = block_response(block);
+            if let Ok(value) = HeaderValue::from_str(reason) {
+                response.headers_mut().insert(BLOCK_REASON, value);
+            }
+            response
+        }
+        BlockResponse::Expiring(block, expires) => {
+            let mut response = block_response(block);
+            let (retry_after, expires) = expiry_headers(*expires);
+            let headers = response.headers_mut();
+            headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            if let Ok(value) = HeaderValue::from_str(&expires) {
+                headers.insert(BAN_EXPIRES, value);
+            }
+            response
+        }
+    }
+}
+
+/// `Retry-After` seconds and `X-Ban-Expires` time (RFC 3339) of a block
+/// lasting until `expires`.
+pub fn expiry_headers(expires: DateTime<Utc>) -> (u64, String) {
+    let left = (expires - Utc::now()).to_std().unwrap_or_default();
+    let expires = expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    (retry_after_secs(left), expires)
+}
+
+/// `WWW-Authenticate` value asking for Basic credentials.
+pub fn basic_challenge(realm: &str) -> String {
+    format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm.replace(['"', '\\'], ""))
+}
+
+/// `Retry-After` value in whole seconds, rounded up so clients never retry
+/// too early.
+pub fn retry_after_secs(delay: Duration) -> u64 {
+    delay.as_secs() + u64::from(delay.subsec_nanos() > 0)
+}
+
+/// URI (path and query) of the original request being authorized.
+///
+/// Traefik's ForwardAuth sends the original URI in `X-Forwarded-Uri`; the
+/// URI of the auth request itself is only used when that header is absent.
+fn forwarded_uri(headers: &HeaderMap, req: &Request) -> String {
+    headers
+        .get("x-forwarded-uri")
+        .and_then(|h| h.to_str().ok())
+        .filter(|uri| !uri.is_empty() && !uri.starts_with(['?', '#']))
+        .unwrap_or_else(|| req.uri().path_and_query().map_or("/", |pq| pq.as_str()))
+        .to_string()
+}
+
+/// Host of the original request, without the port: `X-Forwarded-Host`,
+/// falling back to `Host`.
+pub fn forwarded_host(headers: &HeaderMap) -> Option<&str> {
+    let host = ["x-forwarded-host", "host"]
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|h| h.to_str().ok()))?
+        .trim();
+    let host = match host.strip_prefix('[') {
+        // IPv6 literal, keeping the brackets
+        Some(rest) => &host[..rest.find(']').map_or(host.len(), |end| end + 2)],
+        None => host.split(':').next().unwrap_or(host),
+    };
+    Some(host).filter(|h| !h.is_empty())
+}
+
+/// Whether `path` equals one of `prefixes` or lies below it.
+pub fn path_matches(prefixes: &[String], path: &str) -> bool {
+    prefixes.iter().any(|prefix| {
+        let prefix = prefix.trim_end_matches('/');
+        path.starts_with(prefix) && matches!(path.as_bytes().get(prefix.len()), None | Some(b'/'))
+    })
+}
+
+/// Splits a request URI into its path and query string; the fragment is
+/// dropped.
+pub fn split_uri(uri: &str) -> (&str, &str) {
+    let uri = uri.split('#').next().unwrap_or(uri);
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    (if path.is_empty() { "/" } else { path }, query)
 }
 
 // === Handler for all routes ===
@@ -102,16 +1041,140 @@ pub async fn handler() -> impl IntoResponse {
 pub struct HealthResponse {
     status: String,
     banned_ip_count: usize,
+    banned_ip_files: Vec<ListFile>,
+    /// Malformed list lines skipped on the last refresh of each list
+    rejected_lines: usize,
+    enforce: bool,
+    requests_allowed: u64,
+    requests_blocked: u64,
+    requests_would_block: u64,
+    requests_tarpitted: u64,
+    requests_honeypot: u64,
+    /// Requests whose query matched a `QUERY_RULES` rule
+    requests_query_rule: u64,
+    /// Clients banned for their bot score
+    requests_bot_banned: u64,
+    requests_greylisted: u64,
+    /// Requests over their rate limit
+    requests_rate_limited: u64,
+    /// Requests counted locally because Redis couldn't be reached
+    /// (`RATE_LIMIT_REDIS`)
+    rate_limit_fallbacks: u64,
+    requests_challenged: u64,
+    challenges_passed: u64,
+    requests_unauthorized: u64,
+    requests_shed: u64,
+    requests_timed_out: u64,
+    /// Requests answered 503 by maintenance mode
+    requests_maintenance: u64,
+    /// Requests blocked by a lockdown
+    requests_locked_out: u64,
+    /// End of the current lockdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lockdown_until: Option<DateTime<Utc>>,
+    /// Requests whose ban and reputation checks were skipped, see
+    /// [`crate::decision_cache`]
+    decision_cache_hits: u64,
+    admin_auth_failures: u64,
+    /// Gossip datagrams dropped for a bad signature or a stale timestamp
+    gossip_dropped: u64,
+    /// Time spent deciding ForwardAuth requests
+    decision_latency: DecisionLatency,
+    /// Expiry sweeps run, see [`crate::sweeper`]
+    sweeps: u64,
+    /// Expired dynamic bans removed by the sweeps
+    swept_bans: u64,
+    /// Stale offense, greylist and rate limit entries removed by the sweeps
+    swept_counters: u64,
+    sweep_duration: LatencySummary,
+    /// Background tasks restarted after a panic
+    task_restarts: u64,
+    dynamic_ban_count: usize,
+    /// Blocked requests per client country, when a GeoIP database is set
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    blocked_by_country: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+pub struct DecisionLatency {
+    allowed: LatencySummary,
+    /// Includes tarpit delays
+    blocked: LatencySummary,
 }
 
 // === Health check handler ===
 pub async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     let cache = state.banned_ips.read().await;
-    let count = cache.ips.len();
+    let count = cache.len();
+    let files = cache.files.clone();
+    let rejected_lines = cache.rejected_lines();
     drop(cache);
 
     Json(HealthResponse {
         status: "ok".to_string(),
         banned_ip_count: count,
+        banned_ip_files: files,
+        rejected_lines,
+        enforce: state.enforce(),
+        requests_allowed: Metrics::get(&state.metrics.allowed),
+        requests_blocked: Metrics::get(&state.metrics.blocked),
+        requests_would_block: Metrics::get(&state.metrics.would_block),
+        requests_tarpitted: Metrics::get(&state.metrics.tarpitted),
+        requests_honeypot: Metrics::get(&state.metrics.honeypot_hits),
+        requests_query_rule: Metrics::get(&state.metrics.query_rule_hits),
+        requests_bot_banned: Metrics::get(&state.metrics.bot_bans),
+        requests_greylisted: Metrics::get(&state.metrics.greylisted),
+        requests_rate_limited: Metrics::get(&state.metrics.rate_limited),
+        rate_limit_fallbacks: Metrics::get(&state.metrics.rate_limit_fallbacks),
+        requests_challenged: Metrics::get(&state.metrics.challenged),
+        challenges_passed: Metrics::get(&state.metrics.challenges_passed),
+        requests_unauthorized: Metrics::get(&state.metrics.unauthorized),
+        requests_shed: Metrics::get(&state.metrics.shed),
+        requests_timed_out: Metrics::get(&state.metrics.timed_out),
+        requests_maintenance: Metrics::get(&state.metrics.maintenance),
+        requests_locked_out: Metrics::get(&state.metrics.locked_out),
+        lockdown_until: lockdown::status(&state).map(|(until, _)| until),
+        decision_cache_hits: Metrics::get(&state.metrics.decision_cache_hits),
+        admin_auth_failures: Metrics::get(&state.metrics.admin_auth_failures),
+        gossip_dropped: Metrics::get(&state.metrics.gossip_dropped),
+        decision_latency: DecisionLatency {
+            allowed: state.metrics.latency_allowed.summary(),
+            blocked: state.metrics.latency_blocked.summary(),
+        },
+        sweeps: Metrics::get(&state.metrics.sweeps),
+        swept_bans: Metrics::get(&state.metrics.swept_bans),
+        swept_counters: Metrics::get(&state.metrics.swept_counters),
+        sweep_duration: state.metrics.latency_sweep.summary(),
+        task_restarts: Metrics::get(&state.metrics.task_restarts),
+        dynamic_ban_count: state.dynamic_bans.len(),
+        blocked_by_country: state.metrics.blocked_by_country.snapshot(),
     })
 }
+
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    ready: bool,
+    /// Whether the banned IPs files were loaded at least once
+    banned_ips_loaded: bool,
+    tasks: Vec<TaskStatus>,
+}
+
+// === Readiness handler ===
+/// Answers 503 until the banned IPs files are loaded and while a background
+/// task is stalled or stopped, so orchestrators route around the instance.
+pub async fn readiness(State(state): State<AppState>) -> Response {
+    let banned_ips_loaded = state.banned_ips.read().await.loaded;
+    let tasks = state.tasks.statuses();
+    let ready = banned_ips_loaded && tasks.iter().all(|t| t.state == TaskState::Running);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = ReadyResponse {
+        ready,
+        banned_ips_loaded,
+        tasks,
+    };
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,270 @@
+//! CrowdSec bouncer integration.
+//!
+//! When `CROWDSEC_LAPI_URL` is set the service acts as a CrowdSec bouncer:
+//! it polls the Local API decision stream and merges active `ban` decisions
+//! (single IPs and ranges) into the banned IPs cache under the `crowdsec`
+//! source. With machine credentials configured, automatic bans made by this
+//! service (e.g. honeypot hits) are pushed back to the Local API as alerts so
+//! the rest of the CrowdSec network learns about them.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{sync::broadcast::error::RecvError, time::sleep};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::CrowdSecConfig, events::SecurityEvent, lists::parse_entry, supervisor, AppState,
+};
+
+/// Cache source name for CrowdSec decisions.
+pub const SOURCE: &str = "crowdsec";
+
+/// Origin reported on decisions pushed back to CrowdSec.
+const ORIGIN: &str = "tezcatlipoca";
+
+/// Response of `GET /v1/decisions/stream`.
+#[derive(Debug, Default, Deserialize)]
+struct DecisionStream {
+    #[serde(default)]
+    new: Option<Vec<Decision>>,
+    #[serde(default)]
+    deleted: Option<Vec<Decision>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Decision {
+    scope: String,
+    value: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl Decision {
+    /// The banned address or network, for `ban` decisions with `Ip` or
+    /// `Range` scope. Other remediations (captcha) and scopes are ignored.
+    fn banned_net(&self) -> Option<IpNet> {
+        let scope_ok =
+            self.scope.eq_ignore_ascii_case("ip") || self.scope.eq_ignore_ascii_case("range");
+        if !self.kind.eq_ignore_ascii_case("ban") || !scope_ok {
+            return None;
+        }
+        parse_entry(self.value.trim())
+    }
+}
+
+/// Polls the decision stream forever, applying deltas to the cache.
+///
+/// The first successful poll uses `startup=true`, which returns every active
+/// decision and replaces the source contents; later polls only return what
+/// changed since the previous one.
+pub async fn decision_stream_task(state: AppState, config: CrowdSecConfig) {
+    let mut startup = true;
+    loop {
+        match fetch_decisions(&state.http, &config, startup).await {
+            Ok(stream) => {
+                apply_decisions(&state, stream, startup).await;
+                startup = false;
+                supervisor::ran();
+            }
+            Err(e) => {
+                warn!("Failed to pull CrowdSec decisions: {}", e);
+                supervisor::failed(&e);
+            }
+        }
+        sleep(config.poll_interval).await;
+    }
+}
+
+async fn fetch_decisions(
+    http: &reqwest::Client,
+    config: &CrowdSecConfig,
+    startup: bool,
+) -> Result<DecisionStream, reqwest::Error> {
+    let url = format!("{}/v1/decisions/stream", config.lapi_url);
+    let stream = http
+        .get(url)
+        .query(&[("startup", startup)])
+        .header("X-Api-Key", &config.api_key)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Option<DecisionStream>>()
+        .await?;
+    Ok(stream.unwrap_or_default())
+}
+
+async fn apply_decisions(state: &AppState, stream: DecisionStream, startup: bool) {
+    let new = stream.new.unwrap_or_default();
+    let deleted = stream.deleted.unwrap_or_default();
+    let added: Vec<IpNet> = new.iter().filter_map(Decision::banned_net).collect();
+    let removed: Vec<IpNet> = deleted.iter().filter_map(Decision::banned_net).collect();
+    let skipped = new.len() - added.len();
+
+    let mut cache = state.banned_ips.write().await;
+    let entries = cache.source_mut(SOURCE);
+    if startup {
+        entries.clear();
+    }
+    entries.update(added.iter().copied(), removed.iter().copied());
+    let total = entries.len();
+    drop(cache);
+
+    if startup {
+        info!("Loaded {} CrowdSec ban decisions", total);
+    } else if !new.is_empty() || !deleted.is_empty() {
+        debug!(
+            "CrowdSec decisions updated: +{} -{} ({} active)",
+            added.len(),
+            removed.len(),
+            total
+        );
+    }
+    if skipped > 0 {
+        debug!("Ignored {} CrowdSec decisions with unsupported scope or type", skipped);
+    }
+}
+
+/// Machine JWT returned by `POST /v1/watchers/login`.
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    token: String,
+    expire: DateTime<Utc>,
+}
+
+/// Pushes automatic bans to the Local API as alerts.
+///
+/// Requires `CROWDSEC_MACHINE_ID` and `CROWDSEC_MACHINE_PASSWORD`. The machine
+/// token is cached until shortly before it expires.
+pub async fn alert_push_task(state: AppState, config: CrowdSecConfig) {
+    let mut events = state.events.subscribe();
+    let mut token: Option<(String, Instant)> = None;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("CrowdSec alert pusher fell behind, {} events dropped", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let SecurityEvent::AutoBan {
+            ip,
+            path,
+            reason,
+            duration_secs,
+            timestamp,
+        } = event
+        else {
+            continue;
+        };
+
+        let alert = build_alert(&ip, &path, &reason, duration_secs, timestamp);
+        if let Err(e) = push_alert(&state.http, &config, &mut token, &alert).await {
+            warn!("Failed to push CrowdSec alert for {}: {}", ip, e);
+        } else {
+            debug!("Pushed CrowdSec alert for {} ({})", ip, reason);
+        }
+    }
+}
+
+fn build_alert(
+    ip: &str,
+    path: &str,
+    reason: &str,
+    duration_secs: Option<u64>,
+    timestamp: DateTime<Utc>,
+) -> serde_json::Value {
+    let scenario = format!("{}/{}", ORIGIN, reason);
+    let at = timestamp.to_rfc3339();
+    // CrowdSec has no "forever"; ten years is effectively permanent
+    let duration = format!("{}s", duration_secs.unwrap_or(10 * 365 * 24 * 3600));
+
+    json!([{
+        "scenario": scenario,
+        "scenario_hash": "",
+        "scenario_version": "",
+        "message": format!("{} banned by {} after requesting {}", ip, ORIGIN, path),
+        "events_count": 1,
+        "start_at": at,
+        "stop_at": at,
+        "capacity": 0,
+        "leakspeed": "0",
+        "simulated": false,
+        "events": [{
+            "timestamp": at,
+            "meta": [{ "key": "path", "value": path }],
+        }],
+        "source": { "scope": "Ip", "value": ip, "ip": ip },
+        "decisions": [{
+            "duration": duration,
+            "origin": ORIGIN,
+            "scenario": scenario,
+            "scope": "Ip",
+            "type": "ban",
+            "value": ip,
+        }],
+    }])
+}
+
+async fn push_alert(
+    http: &reqwest::Client,
+    config: &CrowdSecConfig,
+    token: &mut Option<(String, Instant)>,
+    alert: &serde_json::Value,
+) -> Result<(), reqwest::Error> {
+    // Retry once with a fresh token if the cached one was rejected
+    for attempt in 0..2 {
+        let jwt = match token {
+            Some((jwt, valid_until)) if *valid_until > Instant::now() => jwt.clone(),
+            _ => {
+                let (jwt, valid_until) = login(http, config).await?;
+                *token = Some((jwt.clone(), valid_until));
+                jwt
+            }
+        };
+
+        let res = http
+            .post(format!("{}/v1/alerts", config.lapi_url))
+            .bearer_auth(jwt)
+            .json(alert)
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::UNAUTHORIZED && attempt == 0 {
+            *token = None;
+            continue;
+        }
+        res.error_for_status()?;
+        break;
+    }
+    Ok(())
+}
+
+async fn login(
+    http: &reqwest::Client,
+    config: &CrowdSecConfig,
+) -> Result<(String, Instant), reqwest::Error> {
+    let res: LoginResponse = http
+        .post(format!("{}/v1/watchers/login", config.lapi_url))
+        .json(&json!({
+            "machine_id": config.machine_id,
+            "password": config.machine_password,
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // Renew a minute early to avoid racing the expiry
+    let lifetime = (res.expire - Utc::now())
+        .to_std()
+        .unwrap_or_default()
+        .saturating_sub(Duration::from_secs(60));
+    Ok((res.token, Instant::now() + lifetime))
+}
@@ -0,0 +1,96 @@
+//! Short-lived cache of block decisions per client IP.
+//!
+//! With `DECISION_CACHE_SIZE` above 0, the outcome of the ban, reputation,
+//! DNSBL, schedule, OPA, script and plugin checks for a client IP and request
+//! target (method, host, path and policy) is kept for `DECISION_CACHE_TTL`
+//! (1 second by default) in an LRU of that many entries. Bursts of the same
+//! request, like polling or a flood, then skip those checks and the GeoIP
+//! lookups they need. Honeypot paths, query and header rules, TLS
+//! fingerprints, bot scores, signatures, logins, challenges and the greylist
+//! are still checked on every request, and every request is still recorded
+//! in the metrics, stats and events.
+//!
+//! Entries are dropped as soon as the banned IPs (files, feeds, KV, ...) or
+//! the dynamic bans change. Other inputs (reputation scores arriving, OPA
+//! data, headers a script or plugin looks at) are only picked up once an
+//! entry expires.
+
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+use crate::events::BanDetail;
+
+/// What the checks decided for a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    /// Why the request is blocked, `None` when it may pass
+    pub reason: Option<String>,
+    /// The ban behind the block, if one is
+    pub ban: Option<BanDetail>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    decision: Decision,
+    stored: Instant,
+    /// Versions of the banned IPs and dynamic bans the decision was made with
+    versions: (u64, u64),
+}
+
+/// LRU of recent decisions by client IP and request target.
+#[derive(Debug)]
+pub struct DecisionCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<(IpAddr, String), Entry>>,
+}
+
+impl DecisionCache {
+    /// `None` when `capacity` is 0, which disables the cache.
+    pub fn new(capacity: usize, ttl: Duration) -> Option<Self> {
+        let capacity = NonZeroUsize::new(capacity)?;
+        Some(Self {
+            ttl,
+            entries: Mutex::new(LruCache::new(capacity)),
+        })
+    }
+
+    /// The decision for `ip` and `target`, unless older than the TTL or made
+    /// with other `versions` of the ban lists.
+    pub fn get(&self, ip: IpAddr, target: &str, versions: (u64, u64)) -> Option<Decision> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (ip, target.to_string());
+        let entry = entries.get(&key)?;
+        if entry.versions == versions && entry.stored.elapsed() < self.ttl {
+            return Some(entry.decision.clone());
+        }
+        entries.pop(&key);
+        None
+    }
+
+    /// Remembers the decision for `ip` and `target`, made with `versions`
+    /// of the ban lists.
+    pub fn insert(&self, ip: IpAddr, target: &str, versions: (u64, u64), decision: Decision) {
+        let entry = Entry {
+            decision,
+            stored: Instant::now(),
+            versions,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.put((ip, target.to_string()), entry);
+    }
+
+    /// Number of entries, expired or not.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,258 @@
+//! DNS blocklist (DNSBL) lookups (`dns` feature).
+//!
+//! With `DNSBL_ZONES` set, client IPs not on any ban list are looked up in
+//! each zone and blocked while listed in one, like a bad AbuseIPDB score.
+//! An address is listed when `<reversed address>.<zone>` resolves to an
+//! address in `127.0.0.0/8`; IPv4 octets and IPv6 nibbles are reversed, so
+//! `203.0.113.7` is queried as `7.113.0.203.zen.spamhaus.org`.
+//!
+//! Lookups never run on the request path: an unknown IP is queued for the
+//! background [`lookup_task`] and allowed through (fail-open) until its
+//! result is cached for `DNSBL_CACHE_TTL`. Failed lookups are cached
+//! briefly as unknown, blocking the IP meanwhile with `FAIL_MODE=closed`.
+//! Queries go to the system resolver, or to
+//! `DNSBL_RESOLVER` when set; note that some operators (e.g. Spamhaus)
+//! refuse queries relayed by large public resolvers.
+
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig},
+    name_server::TokioConnectionProvider,
+    ResolveError, TokioResolver,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{
+    abuseipdb::is_public, config::DnsblConfig, metrics::Metrics, shard::ShardedMap, AppState,
+};
+
+/// Maximum lookups waiting for the background task; extra IPs are dropped
+/// and retried on their next request.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How long a failed lookup is remembered before the IP is retried.
+const FAILURE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+struct CachedListing {
+    /// Zone listing the IP, `Some(None)` when no zone does and `None` when
+    /// the lookup failed
+    listing: Option<Option<String>>,
+    expires: Instant,
+}
+
+/// Lookup cache plus the queue feeding the lookup task.
+#[derive(Debug)]
+pub struct Dnsbl {
+    config: DnsblConfig,
+    results: ShardedMap<String, CachedListing>,
+    /// IPs queued or being looked up, to avoid duplicate queries
+    pending: Mutex<HashSet<String>>,
+    queue: mpsc::Sender<String>,
+    receiver: Mutex<Option<mpsc::Receiver<String>>>,
+}
+
+impl Dnsbl {
+    pub fn new(config: DnsblConfig) -> Self {
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            config,
+            results: ShardedMap::new(),
+            pending: Mutex::new(HashSet::new()),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Zone listing `ip`, queuing a lookup when it isn't known yet.
+    ///
+    /// Returns `None` for unknown, unlisted, failed or non-public
+    /// addresses, which the caller treats as "allow".
+    pub fn listing(&self, ip: &str) -> Option<String> {
+        let cached = self.results.get(ip);
+        if let Some(cached) = cached
+            && cached.expires > Instant::now()
+        {
+            return cached.listing.flatten();
+        }
+
+        if is_public(ip) {
+            self.enqueue(ip);
+        }
+        None
+    }
+
+    /// Whether the last lookup of `ip` failed and isn't due for a retry yet.
+    pub fn failed(&self, ip: &str) -> bool {
+        self.results
+            .get(ip)
+            .is_some_and(|cached| cached.listing.is_none() && cached.expires > Instant::now())
+    }
+
+    fn enqueue(&self, ip: &str) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.contains(ip) {
+            return;
+        }
+        if self.queue.try_send(ip.to_string()).is_ok() {
+            pending.insert(ip.to_string());
+        }
+    }
+
+    fn store(&self, ip: String, listing: Option<Option<String>>) {
+        let ttl = if listing.is_some() {
+            self.config.cache_ttl
+        } else {
+            FAILURE_TTL
+        };
+        let now = Instant::now();
+        // The cache limit is split evenly across shards
+        let shard_limit = self.config.max_cached.div_ceil(self.results.shard_count());
+        let mut results = self.results.write(&ip);
+        if results.len() >= shard_limit {
+            results.retain(|_, cached| cached.expires > now);
+            if results.len() >= shard_limit
+                && let Some(victim) = results.keys().next().cloned()
+            {
+                results.remove(&victim);
+            }
+        }
+        results.insert(
+            ip.clone(),
+            CachedListing {
+                listing,
+                expires: now + ttl,
+            },
+        );
+        drop(results);
+
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&ip);
+    }
+
+    /// Number of cached results, including expired ones not yet evicted.
+    pub fn cached_count(&self) -> usize {
+        self.results.len()
+    }
+}
+
+/// Name to resolve to find `ip` in `zone`.
+pub fn query_name(ip: IpAddr, zone: &str) -> String {
+    let mut name = String::new();
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            for octet in v4.octets().iter().rev() {
+                let _ = write!(name, "{}.", octet);
+            }
+        }
+        IpAddr::V6(v6) => {
+            for byte in v6.octets().iter().rev() {
+                let _ = write!(name, "{:x}.{:x}.", byte & 0xf, byte >> 4);
+            }
+        }
+    }
+    // Fully qualified, so resolver search domains are never appended
+    name.push_str(zone);
+    name.push('.');
+    name
+}
+
+/// Resolver querying `server`, or the name servers of the system
+/// configuration. Queries aren't retried, and answers aren't cached: callers
+/// keep their own results.
+pub(crate) fn resolver(
+    server: Option<SocketAddr>,
+    timeout: Duration,
+) -> Result<TokioResolver, ResolveError> {
+    let mut builder = match server {
+        Some(addr) => {
+            let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+            TokioResolver::builder_with_config(
+                ResolverConfig::from_parts(None, Vec::new(), servers),
+                TokioConnectionProvider::default(),
+            )
+        }
+        None => TokioResolver::builder_tokio()?,
+    };
+    let options = builder.options_mut();
+    options.timeout = timeout;
+    options.attempts = 1;
+    options.cache_size = 0;
+    Ok(builder.build())
+}
+
+/// Whether `zone` lists `ip`; `Err` when the zone couldn't be queried.
+async fn listed(resolver: &TokioResolver, ip: IpAddr, zone: &str) -> Result<bool, ResolveError> {
+    match resolver.ipv4_lookup(query_name(ip, zone)).await {
+        Ok(answer) => Ok(answer.iter().any(|a| a.0.octets()[0] == 127)),
+        Err(e) if e.is_nx_domain() || e.is_no_records_found() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Works through queued lookups one IP at a time, querying its zones
+/// concurrently.
+///
+/// Runs until the state is dropped. Does nothing if DNSBL lookups are
+/// disabled or the task was already started.
+pub async fn lookup_task(state: AppState) {
+    let Some(dnsbl) = state.dnsbl.clone() else {
+        return;
+    };
+    let Some(mut receiver) = dnsbl
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    else {
+        return;
+    };
+    let resolver = match resolver(dnsbl.config.resolver, dnsbl.config.timeout) {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            warn!("DNSBL lookups disabled, no usable resolver: {}", e);
+            return;
+        }
+    };
+
+    while let Some(ip) = receiver.recv().await {
+        let Ok(addr) = ip.parse::<IpAddr>() else {
+            dnsbl.store(ip, None);
+            continue;
+        };
+        Metrics::incr(&state.metrics.dnsbl_lookups);
+        let zones = &dnsbl.config.zones;
+        let results = join_all(zones.iter().map(|zone| listed(&resolver, addr, zone))).await;
+
+        let mut failed = false;
+        let mut listing = None;
+        for (zone, result) in zones.iter().zip(results) {
+            match result {
+                Ok(true) => {
+                    listing = Some(zone.clone());
+                    break;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("DNSBL lookup of {} in {} failed: {}", ip, zone, e);
+                    failed = true;
+                }
+            }
+        }
+        debug!("DNSBL result for {}: {:?}", ip, listing);
+        // A listing counts even when another zone failed
+        let listing = if listing.is_none() && failed { None } else { Some(listing) };
+        dnsbl.store(ip, listing);
+    }
+}
@@ -0,0 +1,326 @@
+//! Deployment check behind `tezcatlipoca-auth check-config`.
+//!
+//! Goes through what the service needs at startup without starting it:
+//! the banned IPs files are parsed like on a refresh, the other files the
+//! configuration names must be readable, the directories it writes to
+//! writable, and every URL one the service can use. Nothing is contacted
+//! over the network, so the check can run in CI against the environment of
+//! a deployment manifest.
+//!
+//! Invalid variables already fail the configuration before the check runs
+//! (unless `CONFIG_VALIDATION=warn`, where they are reported here).
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io,
+    path::Path,
+};
+
+use reqwest::Url;
+
+use crate::{
+    cache::BannedIpsCache,
+    client_ip,
+    config::{self, AuthMode, Config, LogTarget},
+    feeds::Source,
+    geoip::GeoIp,
+};
+
+/// Outcome of one check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, but likely not as intended
+    Warning,
+    /// Would keep the service from starting or working
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "ok",
+            Status::Warning => "warn",
+            Status::Failed => "FAIL",
+        })
+    }
+}
+
+/// One checked item: a file, a directory, a URL or a variable.
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub status: Status,
+    pub subject: String,
+    pub detail: String,
+}
+
+/// Every check made, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, status: Status, subject: impl Into<String>, detail: impl Into<String>) {
+        self.checks.push(Check {
+            status,
+            subject: subject.into(),
+            detail: detail.into(),
+        });
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+
+    /// Whether no check failed; warnings don't count.
+    pub fn passed(&self) -> bool {
+        self.count(Status::Failed) == 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{:<4}  {}: {}",
+                check.status, check.subject, check.detail
+            )?;
+        }
+        writeln!(
+            f,
+            "{} checks, {} failed, {} warnings: {}",
+            self.checks.len(),
+            self.count(Status::Failed),
+            self.count(Status::Warning),
+            if self.passed() { "ready" } else { "not ready" }
+        )
+    }
+}
+
+/// Checks the files, directories and URLs `config` refers to.
+pub async fn check(config: &Config) -> Report {
+    let mut report = Report::default();
+
+    for issue in &config.validation_warnings {
+        report.add(
+            Status::Failed,
+            &issue.var,
+            format!("{}, using the default", issue),
+        );
+    }
+    for name in &config.unknown_vars {
+        let detail = match config::suggest(name) {
+            Some(known) => format!("unknown variable, ignored (did you mean {}?)", known),
+            None => "unknown variable, ignored".to_string(),
+        };
+        report.add(Status::Warning, name, detail);
+    }
+
+    banned_ips(config, &mut report).await;
+
+    if client_ip::cloudflare_proxy_untrusted(config) {
+        report.add(
+            Status::Warning,
+            "TRUSTED_PROXIES",
+            "unset, so cf-connecting-ip is ignored behind a proxy not on loopback (e.g. Traefik in Docker); set it to the proxy's network",
+        );
+    }
+
+    if let Some(AuthMode::Basic { users_file }) = config.auth.as_ref().map(|auth| &auth.mode) {
+        readable(&mut report, "AUTH_USERS_FILE", users_file);
+    }
+    if let Some(path) = &config.script_file {
+        readable(&mut report, "SCRIPT_FILE", path);
+    }
+    if let Some(dir) = &config.plugin_dir {
+        match fs::read_dir(dir) {
+            Ok(_) => report.add(Status::Ok, "PLUGIN_DIR", format!("{} is readable", dir)),
+            Err(e) => report.add(Status::Failed, "PLUGIN_DIR", format!("{}: {}", dir, e)),
+        }
+    }
+    let databases = [
+        ("GEOIP_COUNTRY_DB", &config.geoip_country_db),
+        ("GEOIP_ASN_DB", &config.geoip_asn_db),
+    ];
+    for (var, path) in databases {
+        let Some(path) = path else { continue };
+        match GeoIp::open(Some(path), None) {
+            Some(_) => report.add(Status::Ok, var, format!("{} opened", path)),
+            None => report.add(
+                Status::Failed,
+                var,
+                format!("{} is not a MaxMind database", path),
+            ),
+        }
+    }
+
+    if config.log_target == LogTarget::File {
+        writable_dir(&mut report, "LOG_DIR", Path::new(&config.log_dir));
+    }
+    let written = [
+        ("BAN_HISTORY_FILE", &config.ban_history_file),
+        ("STATE_SNAPSHOT_FILE", &config.state_snapshot_file),
+        ("AUDIT_LOG", &config.audit_log),
+    ];
+    for (var, path) in written {
+        if let Some(path) = path.as_deref().filter(|p| !p.starts_with("unix://")) {
+            writable_file(&mut report, var, path);
+        }
+    }
+
+    urls(config, &mut report);
+    report
+}
+
+/// Parses the banned IPs files like a refresh, reporting each file.
+async fn banned_ips(config: &Config, report: &mut Report) {
+    for path in &config.banned_ips_files {
+        if !Path::new(path).exists() {
+            report.add(
+                Status::Warning,
+                "BANNED_IPS_FILE",
+                format!("{} not found, treated as empty", path),
+            );
+        }
+    }
+    let mut cache = BannedIpsCache::new(config.cache_ttl);
+    if let Err(e) = cache.refresh(&config.banned_ips_files).await {
+        report.add(Status::Failed, "BANNED_IPS_FILE", e.to_string());
+        return;
+    }
+    for file in &cache.files {
+        let (status, rejected) = match file.rejected {
+            0 => (Status::Ok, String::new()),
+            n => (
+                Status::Warning,
+                format!(", {} malformed line(s) skipped", n),
+            ),
+        };
+        let detail = format!("{}: {} entries{}", file.path, file.entries, rejected);
+        report.add(status, "BANNED_IPS_FILE", detail);
+    }
+}
+
+fn readable(report: &mut Report, var: &str, path: &str) {
+    match fs::File::open(path) {
+        Ok(_) => report.add(Status::Ok, var, format!("{} is readable", path)),
+        Err(e) => report.add(Status::Failed, var, format!("{}: {}", path, e)),
+    }
+}
+
+/// Whether a file can be created in `dir`, by creating and removing one.
+fn try_write(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".tezcatlipoca-check-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(probe)
+}
+
+fn writable_dir(report: &mut Report, var: &str, dir: &Path) {
+    // A missing directory is created at startup if its parent allows it
+    let existing = dir
+        .ancestors()
+        .find(|d| d.as_os_str().is_empty() || d.exists());
+    let existing = match existing {
+        Some(d) if d.as_os_str().is_empty() => Path::new("."),
+        Some(d) => d,
+        None => Path::new("/"),
+    };
+    let detail = match try_write(existing) {
+        Ok(()) if existing == dir => Ok(format!("{} is writable", dir.display())),
+        Ok(()) => Ok(format!("{} will be created", dir.display())),
+        Err(e) => Err(format!("{}: {}", existing.display(), e)),
+    };
+    match detail {
+        Ok(detail) => report.add(Status::Ok, var, detail),
+        Err(detail) => report.add(Status::Failed, var, detail),
+    }
+}
+
+fn writable_file(report: &mut Report, var: &str, path: &str) {
+    if Path::new(path).exists() {
+        match OpenOptions::new().append(true).open(path) {
+            Ok(_) => report.add(Status::Ok, var, format!("{} is writable", path)),
+            Err(e) => report.add(Status::Failed, var, format!("{}: {}", path, e)),
+        }
+        return;
+    }
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match try_write(parent) {
+        Ok(()) => report.add(Status::Ok, var, format!("{} can be created", path)),
+        Err(e) => report.add(Status::Failed, var, format!("{}: {}", parent.display(), e)),
+    }
+}
+
+/// Checks that every configured URL parses with a scheme its client speaks.
+fn urls(config: &Config, report: &mut Report) {
+    const HTTP: &[&str] = &["http", "https"];
+    let mut urls: Vec<(String, &str, &[&str])> = Vec::new();
+    if config.cloudflare_ips_refresh.is_some() {
+        for url in &config.cloudflare_ips_urls {
+            urls.push(("CLOUDFLARE_IPS_URLS".to_string(), url, HTTP));
+        }
+    }
+    if let Some(crowdsec) = &config.crowdsec {
+        urls.push(("CROWDSEC_LAPI_URL".to_string(), &crowdsec.lapi_url, HTTP));
+    }
+    if let Some(AuthMode::Ldap(ldap)) = config.auth.as_ref().map(|auth| &auth.mode) {
+        urls.push(("LDAP_URL".to_string(), &ldap.url, &["ldap", "ldaps"]));
+    }
+    if let Some(upstream) = &config.upstream_auth {
+        urls.push(("UPSTREAM_AUTH_URL".to_string(), &upstream.url, HTTP));
+    }
+    if let Some(opa) = &config.opa {
+        urls.push(("OPA_URL".to_string(), &opa.url, HTTP));
+    }
+    if let Some(webhook) = &config.webhook {
+        urls.push(("WEBHOOK_URL".to_string(), &webhook.url, HTTP));
+    }
+    if let Some(redis) = &config.redis {
+        urls.push(("REDIS_URL".to_string(), &redis.url, &["redis", "rediss"]));
+    }
+    if let Some(kv) = &config.kv {
+        urls.push(("KV_URL".to_string(), &kv.url, HTTP));
+    }
+    if let Some(loki) = &config.loki {
+        urls.push(("LOKI_URL".to_string(), &loki.url, HTTP));
+    }
+    for feed in &config.feeds {
+        let subject = format!("feed '{}'", feed.name);
+        match Source::new(&feed.url) {
+            Ok(Source::Http(_)) => urls.push((subject, &feed.url, HTTP)),
+            #[cfg(feature = "object-store")]
+            Ok(Source::Object(_)) => {
+                report.add(Status::Ok, subject, format!("{} is usable", feed.url))
+            }
+            Err(e) => report.add(Status::Failed, subject, format!("{}: {}", feed.url, e)),
+        }
+    }
+
+    for (subject, url, schemes) in urls {
+        match Url::parse(url) {
+            Ok(parsed) if schemes.contains(&parsed.scheme()) => {
+                report.add(Status::Ok, subject, format!("{} is usable", url))
+            }
+            Ok(parsed) => report.add(
+                Status::Failed,
+                subject,
+                format!(
+                    "{}: unsupported scheme {}, expected {}",
+                    url,
+                    parsed.scheme(),
+                    schemes.join(" or ")
+                ),
+            ),
+            Err(e) => report.add(Status::Failed, subject, format!("{}: {}", url, e)),
+        }
+    }
+}
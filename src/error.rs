@@ -0,0 +1,89 @@
+//! Crate-wide error type.
+//!
+//! Modules keep their own error types where callers need the details (e.g.
+//! [`FeedError`]); [`Error`] wraps them, with the file or source that
+//! failed, so library users and the admin API handle one type and can still
+//! tell a broken configuration from an unreadable banned IPs file or an
+//! unreachable feed. Admin handlers return it too, and answer with
+//! [`Error::status`].
+
+use std::io;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    auth::AuthError, cloudflare::RefreshError, config::ConfigError, feeds::FeedError,
+    vault::VaultError,
+};
+
+/// Result with the crate's [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors of the library API.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Invalid environment variables
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// A banned IPs file or directory couldn't be read
+    #[error("failed to read banned IPs from {path}: {source}")]
+    Cache {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A remote blocklist couldn't be downloaded
+    #[error("failed to fetch feed '{name}': {source}")]
+    Feed {
+        name: String,
+        #[source]
+        source: FeedError,
+    },
+    /// Cloudflare's IP ranges couldn't be refreshed
+    #[error("failed to refresh Cloudflare IP ranges: {0}")]
+    CloudflareRanges(#[from] RefreshError),
+    /// Secrets couldn't be loaded from Vault
+    #[error("failed to load secrets from Vault: {0}")]
+    Vault(#[from] VaultError),
+    /// The request wasn't authenticated
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    /// An admin request had an invalid parameter
+    #[error("{0}")]
+    BadRequest(String),
+    /// An admin request named something that doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+}
+
+impl Error {
+    /// Wraps an error reading the banned IPs at `path`.
+    pub fn cache(path: impl Into<String>, source: io::Error) -> Self {
+        Error::Cache {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Status an admin endpoint answers with for this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Config(_) | Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Cache { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Feed { .. } | Error::CloudflareRanges(_) | Error::Vault(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            Error::Auth(_) => StatusCode::UNAUTHORIZED,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.status(), self.to_string()).into_response()
+    }
+}
@@ -0,0 +1,182 @@
+//! In-process bus for security events.
+//!
+//! The middleware publishes events (blocked requests, automatic bans) without
+//! knowing who consumes them; integrations like the CrowdSec alert pusher and
+//! the webhook sender subscribe and handle them in their own background
+//! tasks. Publishing never blocks: with no subscribers the event is dropped,
+//! and slow subscribers lose the oldest events rather than holding up
+//! requests.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::geoip::Geo;
+
+/// Number of events buffered per subscriber before the oldest are dropped.
+const EVENT_BUFFER: usize = 1024;
+
+/// A security-relevant event produced while handling requests.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SecurityEvent {
+    /// A request from a banned client was rejected (or would have been,
+    /// when `enforced` is false because of `ENFORCE=false`)
+    Blocked {
+        ip: String,
+        path: String,
+        reason: String,
+        /// The ban behind the block, if one is
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ban: Option<BanDetail>,
+        /// Forward-confirmed reverse DNS name of the client (`RDNS`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hostname: Option<String>,
+        enforced: bool,
+        timestamp: DateTime<Utc>,
+    },
+    /// The service banned an IP on its own (e.g. a honeypot hit)
+    AutoBan {
+        ip: String,
+        path: String,
+        reason: String,
+        /// Ban length in seconds, `None` when permanent
+        duration_secs: Option<u64>,
+        timestamp: DateTime<Utc>,
+    },
+    /// An operator lifted the dynamic ban of an IP
+    Unban { ip: String, timestamp: DateTime<Utc> },
+    /// An operator changed how long the dynamic ban of an IP lasts
+    BanUpdated {
+        ip: String,
+        reason: String,
+        /// Ban length in seconds from now, `None` when permanent
+        duration_secs: Option<u64>,
+        timestamp: DateTime<Utc>,
+    },
+    /// An operator started (`until` set) or lifted a lockdown
+    Lockdown {
+        until: Option<DateTime<Utc>>,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+/// Where the ban behind a block comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BanDetail {
+    /// Ban list or mechanism, e.g. `files`, `crowdsec`, `local` (automatic
+    /// bans of this instance), `gossip` or `import`
+    pub source: String,
+    /// Free text recorded with the ban, e.g. `honeypot`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// End of a temporary ban
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// `reason`, followed by the ban's source and free text when there is one,
+/// e.g. `DYNAMIC BAN (local: honeypot)`.
+pub fn describe_block(reason: &str, ban: Option<&BanDetail>) -> String {
+    match ban {
+        Some(BanDetail {
+            source,
+            reason: Some(text),
+            ..
+        }) => format!("{} ({}: {})", reason, source, text),
+        Some(BanDetail {
+            source,
+            reason: None,
+            ..
+        }) => format!("{} ({})", reason, source),
+        None => reason.to_string(),
+    }
+}
+
+/// Outcome of the IP check for a single request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Allowed,
+    Blocked,
+    /// Banned, but let through because enforcement is disabled
+    WouldBlock,
+    /// First request from an unknown IP, deferred by the greylist
+    Greylisted,
+    /// Over its rate limit, told to retry later
+    RateLimited,
+    /// Suspicious IP sent the challenge page
+    Challenged,
+    /// Missing or invalid request signature
+    Unauthorized,
+}
+
+/// Per-request decision, published only while someone is watching.
+#[derive(Clone, Debug, Serialize)]
+pub struct DecisionEvent {
+    pub ip: String,
+    pub path: String,
+    pub verdict: Verdict,
+    pub reason: Option<String>,
+    /// The ban behind a block, if one is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ban: Option<BanDetail>,
+    /// Forward-confirmed reverse DNS name of a blocked client (`RDNS`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Country and ASN of the client, when GeoIP databases are configured
+    #[serde(flatten)]
+    pub geo: Geo,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Broadcast channels shared through `AppState`.
+///
+/// Security events and per-request decisions use separate channels: decisions
+/// are high-volume, and mixing them in would push rare security events out of
+/// slow subscribers' buffers.
+#[derive(Debug)]
+pub struct EventBus {
+    tx: broadcast::Sender<SecurityEvent>,
+    decisions: broadcast::Sender<DecisionEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        let (decisions, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx, decisions }
+    }
+
+    /// Publishes an event to all current subscribers.
+    pub fn publish(&self, event: SecurityEvent) {
+        // An error only means nobody is subscribed
+        let _ = self.tx.send(event);
+    }
+
+    /// Subscribes to events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<SecurityEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Publishes a request decision if anyone is subscribed.
+    ///
+    /// The event is built lazily so unobserved requests pay nothing.
+    pub fn publish_decision(&self, event: impl FnOnce() -> DecisionEvent) {
+        if self.decisions.receiver_count() > 0 {
+            let _ = self.decisions.send(event());
+        }
+    }
+
+    /// Subscribes to per-request decisions published from now on.
+    pub fn subscribe_decisions(&self) -> broadcast::Receiver<DecisionEvent> {
+        self.decisions.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,498 @@
+//! Envoy `ext_authz` gRPC server.
+//!
+//! Available with the `ext-authz` feature and started when `EXT_AUTHZ_PORT`
+//! is set. Envoy (or Istio) calls `envoy.service.auth.v3.Authorization/Check`
+//! for every request; the answer comes from the same decision engine as the
+//! ForwardAuth endpoint ([`controllers::check`]), so bans, honeypots,
+//! reputation, metrics and events behave identically for both.
+//!
+//! The client address is resolved like [`ClientIp`] does for HTTP: proxy
+//! headers forwarded by Envoy first, then the downstream source address.
+//!
+//! Only the fields of the Envoy API this service reads or writes are
+//! modelled in [`proto`]; unknown fields are skipped by the decoder.
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    task::{Context, Poll},
+};
+
+use axum::http::{self, HeaderMap, HeaderName, HeaderValue};
+use futures_util::future::BoxFuture;
+use tonic::{
+    body::Body,
+    codegen::{Body as HttpBody, Service, StdError},
+    server::{Grpc, NamedService, UnaryService},
+    transport::Server,
+};
+use tracing::{info, warn};
+
+use crate::{
+    auth, challenge,
+    client_ip::{ClientIp, Trust},
+    config::BlockResponse,
+    controllers, maintenance, AppState,
+};
+
+/// Subset of the `envoy.service.auth.v3` messages.
+pub mod proto {
+    use std::collections::HashMap;
+
+    /// `envoy.service.auth.v3.CheckRequest`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CheckRequest {
+        #[prost(message, optional, tag = "1")]
+        pub attributes: Option<AttributeContext>,
+    }
+
+    /// `envoy.service.auth.v3.AttributeContext`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AttributeContext {
+        /// Downstream peer, i.e. the client connected to Envoy
+        #[prost(message, optional, tag = "1")]
+        pub source: Option<Peer>,
+        #[prost(message, optional, tag = "4")]
+        pub request: Option<Request>,
+    }
+
+    /// `envoy.service.auth.v3.AttributeContext.Peer`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Peer {
+        #[prost(message, optional, tag = "1")]
+        pub address: Option<Address>,
+    }
+
+    /// `envoy.config.core.v3.Address`, socket addresses only
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Address {
+        #[prost(message, optional, tag = "1")]
+        pub socket_address: Option<SocketAddress>,
+    }
+
+    /// `envoy.config.core.v3.SocketAddress`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SocketAddress {
+        #[prost(string, tag = "2")]
+        pub address: String,
+        #[prost(uint32, tag = "3")]
+        pub port_value: u32,
+    }
+
+    /// `envoy.service.auth.v3.AttributeContext.Request`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Request {
+        #[prost(message, optional, tag = "2")]
+        pub http: Option<HttpRequest>,
+    }
+
+    /// `envoy.service.auth.v3.AttributeContext.HttpRequest`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpRequest {
+        #[prost(string, tag = "2")]
+        pub method: String,
+        /// Lower-cased header names
+        #[prost(map = "string, string", tag = "3")]
+        pub headers: HashMap<String, String>,
+        /// Path including the query string
+        #[prost(string, tag = "4")]
+        pub path: String,
+        #[prost(string, tag = "5")]
+        pub host: String,
+    }
+
+    /// `envoy.service.auth.v3.CheckResponse`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CheckResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<RpcStatus>,
+        #[prost(oneof = "HttpResponse", tags = "2, 3")]
+        pub http_response: Option<HttpResponse>,
+    }
+
+    /// `CheckResponse.http_response`
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum HttpResponse {
+        #[prost(message, tag = "2")]
+        Denied(DeniedHttpResponse),
+        #[prost(message, tag = "3")]
+        Ok(OkHttpResponse),
+    }
+
+    /// `google.rpc.Status`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RpcStatus {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(string, tag = "2")]
+        pub message: String,
+    }
+
+    /// `envoy.service.auth.v3.DeniedHttpResponse`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DeniedHttpResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<HttpStatus>,
+        #[prost(message, repeated, tag = "2")]
+        pub headers: Vec<HeaderValueOption>,
+        #[prost(string, tag = "3")]
+        pub body: String,
+    }
+
+    /// `envoy.config.core.v3.HeaderValueOption`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderValueOption {
+        #[prost(message, optional, tag = "1")]
+        pub header: Option<HeaderValue>,
+    }
+
+    /// `envoy.config.core.v3.HeaderValue`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    /// `envoy.type.v3.HttpStatus`; the enum values are the HTTP codes
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpStatus {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+    }
+
+    /// `envoy.service.auth.v3.OkHttpResponse`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OkHttpResponse {
+        /// Added to the request sent upstream
+        #[prost(message, repeated, tag = "2")]
+        pub headers: Vec<HeaderValueOption>,
+        /// Added to the response sent to the client
+        #[prost(message, repeated, tag = "6")]
+        pub response_headers_to_add: Vec<HeaderValueOption>,
+    }
+}
+
+use proto::{CheckRequest, CheckResponse};
+
+/// gRPC service name implemented by [`ExtAuthzService`].
+pub const SERVICE_NAME: &str = "envoy.service.auth.v3.Authorization";
+
+/// `google.rpc.Code` values used in check responses.
+const CODE_OK: i32 = 0;
+const CODE_PERMISSION_DENIED: i32 = 7;
+
+/// Answers one `Check` call.
+///
+/// Requests without any usable client address are denied.
+pub async fn authorize(state: &AppState, req: CheckRequest) -> CheckResponse {
+    let attributes = req.attributes.unwrap_or_default();
+    let http = attributes
+        .request
+        .and_then(|r| r.http)
+        .unwrap_or_default();
+
+    let mut headers = header_map(&http.headers);
+    // Envoy passes the authority separately from the headers
+    if !headers.contains_key(http::header::HOST)
+        && let Ok(host) = HeaderValue::from_str(&http.host)
+        && !http.host.is_empty()
+    {
+        headers.insert(http::header::HOST, host);
+    }
+    let peer = attributes
+        .source
+        .and_then(|p| p.address)
+        .and_then(|a| a.socket_address)
+        .and_then(|s| {
+            let ip = s.address.parse::<IpAddr>().ok()?;
+            Some(SocketAddr::new(ip, s.port_value as u16))
+        });
+    let client_ip = {
+        let config = &state.config;
+        let ranges = state.cloudflare_ips.read().unwrap_or_else(|e| e.into_inner());
+        let trust = Trust::new(config, &ranges);
+        ClientIp::resolve(&headers, peer, &config.client_ip_sources, trust)
+    };
+    let Some(ClientIp(ip)) = client_ip else {
+        return denied(&BlockResponse::Status(403), "no client address");
+    };
+
+    let (path, query) = controllers::split_uri(&http.path);
+
+    if let Some(block) = maintenance::block(state, ip, path) {
+        return denied(&block, "maintenance");
+    }
+    if let Some(block) = controllers::locked_out(state, ip, path, &headers).await {
+        return denied(&block, "lockdown");
+    }
+
+    if let Some(config) = &state.config.challenge
+        && path == config.path
+    {
+        return match challenge::verify(state, ip, query, &headers).await {
+            Ok(passed) => denied_response(
+                http::StatusCode::FOUND,
+                vec![
+                    header_option("location", passed.location),
+                    header_option("set-cookie", passed.set_cookie),
+                ],
+                String::new(),
+                "challenge passed",
+            ),
+            Err(block) => denied(&block, "challenge failed"),
+        };
+    }
+
+    if let Some(result) =
+        auth::totp_endpoint(state, ip, &http.method, path, query, &headers).await
+    {
+        return match result {
+            Ok(passed) => denied_response(
+                http::StatusCode::FOUND,
+                vec![
+                    header_option("location", passed.location),
+                    header_option("set-cookie", passed.set_cookie),
+                ],
+                String::new(),
+                "second factor passed",
+            ),
+            Err(block) => denied(&block, "second factor failed"),
+        };
+    }
+
+    match controllers::check(state, ip, &http.method, path, query, &headers).await {
+        Ok(allowed) => CheckResponse {
+            status: Some(proto::RpcStatus {
+                code: CODE_OK,
+                message: String::new(),
+            }),
+            http_response: Some(proto::HttpResponse::Ok(allowed_response(allowed))),
+        },
+        Err(block) => denied(&block, "blocked"),
+    }
+}
+
+/// Identity headers go to the upstream service, cookies to the client.
+fn allowed_response(allowed: controllers::Allowed) -> proto::OkHttpResponse {
+    let (cookies, upstream): (Vec<_>, Vec<_>) = allowed
+        .upstream_headers
+        .into_iter()
+        .partition(|(name, _)| name.eq_ignore_ascii_case("set-cookie"));
+    proto::OkHttpResponse {
+        headers: allowed
+            .user
+            .map(|user| (auth::USER_HEADER.to_string(), user))
+            .into_iter()
+            .chain(upstream)
+            .map(|(name, value)| header_option(&name, value))
+            .collect(),
+        response_headers_to_add: allowed
+            .set_cookie
+            .map(|cookie| ("set-cookie".to_string(), cookie))
+            .into_iter()
+            .chain(cookies)
+            .map(|(name, value)| header_option(&name, value))
+            .collect(),
+    }
+}
+
+fn denied(block: &BlockResponse, message: &str) -> CheckResponse {
+    let status = http::StatusCode::from_u16(block.status()).unwrap_or(http::StatusCode::FORBIDDEN);
+    let (headers, body) = denied_parts(block, status);
+    denied_response(status, headers, body, message)
+}
+
+/// Headers and body of the denied response.
+fn denied_parts(
+    block: &BlockResponse,
+    status: http::StatusCode,
+) -> (Vec<proto::HeaderValueOption>, String) {
+    let reason = status.canonical_reason().unwrap_or_default().to_string();
+    match block {
+        BlockResponse::Redirect(url) => (vec![header_option("location", url.clone())], reason),
+        BlockResponse::RetryAfter(delay) => (
+            vec![header_option(
+                "retry-after",
+                controllers::retry_after_secs(*delay).to_string(),
+            )],
+            reason,
+        ),
+        BlockResponse::Challenge(page) => (
+            vec![
+                header_option("content-type", "text/html; charset=utf-8".to_string()),
+                header_option("cache-control", "no-store".to_string()),
+            ],
+            page.clone(),
+        ),
+        BlockResponse::Unauthorized(realm) => (
+            vec![header_option("www-authenticate", controllers::basic_challenge(realm))],
+            reason,
+        ),
+        BlockResponse::Upstream(upstream) => (
+            upstream
+                .headers
+                .iter()
+                .map(|(name, value)| header_option(name, value.clone()))
+                .collect(),
+            upstream.body.clone(),
+        ),
+        BlockResponse::Maintenance(retry_after, page) => (
+            vec![
+                header_option(
+                    "retry-after",
+                    controllers::retry_after_secs(*retry_after).to_string(),
+                ),
+                header_option("content-type", "text/html; charset=utf-8".to_string()),
+                header_option("cache-control", "no-store".to_string()),
+            ],
+            page.clone(),
+        ),
+        BlockResponse::WithReason(block, text) => {
+            let (mut headers, body) = denied_parts(block, status);
+            headers.push(header_option(controllers::BLOCK_REASON, text.clone()));
+            (headers, body)
+        }
+        BlockResponse::Expiring(block, expires) => {
+            let (mut headers, body) = denied_parts(block, status);
+            let (retry_after, expires) = controllers::expiry_headers(*expires);
+            headers.push(header_option("retry-after", retry_after.to_string()));
+            headers.push(header_option(controllers::BAN_EXPIRES, expires));
+            (headers, body)
+        }
+        BlockResponse::Status(_) => (Vec::new(), reason),
+    }
+}
+
+fn denied_response(
+    status: http::StatusCode,
+    headers: Vec<proto::HeaderValueOption>,
+    body: String,
+    message: &str,
+) -> CheckResponse {
+    CheckResponse {
+        status: Some(proto::RpcStatus {
+            code: CODE_PERMISSION_DENIED,
+            message: message.to_string(),
+        }),
+        http_response: Some(proto::HttpResponse::Denied(proto::DeniedHttpResponse {
+            status: Some(proto::HttpStatus {
+                code: status.as_u16() as i32,
+            }),
+            headers,
+            body,
+        })),
+    }
+}
+
+fn header_option(key: &str, value: String) -> proto::HeaderValueOption {
+    proto::HeaderValueOption {
+        header: Some(proto::HeaderValue {
+            key: key.to_string(),
+            value,
+        }),
+    }
+}
+
+/// Envoy sends headers as a string map; invalid names or values are dropped.
+fn header_map(headers: &std::collections::HashMap<String, String>) -> HeaderMap {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Tower service routing `Authorization/Check` calls to [`authorize`].
+#[derive(Clone)]
+pub struct ExtAuthzService {
+    state: AppState,
+}
+
+impl ExtAuthzService {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl NamedService for ExtAuthzService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+/// Unary handler for the `Check` method.
+struct CheckMethod(AppState);
+
+impl UnaryService<CheckRequest> for CheckMethod {
+    type Response = CheckResponse;
+    type Future = BoxFuture<'static, Result<tonic::Response<CheckResponse>, tonic::Status>>;
+
+    fn call(&mut self, request: tonic::Request<CheckRequest>) -> Self::Future {
+        let state = self.0.clone();
+        Box::pin(async move {
+            let response = authorize(&state, request.into_inner()).await;
+            Ok(tonic::Response::new(response))
+        })
+    }
+}
+
+impl<B> Service<http::Request<B>> for ExtAuthzService
+where
+    B: HttpBody + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != "/envoy.service.auth.v3.Authorization/Check" {
+            return Box::pin(async { Ok(unimplemented()) });
+        }
+        let method = CheckMethod(self.state.clone());
+        Box::pin(async move {
+            let mut grpc = Grpc::new(tonic_prost::ProstCodec::default());
+            Ok(grpc.unary(method, req).await)
+        })
+    }
+}
+
+/// Response for methods this service doesn't implement.
+fn unimplemented() -> http::Response<Body> {
+    let mut response = http::Response::new(Body::default());
+    let headers = response.headers_mut();
+    headers.insert(
+        tonic::Status::GRPC_STATUS,
+        (tonic::Code::Unimplemented as i32).into(),
+    );
+    headers.insert(http::header::CONTENT_TYPE, tonic::metadata::GRPC_CONTENT_TYPE);
+    response
+}
+
+/// Serves the ext_authz API on `hostname:port` until the process exits.
+pub async fn serve(state: AppState, port: u16) {
+    let addr = format!("{}:{}", state.config.hostname, port);
+    let addr: SocketAddr = match tokio::net::lookup_host(&addr).await.map(|mut a| a.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            warn!("Failed to resolve ext_authz address {}", addr);
+            return;
+        }
+    };
+    info!("Starting Envoy ext_authz gRPC server on {}", addr);
+    if let Err(e) = Server::builder()
+        .add_service(ExtAuthzService::new(state))
+        .serve(addr)
+        .await
+    {
+        warn!("ext_authz gRPC server failed: {}", e);
+    }
+}
@@ -0,0 +1,221 @@
+//! Remote blocklist feeds.
+//!
+//! A feed is a text list of IPs and CIDR networks fetched over HTTP(S) on a
+//! schedule and merged into the banned IPs cache under its own source name.
+//! Tor exit nodes, FireHOL `.netset` files and Spamhaus DROP lists all use
+//! this path (see [`crate::lists`] for the accepted format). Each feed runs in
+//! its own background task; a failed download keeps the previously loaded
+//! entries so a flaky upstream doesn't silently unban everyone.
+//!
+//! Refreshes are conditional: the `ETag` and `Last-Modified` validators of
+//! the last download are sent back, and a `304 Not Modified` answer keeps
+//! the current entries without re-downloading or re-parsing anything.
+//! Responses are requested gzip-compressed.
+//!
+//! With the `object-store` feature, a feed URL can also name an object in an
+//! S3, GCS or MinIO bucket (see [`crate::storage`]).
+
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "object-store")]
+use crate::storage::{self, ObjectList};
+use crate::{
+    cache::IpSet,
+    config::FeedConfig,
+    error::{self, Error},
+    lists::{log_rejected, parse_list, RejectedLine},
+    supervisor,
+    AppState,
+};
+
+/// Cache validators from the last successful download of a feed.
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Result of a conditional feed download.
+#[derive(Debug)]
+pub enum Fetched {
+    /// The server answered `304 Not Modified`
+    NotModified,
+    /// New list contents and the validators to send next time
+    Updated {
+        entries: Box<IpSet>,
+        rejected: Vec<RejectedLine>,
+        validators: Validators,
+    },
+}
+
+/// Why a feed download failed
+#[derive(Debug, thiserror::Error)]
+pub enum FeedError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[cfg(feature = "object-store")]
+    #[error(transparent)]
+    Storage(#[from] object_store::Error),
+}
+
+/// Where a feed is downloaded from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// An `http://` or `https://` URL
+    Http(String),
+    /// An object in a bucket (`s3://` or `gs://`)
+    #[cfg(feature = "object-store")]
+    Object(ObjectList),
+}
+
+impl Source {
+    /// Source of a feed URL.
+    pub fn new(url: &str) -> Result<Self, FeedError> {
+        #[cfg(feature = "object-store")]
+        if storage::is_object_url(url) {
+            return Ok(Source::Object(ObjectList::from_url(url)?));
+        }
+        Ok(Source::Http(url.to_string()))
+    }
+
+    /// Downloads the list unless it is unchanged since `validators` were
+    /// taken, and parses it.
+    pub async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        validators: &Validators,
+    ) -> Result<Fetched, FeedError> {
+        match self {
+            Source::Http(url) => Ok(fetch(http, url, validators).await?),
+            #[cfg(feature = "object-store")]
+            Source::Object(object) => Ok(object.fetch(validators).await?),
+        }
+    }
+}
+
+/// Fetches `feed` every `feed.refresh` and replaces its cache source.
+pub async fn refresh_task(state: AppState, feed: FeedConfig) {
+    let source = match Source::new(&feed.url) {
+        Ok(source) => source,
+        Err(e) => {
+            warn!("Feed '{}' disabled, {} can't be used: {}", feed.name, feed.url, e);
+            return;
+        }
+    };
+    let mut validators = Validators::default();
+    loop {
+        match source.fetch(&state.http, &validators).await {
+            Ok(Fetched::NotModified) => {
+                debug!("Feed '{}' not modified, keeping current entries", feed.name);
+                supervisor::ran();
+            }
+            Ok(Fetched::Updated {
+                entries,
+                rejected,
+                validators: latest,
+            }) => {
+                validators = latest;
+                store(&state, &feed, *entries, &rejected).await;
+                supervisor::ran();
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to refresh feed '{}' from {}, keeping previous entries: {}",
+                    feed.name, feed.url, e
+                );
+                supervisor::failed(&e);
+            }
+        }
+        sleep(feed.refresh).await;
+    }
+}
+
+/// Downloads `feed` right away, ignoring the validators of the scheduled
+/// task, and replaces its cache source. Returns the number of entries.
+pub async fn refresh_now(state: &AppState, feed: &FeedConfig) -> error::Result<usize> {
+    let fetched = match Source::new(&feed.url) {
+        Ok(source) => source.fetch(&state.http, &Validators::default()).await,
+        Err(e) => Err(e),
+    };
+    let fetched = fetched.map_err(|source| Error::Feed {
+        name: feed.name.clone(),
+        source,
+    })?;
+    match fetched {
+        Fetched::Updated {
+            entries, rejected, ..
+        } => Ok(store(state, feed, *entries, &rejected).await),
+        // Not sent without validators, but keep the current entries if it is
+        Fetched::NotModified => Ok(state
+            .banned_ips
+            .read()
+            .await
+            .sources
+            .get(&feed.name)
+            .map_or(0, IpSet::len)),
+    }
+}
+
+async fn store(
+    state: &AppState,
+    feed: &FeedConfig,
+    entries: IpSet,
+    rejected: &[RejectedLine],
+) -> usize {
+    let count = entries.len();
+    log_rejected(&feed.name, rejected);
+    // The set is built before taking the lock, so requests only wait for the
+    // pointer swap, not for parsing
+    state
+        .banned_ips
+        .write()
+        .await
+        .set_source(&feed.name, entries, rejected.len());
+    info!("Feed '{}' refreshed with {} entries", feed.name, count);
+    count
+}
+
+/// Downloads a feed over HTTP unless it is unchanged since `validators`
+/// were taken, and parses it.
+pub async fn fetch(
+    http: &reqwest::Client,
+    url: &str,
+    validators: &Validators,
+) -> Result<Fetched, reqwest::Error> {
+    let mut req = http.get(url);
+    if let Some(etag) = &validators.etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        req = req.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let res = req.send().await?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+    let res = res.error_for_status()?;
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    let body = res.text().await?;
+    let (entries, rejected) = parse_list(&body);
+    Ok(Fetched::Updated {
+        entries: Box::new(entries.into_iter().collect()),
+        rejected,
+        validators,
+    })
+}
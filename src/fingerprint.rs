@@ -0,0 +1,48 @@
+//! TLS fingerprint blocklist (`BLOCKED_TLS_FINGERPRINTS`).
+//!
+//! Botnets rotate IPs but usually keep the same TLS stack, so its JA3 or JA4
+//! fingerprint stays the same across addresses. TLS ends at Traefik, which
+//! doesn't compute fingerprints itself; a plugin can add them to the
+//! forwarded request in headers (`TLS_FINGERPRINT_HEADERS`, `X-JA3-Hash` and
+//! `X-JA4` by default). A request carrying a listed fingerprint in any of
+//! those headers is blocked with the reason `TLS FINGERPRINT` followed by the
+//! fingerprint.
+//!
+//! Fingerprints are compared without regard to ASCII case. The list is
+//! usually long and kept in `BLOCKED_TLS_FINGERPRINTS_FILE`, one per line;
+//! `#` starts a comment.
+
+use std::collections::HashSet;
+
+use axum::http::{HeaderMap, HeaderName};
+
+/// Default `TLS_FINGERPRINT_HEADERS`
+pub const DEFAULT_HEADERS: &[&str] = &["x-ja3-hash", "x-ja4"];
+
+/// Parses fingerprints separated by commas, whitespace or lines, with `#`
+/// comments, into lower case.
+pub fn parse_list(s: &str) -> HashSet<String> {
+    s.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|fingerprint| !fingerprint.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// The first blocked fingerprint found in `fingerprint_headers`.
+pub fn blocked(
+    blocklist: &HashSet<String>,
+    fingerprint_headers: &[HeaderName],
+    headers: &HeaderMap,
+) -> Option<String> {
+    if blocklist.is_empty() {
+        return None;
+    }
+    fingerprint_headers
+        .iter()
+        .flat_map(|name| headers.get_all(name))
+        .filter_map(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .find(|fingerprint| blocklist.contains(fingerprint))
+}
@@ -0,0 +1,114 @@
+//! Country and ASN of client IPs from MaxMind databases.
+//!
+//! `GEOIP_COUNTRY_DB` names a GeoIP2/GeoLite2 Country (or City) database and
+//! `GEOIP_ASN_DB` a GeoLite2 ASN database, both in the `.mmdb` format. When
+//! either is set, every decision is annotated with what they know about the
+//! client (`country`, `asn`, `as_org` in the audit trail, Loki and the admin
+//! event stream), and blocks are counted per country. Nothing is blocked
+//! because of it.
+//!
+//! Databases are read into memory at startup; restart to load updates.
+
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use tracing::error;
+
+/// What the databases know about an IP; empty without databases.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Geo {
+    /// ISO 3166-1 alpha-2 code of the country the IP is located in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Autonomous system announcing the IP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// Organization owning that autonomous system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+}
+
+impl Geo {
+    /// Short form for log lines, e.g. `DE, AS3320`.
+    pub fn describe(&self) -> Option<String> {
+        match (&self.country, self.asn) {
+            (Some(country), Some(asn)) => Some(format!("{}, AS{}", country, asn)),
+            (Some(country), None) => Some(country.clone()),
+            (None, Some(asn)) => Some(format!("AS{}", asn)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Opened GeoIP databases.
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl std::fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIp")
+            .field("country", &self.country.as_ref().map(|db| &db.metadata.database_type))
+            .field("asn", &self.asn.as_ref().map(|db| &db.metadata.database_type))
+            .finish()
+    }
+}
+
+impl GeoIp {
+    /// Opens the configured databases. A database that can't be read is
+    /// logged and left out; `None` when no database is usable.
+    pub fn open(country_db: Option<&str>, asn_db: Option<&str>) -> Option<Self> {
+        let geoip = Self {
+            country: country_db.and_then(open),
+            asn: asn_db.and_then(open),
+        };
+        (geoip.country.is_some() || geoip.asn.is_some()).then_some(geoip)
+    }
+
+    /// Whether the country database is open.
+    pub fn has_country(&self) -> bool {
+        self.country.is_some()
+    }
+
+    /// Whether the ASN database is open.
+    pub fn has_asn(&self) -> bool {
+        self.asn.is_some()
+    }
+
+    /// Looks up `ip`; fields the databases have no answer for stay `None`.
+    pub fn lookup(&self, ip: &str) -> Geo {
+        let Ok(ip) = ip.parse::<IpAddr>() else {
+            return Geo::default();
+        };
+        let ip = ip.to_canonical();
+        let mut geo = Geo::default();
+        if let Some(db) = &self.country
+            && let Ok(record) = db.lookup::<geoip2::Country>(ip)
+        {
+            geo.country = record
+                .country
+                .or(record.registered_country)
+                .and_then(|country| country.iso_code)
+                .map(str::to_string);
+        }
+        if let Some(db) = &self.asn
+            && let Ok(record) = db.lookup::<geoip2::Asn>(ip)
+        {
+            geo.asn = record.autonomous_system_number;
+            geo.as_org = record.autonomous_system_organization.map(str::to_string);
+        }
+        geo
+    }
+}
+
+fn open(path: &str) -> Option<Reader<Vec<u8>>> {
+    match Reader::open_readfile(path) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            error!("Failed to open GeoIP database {}: {}", path, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Ban propagation between replicas over UDP.
+//!
+//! For HA deployments without a shared store: with `GOSSIP_BIND` set, every
+//! automatic ban (e.g. a honeypot hit) is sent as one datagram to each
+//! address in `GOSSIP_PEERS`, and bans received from peers are applied to the
+//! local dynamic bans. Peers are resolved on every send, so a headless
+//! Kubernetes service name works as a peer.
+//!
+//! A datagram is a 32-byte HMAC-SHA256 tag over the JSON message that
+//! follows it, keyed with `GOSSIP_SECRET`. Messages with a bad tag, sent
+//! more than [`MAX_AGE`] ago (or ahead), or already seen are dropped, so a
+//! captured datagram can't be replayed later. Bad and stale datagrams are
+//! only logged at debug level, since anyone can send them, and are counted
+//! as `gossip_dropped` in `/health`. Received bans are not passed
+//! on: every replica lists the others itself.
+//!
+//! Delivery is best effort, like UDP. A replica that was down while a ban
+//! was sent doesn't learn it; `STATE_SNAPSHOT_FILE` still keeps its own bans
+//! across restarts.
+
+use std::{io, num::NonZeroUsize, time::Duration};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    sync::broadcast::error::RecvError,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    bans::SOURCE_GOSSIP, config::GossipConfig, events::SecurityEvent, metrics::Metrics, AppState,
+};
+
+/// Oldest (or most future-dated) message accepted, bounding replays and
+/// clock skew between replicas.
+pub const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Length of the signature prefix of a datagram.
+const TAG_LEN: usize = 32;
+
+/// Message ids remembered to drop duplicates.
+const SEEN_CAPACITY: usize = 4096;
+
+/// A ban announced to the other replicas.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanMessage {
+    /// Random id of the sending instance, to ignore our own messages
+    pub origin: u64,
+    /// Increases by one per message of an origin
+    pub id: u64,
+    pub sent: DateTime<Utc>,
+    pub ip: String,
+    pub reason: String,
+    /// Ban length in seconds, `None` when permanent
+    pub duration_secs: Option<u64>,
+}
+
+/// Why a datagram was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum GossipError {
+    /// The signature doesn't match, e.g. a different `GOSSIP_SECRET`
+    #[error("bad signature")]
+    BadSignature,
+    /// Not a valid message
+    #[error("malformed message")]
+    Malformed,
+    /// Sent outside [`MAX_AGE`] of now
+    #[error("stale message")]
+    Stale,
+}
+
+/// Signs `message` into a datagram.
+pub fn seal(message: &BanMessage, secret: &str) -> Vec<u8> {
+    let body = serde_json::to_vec(message).unwrap_or_default();
+    let mut datagram = mac(secret, &body).finalize().into_bytes().to_vec();
+    datagram.extend_from_slice(&body);
+    datagram
+}
+
+/// Checks the signature and age of a datagram and decodes it.
+pub fn open(datagram: &[u8], secret: &str) -> Result<BanMessage, GossipError> {
+    if datagram.len() <= TAG_LEN {
+        return Err(GossipError::Malformed);
+    }
+    let (tag, body) = datagram.split_at(TAG_LEN);
+    // `verify_slice` compares in constant time
+    mac(secret, body)
+        .verify_slice(tag)
+        .map_err(|_| GossipError::BadSignature)?;
+    let message: BanMessage = serde_json::from_slice(body).map_err(|_| GossipError::Malformed)?;
+    let age = (Utc::now() - message.sent).abs().to_std().unwrap_or(Duration::MAX);
+    if age > MAX_AGE {
+        return Err(GossipError::Stale);
+    }
+    Ok(message)
+}
+
+fn mac(secret: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac
+}
+
+/// Binds the gossip socket.
+pub async fn bind(config: &GossipConfig) -> io::Result<UdpSocket> {
+    UdpSocket::bind(config.bind).await
+}
+
+/// Sends automatic bans to the peers and applies bans received from them.
+pub async fn gossip_task(state: AppState, config: GossipConfig, socket: UdpSocket) {
+    let mut origin = [0u8; 8];
+    getrandom::fill(&mut origin).expect("OS random number generator is available");
+    let origin = u64::from_le_bytes(origin);
+    let mut next_id = 0;
+    let mut seen = LruCache::new(NonZeroUsize::new(SEEN_CAPACITY).expect("capacity is not zero"));
+    let mut events = state.events.subscribe();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(SecurityEvent::AutoBan { ip, reason, duration_secs, .. }) => {
+                    next_id += 1;
+                    let message = BanMessage {
+                        origin,
+                        id: next_id,
+                        sent: Utc::now(),
+                        ip,
+                        reason,
+                        duration_secs,
+                    };
+                    send(&socket, &config, &seal(&message, &config.secret)).await;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Gossip fell behind, {} security events not propagated", missed)
+                }
+                Err(RecvError::Closed) => return,
+            },
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Failed to receive gossip: {}", e);
+                        continue;
+                    }
+                };
+                let message = match open(&buf[..len], &config.secret) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        debug!("Dropping gossip from {}: {:?}", from, e);
+                        Metrics::incr(&state.metrics.gossip_dropped);
+                        continue;
+                    }
+                };
+                let duplicate = seen.put((message.origin, message.id), ()).is_some();
+                if message.origin == origin || duplicate {
+                    continue;
+                }
+                info!(
+                    "Ban of {} received from peer {} [{}]",
+                    message.ip, from, message.reason
+                );
+                state.dynamic_bans.ban(
+                    &message.ip,
+                    message.duration_secs.map(Duration::from_secs),
+                    &message.reason,
+                    SOURCE_GOSSIP,
+                );
+            }
+        }
+    }
+}
+
+async fn send(socket: &UdpSocket, config: &GossipConfig, datagram: &[u8]) {
+    for peer in &config.peers {
+        let addrs = match lookup_host(peer.as_str()).await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                warn!("Failed to resolve gossip peer {}: {}", peer, e);
+                continue;
+            }
+        };
+        for addr in addrs {
+            match socket.send_to(datagram, addr).await {
+                Ok(_) => debug!("Sent ban to gossip peer {}", addr),
+                Err(e) => warn!("Failed to send ban to gossip peer {}: {}", addr, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Greylisting for first-time client IPs.
+//!
+//! With `GREYLIST=true`, the first request from an IP that isn't known yet is
+//! answered with `429 Too Many Requests` and a `Retry-After` header. Once
+//! `GREYLIST_DELAY` has passed, the next request is let through and the IP
+//! is trusted for `GREYLIST_TTL`. Browsers and well-behaved clients retry;
+//! most fire-and-forget bots never come back.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::shard::ShardedMap;
+
+#[derive(Clone, Copy, Debug)]
+enum Entry {
+    /// Deferred once, may pass from `since + delay`
+    Pending { since: Instant },
+    /// Retried after the delay; trusted until `since + ttl`
+    Passed { since: Instant },
+}
+
+/// Greylist state of one IP, as saved in the runtime state snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GreylistEntry {
+    pub ip: String,
+    /// Retried after the delay and now trusted
+    pub passed: bool,
+    /// When the IP was deferred, or when it passed
+    pub since: DateTime<Utc>,
+}
+
+/// Greylist state per client IP.
+#[derive(Debug)]
+pub struct Greylist {
+    delay: Duration,
+    ttl: Duration,
+    entries: ShardedMap<String, Entry>,
+}
+
+impl Greylist {
+    pub fn new(delay: Duration, ttl: Duration) -> Self {
+        Self {
+            delay,
+            ttl,
+            entries: ShardedMap::new(),
+        }
+    }
+
+    /// Checks a request from `ip`, returning how long the client should
+    /// wait before retrying, or `None` when the request may pass.
+    pub fn check(&self, ip: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let mut entries = self.entries.write(ip);
+        match entries.get(ip).copied() {
+            Some(Entry::Passed { since }) if since.elapsed() < self.ttl => None,
+            Some(Entry::Pending { since }) if since.elapsed() < self.ttl => {
+                let waited = since.elapsed();
+                if waited >= self.delay {
+                    entries.insert(ip.to_string(), Entry::Passed { since: now });
+                    None
+                } else {
+                    Some(self.delay - waited)
+                }
+            }
+            // Unknown, or trust has expired
+            _ => {
+                entries.insert(ip.to_string(), Entry::Pending { since: now });
+                Some(self.delay)
+            }
+        }
+    }
+
+    /// Drops entries older than the trust period, returning how many.
+    pub fn prune(&self) -> usize {
+        self.entries.retain(|_, entry| match entry {
+            Entry::Pending { since } | Entry::Passed { since } => since.elapsed() < self.ttl,
+        })
+    }
+
+    /// Snapshot of the tracked IPs, in no particular order.
+    pub fn export(&self) -> Vec<GreylistEntry> {
+        let now = Utc::now();
+        self.entries
+            .entries()
+            .into_iter()
+            .filter_map(|(ip, entry)| {
+                let (passed, since) = match entry {
+                    Entry::Pending { since } => (false, since),
+                    Entry::Passed { since } => (true, since),
+                };
+                let age = chrono::Duration::from_std(since.elapsed()).ok()?;
+                Some(GreylistEntry {
+                    ip,
+                    passed,
+                    since: now - age,
+                })
+            })
+            .collect()
+    }
+
+    /// Re-adds exported entries, dropping those past the trust period.
+    pub fn restore(&self, entries: Vec<GreylistEntry>) {
+        let now = Utc::now();
+        for entry in entries {
+            let age = (now - entry.since).to_std().unwrap_or_default();
+            if age >= self.ttl {
+                continue;
+            }
+            let Some(since) = Instant::now().checked_sub(age) else {
+                continue;
+            };
+            let state = if entry.passed {
+                Entry::Passed { since }
+            } else {
+                Entry::Pending { since }
+            };
+            self.entries.insert(entry.ip, state);
+        }
+    }
+
+    /// Number of IPs currently tracked (deferred or trusted).
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,132 @@
+//! Request header rules that block matching requests (`HEADER_RULES`).
+//!
+//! Rules are separated by `;` and block a request when:
+//!
+//! - `x-debug`: the header is present
+//! - `!x-internal-token`: the header is missing, e.g. a header the internal
+//!   proxy always adds
+//! - `x-requested-with=BotNet`: a value of the header is exactly this
+//! - `user-agent~(?i)masscan|zgrab`: the regular expression matches a value of
+//!   the header (write a `;` in it as `\x3b`)
+//!
+//! Header names ignore ASCII case. Requests blocked by a rule carry the reason
+//! `HEADER RULE` followed by the rule, and are not banned.
+
+use std::{collections::HashMap, fmt};
+
+use axum::http::{HeaderMap, HeaderName};
+
+use crate::patterns::PatternSet;
+
+/// A `HEADER_RULES` entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderRule {
+    pub name: HeaderName,
+    pub condition: Condition,
+}
+
+/// What a header must look like for a [`HeaderRule`] to block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Present,
+    Missing,
+    Equals(String),
+    Pattern(String),
+}
+
+impl HeaderRule {
+    /// Parses one rule; `None` for an invalid header name. Expressions are
+    /// checked when compiled by [`HeaderRules::parse`].
+    pub fn parse(s: &str) -> Option<Self> {
+        let (name, condition) = match s.find(['=', '~']) {
+            Some(at) if s.as_bytes()[at] == b'=' => {
+                (&s[..at], Condition::Equals(s[at + 1..].trim().to_string()))
+            }
+            Some(at) => (&s[..at], Condition::Pattern(s[at + 1..].trim().to_string())),
+            None => match s.strip_prefix('!') {
+                Some(name) => (name, Condition::Missing),
+                None => (s, Condition::Present),
+            },
+        };
+        let name = HeaderName::try_from(name.trim().to_ascii_lowercase()).ok()?;
+        Some(Self { name, condition })
+    }
+}
+
+impl fmt::Display for HeaderRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.condition {
+            Condition::Present => write!(f, "{}", self.name),
+            Condition::Missing => write!(f, "!{}", self.name),
+            Condition::Equals(value) => write!(f, "{}={}", self.name, value),
+            Condition::Pattern(pattern) => write!(f, "{}~{}", self.name, pattern),
+        }
+    }
+}
+
+/// `HEADER_RULES` with their expressions compiled.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+    /// The expressions of the `Pattern` rules of each header, in order
+    patterns: HashMap<HeaderName, PatternSet>,
+}
+
+impl HeaderRules {
+    /// Parses rules separated by `;` and compiles their expressions.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        let mut sources: HashMap<HeaderName, Vec<String>> = HashMap::new();
+        for rule in s.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let rule = HeaderRule::parse(rule).ok_or_else(|| format!("invalid rule '{}'", rule))?;
+            if let Condition::Pattern(pattern) = &rule.condition {
+                sources.entry(rule.name.clone()).or_default().push(pattern.clone());
+            }
+            rules.push(rule);
+        }
+        let mut patterns = HashMap::new();
+        for (name, sources) in sources {
+            patterns.insert(name, PatternSet::new(&sources)?);
+        }
+        Ok(Self { rules, patterns })
+    }
+
+    pub fn rules(&self) -> &[HeaderRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first rule blocking a request with `headers`.
+    pub fn matching(&self, headers: &HeaderMap) -> Option<&HeaderRule> {
+        let values = |name: &HeaderName| {
+            headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                .collect::<Vec<_>>()
+        };
+        // One pass over each header's values for all of its expressions
+        let mut matched: HashMap<&HeaderName, std::vec::IntoIter<bool>> = self
+            .patterns
+            .iter()
+            .map(|(name, set)| {
+                let values = values(name);
+                (name, set.matches(values.iter().map(|v| v.as_ref())).into_iter())
+            })
+            .collect();
+        self.rules.iter().find(|rule| match &rule.condition {
+            Condition::Present => headers.contains_key(&rule.name),
+            Condition::Missing => !headers.contains_key(&rule.name),
+            Condition::Equals(expected) => {
+                values(&rule.name).iter().any(|value| value == expected.as_str())
+            }
+            Condition::Pattern(_) => matched
+                .get_mut(&rule.name)
+                .and_then(|matched| matched.next())
+                .unwrap_or(false),
+        })
+    }
+}
@@ -0,0 +1,18 @@
+//! Honeypot (trap) path matching.
+//!
+//! Trap paths are URLs no legitimate visitor requests, such as `/.env` or
+//! `/wp-login.php` on a site that doesn't run WordPress. Any client asking for
+//! one is assumed to be a scanner and gets banned dynamically.
+
+/// Whether `path` hits one of the configured trap paths.
+///
+/// A trap matches the exact path or anything below it (`/phpmyadmin` matches
+/// `/phpmyadmin/index.php`), ignoring ASCII case since scanners vary it.
+pub fn is_trap(trap_paths: &[String], path: &str) -> bool {
+    trap_paths.iter().any(|trap| {
+        let trap = trap.trim_end_matches('/');
+        path.len() >= trap.len()
+            && path[..trap.len()].eq_ignore_ascii_case(trap)
+            && matches!(path.as_bytes().get(trap.len()), None | Some(b'/'))
+    })
+}
@@ -0,0 +1,303 @@
+//! Bans and config overrides watched in etcd or Consul.
+//!
+//! With `KV_BACKEND` set, every key under `KV_PREFIX` is read and then
+//! watched, so a change made with `consul kv put` or `etcdctl put` reaches
+//! every replica within a moment:
+//!
+//! - `<prefix>bans/<name>`: a blocklist in the [`crate::lists`] format, one
+//!   IP or network per line. The key name is free (`bans/203.0.113.7`,
+//!   `bans/office-scanners`); all values are merged into the `kv` source
+//!   of the banned IPs cache.
+//! - `<prefix>config/ENFORCE`, `<prefix>config/HONEYPOT_PATHS` and
+//!   `<prefix>config/MAINTENANCE`: override the environment variables of the
+//!   same name until the key is deleted.
+//! - `<prefix>config/LOCKDOWN_UNTIL`: an RFC 3339 time until which every
+//!   replica is locked down (see [`crate::lockdown`]).
+//!
+//! Consul is watched with blocking queries, etcd through the watch stream
+//! of its v3 JSON gateway. When the store is unreachable the last read
+//! entries stay in place and the watch is retried.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{parse_bool, KvBackend, KvConfig},
+    lists::{log_rejected, parse_list},
+    AppState,
+};
+
+/// Cache source name of the banned entries
+pub const SOURCE: &str = "kv";
+
+/// How long one watch waits for a change before reading everything again
+const WATCH_WAIT: Duration = Duration::from_secs(300);
+
+/// Pause after a failed read
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Config values set in the key-value store, taking precedence over the
+/// environment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overrides {
+    /// `config/ENFORCE`
+    pub enforce: Option<bool>,
+    /// `config/HONEYPOT_PATHS`
+    pub honeypot_paths: Option<Vec<String>>,
+    /// `config/MAINTENANCE`
+    pub maintenance: Option<bool>,
+    /// `config/LOCKDOWN_UNTIL`
+    pub lockdown_until: Option<DateTime<Utc>>,
+}
+
+/// Keys under the prefix (with the prefix removed) and their values.
+pub type Entries = BTreeMap<String, String>;
+
+/// Reads the prefix, then applies every change to it.
+pub async fn watch_task(state: AppState, config: KvConfig) {
+    let mut version = 0;
+    loop {
+        let read = match config.backend {
+            KvBackend::Consul => read_consul(&state.http, &config, version).await,
+            KvBackend::Etcd => read_etcd(&state.http, &config, version).await,
+        };
+        match read {
+            Ok((latest, entries)) => {
+                // A blocking query that timed out returns the same version
+                if latest != version {
+                    apply(&state, &entries).await;
+                }
+                version = latest;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to read {} keys under '{}', keeping previous entries: {}",
+                    config.backend, config.prefix, e
+                );
+                version = 0;
+                sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Replaces the `kv` cache source and the config overrides with `entries`.
+/// Returns the number of banned entries.
+pub async fn apply(state: &AppState, entries: &Entries) -> usize {
+    let mut bans = String::new();
+    let mut overrides = Overrides::default();
+    for (key, value) in entries {
+        if key.starts_with("bans/") {
+            bans.push_str(value);
+            bans.push('\n');
+        } else if let Some(name) = key.strip_prefix("config/") {
+            match name {
+                "ENFORCE" => match parse_bool(value.trim()) {
+                    Some(enforce) => overrides.enforce = Some(enforce),
+                    None => warn!("Ignoring KV key {}: '{}' is not a boolean", key, value),
+                },
+                "MAINTENANCE" => match parse_bool(value.trim()) {
+                    Some(maintenance) => overrides.maintenance = Some(maintenance),
+                    None => warn!("Ignoring KV key {}: '{}' is not a boolean", key, value),
+                },
+                "LOCKDOWN_UNTIL" => match DateTime::parse_from_rfc3339(value.trim()) {
+                    Ok(until) => overrides.lockdown_until = Some(until.to_utc()),
+                    Err(_) => warn!("Ignoring KV key {}: '{}' is not an RFC 3339 time", key, value),
+                },
+                "HONEYPOT_PATHS" => {
+                    overrides.honeypot_paths = Some(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                }
+                _ => warn!("Ignoring KV key {}: not a supported config override", key),
+            }
+        } else {
+            debug!("Ignoring KV key {}", key);
+        }
+    }
+
+    let (banned, rejected) = parse_list(&bans);
+    log_rejected(SOURCE, &rejected);
+    let count = banned.len();
+    state
+        .banned_ips
+        .write()
+        .await
+        .set_source(SOURCE, banned.into_iter().collect(), rejected.len());
+
+    let mut current = state.overrides.write().unwrap_or_else(|e| e.into_inner());
+    if *current != overrides {
+        info!("KV config overrides changed: {:?}", overrides);
+        *current = overrides;
+    }
+    info!("KV store loaded with {} banned entries", count);
+    count
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulEntry {
+    key: String,
+    value: Option<String>,
+}
+
+/// Consul blocking query: returns once the prefix changed after `index`
+/// (0 returns right away) or [`WATCH_WAIT`] elapsed, with the new index.
+pub async fn read_consul(
+    http: &reqwest::Client,
+    config: &KvConfig,
+    index: u64,
+) -> Result<(u64, Entries), reqwest::Error> {
+    let mut req = http
+        .get(format!("{}/v1/kv/{}", config.url, config.prefix))
+        .query(&[
+            ("recurse", "true".to_string()),
+            ("index", index.to_string()),
+            ("wait", format!("{}s", WATCH_WAIT.as_secs())),
+        ])
+        // Consul holds the request for up to `wait` plus some jitter
+        .timeout(WATCH_WAIT * 2);
+    if let Some(token) = &config.token {
+        req = req.header("X-Consul-Token", token);
+    }
+    let res = req.send().await?;
+    let latest = res
+        .headers()
+        .get("X-Consul-Index")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    // No key under the prefix
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok((latest, Entries::new()));
+    }
+    let listed: Vec<ConsulEntry> = res.error_for_status()?.json().await?;
+    let entries = listed
+        .into_iter()
+        .filter_map(|entry| {
+            let key = entry.key.strip_prefix(&config.prefix)?.to_string();
+            Some((key, decode(&entry.value?)?))
+        })
+        .collect();
+    // The index may go backwards after a Consul restore; start over then
+    Ok((if latest < index { 0 } else { latest }, entries))
+}
+
+#[derive(Deserialize)]
+struct EtcdRange {
+    header: EtcdHeader,
+    #[serde(default)]
+    kvs: Vec<EtcdKv>,
+}
+
+#[derive(Deserialize)]
+struct EtcdHeader {
+    /// int64 values are strings in the JSON gateway
+    revision: String,
+}
+
+#[derive(Deserialize)]
+struct EtcdKv {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+/// etcd read: returns once the prefix changed after `revision` (0 returns
+/// right away) or [`WATCH_WAIT`] elapsed, with the new revision.
+pub async fn read_etcd(
+    http: &reqwest::Client,
+    config: &KvConfig,
+    revision: u64,
+) -> Result<(u64, Entries), reqwest::Error> {
+    let key = STANDARD.encode(&config.prefix);
+    let range_end = STANDARD.encode(prefix_end(config.prefix.as_bytes()));
+
+    if revision > 0 {
+        let watch = json!({"create_request": {
+            "key": key,
+            "range_end": range_end,
+            "start_revision": (revision + 1).to_string(),
+        }});
+        let mut res = etcd_request(http, config, "watch", &watch)
+            .timeout(WATCH_WAIT * 2)
+            .send()
+            .await?
+            .error_for_status()?;
+        // The first message confirms the watch; any later one carries events
+        let changed = timeout(WATCH_WAIT, async {
+            let mut received = Vec::new();
+            while let Some(chunk) = res.chunk().await? {
+                received.extend_from_slice(&chunk);
+                if received.windows(8).any(|w| w == b"\"events\"") {
+                    break;
+                }
+            }
+            Ok::<_, reqwest::Error>(())
+        })
+        .await;
+        if let Ok(result) = changed {
+            result?;
+        }
+    }
+
+    let range = json!({"key": key, "range_end": range_end});
+    let listed: EtcdRange = etcd_request(http, config, "kv/range", &range)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let entries = listed
+        .kvs
+        .into_iter()
+        .filter_map(|kv| {
+            let key = decode(&kv.key)?;
+            let key = key.strip_prefix(&config.prefix)?.to_string();
+            Some((key, decode(&kv.value)?))
+        })
+        .collect();
+    Ok((listed.header.revision.parse().unwrap_or(0), entries))
+}
+
+fn etcd_request(
+    http: &reqwest::Client,
+    config: &KvConfig,
+    endpoint: &str,
+    body: &serde_json::Value,
+) -> reqwest::RequestBuilder {
+    let req = http.post(format!("{}/v3/{}", config.url, endpoint)).json(body);
+    match &config.token {
+        Some(token) => req.header("Authorization", token),
+        None => req,
+    }
+}
+
+/// End of the etcd key range covering every key that starts with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // Empty (or all 0xff) prefix: every key
+    vec![0]
+}
+
+fn decode(value: &str) -> Option<String> {
+    let bytes = STANDARD.decode(value).ok()?;
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,91 @@
+//! LDAP / Active Directory credential checks for `AUTH_MODE=ldap`.
+//!
+//! Credentials are verified by binding to `LDAP_URL` as the user: the login
+//! is substituted into `LDAP_USER_DN`, so no service account is needed. With
+//! `LDAP_GROUPS` set, the user's entry is then looked up under `LDAP_BASE_DN`
+//! (still bound as the user) and one of its `memberOf` values must name a
+//! listed group. That attribute is maintained by Active Directory and by
+//! OpenLDAP's `memberof` overlay.
+//!
+//! Available with the `ldap` feature. A successful login starts a session
+//! like any other mode, so the directory is only asked again once the
+//! session cookie expires.
+
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use tracing::{debug, warn};
+
+use crate::config::LdapConfig;
+
+/// Result code of a bind with wrong credentials.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Whether `user` can bind with `password` and is in one of the required
+/// groups. Unreachable servers and timeouts count as failures.
+pub async fn authenticate(config: &LdapConfig, user: &str, password: &str) -> bool {
+    // An empty password is an unauthenticated bind, which many servers accept
+    if user.is_empty() || password.is_empty() {
+        return false;
+    }
+    match tokio::time::timeout(config.timeout, bind(config, user, password)).await {
+        Ok(Ok(allowed)) => allowed,
+        Ok(Err(e)) => {
+            warn!("LDAP login of {} against {} failed: {}", user, config.url, e);
+            false
+        }
+        Err(_) => {
+            warn!("LDAP server {} timed out after {:?}", config.url, config.timeout);
+            false
+        }
+    }
+}
+
+async fn bind(config: &LdapConfig, user: &str, password: &str) -> Result<bool, LdapError> {
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(config.timeout)
+        .set_starttls(config.starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+    ldap3::drive!(conn);
+
+    let bind_dn = config.bind_dn.replace("{user}", &dn_escape(user));
+    let result = ldap.simple_bind(&bind_dn, password).await?;
+    if result.rc == INVALID_CREDENTIALS {
+        debug!("LDAP bind as {} refused: invalid credentials", bind_dn);
+        return Ok(false);
+    }
+    result.success()?;
+
+    let allowed = match &config.base_dn {
+        Some(base_dn) if !config.groups.is_empty() => {
+            let filter = config.user_filter.replace("{user}", &ldap_escape(user));
+            let (entries, _) = ldap
+                .search(base_dn, Scope::Subtree, &filter, vec!["memberOf"])
+                .await?
+                .success()?;
+            let member_of = entries
+                .into_iter()
+                .map(SearchEntry::construct)
+                .flat_map(|entry| entry.attrs.into_iter())
+                .filter(|(name, _)| name.eq_ignore_ascii_case("memberOf"))
+                .flat_map(|(_, values)| values);
+            let allowed = in_groups(member_of, &config.groups);
+            if !allowed {
+                debug!("LDAP user {} is in none of the required groups", user);
+            }
+            allowed
+        }
+        _ => true,
+    };
+    let _ = ldap.unbind().await;
+    Ok(allowed)
+}
+
+/// Whether one of the group DNs has a CN listed in `groups`.
+pub fn in_groups(member_of: impl IntoIterator<Item = String>, groups: &[String]) -> bool {
+    member_of.into_iter().any(|dn| {
+        dn.split(',')
+            .next()
+            .and_then(|rdn| rdn.split_once('='))
+            .filter(|(attr, _)| attr.trim().eq_ignore_ascii_case("cn"))
+            .is_some_and(|(_, cn)| groups.iter().any(|g| g.eq_ignore_ascii_case(cn.trim())))
+    })
+}
@@ -0,0 +1,442 @@
+//! Tezcatlipoca Authentication Service
+//!
+//! A high-performance authentication service for Traefik's ForwardAuth system.
+//! Provides IP-based access control with automatic cache management and
+//! comprehensive logging.
+//!
+//! # Features
+//! - IP-based authentication and blocking
+//! - Automatic cache refresh for banned IPs
+//! - Cloudflare and proxy support (X-Forwarded-For)
+//! - Configurable log rotation
+//! - Health check endpoint with metrics
+//! - Zero-downtime cache updates
+//!
+//! # Environment Variables
+//! See `config` module for full list of configuration options.
+//!
+//! # Architecture
+//! - `controllers`: HTTP handlers and authentication middleware
+//! - `admin`: Operational `/admin` API (live event stream)
+//! - `audit`: Append-only JSON Lines audit trail of decisions
+//! - `auth`: Primary authentication (HTTP Basic, LDAP) with session cookies
+//! - `client_ip`: Client IP extractor (proxy headers, then socket address)
+//! - `bot_score`: Heuristic bot score from cheap request signals
+//! - `cache`: In-memory IP cache with background refresh
+//! - `abuseipdb`: Reputation lookups with local score cache
+//! - `bloom`: Bloom filter fast path for IP set lookups
+//! - `cloudflare`: Cloudflare's IP ranges, refreshed from its published lists
+//! - `challenge`: Captcha challenge (Turnstile, hCaptcha) for suspicious IPs
+//! - `bans`: Dynamic bans added at runtime (e.g. by honeypot hits)
+//! - `bench`: ForwardAuth load generator with latency percentiles (`bench`)
+//! - `crowdsec`: CrowdSec bouncer (decision stream and alert push)
+//! - `decision_cache`: Short-lived LRU of block decisions per client IP
+//! - `doctor`: Deployment check of files, directories and URLs (`check-config`)
+//! - `dnsbl`: DNS blocklist lookups with local result cache (`dns` feature)
+//! - `error`: Crate-wide error type (config, cache, feed and auth errors)
+//! - `events`: Broadcast bus for security events
+//! - `ext_authz`: Envoy ext_authz gRPC server (`ext-authz` feature)
+//! - `feeds`: Remote blocklists refreshed on a schedule (e.g. Tor exit nodes)
+//! - `config`: Configuration management
+//! - `fingerprint`: Blocklist of TLS (JA3/JA4) fingerprints forwarded in headers
+//! - `geoip`: Country and ASN of client IPs from MaxMind databases
+//! - `gossip`: Signed UDP ban propagation between replicas
+//! - `greylist`: Temporary deferral of first-time client IPs
+//! - `header_rules`: Request header conditions that block requests
+//! - `honeypot`: Trap paths that trigger automatic bans
+//! - `kv`: Bans and config overrides watched in etcd or Consul
+//! - `ldap`: LDAP / Active Directory credential checks (`ldap` feature)
+//! - `limits`: Concurrency limits, request timeout and body size limit
+//! - `lists`: Blocklist text format parsing (IPs, CIDRs, netset, DROP)
+//! - `lockdown`: Emergency deny-by-default mode with automatic expiry
+//! - `logger`: Structured logging setup (files, syslog, journald)
+//! - `loki`: Decision logs pushed to Grafana Loki
+//! - `maintenance`: Maintenance mode answering 503 to all but an allowlist
+//! - `metrics`: Request decision counters
+//! - `opa`: Open Policy Agent decisions
+//! - `patterns`: Regular expressions of the request rules, compiled once
+//! - `plugins`: Sandboxed WebAssembly decision plugins (`plugins` feature)
+//! - `policies`: Named policies selected per request by a header
+//! - `proxy_protocol`: PROXY protocol v1/v2 on the HTTP listener
+//! - `pubsub`: Ban propagation through Redis pub/sub (`redis` feature)
+//! - `query_rules`: Query-string rules that ban exploit probes
+//! - `rate_limit`: Per-IP request rate limits with per-path overrides
+//! - `rate_limit_redis`: Rate limits counted in Redis across replicas (`redis` feature)
+//! - `rdns`: Forward-confirmed reverse DNS names of blocked clients (`dns` feature)
+//! - `schedule`: Opening hours of hosts and paths (day/time ranges, cron)
+//! - `script`: Rhai decision hook (`scripting` feature)
+//! - `server`: HTTP listener with keep-alive, HTTP/2 and backlog tuning
+//! - `session`: Signed cookie tokens
+//! - `snapshot`: Runtime state (dynamic bans, offenses, greylist) saved across restarts
+//! - `signature`: HMAC request signatures for machine traffic
+//! - `shard`: Sharded maps for concurrently mutated per-IP state
+//! - `upstream`: Chained authentication through a second ForwardAuth service
+//! - `storage`: Blocklists stored in S3, GCS or MinIO (`object-store` feature)
+//! - `systemd`: Readiness, watchdog and socket activation (Unix)
+//! - `supervisor`: Background task tracking with stall detection (`/readyz`)
+//! - `sweeper`: Periodic removal of expired bans and stale per-IP counters
+//! - `stats`: Per-IP statistics and rolling top-offender counters
+//! - `tarpit`: Delayed responses for banned clients
+//! - `webhook`: Batched webhook notifications for block events
+//! - `totp`: TOTP second factor with enrollment
+//! - `vault`: Secrets fetched from HashiCorp Vault at startup
+//! - `windows`: Windows service wrapper and event log output (Windows)
+//! - `testing`: In-process test harness (`test-support` feature)
+//!
+//! # Embedding
+//! The router can be mounted inside another axum application. The
+//! authentication middleware reads the peer address, so the final service
+//! must be created with `into_make_service_with_connect_info::<SocketAddr>()`:
+//!
+//! ```no_run
+//! use tezcatlipoca_auth::{build_router, config::Config, AppState};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let state = AppState::new(Config::from_env()?);
+//! let app = build_router(state);
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8199").await?;
+//! axum::serve(
+//!     listener,
+//!     app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod abuseipdb;
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod bans;
+pub mod bench;
+pub mod bloom;
+pub mod bot_score;
+pub mod cache;
+pub mod challenge;
+pub mod client_ip;
+pub mod cloudflare;
+pub mod config;
+pub mod controllers;
+pub mod crowdsec;
+pub mod decision_cache;
+pub mod doctor;
+#[cfg(feature = "dns")]
+pub mod dnsbl;
+pub mod error;
+pub mod events;
+#[cfg(feature = "ext-authz")]
+pub mod ext_authz;
+pub mod feeds;
+pub mod fingerprint;
+pub mod geoip;
+pub mod gossip;
+pub mod greylist;
+pub mod header_rules;
+pub mod honeypot;
+pub mod kv;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+pub mod limits;
+pub mod lists;
+pub mod lockdown;
+pub mod logger;
+pub mod loki;
+pub mod maintenance;
+pub mod metrics;
+pub mod opa;
+pub mod patterns;
+#[cfg(feature = "plugins")]
+pub mod plugins;
+pub mod policies;
+pub mod proxy_protocol;
+#[cfg(feature = "redis")]
+pub mod pubsub;
+pub mod query_rules;
+pub mod rate_limit;
+#[cfg(feature = "redis")]
+pub mod rate_limit_redis;
+#[cfg(feature = "dns")]
+pub mod rdns;
+pub mod schedule;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod server;
+pub mod session;
+pub mod shard;
+pub mod signature;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "object-store")]
+pub mod storage;
+pub mod supervisor;
+pub mod sweeper;
+#[cfg(unix)]
+pub mod systemd;
+pub mod tarpit;
+pub mod totp;
+pub mod upstream;
+pub mod vault;
+pub mod webhook;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(feature = "test-support")]
+pub mod testing;
+
+use axum::{middleware, routing::any, Router};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use abuseipdb::AbuseIpDb;
+use audit::AuditTrail;
+use auth::Authenticator;
+use bans::DynamicBans;
+use cache::BannedIpsCache;
+use config::{Config, Routes};
+use decision_cache::DecisionCache;
+use events::EventBus;
+use greylist::Greylist;
+use limits::ConcurrencyLimits;
+use metrics::Metrics;
+use rate_limit::RateLimiter;
+use session::Signer;
+use stats::{IpStatsTable, TopCounters};
+use tarpit::Tarpit;
+use upstream::UpstreamAuth;
+
+/// Shared application state accessible across all handlers.
+///
+/// Contains the banned IPs cache and configuration, wrapped in Arc
+/// for efficient cloning across async tasks.
+#[derive(Clone)]
+pub struct AppState {
+    /// Thread-safe cache of banned IP addresses
+    pub banned_ips: Arc<RwLock<BannedIpsCache>>,
+    /// Bans added at runtime, e.g. by honeypot hits
+    pub dynamic_bans: Arc<DynamicBans>,
+    /// AbuseIPDB score cache, when enabled
+    pub abuseipdb: Option<Arc<AbuseIpDb>>,
+    /// DNSBL result cache, when `DNSBL_ZONES` is set
+    #[cfg(feature = "dns")]
+    pub dnsbl: Option<Arc<dnsbl::Dnsbl>>,
+    /// Hostname cache, when `RDNS=true`
+    #[cfg(feature = "dns")]
+    pub rdns: Option<Arc<rdns::Rdns>>,
+    /// GeoIP databases, when `GEOIP_COUNTRY_DB` or `GEOIP_ASN_DB` is set
+    pub geoip: Option<Arc<geoip::GeoIp>>,
+    /// First-time IP deferral, when `GREYLIST=true`
+    pub greylist: Option<Arc<Greylist>>,
+    /// Request rate buckets, when `RATE_LIMIT` or `RATE_LIMIT_RULES` is set
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Counters shared by all replicas, when `RATE_LIMIT_REDIS=true`; the
+    /// local buckets take over while Redis is unreachable
+    #[cfg(feature = "redis")]
+    pub redis_rate_limiter: Option<Arc<rate_limit_redis::RedisRateLimiter>>,
+    /// Credential checks, when `AUTH_MODE` is set
+    pub auth: Option<Arc<Authenticator>>,
+    /// Second ForwardAuth service, when `UPSTREAM_AUTH_URL` is set
+    pub upstream_auth: Option<Arc<UpstreamAuth>>,
+    /// Decision override script, when `SCRIPT_FILE` is set and compiles
+    #[cfg(feature = "scripting")]
+    pub script: Option<Arc<script::ScriptHook>>,
+    /// WebAssembly decision plugins, when `PLUGIN_DIR` is set
+    #[cfg(feature = "plugins")]
+    pub plugins: Option<Arc<plugins::Plugins>>,
+    /// Decision audit trail, when `AUDIT_LOG` is set and could be opened
+    pub audit: Option<Arc<AuditTrail>>,
+    /// Application configuration
+    pub config: Config,
+    /// Maintenance mode set through the admin API
+    pub maintenance: Arc<maintenance::MaintenanceToggle>,
+    /// Lockdown started through the admin API
+    pub lockdown: Arc<lockdown::LockdownState>,
+    /// Config values overridden in the KV store (`KV_BACKEND`)
+    pub overrides: Arc<std::sync::RwLock<kv::Overrides>>,
+    /// Cloudflare's ranges, `CLOUDFLARE_IPS` until refreshed
+    pub cloudflare_ips: Arc<std::sync::RwLock<Vec<ipnet::IpNet>>>,
+    /// Request decision counters
+    pub metrics: Arc<Metrics>,
+    /// In-flight request counters (`MAX_INFLIGHT`, `MAX_INFLIGHT_PER_IP`)
+    pub limits: Arc<ConcurrencyLimits>,
+    /// Holds banned connections before they are rejected
+    pub tarpit: Arc<Tarpit>,
+    /// Security events for background integrations
+    pub events: Arc<EventBus>,
+    /// Per-IP hit and block counters
+    pub ip_stats: Arc<IpStatsTable>,
+    /// Rolling counters for top-offender reports
+    pub top_stats: Arc<TopCounters>,
+    /// Recent decisions, when `DECISION_CACHE_SIZE` is above 0
+    pub decision_cache: Option<Arc<DecisionCache>>,
+    /// Shared HTTP client for outbound integrations
+    pub http: reqwest::Client,
+    /// Signs and checks cookies issued by the service (`COOKIE_SECRET`)
+    pub cookie_signer: Arc<Signer>,
+    /// Background tasks and their progress
+    pub tasks: Arc<supervisor::Tasks>,
+}
+
+impl AppState {
+    /// Creates the state with an empty banned IPs cache.
+    ///
+    /// Nothing is loaded here and requests never load it: call
+    /// [`cache::reload_banned_ips`] before serving, as the binary does at
+    /// startup. Until then `/readyz` answers 503.
+    pub fn new(config: Config) -> Self {
+        let metrics = Arc::new(Metrics::default());
+        Self {
+            banned_ips: Arc::new(RwLock::new(BannedIpsCache::new(config.cache_ttl))),
+            dynamic_bans: Arc::new(DynamicBans::with_escalation(
+                config.ban_escalation.clone(),
+                config.ban_escalation_reset,
+            )),
+            abuseipdb: config.abuseipdb.clone().map(|c| Arc::new(AbuseIpDb::new(c))),
+            #[cfg(feature = "dns")]
+            dnsbl: config.dnsbl.clone().map(|c| Arc::new(dnsbl::Dnsbl::new(c))),
+            #[cfg(feature = "dns")]
+            rdns: config.rdns.clone().map(|c| Arc::new(rdns::Rdns::new(c))),
+            geoip: geoip::GeoIp::open(
+                config.geoip_country_db.as_deref(),
+                config.geoip_asn_db.as_deref(),
+            )
+            .map(Arc::new),
+            greylist: config
+                .greylist
+                .then(|| Arc::new(Greylist::new(config.greylist_delay, config.greylist_ttl))),
+            rate_limiter: (config.rate_limit.is_some() || !config.rate_limit_rules.is_empty())
+                .then(|| Arc::new(RateLimiter::new(config.rate_limit_algorithm))),
+            #[cfg(feature = "redis")]
+            redis_rate_limiter: config
+                .redis
+                .as_ref()
+                .zip(config.rate_limit_redis)
+                .filter(|_| config.rate_limit.is_some() || !config.rate_limit_rules.is_empty())
+                .and_then(|(redis, timeout)| {
+                    let algorithm = config.rate_limit_algorithm;
+                    match rate_limit_redis::RedisRateLimiter::new(redis, algorithm, timeout) {
+                        Ok(limiter) => Some(Arc::new(limiter)),
+                        Err(e) => {
+                            tracing::error!("Invalid Redis URL for rate limits: {}", e);
+                            None
+                        }
+                    }
+                }),
+            auth: config.auth.clone().map(|c| Arc::new(Authenticator::new(c))),
+            upstream_auth: config
+                .upstream_auth
+                .clone()
+                .map(|c| Arc::new(UpstreamAuth::new(c))),
+            #[cfg(feature = "scripting")]
+            script: config.script_file.as_deref().and_then(|path| {
+                match script::ScriptHook::load(path) {
+                    Ok(script) => Some(Arc::new(script)),
+                    Err(e) => {
+                        tracing::error!("Failed to compile decision script {}: {}", path, e);
+                        None
+                    }
+                }
+            }),
+            #[cfg(feature = "plugins")]
+            plugins: config
+                .plugin_dir
+                .as_deref()
+                .map(|dir| Arc::new(plugins::Plugins::load(dir, config.plugin_memory_mb))),
+            audit: config.audit_log.as_deref().and_then(|destination| {
+                match AuditTrail::open(destination, config.enforce) {
+                    Ok(audit) => Some(Arc::new(audit)),
+                    Err(e) => {
+                        tracing::error!("Failed to open audit trail {}: {}", destination, e);
+                        None
+                    }
+                }
+            }),
+            maintenance: Arc::default(),
+            lockdown: Arc::default(),
+            overrides: Arc::default(),
+            cloudflare_ips: Arc::new(std::sync::RwLock::new(config.cloudflare_ips.clone())),
+            tasks: Arc::new(supervisor::Tasks::new(metrics.clone())),
+            metrics,
+            limits: Arc::new(ConcurrencyLimits::new(
+                config.max_inflight,
+                config.max_inflight_per_ip,
+            )),
+            tarpit: Arc::new(Tarpit::new(config.tarpit_delay, config.tarpit_max_concurrent)),
+            events: Arc::new(EventBus::new()),
+            ip_stats: Arc::new(IpStatsTable::new(config.ip_stats_capacity)),
+            decision_cache: DecisionCache::new(
+                config.decision_cache_size,
+                config.decision_cache_ttl,
+            )
+            .map(Arc::new),
+            top_stats: Arc::new(TopCounters::new(config.top_stats_retention)),
+            http: http_client(),
+            cookie_signer: Arc::new(Signer::from_secrets(
+                &config.cookie_secrets,
+                config.cookie_encrypt,
+            )),
+            config,
+        }
+    }
+
+    /// Whether blocks are enforced: `ENFORCE`, unless overridden in the KV
+    /// store.
+    pub fn enforce(&self) -> bool {
+        self.overrides
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .enforce
+            .unwrap_or(self.config.enforce)
+    }
+}
+
+/// HTTP client used for all outbound calls (feeds, APIs).
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(concat!("tezcatlipoca-auth/", env!("CARGO_PKG_VERSION")))
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("HTTP client configuration is valid")
+}
+
+/// Builds the service router with the authentication middleware applied.
+///
+/// Routes:
+/// - `/health`: health check with cache metrics
+/// - `/readyz`: readiness, 503 until the banned IPs are loaded or while a
+///   background task is stalled or stopped
+/// - `/` and `/{*path}`: ForwardAuth endpoint, answers 200 when the client is allowed
+/// - `/admin/*`: admin API when `ADMIN_API=true`, not subject to the IP check
+///
+/// Every route but the admin API is subject to the concurrency limits, the
+/// request timeout and the body size limit.
+pub fn build_router(state: AppState) -> Router {
+    build_router_for(state, Routes::All)
+}
+
+/// Builds the router of a listener serving `routes` (`LISTEN`).
+///
+/// Every listener answers `/health` and `/readyz`. Serving the admin API on
+/// its own loopback listener keeps it out of reach of the proxy network.
+pub fn build_router_for(state: AppState, routes: Routes) -> Router {
+    let health = Router::new()
+        .route("/health", any(controllers::health_check))
+        .route("/readyz", any(controllers::readiness))
+        .with_state(state.clone());
+    let router = if routes == Routes::Admin {
+        health
+    } else {
+        health
+            .route("/{*path}", any(controllers::handler))
+            .route("/", any(controllers::handler))
+            .layer(middleware::from_fn_with_state(state.clone(), controllers::auth_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), limits::timeout))
+            .layer(middleware::from_fn_with_state(state.clone(), limits::body_limit))
+            .layer(middleware::from_fn_with_state(state.clone(), limits::middleware))
+    };
+
+    if state.config.admin_api && routes != Routes::Auth {
+        router.merge(admin::router(state))
+    } else {
+        router
+    }
+}
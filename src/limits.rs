@@ -0,0 +1,180 @@
+//! Concurrency and time limits for the request path.
+//!
+//! A flood of auth checks, say from a client holding many connections open
+//! while the tarpit or a slow backend delays the answers, could otherwise
+//! pile up tasks, buffers and file descriptors until auth fails for
+//! everyone. Requests over a limit are answered at once instead of queueing:
+//!
+//! - `MAX_INFLIGHT`: requests being handled across all clients; further
+//!   requests get `503 Service Unavailable`.
+//! - `MAX_INFLIGHT_PER_IP`: requests being handled for one client IP;
+//!   further requests from that IP get `429 Too Many Requests`.
+//!
+//! `REQUEST_TIMEOUT_MS` bounds how long any request is handled, so a hung
+//! cache refresh, a slow DNS lookup or an unresponsive backend can't hold a
+//! ForwardAuth request open indefinitely. Requests over the deadline get
+//! `503 Service Unavailable`, which Traefik treats as a denial. The tarpit
+//! delay counts towards the deadline.
+//!
+//! ForwardAuth requests don't carry bodies. `MAX_BODY_BYTES` answers a
+//! request announcing a larger body with `413 Payload Too Large` before any
+//! of it is read, and closes the connection rather than draining it. Bodies
+//! without a length are cut off at the limit wherever they are read.
+
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        header::{CONNECTION, CONTENT_LENGTH, RETRY_AFTER},
+        StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::Limited as LimitedBody;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+use crate::{client_ip::ClientIp, metrics::Metrics, shard::ShardedMap, AppState};
+
+/// Which limit a request ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limited {
+    /// `MAX_INFLIGHT` requests are already being handled
+    Global,
+    /// `MAX_INFLIGHT_PER_IP` requests from the same IP are being handled
+    PerIp,
+}
+
+/// In-flight request counters.
+#[derive(Debug)]
+pub struct ConcurrencyLimits {
+    global: Option<Arc<Semaphore>>,
+    per_ip: Option<usize>,
+    /// Requests being handled per IP; IPs with none are removed
+    inflight: ShardedMap<IpAddr, usize>,
+}
+
+/// Slot held while a request is handled, released on drop.
+#[derive(Debug)]
+pub struct Slot {
+    limits: Arc<ConcurrencyLimits>,
+    ip: Option<IpAddr>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConcurrencyLimits {
+    /// Creates the counters; `None` leaves a limit off.
+    pub fn new(max_inflight: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        Self {
+            global: max_inflight.map(|n| Arc::new(Semaphore::new(n))),
+            per_ip: max_per_ip,
+            inflight: ShardedMap::new(),
+        }
+    }
+
+    /// Takes a slot for a request from `ip`, or reports the limit it hit.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Result<Slot, Limited> {
+        let permit = match &self.global {
+            Some(global) => Some(global.clone().try_acquire_owned().map_err(|_| Limited::Global)?),
+            None => None,
+        };
+        let ip = match self.per_ip {
+            Some(max) => {
+                let mut shard = self.inflight.write(&ip);
+                let count = shard.entry(ip).or_insert(0);
+                if *count >= max {
+                    return Err(Limited::PerIp);
+                }
+                *count += 1;
+                Some(ip)
+            }
+            None => None,
+        };
+        Ok(Slot {
+            limits: self.clone(),
+            ip,
+            _permit: permit,
+        })
+    }
+
+    /// Requests currently being handled for `ip` (only counted with a
+    /// per-IP limit).
+    pub fn inflight(&self, ip: IpAddr) -> usize {
+        self.inflight.get(&ip).unwrap_or(0)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let Some(ip) = self.ip else {
+            return;
+        };
+        let mut shard = self.limits.inflight.write(&ip);
+        if let Some(count) = shard.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                shard.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Middleware holding a slot for the duration of each request.
+pub async fn middleware(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    match state.limits.acquire(ip) {
+        Ok(_slot) => next.run(req).await,
+        Err(limited) => {
+            Metrics::incr(&state.metrics.shed);
+            debug!("🚦 SHED: IP {} over the {:?} concurrency limit", ip, limited);
+            let status = match limited {
+                Limited::Global => StatusCode::SERVICE_UNAVAILABLE,
+                Limited::PerIp => StatusCode::TOO_MANY_REQUESTS,
+            };
+            (status, [(RETRY_AFTER, "1")]).into_response()
+        }
+    }
+}
+
+/// Middleware answering requests still running after `REQUEST_TIMEOUT_MS`.
+pub async fn timeout(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(deadline) = state.config.request_timeout else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(deadline, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            Metrics::incr(&state.metrics.timed_out);
+            warn!("⏱️ TIMEOUT: request for {} took longer than {:?}", path, deadline);
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+/// Middleware enforcing `MAX_BODY_BYTES`.
+pub async fn body_limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(limit) = state.config.max_body_bytes else {
+        return next.run(req).await;
+    };
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|&length| length > limit as u64);
+    if let Some(length) = length {
+        Metrics::incr(&state.metrics.shed);
+        debug!("🚦 SHED: {}-byte body over the {}-byte limit", length, limit);
+        return (StatusCode::PAYLOAD_TOO_LARGE, [(CONNECTION, "close")]).into_response();
+    }
+    next.run(req.map(|body| Body::new(LimitedBody::new(body, limit))))
+        .await
+}
@@ -0,0 +1,155 @@
+//! Blocklist text formats.
+//!
+//! One parser handles the banned IPs file and remote lists. Each line holds a
+//! single IP address or CIDR network; everything after `#` or `;` is a
+//! comment. That covers plain IP lists, FireHOL `.netset` files and the
+//! Spamhaus DROP/EDROP format (`1.10.16.0/20 ; SBL256894`).
+//!
+//! Blank and comment-only lines are ignored. Malformed lines are skipped and
+//! reported with their line number, so one bad entry never discards a list.
+//!
+//! Banned IPs files may also tag entries with a category: a `[name]` line
+//! puts the entries below it, up to the next header or the end of the file,
+//! in that category (see [`parse_sections`]). Category names are ASCII
+//! letters, digits, `-` and `_`, compared case-insensitively.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use ipnet::IpNet;
+use tracing::warn;
+
+/// Malformed lines logged per list before the rest are only counted.
+const MAX_LOGGED_REJECTS: usize = 10;
+
+/// A non-empty line that isn't an IP address or network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectedLine {
+    /// 1-based line number
+    pub number: usize,
+    /// The line, without its comment
+    pub text: String,
+}
+
+/// Parses a whole list, returning every valid entry and the lines that
+/// couldn't be parsed.
+pub fn parse_list(content: &str) -> (Vec<IpNet>, Vec<RejectedLine>) {
+    let mut entries = Vec::with_capacity(content.len() / 16);
+    let mut rejected = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        match parse_line(line) {
+            Some(Ok(net)) => entries.push(net),
+            Some(Err(())) => rejected.push(RejectedLine {
+                number: index + 1,
+                text: strip_comment(line).to_string(),
+            }),
+            None => {}
+        }
+    }
+    (entries, rejected)
+}
+
+/// Entries of a list with `[category]` headers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sections {
+    /// Entries above the first header
+    pub uncategorized: Vec<IpNet>,
+    /// Entries below a header, by category
+    pub categories: HashMap<String, Vec<IpNet>>,
+}
+
+impl Sections {
+    /// Number of entries across all sections.
+    pub fn len(&self) -> usize {
+        self.uncategorized.len() + self.categories.values().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Like [`parse_list`], sorting entries into the categories named by
+/// `[category]` header lines. A header with an invalid name is rejected
+/// and leaves the current category unchanged.
+pub fn parse_sections(content: &str) -> (Sections, Vec<RejectedLine>) {
+    let mut sections = Sections::default();
+    let mut category: Option<String> = None;
+    let mut rejected = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let entry = strip_comment(line);
+        if let Some(name) = entry.strip_prefix('[').and_then(|e| e.strip_suffix(']')) {
+            match category_name(name) {
+                Some(name) => category = Some(name),
+                None => rejected.push(RejectedLine {
+                    number: index + 1,
+                    text: entry.to_string(),
+                }),
+            }
+            continue;
+        }
+        match parse_line(line) {
+            Some(Ok(net)) => match &category {
+                Some(name) => sections.categories.entry(name.clone()).or_default().push(net),
+                None => sections.uncategorized.push(net),
+            },
+            Some(Err(())) => rejected.push(RejectedLine {
+                number: index + 1,
+                text: entry.to_string(),
+            }),
+            None => {}
+        }
+    }
+    (sections, rejected)
+}
+
+/// Normalized category name, or `None` when it holds anything but ASCII
+/// letters, digits, `-` and `_`.
+pub fn category_name(name: &str) -> Option<String> {
+    let name = name.trim();
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| name.to_ascii_lowercase())
+}
+
+/// Logs the malformed lines of list `source` (a file path or feed name).
+///
+/// Only the first few lines are logged individually so a list that turned
+/// into garbage doesn't flood the log.
+pub fn log_rejected(source: &str, rejected: &[RejectedLine]) {
+    for line in rejected.iter().take(MAX_LOGGED_REJECTS) {
+        warn!("Skipping malformed entry in {} line {}: {:?}", source, line.number, line.text);
+    }
+    if rejected.len() > MAX_LOGGED_REJECTS {
+        warn!(
+            "Skipped {} more malformed entries in {}",
+            rejected.len() - MAX_LOGGED_REJECTS,
+            source
+        );
+    }
+}
+
+/// Parses a single line.
+///
+/// Returns `None` for blank and comment-only lines, `Some(Err(()))` for lines
+/// that hold something other than an IP or network.
+pub fn parse_line(line: &str) -> Option<Result<IpNet, ()>> {
+    let entry = strip_comment(line);
+    if entry.is_empty() {
+        return None;
+    }
+    Some(parse_entry(entry).ok_or(()))
+}
+
+/// Parses `"203.0.113.7"` or `"203.0.113.0/24"`; host bits of a network are
+/// cleared (`10.1.2.3/8` becomes `10.0.0.0/8`).
+pub fn parse_entry(entry: &str) -> Option<IpNet> {
+    if entry.contains('/') {
+        entry.parse::<IpNet>().ok().map(|net| net.trunc())
+    } else {
+        entry.parse::<IpAddr>().ok().map(IpNet::from)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split(['#', ';']).next().unwrap_or("").trim()
+}
@@ -0,0 +1,88 @@
+//! Emergency lockdown.
+//!
+//! For an active compromise: while locked down, every request is blocked
+//! (reason `LOCKDOWN`, with the configured block response and regardless of
+//! `ENFORCE`) unless it comes from a `LOCKDOWN_ALLOWLIST` address, which goes
+//! through the regular checks. `/health` keeps answering.
+//!
+//! A lockdown always ends on its own, `LOCKDOWN_DURATION` after it started
+//! unless another length (up to `LOCKDOWN_MAX_DURATION`) is asked for, so it
+//! can't be left on by accident. It is started with a single call:
+//!
+//! - `POST /admin/lockdown` locks this replica down and, with `REDIS_URL`
+//!   set, every other one through the Redis channel; `DELETE` lifts it the
+//!   same way.
+//! - the `config/LOCKDOWN_UNTIL` key of the KV store, holding an RFC 3339
+//!   end time, locks down every replica watching the store until then or
+//!   until the key is deleted.
+//!
+//! Lockdowns are not sent over UDP gossip.
+
+use std::{net::IpAddr, sync::RwLock, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::AppState;
+
+/// What started the current lockdown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// `POST /admin/lockdown`, here or on another replica
+    Admin,
+    /// `config/LOCKDOWN_UNTIL` in the KV store
+    Kv,
+}
+
+/// End of the lockdown started through the admin API.
+#[derive(Debug, Default)]
+pub struct LockdownState(RwLock<Option<DateTime<Utc>>>);
+
+impl LockdownState {
+    /// Locks down until `until`, or with `None` lifts the lockdown.
+    pub fn set(&self, until: Option<DateTime<Utc>>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = until;
+    }
+
+    fn get(&self) -> Option<DateTime<Utc>> {
+        *self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// End of the current lockdown and what started it, `None` when not locked
+/// down. When both the admin API and the KV store lock down, the later end
+/// wins.
+pub fn status(state: &AppState) -> Option<(DateTime<Utc>, Origin)> {
+    let now = Utc::now();
+    let admin = state.lockdown.get().map(|until| (until, Origin::Admin));
+    let kv = state
+        .overrides
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .lockdown_until
+        .map(|until| (until, Origin::Kv));
+    [admin, kv]
+        .into_iter()
+        .flatten()
+        .filter(|(until, _)| *until > now)
+        .max_by_key(|(until, _)| *until)
+}
+
+/// End of a lockdown starting now and lasting `duration`, or
+/// `LOCKDOWN_DURATION` when not given. `None` when longer than
+/// `LOCKDOWN_MAX_DURATION`.
+pub fn end(state: &AppState, duration: Option<Duration>) -> Option<DateTime<Utc>> {
+    let config = &state.config;
+    let duration = duration.unwrap_or(config.lockdown_duration);
+    if duration.is_zero() || duration > config.lockdown_max_duration {
+        return None;
+    }
+    Some(Utc::now() + chrono::Duration::from_std(duration).ok()?)
+}
+
+/// Whether a request from `ip` is blocked by a lockdown.
+pub fn denies(state: &AppState, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    status(state).is_some() && !state.config.lockdown_allowlist.iter().any(|net| net.contains(&ip))
+}
@@ -1,18 +1,52 @@
 //! Logging configuration and setup.
 //!
-//! This module configures the tracing subscriber with both file and console output,
-//! including log rotation and environment-based log level filtering.
+//! This module configures the tracing subscriber with console output plus
+//! either rotated log files or a syslog destination (`LOG_TARGET`), and
+//! environment-based log level filtering. Under systemd, `LOG_TARGET=journald`
+//! sends events straight to the journal instead, with no console output to
+//! collect twice.
+//!
+//! Syslog messages are RFC 5424 formatted, with the event level mapped to the
+//! syslog severity. They are handed to a background thread, so a slow or
+//! unreachable collector never blocks request handling; messages are dropped
+//! while its queue is full.
+//!
+//! Journal entries carry the level as `PRIORITY` and every event field as a
+//! field of its own (`IP`, `STATUS`, ...), so `journalctl IP=203.0.113.7`
+//! finds the events for one client.
+//!
+//! As a Windows service, `LOG_TARGET=eventlog` writes every event to the
+//! Application event log instead of stdout, typed after its level.
 
-use crate::config::{Config, LogRotation};
-use std::path::Path;
+use crate::config::{Config, LogRotation, LogTarget, SyslogConfig, SyslogTransport};
+use std::{
+    io::{self, Write},
+    fmt::Write as _,
+    net::{TcpStream, UdpSocket},
+    path::Path,
+    sync::mpsc::{self, SyncSender},
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Metadata, Subscriber,
+};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::filter::EnvFilter;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    Layer,
+};
 
-/// Sets up logging with file rotation and console output.
+/// Messages waiting for the syslog thread before new ones are dropped.
+const SYSLOG_QUEUE: usize = 4096;
+
+/// Sets up logging with file rotation or syslog, and console output.
 ///
 /// Configures a tracing subscriber with:
-/// - File output with configurable rotation (hourly, daily, or never)
+/// - File output with configurable rotation (hourly, daily, or never), or
+///   syslog output with `LOG_TARGET=syslog`
 /// - Console output to stdout
 /// - Log level filtering via `RUST_LOG` environment variable (defaults to "info")
 /// - Automatic log file management with maximum file retention
@@ -57,7 +91,57 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 ///
 /// # Errors
 /// Returns an error if the log file appender cannot be created (e.g., permission issues)
+/// or the syslog socket cannot be opened
 pub fn setup_logging(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut file, mut syslog, mut journald, mut eventlog) = (None, None, None, None);
+    match &config.log_target {
+        LogTarget::File => file = Some(file_layer(config)?),
+        LogTarget::Syslog(syslog_config) => {
+            syslog = Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(Syslog::start(syslog_config)?)
+                    .with_ansi(false)
+                    .with_target(false)
+                    .with_level(false)
+                    .without_time(),
+            )
+        }
+        LogTarget::Journald { socket } => journald = Some(JournaldLayer::new(socket)?),
+        LogTarget::EventLog => eventlog = Some(eventlog_layer()?),
+    }
+
+    let console_layer = (journald.is_none() && eventlog.is_none()).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stdout)
+            .with_target(false)
+    });
+
+    // Build EnvFilter with fallback to config or "info"
+    // Priority: RUST_LOG env var > explicit config > "info" default
+    let env_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .unwrap_or_else(|_| {
+            // Fallback if both fail (shouldn't happen with "info")
+            EnvFilter::new("info")
+        });
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(file)
+        .with(syslog)
+        .with(journald)
+        .with(eventlog)
+        .with(console_layer)
+        .init();
+
+    Ok(())
+}
+
+/// Rotated log files under `LOG_DIR`.
+fn file_layer<S>(config: &Config) -> Result<impl Layer<S>, Box<dyn std::error::Error>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
     // Parse log file name (remove path and extension)
     let log_path = Path::new(&config.log_file);
     let log_prefix = log_path
@@ -91,29 +175,327 @@ pub fn setup_logging(config: &Config) -> Result<(), Box<dyn std::error::Error>>
             e
         })?;
 
-    let file_layer = tracing_subscriber::fmt::layer()
+    Ok(tracing_subscriber::fmt::layer()
         .with_writer(file_appender)
         .with_ansi(false)
-        .with_target(false);
+        .with_target(false))
+}
 
-    let console_layer = tracing_subscriber::fmt::layer()
-        .with_writer(std::io::stdout)
-        .with_target(false);
+/// Layer writing events to the Windows event log.
+#[cfg(windows)]
+fn eventlog_layer<S>() -> io::Result<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    Ok(tracing_subscriber::fmt::layer()
+        .with_writer(crate::windows::EventLog::open()?)
+        .with_ansi(false)
+        .with_target(false)
+        .with_level(false)
+        .without_time())
+}
 
-    // Build EnvFilter with fallback to config or "info"
-    // Priority: RUST_LOG env var > explicit config > "info" default
-    let env_filter = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("info"))
-        .unwrap_or_else(|_| {
-            // Fallback if both fail (shouldn't happen with "info")
-            EnvFilter::new("info")
-        });
+#[cfg(not(windows))]
+fn eventlog_layer() -> io::Result<tracing_subscriber::layer::Identity> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the event log is only available on Windows",
+    ))
+}
 
-    tracing_subscriber::registry()
-        .with(env_filter)
-        .with(file_layer)
-        .with(console_layer)
-        .init();
+/// Writer factory for syslog; each event becomes one message.
+#[derive(Clone)]
+struct Syslog {
+    queue: SyncSender<Vec<u8>>,
+    facility: u8,
+    hostname: String,
+    pid: u32,
+}
 
-    Ok(())
+impl Syslog {
+    /// Opens the socket and starts the thread sending queued messages.
+    fn start(config: &SyslogConfig) -> io::Result<Self> {
+        let mut sink = SyslogSink::open(config)?;
+        let (queue, messages) = mpsc::sync_channel::<Vec<u8>>(SYSLOG_QUEUE);
+        std::thread::Builder::new()
+            .name("syslog".to_string())
+            .spawn(move || {
+                for message in messages {
+                    if let Err(e) = sink.send(&message) {
+                        eprintln!("Failed to send log message to syslog: {}", e);
+                    }
+                }
+            })?;
+        Ok(Self {
+            queue,
+            facility: config.facility,
+            hostname: hostname(),
+            pid: std::process::id(),
+        })
+    }
+
+    fn message(&self, level: &Level) -> SyslogMessage<'_> {
+        SyslogMessage {
+            syslog: self,
+            severity: severity(level),
+            text: Vec::new(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogMessage<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.message(&Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.message(meta.level())
+    }
+}
+
+/// One formatted event, queued when dropped.
+struct SyslogMessage<'a> {
+    syslog: &'a Syslog,
+    severity: u8,
+    text: Vec<u8>,
+}
+
+impl Write for SyslogMessage<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogMessage<'_> {
+    fn drop(&mut self) {
+        let text = self.text.trim_ascii_end();
+        if text.is_empty() {
+            return;
+        }
+        let syslog = self.syslog;
+        let message = format_rfc5424(
+            syslog.facility * 8 + self.severity,
+            &chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            &syslog.hostname,
+            syslog.pid,
+            text,
+        );
+        // A full queue means the collector can't keep up; drop rather than block
+        let _ = syslog.queue.try_send(message);
+    }
+}
+
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG`, without
+/// structured data or message ID.
+pub fn format_rfc5424(priority: u8, timestamp: &str, hostname: &str, pid: u32, text: &[u8]) -> Vec<u8> {
+    let mut message = format!(
+        "<{}>1 {} {} {} {} - - ",
+        priority,
+        timestamp,
+        hostname,
+        env!("CARGO_PKG_NAME"),
+        pid
+    )
+    .into_bytes();
+    message.extend_from_slice(text);
+    message
+}
+
+/// Syslog severity for a tracing level.
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Host name for the syslog header and Loki labels, `-` (nil) when unknown.
+pub(crate) fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name.is_ascii() && !name.contains(' '))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Socket messages are sent on, owned by the syslog thread.
+enum SyslogSink {
+    Udp(UdpSocket),
+    Tcp {
+        address: String,
+        stream: Option<TcpStream>,
+    },
+    #[cfg(unix)]
+    Unix {
+        socket: std::os::unix::net::UnixDatagram,
+        path: String,
+    },
+}
+
+impl SyslogSink {
+    fn open(config: &SyslogConfig) -> io::Result<Self> {
+        Ok(match config.transport {
+            SyslogTransport::Udp => {
+                let socket = UdpSocket::bind(if config.address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })?;
+                socket.connect(&config.address)?;
+                Self::Udp(socket)
+            }
+            // Connected lazily, so the collector may start after the service
+            SyslogTransport::Tcp => Self::Tcp {
+                address: config.address.clone(),
+                stream: None,
+            },
+            #[cfg(unix)]
+            SyslogTransport::Unix => Self::Unix {
+                socket: std::os::unix::net::UnixDatagram::unbound()?,
+                path: config.address.clone(),
+            },
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix syslog sockets are not supported on this platform",
+                ));
+            }
+        })
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Udp(socket) => socket.send(message).map(drop),
+            Self::Tcp { address, stream } => {
+                let connection = match stream {
+                    Some(connection) => connection,
+                    None => stream.insert(TcpStream::connect(address.as_str())?),
+                };
+                let mut frame = format!("{} ", message.len()).into_bytes();
+                frame.extend_from_slice(message);
+                let result = connection.write_all(&frame);
+                if result.is_err() {
+                    // Reconnect for the next message
+                    *stream = None;
+                }
+                result
+            }
+            #[cfg(unix)]
+            Self::Unix { socket, path } => socket.send_to(message, path.as_str()).map(drop),
+        }
+    }
+}
+
+/// Layer writing events to the systemd journal over its native protocol.
+#[derive(Debug)]
+pub struct JournaldLayer {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    path: String,
+}
+
+impl JournaldLayer {
+    /// Connects to the journal socket, normally `/run/systemd/journal/socket`.
+    #[cfg(unix)]
+    pub fn new(path: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        // Entries are dropped rather than blocking a request while journald is busy
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            path: path.to_string(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(path: &str) -> io::Result<Self> {
+        let _ = path;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "journald is not supported on this platform",
+        ))
+    }
+}
+
+impl<S: Subscriber> Layer<S> for JournaldLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut entry = JournalEntry::default();
+        entry.field("PRIORITY", &severity(meta.level()).to_string());
+        entry.field("SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+        entry.field("TARGET", meta.target());
+        if let Some(file) = meta.file() {
+            entry.field("CODE_FILE", file);
+        }
+        if let Some(line) = meta.line() {
+            entry.field("CODE_LINE", &line.to_string());
+        }
+        event.record(&mut entry);
+
+        #[cfg(unix)]
+        if let Err(e) = self.socket.send_to(&entry.0, &self.path)
+            && e.kind() != io::ErrorKind::WouldBlock
+        {
+            eprintln!("Failed to send log entry to journald: {}", e);
+        }
+    }
+}
+
+/// Journal entry in the native protocol: `NAME=value` lines, or the name, a
+/// little-endian length and the raw value for values spanning lines.
+#[derive(Default)]
+struct JournalEntry(Vec<u8>);
+
+impl JournalEntry {
+    fn field(&mut self, name: &str, value: &str) {
+        self.0.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            self.0.push(b'\n');
+            self.0.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            self.0.push(b'=');
+        }
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(b'\n');
+    }
+}
+
+impl Visit for JournalEntry {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.field(&journal_field_name(field.name()), value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let mut text = String::new();
+        let _ = write!(text, "{:?}", value);
+        self.field(&journal_field_name(field.name()), &text);
+    }
+}
+
+/// Journal field name for an event field: `message` becomes `MESSAGE`, other
+/// names are upper-cased with anything outside `A-Z0-9_` replaced by `_`.
+/// Names may not start with `_` (trusted fields) or a digit.
+pub fn journal_field_name(name: &str) -> String {
+    let mut field: String = name
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9' | '_') => c,
+            _ => '_',
+        })
+        .collect();
+    if !field.starts_with(|c: char| c.is_ascii_uppercase()) {
+        field.insert(0, 'F');
+    }
+    field
 }
@@ -0,0 +1,116 @@
+//! Decision logs pushed to Grafana Loki.
+//!
+//! When `LOKI_URL` is set, every request decision is sent to Loki's push API
+//! as a JSON log line, without a Promtail sidecar tailing the log files.
+//! Decisions are batched (up to `LOKI_BATCH_SIZE`, or whatever arrived within
+//! `LOKI_BATCH_SECS` of the first one) and grouped into one stream per
+//! verdict. Streams are labelled `service`, `host` and `decision`, plus the
+//! static labels of `LOKI_LABELS`; labels stay low-cardinality, the client IP
+//! and path are only in the line. A batch Loki refuses is dropped.
+
+use std::collections::BTreeMap;
+
+use serde_json::json;
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::{timeout_at, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    config::LokiConfig,
+    events::{DecisionEvent, Verdict},
+    logger, AppState,
+};
+
+/// Pushes request decisions to Loki until the event bus closes.
+pub async fn push_task(state: AppState, config: LokiConfig) {
+    let mut decisions = state.events.subscribe_decisions();
+    let host = logger::hostname();
+    while let Some(batch) = next_batch(&mut decisions, &config).await {
+        let payload = render(&batch, &config, &host);
+        let mut request = state.http.post(&config.url).json(&payload);
+        if let Some(tenant) = &config.tenant {
+            request = request.header("X-Scope-OrgID", tenant);
+        }
+        match request.send().await.and_then(|res| res.error_for_status()) {
+            Ok(_) => debug!("Pushed {} decisions to Loki", batch.len()),
+            Err(e) => warn!("Dropping {} decisions, Loki push failed: {}", batch.len(), e),
+        }
+    }
+}
+
+/// Waits for the first decision, then collects more until the batch is full
+/// or the batch window has passed. Returns `None` once the bus is closed.
+async fn next_batch(
+    decisions: &mut Receiver<DecisionEvent>,
+    config: &LokiConfig,
+) -> Option<Vec<DecisionEvent>> {
+    let first = recv(decisions).await?;
+    let deadline = Instant::now() + config.batch_window;
+    let mut batch = vec![first];
+
+    while batch.len() < config.batch_size {
+        match timeout_at(deadline, recv(decisions)).await {
+            Ok(Some(decision)) => batch.push(decision),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+async fn recv(decisions: &mut Receiver<DecisionEvent>) -> Option<DecisionEvent> {
+    loop {
+        match decisions.recv().await {
+            Ok(decision) => return Some(decision),
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Loki pusher fell behind, {} decisions dropped", missed)
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Push API body: one stream per verdict, entries in arrival order.
+pub fn render(batch: &[DecisionEvent], config: &LokiConfig, host: &str) -> serde_json::Value {
+    let mut streams: BTreeMap<&'static str, Vec<serde_json::Value>> = BTreeMap::new();
+    for decision in batch {
+        let timestamp = decision
+            .timestamp
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_string();
+        let line = serde_json::to_string(decision).unwrap_or_default();
+        streams
+            .entry(verdict_label(decision.verdict))
+            .or_default()
+            .push(json!([timestamp, line]));
+    }
+
+    let streams: Vec<_> = streams
+        .into_iter()
+        .map(|(decision, values)| {
+            let mut labels = BTreeMap::new();
+            labels.insert("service".to_string(), env!("CARGO_PKG_NAME").to_string());
+            labels.insert("host".to_string(), host.to_string());
+            for (name, value) in &config.labels {
+                labels.insert(name.clone(), value.clone());
+            }
+            labels.insert("decision".to_string(), decision.to_string());
+            json!({ "stream": labels, "values": values })
+        })
+        .collect();
+    json!({ "streams": streams })
+}
+
+fn verdict_label(verdict: Verdict) -> &'static str {
+    match verdict {
+        Verdict::Allowed => "allowed",
+        Verdict::Blocked => "blocked",
+        Verdict::WouldBlock => "would_block",
+        Verdict::Greylisted => "greylisted",
+        Verdict::RateLimited => "rate_limited",
+        Verdict::Challenged => "challenged",
+        Verdict::Unauthorized => "unauthorized",
+    }
+}
@@ -1,53 +1,47 @@
-//! Tezcatlipoca Authentication Service
+//! Tezcatlipoca Authentication Service binary.
 //!
-//! A high-performance authentication service for Traefik's ForwardAuth system.
-//! Provides IP-based access control with automatic cache management and
-//! comprehensive logging.
+//! Thin wrapper around the `tezcatlipoca_auth` library: loads configuration,
+//! sets up logging, starts the background cache refresh and serves the router.
 //!
-//! # Features
-//! - IP-based authentication and blocking
-//! - Automatic cache refresh for banned IPs
-//! - Cloudflare and proxy support (X-Forwarded-For)
-//! - Configurable log rotation
-//! - Health check endpoint with metrics
-//! - Zero-downtime cache updates
-//!
-//! # Environment Variables
-//! See `config` module for full list of configuration options.
-//!
-//! # Architecture
-//! - `controllers`: HTTP handlers and authentication middleware
-//! - `cache`: In-memory IP cache with background refresh
-//! - `config`: Configuration management
-//! - `logger`: Structured logging setup
-
-mod cache;
-mod config;
-mod controllers;
-mod logger;
-
-use axum::{middleware, routing::any, Router};
-use config::Config;
-use std::sync::Arc;
-use tokio::{net::TcpListener, sync::RwLock};
-use tracing::{info, warn};
+//! `tezcatlipoca-auth bans export|import` instead calls the admin API of a
+//! running instance (see [`bans_command`]), `tezcatlipoca-auth healthcheck`
+//! checks that it is ready (see [`healthcheck_command`]),
+//! `tezcatlipoca-auth check-config` checks a deployment's configuration
+//! without starting (see [`check_config_command`]) and `tezcatlipoca-auth
+//! bench` load-tests an instance (see [`bench_command`]). On Windows,
+//! `tezcatlipoca-auth service install|uninstall` registers the service with
+//! the SCM, which starts it as `tezcatlipoca-auth service run`.
 
-use cache::BannedIpsCache;
-use logger::setup_logging;
-
-use crate::cache::cache_refresh_task;
-
-/// Shared application state accessible across all handlers.
-///
-/// Contains the banned IPs cache and configuration, wrapped in Arc
-/// for efficient cloning across async tasks.
-#[derive(Clone)]
-struct AppState {
-    /// Thread-safe cache of banned IP addresses
-    banned_ips: Arc<RwLock<BannedIpsCache>>,
-    /// Application configuration
-    config: Config,
-}
+use tezcatlipoca_auth::{
+    abuseipdb,
+    bans::{self, BanFormat},
+    bench,
+    build_router_for,
+    cache::{cache_refresh_task, reload_banned_ips, watch_banned_ips},
+    client_ip,
+    cloudflare,
+    config::{
+        AuthMode, BlockResponse, ChallengeMode, Config, FailMode, LogTarget, Routes, VaultConfig,
+    },
+    crowdsec,
+    doctor,
+    error::Error,
+    feeds,
+    gossip,
+    http_client,
+    kv,
+    logger::setup_logging,
+    loki,
+    proxy_protocol::ProxyProtocolListener,
+    server,
+    snapshot,
+    supervisor,
+    sweeper,
+    vault,
+    webhook,
+    AppState,
+};
+use tracing::{info, warn};
 
 /// Application entry point.
 ///
@@ -62,8 +56,7 @@ struct AppState {
 /// # Panics
 /// - If the server address cannot be bound
 /// - If the server fails to start
-#[tokio::main]
-async fn main() {
+fn main() {
     // Set up panic hook to catch and log panics
     std::panic::set_hook(Box::new(|panic_info| {
         eprintln!("PANIC: Application panicked!");
@@ -77,75 +70,912 @@ async fn main() {
         }
     }));
 
-    if let Err(e) = run().await {
+    // The service dispatcher must own the main thread, before any runtime exists
+    #[cfg(windows)]
+    if std::env::args().nth(1).as_deref() == Some("service") {
+        if let Err(e) = service_command() {
+            eprintln!("Fatal error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = runtime().block_on(run(None)) {
         eprintln!("Fatal error: {}", e);
         std::process::exit(1);
     }
 }
 
-async fn run() -> Result<(), Box<dyn std::error::Error>> {
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the Tokio runtime")
+}
+
+#[cfg(windows)]
+const SERVICE_USAGE: &str = "usage: tezcatlipoca-auth service install|uninstall|run";
+
+/// Windows service installation, and the entry point the SCM starts
+/// (`service run`).
+#[cfg(windows)]
+fn service_command() -> Result<(), Box<dyn std::error::Error>> {
+    use tezcatlipoca_auth::windows;
+
+    match std::env::args().nth(2).as_deref() {
+        Some("install") => {
+            windows::install()?;
+            println!("Installed the {} service", windows::SERVICE_NAME);
+        }
+        Some("uninstall") => {
+            windows::uninstall()?;
+            println!("Removed the {} service", windows::SERVICE_NAME);
+        }
+        Some("run") => windows::run(|stop| {
+            runtime()
+                .block_on(run(Some(stop)))
+                .map_err(|e| e.to_string())
+        })?,
+        _ => return Err(SERVICE_USAGE.into()),
+    }
+    Ok(())
+}
+
+/// Runs the service until it fails, or until `stop` fires when running as a
+/// Windows service.
+async fn run(
+    stop: Option<tokio::sync::oneshot::Receiver<()>>,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file if present (fails silently if not found)
     dotenvy::dotenv().ok();
 
-    let config = Config::from_env();
+    // Runs every few seconds: answered without logging in to Vault
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("healthcheck") {
+        return healthcheck_command(&Config::from_env()?, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("bench") {
+        return bench_command(&args[1..]).await;
+    }
+
+    let vault = match VaultConfig::from_env()? {
+        Some(config) => {
+            let loaded = vault::load(&http_client(), &config)
+                .await
+                .map_err(Error::Vault)?;
+            Some((config, loaded))
+        }
+        None => None,
+    };
+    let config = match &vault {
+        Some((_, loaded)) => Config::from_env_with_secrets(&loaded.secrets)?,
+        None => Config::from_env()?,
+    };
+
+    if args.first().map(String::as_str) == Some("bans") {
+        return bans_command(&config, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("check-config") {
+        return check_config_command(&config).await;
+    }
 
     //setup loggin
+    #[cfg(not(windows))]
     setup_logging(&config).map_err(|e| format!("Failed to setup logging: {}", e))?;
+    // As a service stdout goes nowhere, so the event log is the fallback
+    #[cfg(windows)]
+    let config = match setup_logging(&config) {
+        Err(e) if stop.is_some() && config.log_target != LogTarget::EventLog => {
+            tezcatlipoca_auth::windows::report_error(&format!(
+                "Failed to setup logging, using the event log instead: {}",
+                e
+            ));
+            let config = Config {
+                log_target: LogTarget::EventLog,
+                ..config
+            };
+            setup_logging(&config).map_err(|e| format!("Failed to setup logging: {}", e))?;
+            config
+        }
+        result => {
+            result.map_err(|e| format!("Failed to setup logging: {}", e))?;
+            config
+        }
+    };
+
+    for issue in &config.validation_warnings {
+        warn!("Invalid configuration, using default instead: {}", issue);
+    }
+    for name in &config.unknown_vars {
+        match tezcatlipoca_auth::config::suggest(name) {
+            Some(known) => warn!("Unknown variable {} is ignored, did you mean {}?", name, known),
+            None => warn!("Unknown variable {} is ignored", name),
+        }
+    }
 
     info!("Configuration loaded:");
-    info!("  Banned IPs file: {}", config.banned_ips_file);
+    info!("  Banned IPs files: {}", config.banned_ips_files.join(", "));
+    if let Some((vault, loaded)) = &vault {
+        info!("  Vault: {} secret(s) from {}", loaded.secrets.len(), vault.addr);
+    }
     info!("  Cache TTL: {:?}", config.cache_ttl);
+    if config.banned_ips_watch {
+        info!("  Banned IPs files: reloaded on change");
+    }
+    info!(
+        "  Client IP sources: {}",
+        config
+            .client_ip_sources
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if !config.trusted_proxies.is_empty() {
+        let proxies: Vec<String> = config.trusted_proxies.iter().map(ToString::to_string).collect();
+        info!("  X-Forwarded-For: rightmost entry outside {}", proxies.join(", "));
+    } else if config.trusted_hops > 0 {
+        info!("  X-Forwarded-For: entry {} from the right", config.trusted_hops);
+    }
+    if config.cloudflare_verify {
+        info!(
+            "  Cloudflare header trusted from {} ranges ({})",
+            config.cloudflare_ips.len(),
+            config.cloudflare_ips_refresh.map_or("never refreshed".to_string(), |every| {
+                let urls = config.cloudflare_ips_urls.join(", ");
+                format!("refreshed every {:?} from {}", every, urls)
+            })
+        );
+    } else {
+        info!("  Cloudflare header trusted from any address");
+    }
     info!("  Log file: {}", config.log_file);
     info!("  Log dir: {}", config.log_dir);
     info!("  Log rotation: {:?}", config.log_rotation);
     info!("  Log max files: {}", config.log_max_files);
-    info!("  Port: {}", config.port);
-    info!("  Hostname: {}", config.hostname);
+    match &config.log_target {
+        LogTarget::File => info!("  Log target: file"),
+        LogTarget::Syslog(syslog) => info!(
+            "  Log target: syslog {:?} {} (facility {})",
+            syslog.transport, syslog.address, syslog.facility
+        ),
+        LogTarget::Journald { socket } => info!("  Log target: journald ({})", socket),
+        LogTarget::EventLog => info!("  Log target: Windows event log"),
+    }
+    for listen in &config.listen {
+        info!("  Listen: {} ({} routes)", listen.addr, listen.routes);
+    }
+    info!("  Enforce: {}", config.enforce);
+    info!("  Fail mode: {}", config.fail_mode);
+    match &config.block_response {
+        BlockResponse::Status(code) => info!("  Block response: {}", code),
+        BlockResponse::Redirect(url) => info!("  Block response: 302 to {}", url),
+        BlockResponse::RetryAfter(_)
+        | BlockResponse::Challenge(_)
+        | BlockResponse::Unauthorized(_)
+        | BlockResponse::Upstream(_)
+        | BlockResponse::WithReason(..)
+        | BlockResponse::Expiring(..)
+        | BlockResponse::Maintenance(..) => {}
+    }
+    if config.maintenance {
+        warn!("  Maintenance mode: ON, answering 503 to all but the allowlist");
+    }
+    if !config.maintenance_allowlist.is_empty() {
+        let allowlist: Vec<String> =
+            config.maintenance_allowlist.iter().map(ToString::to_string).collect();
+        info!("  Maintenance allowlist: {}", allowlist.join(", "));
+    }
+    let allowlist: Vec<String> =
+        config.lockdown_allowlist.iter().map(ToString::to_string).collect();
+    info!(
+        "  Lockdown: {:?} by default, at most {:?}, allowlist: {}",
+        config.lockdown_duration,
+        config.lockdown_max_duration,
+        if allowlist.is_empty() { "none".to_string() } else { allowlist.join(", ") }
+    );
+    for rule in &config.category_rules {
+        let target = format!("{}{}", rule.host.as_deref().unwrap_or(""), rule.path);
+        info!("  Ban categories for {}: {}", target, rule.categories);
+    }
+    for rule in &config.schedule_rules {
+        let target = format!("{}{}", rule.host.as_deref().unwrap_or(""), rule.path);
+        info!("  Open hours for {}: {} ({})", target, rule.schedule, config.schedule_timezone);
+    }
+    if let Some(rate) = config.rate_limit {
+        info!("  Rate limit: {} per IP ({})", rate, config.rate_limit_algorithm);
+    }
+    for rule in &config.rate_limit_rules {
+        let target = format!("{}{}", rule.host.as_deref().unwrap_or(""), rule.path);
+        let rate = rule.rate.map_or("off".to_string(), |rate| rate.to_string());
+        info!("  Rate limit for {}: {}", target, rate);
+    }
+    if let Some(timeout) = config.rate_limit_redis {
+        info!("  Rate limits counted in Redis (timeout {:?})", timeout);
+    }
+    if !config.header_rules.is_empty() {
+        let rules: Vec<String> =
+            config.header_rules.rules().iter().map(ToString::to_string).collect();
+        info!("  Header rules: {}", rules.join("; "));
+    }
+    if !config.blocked_tls_fingerprints.is_empty() {
+        let headers: Vec<&str> =
+            config.tls_fingerprint_headers.iter().map(|name| name.as_str()).collect();
+        info!(
+            "  Blocked TLS fingerprints: {} (from {})",
+            config.blocked_tls_fingerprints.len(),
+            headers.join(", ")
+        );
+    }
+    if config.decision_cache_size > 0 {
+        info!(
+            "  Decision cache: {} entries for {:?}",
+            config.decision_cache_size, config.decision_cache_ttl
+        );
+    }
+    if !config.policies.is_empty() {
+        let names: Vec<&str> = config.policies.keys().map(String::as_str).collect();
+        info!("  Policies by {}: {}", config.policy_header, names.join(", "));
+    }
+    if config.block_reason_header {
+        info!("  Block reason header: enabled (reveals ban details to clients)");
+    }
+    if config.greylist {
+        info!(
+            "  Greylist: retry after {:?}, trusted for {:?}",
+            config.greylist_delay, config.greylist_ttl
+        );
+    }
+    if !config.enforce {
+        warn!("Enforcement disabled (ENFORCE=false): banned IPs will be logged but not blocked");
+    }
+    match config.tarpit_delay {
+        Some(delay) => info!(
+            "  Tarpit: {:?}-{:?} (max {} concurrent)",
+            delay.min, delay.max, config.tarpit_max_concurrent
+        ),
+        None => info!("  Tarpit: disabled"),
+    }
+    if config.honeypot_paths.is_empty() {
+        info!("  Honeypot paths: none");
+    } else {
+        info!(
+            "  Honeypot paths: {} (ban: {:?})",
+            config.honeypot_paths.join(", "),
+            config.honeypot_ban_duration
+        );
+    }
+    if !config.query_rules.is_empty() {
+        let rules: Vec<String> =
+            config.query_rules.rules().iter().map(ToString::to_string).collect();
+        info!("  Query rules: {}", rules.join("; "));
+    }
+    if !config.ban_escalation.is_empty() {
+        let steps: Vec<String> = config
+            .ban_escalation
+            .iter()
+            .map(|step| step.map_or("permanent".to_string(), |d| format!("{:?}", d)))
+            .collect();
+        info!(
+            "  Ban escalation: {} (reset after {:?})",
+            steps.join(" -> "),
+            config.ban_escalation_reset
+        );
+    }
+    if let Some(path) = &config.ban_history_file {
+        info!("  Ban history file: {}", path);
+    }
+    if let Some(gossip) = &config.gossip {
+        info!("  Gossip: {} -> {}", gossip.bind, gossip.peers.join(", "));
+    }
+    if let Some(redis) = &config.redis {
+        info!("  Redis: ban sync on channel {}", redis.channel);
+        #[cfg(not(feature = "redis"))]
+        warn!("REDIS_URL is set but this build lacks the redis feature; ignoring it");
+    }
+    if let Some(kv) = &config.kv {
+        info!("  KV store: {} at {} under '{}'", kv.backend, kv.url, kv.prefix);
+    }
+    if let Some(path) = &config.state_snapshot_file {
+        info!(
+            "  State snapshot: {} (every {:?})",
+            path, config.state_snapshot_interval
+        );
+    }
+    match &config.crowdsec {
+        Some(cs) => info!(
+            "  CrowdSec: {} (poll every {:?}, alerts {})",
+            cs.lapi_url,
+            cs.poll_interval,
+            if cs.can_push_alerts() { "enabled" } else { "disabled" }
+        ),
+        None => info!("  CrowdSec: disabled"),
+    }
+    match &config.abuseipdb {
+        Some(abuse) => info!(
+            "  AbuseIPDB: block at score >= {} (cache {:?})",
+            abuse.threshold, abuse.cache_ttl
+        ),
+        None => info!("  AbuseIPDB: disabled"),
+    }
+    if let Some(dnsbl) = &config.dnsbl {
+        info!(
+            "  DNSBL: {} (cache {:?}, resolver {})",
+            dnsbl.zones.join(", "),
+            dnsbl.cache_ttl,
+            dnsbl.resolver.map_or("from the system".to_string(), |addr| addr.to_string())
+        );
+    }
+    if let Some(db) = &config.geoip_country_db {
+        info!("  GeoIP country database: {}", db);
+    }
+    if let Some(db) = &config.geoip_asn_db {
+        info!("  GeoIP ASN database: {}", db);
+    }
+    if let Some(rdns) = &config.rdns {
+        info!(
+            "  Reverse DNS: up to {} lookups/s (cache {:?}, resolver {})",
+            rdns.rate,
+            rdns.cache_ttl,
+            rdns.resolver.map_or("from the system".to_string(), |addr| addr.to_string())
+        );
+    }
+    let bot_challenge = config.bot_score.as_ref().and_then(|bot| bot.challenge_threshold);
+    if let Some(challenge) = &config.challenge {
+        info!(
+            "  Challenge: {} for {}, bypass cookie valid for {:?} (verified at {})",
+            match &challenge.mode {
+                ChallengeMode::Captcha { provider, .. } => format!("{:?}", provider),
+                ChallengeMode::ProofOfWork { difficulty } => {
+                    format!("proof of work ({} bits)", difficulty)
+                }
+            },
+            match challenge.threshold {
+                Some(threshold) => format!("AbuseIPDB score >= {}", threshold),
+                None if bot_challenge.is_some() => "no client by reputation".to_string(),
+                None => "every client".to_string(),
+            },
+            challenge.cookie_ttl,
+            challenge.path
+        );
+    }
+    if let Some(bot) = &config.bot_score {
+        let threshold =
+            |t: Option<u32>| t.map_or("never".to_string(), |t| format!("at score >= {}", t));
+        let header = |h: &Option<axum::http::HeaderName>| {
+            h.as_ref().map_or("not hinted".to_string(), ToString::to_string)
+        };
+        info!(
+            "  Bot score: challenge {}, ban {} ({} user agent patterns, protocol {}, \
+             header order {})",
+            threshold(bot.challenge_threshold),
+            threshold(bot.ban_threshold),
+            bot.user_agents.len(),
+            header(&bot.protocol_header),
+            header(&bot.header_order_header)
+        );
+    }
+    if let Some(auth) = &config.auth {
+        match &auth.mode {
+            AuthMode::Basic { users_file } => info!(
+                "  Auth: basic ({}) for {}, sessions valid for {:?}",
+                users_file,
+                auth.paths.join(", "),
+                auth.session_ttl
+            ),
+            AuthMode::Ldap(ldap) => info!(
+                "  Auth: LDAP ({} as {}{}) for {}, sessions valid for {:?}",
+                ldap.url,
+                ldap.bind_dn,
+                if ldap.groups.is_empty() {
+                    String::new()
+                } else {
+                    format!(", groups {}", ldap.groups.join(", "))
+                },
+                auth.paths.join(", "),
+                auth.session_ttl
+            ),
+        }
+        #[cfg(not(feature = "ldap"))]
+        if matches!(auth.mode, AuthMode::Ldap(_)) {
+            warn!("AUTH_MODE=ldap is set but this build lacks the ldap feature; every login will fail");
+        }
+        if let Some(totp) = &auth.totp {
+            info!(
+                "  TOTP: required for {} (code path {}, store {})",
+                totp.paths.join(", "),
+                totp.path,
+                totp.store_file.as_deref().unwrap_or("in memory")
+            );
+        }
+    }
+    if config.challenge.is_some() || config.auth.is_some() {
+        info!(
+            "  Cookies: {} signing key(s), {}",
+            config.cookie_secrets.len(),
+            if config.cookie_encrypt { "encrypted" } else { "signed only" }
+        );
+        if config.cookie_secrets.is_empty() {
+            warn!("COOKIE_SECRET is not set: cookies are invalidated on every restart");
+        }
+    }
+    if let Some(signature) = &config.signature {
+        info!(
+            "  Request signatures: required for {} (window {:?})",
+            signature.paths.join(", "),
+            signature.window
+        );
+    }
+    if let Some(opa) = &config.opa {
+        info!(
+            "  OPA policy: {} (timeout {:?}, fail {})",
+            opa.url,
+            opa.timeout,
+            if opa.fail_open { "open" } else { "closed" }
+        );
+    }
+    if let Some(script) = &config.script_file {
+        info!("  Decision script: {}", script);
+        #[cfg(not(feature = "scripting"))]
+        warn!("SCRIPT_FILE is set but this build lacks the scripting feature; ignoring it");
+    }
+    if let Some(dir) = &config.plugin_dir {
+        info!("  Decision plugins: {} ({} MiB each)", dir, config.plugin_memory_mb);
+        #[cfg(not(feature = "plugins"))]
+        warn!("PLUGIN_DIR is set but this build lacks the plugins feature; ignoring it");
+    }
+    if let Some(upstream) = &config.upstream_auth {
+        info!("  Upstream auth: {} (timeout {:?})", upstream.url, upstream.timeout);
+    }
+    for feed in &config.feeds {
+        info!("  Feed '{}': {} (every {:?})", feed.name, feed.url, feed.refresh);
+    }
+    match &config.webhook {
+        Some(hook) => info!(
+            "  Webhook: {:?} format, batches of up to {} every {:?}",
+            hook.format, hook.batch_size, hook.batch_window
+        ),
+        None => info!("  Webhook: disabled"),
+    }
+    match &config.loki {
+        Some(loki) => info!(
+            "  Loki: {} (batches of up to {} every {:?})",
+            loki.url, loki.batch_size, loki.batch_window
+        ),
+        None => info!("  Loki: disabled"),
+    }
+    info!("  Audit trail: {}", config.audit_log.as_deref().unwrap_or("disabled"));
+    info!(
+        "  Admin API: {}",
+        match (config.admin_api, &config.admin_token) {
+            (false, _) => "disabled",
+            (true, Some(_)) => "enabled (bearer token required)",
+            (true, None) => "enabled (no token, every request refused)",
+        }
+    );
+    if !config.admin_api && config.listen.iter().any(|l| l.routes == Routes::Admin) {
+        warn!("LISTEN has an admin listener but ADMIN_API=false; it only serves /health");
+    }
+    if client_ip::cloudflare_proxy_untrusted(&config) {
+        warn!(
+            "CLOUDFLARE_VERIFY is on without TRUSTED_PROXIES: cf-connecting-ip is ignored unless the proxy in front runs on loopback; set TRUSTED_PROXIES to its network (e.g. the Docker network of Traefik)"
+        );
+    }
+    info!("  PROXY protocol: {}", if config.proxy_protocol { "required" } else { "disabled" });
+    info!(
+        "  HTTP: {:?}, keep-alive {:?}, {} HTTP/2 streams, backlog {}, IPv6 {:?}",
+        config.http_version,
+        config.keep_alive_timeout,
+        config.http2_max_concurrent_streams,
+        config.tcp_backlog,
+        config.ipv6_mode
+    );
+    info!(
+        "  Concurrency limits: {:?} total, {:?} per IP",
+        config.max_inflight, config.max_inflight_per_ip
+    );
+    info!("  Request timeout: {:?}", config.request_timeout);
+    info!("  Body limit: {:?} bytes", config.max_body_bytes);
+    match config.ext_authz_port {
+        Some(port) => info!("  Envoy ext_authz: port {}", port),
+        None => info!("  Envoy ext_authz: disabled"),
+    }
+    info!("  Config validation: {:?}", config.validation_mode);
 
     // Initialize state
-    let state = AppState {
-        banned_ips: Arc::new(RwLock::new(BannedIpsCache::new(config.cache_ttl))),
-        config: config.clone(),
-    };
-
-    //load initial banned Ips
+    let state = AppState::new(config.clone());
+    if let Some(destination) = &config.audit_log
+        && state.audit.is_none()
     {
-        let mut cache = state.banned_ips.write().await;
-        if let Err(e) = cache.refresh(&state.config.banned_ips_file).await {
-            warn!("Failed to load initial banned IPs: {}", e);
+        // Serving without the trail would leave decisions unrecorded
+        return Err(format!("Audit trail {} could not be opened", destination).into());
+    }
+    if config.fail_mode == FailMode::Closed {
+        // Policies and plugins would decide without the client's location
+        let geoip = state.geoip.as_deref();
+        let databases = [
+            (&config.geoip_country_db, geoip.is_some_and(|g| g.has_country())),
+            (&config.geoip_asn_db, geoip.is_some_and(|g| g.has_asn())),
+        ];
+        for (path, open) in databases {
+            if let Some(path) = path
+                && !open
+            {
+                return Err(format!(
+                    "GeoIP database {} could not be opened (FAIL_MODE=closed)",
+                    path
+                )
+                .into());
+            }
         }
     }
 
+    //load initial banned Ips
+    if let Err(e) = reload_banned_ips(&state).await {
+        warn!("Failed to load initial banned IPs: {}", e);
+    }
+
+    bans::load_history(&state).await;
+    if state.config.ban_history_file.is_some() {
+        tokio::spawn(bans::history_task(state.clone()));
+    }
+
+    snapshot::load(&state).await;
+    if state.config.state_snapshot_file.is_some() {
+        tokio::spawn(snapshot::snapshot_task(state.clone()));
+    }
+
+    let tasks = state.tasks.clone();
+    let sweep_state = state.clone();
+    tasks.spawn("sweeper", Some(state.config.sweep_interval), move || {
+        sweeper::sweep_task(sweep_state.clone())
+    });
+
+    #[cfg(feature = "redis")]
+    if let Some(limiter) = state.redis_rate_limiter.clone() {
+        tokio::spawn(tezcatlipoca_auth::rate_limit_redis::connection_task(limiter));
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(config) = state.config.redis.clone() {
+        tokio::spawn(tezcatlipoca_auth::pubsub::sync_task(state.clone(), config));
+    }
+
+    if let Some((config, loaded)) = vault {
+        tokio::spawn(vault::renew_task(state.http.clone(), config, loaded));
+    }
+
+    if let Some(every) = state.config.cloudflare_ips_refresh {
+        let task_state = state.clone();
+        tasks.spawn("cloudflare", Some(every), move || {
+            cloudflare::refresh_task(task_state.clone(), every)
+        });
+    }
+
+    if let Some(config) = state.config.kv.clone() {
+        tokio::spawn(kv::watch_task(state.clone(), config));
+    }
+
+    if let Some(config) = state.config.gossip.clone() {
+        let socket = gossip::bind(&config)
+            .await
+            .map_err(|e| format!("Failed to bind gossip socket {}: {}", config.bind, e))?;
+        tokio::spawn(gossip::gossip_task(state.clone(), config, socket));
+    }
+
     // spawn background cache refresh task
     let refresh_state = state.clone();
-    tokio::spawn(async move {
-        cache_refresh_task(refresh_state).await;
+    tasks.spawn("cache_refresh", Some(state.config.cache_ttl), move || {
+        cache_refresh_task(refresh_state.clone())
     });
 
-    //build router with middleware
-    let app = Router::new()
-        .route("/health", any(controllers::health_check))
-        .with_state(state.clone())
-        .route("/{*path}", any(controllers::handler))
-        .route("/", any(controllers::handler))
-        .layer(middleware::from_fn_with_state(state.clone(), controllers::auth_middleware));
-
-    // Start server
-    let addr = format!("{}:{}", config.hostname, config.port);
-    info!("Starting server on {}", addr);
-    let listener = TcpListener::bind(&addr)
-        .await
-        .map_err(|e| format!("Failed to bind to {}: {}", addr, e))?;
-    
-    info!("Server successfully bound to {}", addr);
-    
-    // Use into_make_service_with_connect_info to access socket address
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
-    )
-    .await
+    if state.config.banned_ips_watch
+        && let Err(e) = watch_banned_ips(state.clone())
+    {
+        warn!("Failed to watch banned IPs files, relying on CACHE_TTL refreshes: {}", e);
+    }
+
+    if let Some(cs) = state.config.crowdsec.clone() {
+        let (task_state, task_config) = (state.clone(), cs.clone());
+        tasks.spawn("crowdsec_decisions", Some(cs.poll_interval), move || {
+            crowdsec::decision_stream_task(task_state.clone(), task_config.clone())
+        });
+        if cs.can_push_alerts() {
+            tokio::spawn(crowdsec::alert_push_task(state.clone(), cs));
+        }
+    }
+
+    if state.abuseipdb.is_some() {
+        tokio::spawn(abuseipdb::lookup_task(state.clone()));
+    }
+    #[cfg(feature = "dns")]
+    if state.dnsbl.is_some() {
+        tokio::spawn(tezcatlipoca_auth::dnsbl::lookup_task(state.clone()));
+    }
+    #[cfg(feature = "dns")]
+    if state.rdns.is_some() {
+        tokio::spawn(tezcatlipoca_auth::rdns::lookup_task(state.clone()));
+    }
+
+    for feed in state.config.feeds.clone() {
+        let (name, every) = (format!("feed:{}", feed.name), feed.refresh);
+        let task_state = state.clone();
+        tasks.spawn(name, Some(every), move || {
+            feeds::refresh_task(task_state.clone(), feed.clone())
+        });
+    }
+
+    if let Some(hook) = state.config.webhook.clone() {
+        let task_state = state.clone();
+        tasks.spawn("webhook", None, move || {
+            webhook::webhook_task(task_state.clone(), hook.clone())
+        });
+    }
+    tokio::spawn(supervisor::monitor_task(tasks));
+
+    if let Some(loki) = state.config.loki.clone() {
+        tokio::spawn(loki::push_task(state.clone(), loki));
+    }
+
+    if let Some(port) = state.config.ext_authz_port {
+        #[cfg(feature = "ext-authz")]
+        tokio::spawn(tezcatlipoca_auth::ext_authz::serve(state.clone(), port));
+        #[cfg(not(feature = "ext-authz"))]
+        warn!(
+            "EXT_AUTHZ_PORT={} is set but this build lacks the ext-authz feature; not serving gRPC",
+            port
+        );
+    }
+
+    // Start servers; a socket passed by systemd takes the first address's place
+    #[cfg(unix)]
+    let mut inherited = tezcatlipoca_auth::systemd::inherited_listener()
+        .map_err(|e| format!("Failed to use the socket passed by systemd: {}", e))?;
+    #[cfg(not(unix))]
+    let mut inherited = None;
+    let mut servers = tokio::task::JoinSet::new();
+    for listen in &config.listen {
+        let listeners = match inherited.take() {
+            Some(listener) => {
+                let addr = listener.local_addr()?;
+                info!("Serving {} routes on {} (socket passed by systemd)", listen.routes, addr);
+                vec![listener]
+            }
+            None => {
+                info!("Starting server on {} ({} routes)", listen.addr, listen.routes);
+                let listeners = server::bind_all(&listen.addr, &config)
+                    .await
+                    .map_err(|e| format!("Failed to bind to {}: {}", listen.addr, e))?;
+                for listener in &listeners {
+                    info!("Server successfully bound to {}", listener.local_addr()?);
+                }
+                listeners
+            }
+        };
+        //build router with middleware
+        let app = build_router_for(state.clone(), listen.routes);
+        // PROXY headers come from the proxy network, not from local admin clients
+        let proxy_protocol = config.proxy_protocol && listen.routes != Routes::Admin;
+        for listener in listeners {
+            let (app, state) = (app.clone(), state.clone());
+            servers.spawn(async move {
+                if proxy_protocol {
+                    let trusted = state.config.proxy_protocol_trusted.clone();
+                    let listener = ProxyProtocolListener::new(listener, trusted);
+                    server::serve(listener, app, &state.config).await
+                } else {
+                    server::serve(listener, app, &state.config).await
+                }
+            });
+        }
+    }
+    #[cfg(unix)]
+    tezcatlipoca_auth::systemd::spawn(state);
+
+    // Servers only return on failure; the first one ends the process
+    let serve = async {
+        match servers.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(std::io::Error::other(e)),
+            None => Ok(()),
+        }
+    };
+    match stop {
+        Some(stop) => tokio::select! {
+            result = serve => result,
+            _ = stop => {
+                info!("Stopping");
+                Ok(())
+            }
+        },
+        None => serve.await,
+    }
     .map_err(|e| format!("Server failed: {}", e))?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+const HEALTHCHECK_USAGE: &str = "usage: tezcatlipoca-auth healthcheck [--url URL] [--timeout SECS]";
+
+/// Container health check: exits 0 when the local instance answers `/readyz`
+/// with a success status, 1 otherwise, so an instance whose banned IPs
+/// aren't loaded or whose background tasks stopped is reported unhealthy.
+///
+/// Lets images without curl (scratch, distroless) define a `HEALTHCHECK`:
+///
+/// ```dockerfile
+/// HEALTHCHECK CMD ["/app/tezcatlipoca-auth", "healthcheck"]
+/// ```
+///
+/// The instance is reached on its first `LISTEN` address (`APP_HOSTNAME:PORT`
+/// by default), or on loopback when it listens on every address, unless
+/// `--url` says otherwise.
+async fn healthcheck_command(
+    config: &Config,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (host, port) = config.listen[0].addr.rsplit_once(':').ok_or(HEALTHCHECK_USAGE)?;
+    let host = match host {
+        "0.0.0.0" => "127.0.0.1",
+        "[::]" => "[::1]",
+        host => host,
+    };
+    let mut url = format!("http://{}:{}", host, port);
+    let mut timeout = std::time::Duration::from_secs(5);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--url" => {
+                url = args.next().ok_or(HEALTHCHECK_USAGE)?.trim_end_matches('/').to_string()
+            }
+            "--timeout" => {
+                let secs = args.next().ok_or(HEALTHCHECK_USAGE)?.parse::<f64>()?;
+                timeout = std::time::Duration::try_from_secs_f64(secs)?;
+            }
+            _ => return Err(HEALTHCHECK_USAGE.into()),
+        }
+    }
+
+    let result = reqwest::Client::new()
+        .get(format!("{}/readyz", url))
+        .timeout(timeout)
+        .send()
+        .await;
+    match result {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => {
+            eprintln!("unhealthy: {}/readyz answered {}", url, res.status());
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("unhealthy: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Checks the configuration and the files and URLs it refers to, printing
+/// a report; exits 1 when anything would keep the service from working.
+///
+/// Meant for CI against a deployment's environment:
+///
+/// ```sh
+/// env $(cat production.env) tezcatlipoca-auth check-config
+/// ```
+async fn check_config_command(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let report = doctor::check(config).await;
+    print!("{}", report);
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+const BENCH_USAGE: &str = "usage: tezcatlipoca-auth bench [--target URL] [--rps N] [--duration SECS]
+       [--concurrency N] [--ips N] [--host HOST] [--timeout SECS]";
+
+/// Load test: sends ForwardAuth requests from random client IPs to an
+/// instance at a fixed rate and prints the answers and latency percentiles,
+/// to size instances against realistic traffic:
+///
+/// ```sh
+/// tezcatlipoca-auth bench --target http://localhost:8199 --rps 5000
+/// ```
+///
+/// Runs for 10 seconds at 1000 requests per second against
+/// `http://127.0.0.1:8199` unless told otherwise; see [`bench`] for how
+/// requests are made and timed. Needs no configuration.
+async fn bench_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = bench::Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(BENCH_USAGE)?;
+        match arg.as_str() {
+            "--target" => options.target = value.trim_end_matches('/').to_string(),
+            "--rps" => options.rps = value.parse()?,
+            "--duration" => {
+                options.duration = std::time::Duration::try_from_secs_f64(value.parse()?)?
+            }
+            "--concurrency" => options.concurrency = value.parse()?,
+            "--ips" => options.ips = value.parse()?,
+            "--host" => options.host = value.to_string(),
+            "--timeout" => {
+                options.timeout = std::time::Duration::try_from_secs_f64(value.parse()?)?
+            }
+            _ => return Err(BENCH_USAGE.into()),
+        }
+    }
+    if options.rps == 0 || options.concurrency == 0 || options.ips == 0 {
+        return Err("--rps, --concurrency and --ips must be at least 1".into());
+    }
+
+    println!(
+        "Sending {} req/s to {} for {:?} from {} client IPs",
+        options.rps, options.target, options.duration, options.ips
+    );
+    let report = bench::run(&options).await?;
+    print!("{}", report);
+    Ok(())
+}
+
+const BANS_USAGE: &str = "usage: tezcatlipoca-auth bans export [--format csv|json] [--url URL]
+       tezcatlipoca-auth bans import FILE [--format csv|json] [--url URL]";
+
+/// Bulk ban export and import through the admin API of a running instance.
+///
+/// The instance is reached at `http://127.0.0.1:$PORT` unless `--url` says
+/// otherwise, authenticating with `ADMIN_TOKEN` / `ADMIN_TOKEN_FILE`. Exports
+/// go to stdout; imports read FILE (`-` for stdin), with the format taken
+/// from its extension unless `--format` is given.
+async fn bans_command(config: &Config, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut positional = Vec::new();
+    let mut format = None;
+    let mut url = format!("http://127.0.0.1:{}", config.port);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(args.next().ok_or(BANS_USAGE)?.parse::<BanFormat>()?),
+            "--url" => url = args.next().ok_or(BANS_USAGE)?.trim_end_matches('/').to_string(),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let http = reqwest::Client::new();
+    let request = match positional.as_slice() {
+        ["export"] => {
+            let format = format.unwrap_or_default();
+            http.get(format!("{}/admin/bans/export", url))
+                .query(&[("format", format.to_string())])
+        }
+        ["import", file] => {
+            let body = if *file == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(file)?
+            };
+            let format = format.unwrap_or(if file.ends_with(".csv") {
+                BanFormat::Csv
+            } else {
+                BanFormat::Json
+            });
+            http.post(format!("{}/admin/bans/import", url))
+                .query(&[("format", format.to_string())])
+                .header("content-type", format.content_type())
+                .body(body)
+        }
+        _ => return Err(BANS_USAGE.into()),
+    };
+    let request = match &config.admin_token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+
+    let res = request.send().await?;
+    let status = res.status();
+    let body = res.text().await?;
+    if !status.is_success() {
+        return Err(format!("admin API answered {}: {}", status, body.trim()).into());
+    }
+    println!("{}", body.trim_end());
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Maintenance mode.
+//!
+//! While active, every ForwardAuth request is answered `503 Service
+//! Unavailable` with `Retry-After: MAINTENANCE_RETRY_AFTER` and the
+//! `MAINTENANCE_PAGE` HTML (or a short default page), except those from
+//! `MAINTENANCE_ALLOWLIST` addresses, which go through the regular checks.
+//! This holds regardless of `ENFORCE`; `/health` and the admin API keep
+//! working.
+//!
+//! The mode is switched, in order of precedence, with `PUT` and `DELETE
+//! /admin/maintenance` on one replica, the `config/MAINTENANCE` key of the
+//! KV store for every replica at once, or `MAINTENANCE` at startup.
+
+use std::{net::IpAddr, sync::RwLock};
+
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{config::BlockResponse, metrics::Metrics, AppState};
+
+/// Body of the `503` when `MAINTENANCE_PAGE` is unset.
+pub const DEFAULT_PAGE: &str = "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+<title>Down for maintenance</title></head>\n<body><h1>Down for maintenance</h1>\n\
+<p>This service is being worked on and will be back shortly.</p></body></html>\n";
+
+/// Where the current mode comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// `PUT /admin/maintenance`
+    Admin,
+    /// `config/MAINTENANCE` in the KV store
+    Kv,
+    /// `MAINTENANCE`
+    Config,
+}
+
+/// Mode set through the admin API, overriding the KV store and config.
+#[derive(Debug, Default)]
+pub struct MaintenanceToggle(RwLock<Option<bool>>);
+
+impl MaintenanceToggle {
+    /// Sets the mode, or with `None` hands it back to the KV store and
+    /// config.
+    pub fn set(&self, enabled: Option<bool>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = enabled;
+    }
+
+    fn get(&self) -> Option<bool> {
+        *self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether maintenance mode is on, and what decided it.
+pub fn status(state: &AppState) -> (bool, Origin) {
+    if let Some(enabled) = state.maintenance.get() {
+        return (enabled, Origin::Admin);
+    }
+    let overrides = state.overrides.read().unwrap_or_else(|e| e.into_inner());
+    match overrides.maintenance {
+        Some(enabled) => (enabled, Origin::Kv),
+        None => (state.config.maintenance, Origin::Config),
+    }
+}
+
+/// The `503` for a request from `ip` to `path`, unless maintenance mode is
+/// off or the client is on the allowlist.
+///
+/// Checked by the front ends before anything else, so even the challenge
+/// and second factor endpoints are held back.
+pub fn block(state: &AppState, ip: IpAddr, path: &str) -> Option<BlockResponse> {
+    let canonical = ip.to_canonical();
+    let config = &state.config;
+    if !status(state).0 || config.maintenance_allowlist.iter().any(|net| net.contains(&canonical)) {
+        return None;
+    }
+    debug!("🚧 MAINTENANCE: IP {} accessed {}", ip, path);
+    Metrics::incr(&state.metrics.maintenance);
+    let page = config.maintenance_page.as_deref().unwrap_or(DEFAULT_PAGE);
+    Some(BlockResponse::Maintenance(config.maintenance_retry_after, page.to_string()))
+}
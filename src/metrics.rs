@@ -0,0 +1,239 @@
+//! Lightweight in-process request counters.
+//!
+//! Counters are plain atomics so the middleware can update them without
+//! taking any lock. They are reported by the `/health` endpoint.
+//!
+//! Decision latency is kept in log-linear histograms: eight buckets per
+//! power of two microseconds, so percentiles are accurate to within 12.5%
+//! while recording stays a single atomic increment.
+//!
+//! Blocks are also counted per client country when a GeoIP database is
+//! configured, with one counter for each possible two-letter code.
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Request decision counters shared across all handlers.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests that passed the IP check
+    pub allowed: AtomicU64,
+    /// Requests rejected because the client IP is banned
+    pub blocked: AtomicU64,
+    /// Requests from banned IPs let through because enforcement is disabled
+    pub would_block: AtomicU64,
+    /// Blocked requests that were held in the tarpit before the 403
+    pub tarpitted: AtomicU64,
+    /// First requests of unknown IPs deferred by the greylist
+    pub greylisted: AtomicU64,
+    /// Requests over `RATE_LIMIT` or a `RATE_LIMIT_RULES` rate
+    pub rate_limited: AtomicU64,
+    /// Requests Redis couldn't count (`RATE_LIMIT_REDIS`): counted by the
+    /// local buckets, or refused with `FAIL_MODE=closed`
+    pub rate_limit_fallbacks: AtomicU64,
+    /// Requests from suspicious IPs answered with the challenge page
+    pub challenged: AtomicU64,
+    /// Challenges solved, each issuing a bypass cookie
+    pub challenges_passed: AtomicU64,
+    /// Requests refused for a missing or invalid HMAC signature
+    pub unauthorized: AtomicU64,
+    /// Requests for a honeypot path (each one bans the client)
+    pub honeypot_hits: AtomicU64,
+    /// Requests whose query matched a `QUERY_RULES` rule (each one bans the client)
+    pub query_rule_hits: AtomicU64,
+    /// Requests scoring `BOT_SCORE_BAN` or more (each one bans the client)
+    pub bot_bans: AtomicU64,
+    /// Requests answered 503/429/413 by the concurrency or body limits
+    pub shed: AtomicU64,
+    /// Requests answered 503 after running past `REQUEST_TIMEOUT_MS`
+    pub timed_out: AtomicU64,
+    /// Requests answered 503 because of maintenance mode
+    pub maintenance: AtomicU64,
+    /// Requests blocked because of a lockdown
+    pub locked_out: AtomicU64,
+    /// Requests decided from the decision cache
+    pub decision_cache_hits: AtomicU64,
+    /// Admin API requests refused for a missing or wrong token
+    pub admin_auth_failures: AtomicU64,
+    /// AbuseIPDB API lookups performed
+    pub reputation_lookups: AtomicU64,
+    /// DNSBL lookups performed, one per IP across all zones
+    pub dnsbl_lookups: AtomicU64,
+    /// Reverse DNS lookups performed, including forward confirmation
+    pub rdns_lookups: AtomicU64,
+    /// Gossip datagrams dropped for a bad signature or a stale timestamp
+    pub gossip_dropped: AtomicU64,
+    /// Time to decide requests that were let through
+    pub latency_allowed: LatencyHistogram,
+    /// Time to answer refused requests, including any tarpit delay
+    pub latency_blocked: LatencyHistogram,
+    /// Expiry sweeps run, see [`crate::sweeper`]
+    pub sweeps: AtomicU64,
+    /// Expired dynamic bans removed by the sweeps
+    pub swept_bans: AtomicU64,
+    /// Stale offense, greylist and rate limit entries removed by the sweeps
+    pub swept_counters: AtomicU64,
+    /// Time each sweep took
+    pub latency_sweep: LatencyHistogram,
+    /// Background tasks restarted after a panic, see [`crate::supervisor`]
+    pub task_restarts: AtomicU64,
+    /// Blocked (or would-be blocked) requests per client country
+    pub blocked_by_country: CountryCounters,
+}
+
+impl Metrics {
+    /// Increments a counter by one.
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increments a counter by `n`.
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Reads the current value of a counter.
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+}
+
+/// Number of two-letter codes from `AA` to `ZZ`.
+const COUNTRY_CODES: usize = 26 * 26;
+
+/// Lock-free counters keyed by ISO 3166-1 alpha-2 country code.
+#[derive(Debug)]
+pub struct CountryCounters {
+    counts: Box<[AtomicU64]>,
+}
+
+impl Default for CountryCounters {
+    fn default() -> Self {
+        Self {
+            counts: (0..COUNTRY_CODES).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl CountryCounters {
+    /// Counts one event for `code`; anything but two ASCII letters is
+    /// ignored.
+    pub fn incr(&self, code: &str) {
+        if let [a, b] = code.as_bytes()
+            && a.is_ascii_alphabetic()
+            && b.is_ascii_alphabetic()
+        {
+            let index = usize::from(a.to_ascii_uppercase() - b'A') * 26
+                + usize::from(b.to_ascii_uppercase() - b'A');
+            self.counts[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts of the countries seen so far, by code.
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, count)| {
+                let count = count.load(Ordering::Relaxed);
+                let code = [b'A' + (index / 26) as u8, b'A' + (index % 26) as u8];
+                (count > 0).then(|| (String::from_utf8_lossy(&code).into_owned(), count))
+            })
+            .collect()
+    }
+}
+
+/// Linear buckets per power of two.
+const SUB_BUCKETS: u64 = 8;
+
+/// Buckets up to about 2^40 µs (12 days); longer durations land in the last.
+const BUCKETS: usize = 40 * SUB_BUCKETS as usize;
+
+/// Lock-free histogram of durations in microseconds.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+/// Percentiles of a histogram, in microseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Request count and percentiles; each percentile is the upper bound of
+    /// its bucket, 0 while nothing was recorded.
+    pub fn summary(&self) -> LatencySummary {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        LatencySummary {
+            count: counts.iter().sum(),
+            p50_us: quantile(&counts, 0.50),
+            p95_us: quantile(&counts, 0.95),
+            p99_us: quantile(&counts, 0.99),
+        }
+    }
+}
+
+fn quantile(counts: &[u64], q: f64) -> u64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0;
+    }
+    let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (index, count) in counts.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return bucket_upper_bound(index);
+        }
+    }
+    bucket_upper_bound(counts.len() - 1)
+}
+
+/// Values below [`SUB_BUCKETS`] get a bucket each; above, the power of two
+/// picks a group of buckets and the next three bits the bucket within it.
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exponent = 63 - u64::from(micros.leading_zeros());
+    let sub = (micros >> (exponent - 3)) & (SUB_BUCKETS - 1);
+    (((exponent - 2) * SUB_BUCKETS + sub) as usize).min(BUCKETS - 1)
+}
+
+/// Largest value counted in bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let exponent = index / SUB_BUCKETS + 2;
+    let width = 1 << (exponent - 3);
+    (SUB_BUCKETS + index % SUB_BUCKETS) * width + width - 1
+}
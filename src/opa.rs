@@ -0,0 +1,107 @@
+//! Open Policy Agent decisions.
+//!
+//! With `OPA_URL` set to a data API path of an OPA server (e.g.
+//! `http://opa:8181/v1/data/tezcatlipoca/allow`), every request that isn't
+//! already banned is described in an input document and posted there:
+//!
+//! ```json
+//! {"input": {"ip": "198.51.100.1", "method": "GET", "path": "/admin",
+//!            "headers": {"user-agent": "curl/8.5.0", "x-forwarded-host": "app.example"}}}
+//! ```
+//!
+//! With a GeoIP database configured, the client's `country`, `asn` and
+//! `as_org` are added next to `ip` when known.
+//!
+//! The rule may return a boolean or an object with an `allow` field; an
+//! undefined result denies. Denied requests are blocked like banned IPs
+//! (configured block response, `ENFORCE=false` only logs). `Cookie` and
+//! `Authorization` headers are left out of the input so credentials never
+//! reach the policy engine. When OPA can't be reached the request is let
+//! through or denied as `FAIL_MODE` says, unless `OPA_FAIL_OPEN` overrides
+//! it.
+
+use std::net::IpAddr;
+
+use axum::http::{
+    header::{AUTHORIZATION, COOKIE},
+    HeaderMap,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{config::OpaConfig, geoip::Geo, AppState};
+
+/// Why OPA couldn't be asked
+#[derive(Debug, thiserror::Error)]
+pub enum OpaError {
+    #[error("request failed: {0}")]
+    Http(#[source] reqwest::Error),
+    /// The response wasn't a boolean or an object with `allow`
+    #[error("unexpected result {0}")]
+    InvalidResult(Value),
+}
+
+#[derive(Deserialize)]
+struct DataResponse {
+    /// Absent when the rule is undefined for the input
+    result: Option<Value>,
+}
+
+/// Input document describing a request.
+pub fn input(ip: IpAddr, method: &str, path: &str, headers: &HeaderMap, geo: &Geo) -> Value {
+    let mut header_values = Map::new();
+    for (name, value) in headers {
+        if name == COOKIE || name == AUTHORIZATION {
+            continue;
+        }
+        if let Ok(value) = value.to_str() {
+            // Repeated headers are joined like HTTP allows
+            header_values
+                .entry(name.as_str())
+                .and_modify(|existing| {
+                    if let Value::String(existing) = existing {
+                        existing.push_str(", ");
+                        existing.push_str(value);
+                    }
+                })
+                .or_insert_with(|| Value::String(value.to_string()));
+        }
+    }
+    let mut input = json!({
+        "ip": ip.to_string(),
+        "method": method,
+        "path": path,
+        "headers": header_values,
+    });
+    if let Value::Object(fields) = &mut input
+        && let Value::Object(geo) = json!(geo)
+    {
+        fields.extend(geo);
+    }
+    input
+}
+
+/// Asks OPA whether the request described by `input` is allowed.
+pub async fn allowed(state: &AppState, config: &OpaConfig, input: Value) -> Result<bool, OpaError> {
+    let response: DataResponse = state
+        .http
+        .post(&config.url)
+        .timeout(config.timeout)
+        .json(&json!({ "input": input }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(OpaError::Http)?
+        .json()
+        .await
+        .map_err(OpaError::Http)?;
+    match response.result {
+        None => Ok(false),
+        Some(Value::Bool(allow)) => Ok(allow),
+        Some(Value::Object(result)) => match result.get("allow") {
+            Some(Value::Bool(allow)) => Ok(*allow),
+            _ => Err(OpaError::InvalidResult(Value::Object(result))),
+        },
+        Some(other) => Err(OpaError::InvalidResult(other)),
+    }
+}
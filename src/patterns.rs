@@ -0,0 +1,76 @@
+//! Regular expressions of the request rules, compiled once.
+//!
+//! The `~regex` entries of `QUERY_RULES` and `HEADER_RULES`, and the
+//! expressions of the bot score signals, are compiled into a [`RegexSet`]
+//! when the configuration loads, so an invalid expression is reported at
+//! startup and each request is matched against all of a rule list's
+//! expressions in one pass instead of one per rule. The compiled set is
+//! shared by every clone of the configuration.
+
+use regex::RegexSet;
+
+/// Compiled expressions, matched all at once.
+#[derive(Clone, Debug)]
+pub struct PatternSet {
+    set: RegexSet,
+}
+
+impl PatternSet {
+    /// Compiles `patterns`; the error names the first invalid one.
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        // RegexSet's own error doesn't say which pattern failed
+        for pattern in patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(format!("invalid pattern '{}': {}", pattern, e));
+            }
+        }
+        let set = RegexSet::new(patterns).map_err(|e| e.to_string())?;
+        Ok(Self { set })
+    }
+
+    /// Compiles expressions separated by `;`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let patterns: Vec<String> = s
+            .split(';')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+        Self::new(&patterns)
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// Whether any pattern matches `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.set.is_match(text)
+    }
+
+    /// Which patterns, by index, match any of `texts`.
+    pub fn matches<'t>(&self, texts: impl IntoIterator<Item = &'t str>) -> Vec<bool> {
+        let mut matched = vec![false; self.set.len()];
+        if self.set.is_empty() {
+            return matched;
+        }
+        for text in texts {
+            for index in self.set.matches(text).iter() {
+                matched[index] = true;
+            }
+        }
+        matched
+    }
+}
+
+impl Default for PatternSet {
+    fn default() -> Self {
+        Self {
+            set: RegexSet::empty(),
+        }
+    }
+}
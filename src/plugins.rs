@@ -0,0 +1,193 @@
+//! Sandboxed WebAssembly decision plugins.
+//!
+//! With `PLUGIN_DIR` set (and the `plugins` feature), every `*.wasm` (or
+//! `*.wat`) module in that directory is loaded at startup and, in file name
+//! order, may override the verdict of the ban, reputation and policy checks,
+//! like the Rhai hook does. A plugin is a WASI preview 1 module exporting:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`: space for the input document
+//! - `decide(ptr: i32, len: i32) -> i32`: `0` keeps the decision, `1`
+//!   allows the request, `2` blocks it
+//!
+//! The input is a UTF-8 JSON object with `ip`, `method`, `path`, `headers`
+//! (without `cookie` and `authorization`), the GeoIP fields `country`, `asn`
+//! and `as_org` when known, `decision` (`"allow"` or `"block"`) and `reason`.
+//!
+//! Each call runs in a fresh instance with no files, environment or network,
+//! at most `PLUGIN_MEMORY_MB` of memory and a fuel budget, so a misbehaving
+//! plugin fails (keeping the decision) instead of affecting the service.
+
+use std::{net::IpAddr, path::Path, sync::Arc};
+
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+use wasmtime::{Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::{preview1::WasiP1Ctx, WasiCtxBuilder};
+
+use crate::{geoip::Geo, opa};
+
+/// Fuel (roughly wasm instructions) one `decide` call may use.
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+/// Verdicts returned by `decide`.
+const ALLOW: i32 = 1;
+const BLOCK: i32 = 2;
+
+struct PluginState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+struct Plugin {
+    name: String,
+    pre: InstancePre<PluginState>,
+}
+
+/// Loaded plugins, run in file name order.
+pub struct Plugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+    memory_limit: usize,
+}
+
+impl Plugins {
+    /// Compiles the plugins in `dir`; modules that fail to compile or link
+    /// are skipped with a warning.
+    pub fn load(dir: &str, memory_limit_mb: usize) -> Self {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).expect("wasmtime configuration is valid");
+        let mut linker: Linker<PluginState> = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |state| &mut state.wasi)
+            .expect("WASI functions link once");
+
+        let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|ext| ext == "wasm" || ext == "wat")
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Failed to read plugin directory {}: {}", dir, e);
+                Vec::new()
+            }
+        };
+        paths.sort();
+
+        let plugins = paths
+            .iter()
+            .filter_map(|path| match load_plugin(&engine, &linker, path) {
+                Ok(plugin) => {
+                    info!("Loaded decision plugin {}", plugin.name);
+                    Some(plugin)
+                }
+                Err(e) => {
+                    warn!("Skipping decision plugin {}: {:#}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            engine,
+            plugins,
+            memory_limit: memory_limit_mb * 1024 * 1024,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Block reason after every plugin had its say; `reason` is the current
+    /// one (`None` when the request is allowed so far).
+    ///
+    /// Plugins run on the blocking thread pool.
+    pub async fn decide(
+        self: Arc<Self>,
+        ip: IpAddr,
+        method: &str,
+        path: &str,
+        headers: &HeaderMap,
+        geo: &Geo,
+        reason: Option<String>,
+    ) -> Option<String> {
+        if self.plugins.is_empty() {
+            return reason;
+        }
+        let request = opa::input(ip, method, path, headers, geo);
+        let fallback = reason.clone();
+        tokio::task::spawn_blocking(move || {
+            self.plugins.iter().fold(reason, |reason, plugin| {
+                self.run(plugin, &request, reason)
+            })
+        })
+        .await
+        .unwrap_or(fallback)
+    }
+
+    fn run(&self, plugin: &Plugin, request: &Value, reason: Option<String>) -> Option<String> {
+        let mut input = request.clone();
+        input["decision"] = json!(if reason.is_some() { "block" } else { "allow" });
+        input["reason"] = json!(reason.as_deref().unwrap_or_default());
+
+        match self.call(plugin, input.to_string().as_bytes()) {
+            Ok(ALLOW) if reason.is_some() => {
+                debug!("🧩 PLUGIN {} ALLOWED: {} despite [{}]", plugin.name, request["ip"], reason.unwrap_or_default());
+                None
+            }
+            Ok(BLOCK) if reason.is_none() => Some(format!("PLUGIN {}", plugin.name)),
+            Ok(_) => reason,
+            Err(e) => {
+                warn!("Decision plugin {} failed: {:#}", plugin.name, e);
+                reason
+            }
+        }
+    }
+
+    fn call(&self, plugin: &Plugin, input: &[u8]) -> wasmtime::Result<i32> {
+        let state = PluginState {
+            wasi: WasiCtxBuilder::new().build_p1(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.memory_limit)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CALL)?;
+
+        let instance = plugin.pre.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("no exported memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let decide = instance.get_typed_func::<(i32, i32), i32>(&mut store, "decide")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, usize::try_from(ptr)?, input)?;
+        decide.call(&mut store, (ptr, len))
+    }
+}
+
+fn load_plugin(
+    engine: &Engine,
+    linker: &Linker<PluginState>,
+    path: &Path,
+) -> wasmtime::Result<Plugin> {
+    let module = Module::new(engine, std::fs::read(path)?)?;
+    Ok(Plugin {
+        name: path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        pre: linker.instantiate_pre(&module)?,
+    })
+}
@@ -0,0 +1,143 @@
+//! Named policies selected per request (`POLICIES`).
+//!
+//! One deployment can serve many Traefik middlewares with different rules:
+//! each router sets a header naming its policy (`POLICY_HEADER`,
+//! `X-Auth-Policy` by default) through a `headers` middleware placed before
+//! the ForwardAuth one, and the named policy replaces the global settings
+//! for that request. `POLICIES` is a JSON object, usually read from
+//! `POLICIES_FILE`:
+//!
+//! ```json
+//! {
+//!   "internal": {"auth": "required", "category_rules": "/=*"},
+//!   "public": {"auth": "none", "category_rules": "/=-office", "enforce": false},
+//!   "staging": {"schedule_rules": "/=mon-fri 08:00-18:00", "honeypot_paths": ["/.env"]}
+//! }
+//! ```
+//!
+//! - `category_rules`, `schedule_rules`: ban categories and opening hours in
+//!   the `CATEGORY_RULES` and `SCHEDULE_RULES` syntax.
+//! - `honeypot_paths`: trap paths, like `HONEYPOT_PATHS`.
+//! - `enforce`: observe-only (`false`) or enforcing, like `ENFORCE`; the
+//!   `config/ENFORCE` KV override still takes precedence.
+//! - `auth`: `default` (`AUTH_PATHS` decide), `none` (no login) or
+//!   `required` (login on every path, with the configured `AUTH_MODE`).
+//!
+//! Settings a policy leaves out keep their global value, and requests
+//! without the header use the global settings. A request naming an unknown
+//! policy is blocked with the reason `UNKNOWN POLICY`, as it points to a
+//! misconfigured router.
+//!
+//! Clients can send the header themselves: every router must set it, or
+//! its clients may pick the laxest policy.
+
+use std::collections::BTreeMap;
+
+use axum::http::HeaderMap;
+use serde::Deserialize;
+
+use crate::{
+    auth::Authenticator,
+    config::{parse_rules, CategoryRule, ScheduleRule},
+    AppState,
+};
+
+/// Default header naming the policy of a request
+pub const DEFAULT_HEADER: &str = "x-auth-policy";
+
+/// Settings replacing the global ones for the requests of a policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub category_rules: Option<Vec<CategoryRule>>,
+    pub schedule_rules: Option<Vec<ScheduleRule>>,
+    pub honeypot_paths: Option<Vec<String>>,
+    pub enforce: Option<bool>,
+    pub auth: PolicyAuth,
+}
+
+/// Whether the requests of a policy must log in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAuth {
+    /// On the paths under `AUTH_PATHS`
+    #[default]
+    Default,
+    /// Never
+    None,
+    /// On every path
+    Required,
+}
+
+/// A policy as written in `POLICIES`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicy {
+    category_rules: Option<String>,
+    schedule_rules: Option<String>,
+    honeypot_paths: Option<Vec<String>>,
+    enforce: Option<bool>,
+    #[serde(default)]
+    auth: PolicyAuth,
+}
+
+/// Parses the `POLICIES` JSON object of policies by name. Names are matched
+/// without regard to ASCII case.
+pub fn parse(s: &str) -> Result<BTreeMap<String, Policy>, String> {
+    let raw: BTreeMap<String, RawPolicy> = serde_json::from_str(s).map_err(|e| e.to_string())?;
+    let mut policies = BTreeMap::new();
+    for (name, raw) in raw {
+        let category_rules = raw
+            .category_rules
+            .map(|rules| {
+                parse_rules(&rules, CategoryRule::parse)
+                    .ok_or_else(|| format!("{}: invalid category_rules '{}'", name, rules))
+            })
+            .transpose()?;
+        let schedule_rules = raw
+            .schedule_rules
+            .map(|rules| {
+                parse_rules(&rules, ScheduleRule::parse)
+                    .ok_or_else(|| format!("{}: invalid schedule_rules '{}'", name, rules))
+            })
+            .transpose()?;
+        let policy = Policy {
+            category_rules,
+            schedule_rules,
+            honeypot_paths: raw.honeypot_paths,
+            enforce: raw.enforce,
+            auth: raw.auth,
+        };
+        policies.insert(name.to_ascii_lowercase(), policy);
+    }
+    Ok(policies)
+}
+
+/// The policy a request names in `POLICY_HEADER`: `Ok(None)` without the
+/// header, `Err` with the name when no such policy exists.
+pub fn select<'a>(state: &'a AppState, headers: &HeaderMap) -> Result<Option<&'a Policy>, String> {
+    let config = &state.config;
+    let Some(name) = headers.get(&config.policy_header) else {
+        return Ok(None);
+    };
+    let name = String::from_utf8_lossy(name.as_bytes()).trim().to_ascii_lowercase();
+    config.policies.get(&name).map(Some).ok_or(name)
+}
+
+/// Whether blocks are enforced for a request of `policy`: the KV override,
+/// then the policy, then `ENFORCE`.
+pub fn enforce(state: &AppState, policy: Option<&Policy>) -> bool {
+    let overrides = state.overrides.read().unwrap_or_else(|e| e.into_inner());
+    overrides
+        .enforce
+        .or(policy.and_then(|policy| policy.enforce))
+        .unwrap_or(state.config.enforce)
+}
+
+/// Whether a request of `policy` for `path` must log in with `auth`.
+pub fn auth_required(policy: Option<&Policy>, auth: &Authenticator, path: &str) -> bool {
+    match policy.map_or(PolicyAuth::Default, |policy| policy.auth) {
+        PolicyAuth::Default => auth.required(path),
+        PolicyAuth::None => false,
+        PolicyAuth::Required => true,
+    }
+}
@@ -0,0 +1,197 @@
+//! PROXY protocol (v1 and v2) on the HTTP listener.
+//!
+//! Behind HAProxy or a TCP load balancer the connection comes from the
+//! balancer, not the client. With `PROXY_PROTOCOL=true` every connection must
+//! start with a PROXY protocol header; the source address it carries becomes
+//! the connection's address, which `CLIENT_IP_SOURCES=socket` then uses as
+//! the client IP.
+//!
+//! Anyone can send a header, so only connections from the balancers in
+//! `PROXY_PROTOCOL_TRUSTED` (`TRUSTED_PROXIES` by default) are accepted, and
+//! only with a valid one. Other connections are dropped: a client reaching
+//! the port around the balancer can neither claim an address nor be judged
+//! by its own.
+//!
+//! Headers are read in background tasks, so a slow or silent connection only
+//! holds up itself; it is dropped after [`HEADER_TIMEOUT`].
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::serve::Listener;
+use ipnet::IpNet;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tracing::{debug, warn};
+
+/// How long a new connection may take to send its header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature starting a v2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest v1 header line, including `\r\n`.
+const V1_MAX_LEN: usize = 107;
+
+/// Largest v2 address block accepted (addresses plus TLVs).
+const V2_MAX_LEN: usize = 4096;
+
+/// Listener yielding connections with the address from their PROXY header.
+pub struct ProxyProtocolListener {
+    connections: mpsc::Receiver<(TcpStream, SocketAddr)>,
+    local_addr: io::Result<SocketAddr>,
+}
+
+impl ProxyProtocolListener {
+    /// Starts accepting on `listener`, from peers within `trusted` only.
+    pub fn new(listener: TcpListener, trusted: Vec<IpNet>) -> Self {
+        let local_addr = listener.local_addr();
+        let (tx, connections) = mpsc::channel(1024);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+                let ip = peer.ip().to_canonical();
+                if !trusted.iter().any(|net| net.contains(&ip)) {
+                    debug!("Dropping connection from {}: not a trusted balancer", peer);
+                    continue;
+                }
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+                        Ok(Ok(source)) => {
+                            let _ = tx.send((stream, source.unwrap_or(peer))).await;
+                        }
+                        Ok(Err(e)) => debug!("Dropping connection from {}: {}", peer, e),
+                        Err(_) => debug!("Dropping connection from {}: no PROXY header", peer),
+                    }
+                });
+            }
+        });
+        Self {
+            connections,
+            local_addr,
+        }
+    }
+}
+
+impl Listener for ProxyProtocolListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept loop never ends, but don't spin if it did
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match &self.local_addr {
+            Ok(addr) => Ok(*addr),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads exactly the PROXY header off `stream`, leaving the request intact.
+///
+/// Returns the client address, or `None` for headers without one (`LOCAL`
+/// health checks, `UNKNOWN` protocol).
+pub async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 5];
+    stream.read_exact(&mut start).await?;
+    if &start == b"PROXY" {
+        let mut line = start.to_vec();
+        // Byte by byte: anything after the line belongs to the request
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("PROXY v1 header isn't text"))?;
+        return parse_v1(line.trim_end()).ok_or_else(|| invalid("malformed PROXY v1 header"));
+    }
+
+    let mut header = [0u8; 16];
+    header[..5].copy_from_slice(&start);
+    stream.read_exact(&mut header[5..]).await?;
+    if header[..12] != V2_SIGNATURE {
+        return Err(invalid("missing PROXY header"));
+    }
+    let len = usize::from(u16::from_be_bytes([header[14], header[15]]));
+    if len > V2_MAX_LEN {
+        return Err(invalid("PROXY v2 header too long"));
+    }
+    let mut block = vec![0u8; len];
+    stream.read_exact(&mut block).await?;
+    parse_v2(header[12], header[13], &block).ok_or_else(|| invalid("malformed PROXY v2 header"))
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>` or `PROXY UNKNOWN ...`
+fn parse_v1(line: &str) -> Option<Option<SocketAddr>> {
+    let mut fields = line.split(' ');
+    if fields.next()? != "PROXY" {
+        return None;
+    }
+    match fields.next()? {
+        "UNKNOWN" => Some(None),
+        protocol @ ("TCP4" | "TCP6") => {
+            let ip: IpAddr = fields.next()?.parse().ok()?;
+            let _destination: IpAddr = fields.next()?.parse().ok()?;
+            let port: u16 = fields.next()?.parse().ok()?;
+            if ip.is_ipv4() != (protocol == "TCP4") {
+                return None;
+            }
+            Some(Some(SocketAddr::new(ip, port)))
+        }
+        _ => None,
+    }
+}
+
+/// Address block of a v2 header; `version_command` and `family` are bytes
+/// 13 and 14.
+fn parse_v2(version_command: u8, family: u8, block: &[u8]) -> Option<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return None;
+    }
+    match version_command & 0x0f {
+        // LOCAL: the balancer's own connection, e.g. a health check
+        0 => return Some(None),
+        1 => {}
+        _ => return None,
+    }
+    match family >> 4 {
+        1 => {
+            let octets: [u8; 4] = block.get(..4)?.try_into().ok()?;
+            let port = u16::from_be_bytes(block.get(8..10)?.try_into().ok()?);
+            Some(Some(SocketAddr::new(Ipv4Addr::from(octets).into(), port)))
+        }
+        2 => {
+            let octets: [u8; 16] = block.get(..16)?.try_into().ok()?;
+            let port = u16::from_be_bytes(block.get(32..34)?.try_into().ok()?);
+            Some(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // Unspecified or Unix sockets: no client IP to report
+        0 | 3 => Some(None),
+        _ => None,
+    }
+}
@@ -0,0 +1,192 @@
+//! Ban propagation through Redis pub/sub.
+//!
+//! With `REDIS_URL` set (and the `redis` feature), every automatic ban,
+//! every unban or ban length change and every lockdown started or lifted
+//! through the admin API is published on `REDIS_CHANNEL`, and updates
+//! published by other instances are applied locally as they arrive. Each
+//! instance tags its messages with a random origin id and ignores its own.
+//!
+//! When the connection drops the task reconnects every
+//! [`RECONNECT_DELAY`]; bans made meanwhile are published once it is back
+//! (up to the event bus buffer), but updates other instances sent while
+//! disconnected are not replayed, as pub/sub keeps no history.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::sleep,
+};
+use tracing::{info, warn};
+
+use crate::{bans::SOURCE_REDIS, config::RedisConfig, events::SecurityEvent, AppState};
+
+/// Wait before reconnecting after the connection failed.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A change to the dynamic bans or the lockdown, as published on the
+/// channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BanUpdate {
+    Ban {
+        ip: String,
+        reason: String,
+        /// Ban length in seconds, `None` when permanent
+        duration_secs: Option<u64>,
+    },
+    Unban {
+        ip: String,
+    },
+    /// Lockdown until `until`, or lifted when `None`
+    Lockdown {
+        until: Option<DateTime<Utc>>,
+    },
+}
+
+/// Channel message: an update plus the instance that published it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub origin: u64,
+    #[serde(flatten)]
+    pub update: BanUpdate,
+}
+
+impl BanUpdate {
+    /// The update to publish for a security event, if any.
+    pub fn from_event(event: SecurityEvent) -> Option<Self> {
+        match event {
+            SecurityEvent::AutoBan {
+                ip,
+                reason,
+                duration_secs,
+                ..
+            } => Some(Self::Ban {
+                ip,
+                reason,
+                duration_secs,
+            }),
+            SecurityEvent::BanUpdated {
+                ip,
+                reason,
+                duration_secs,
+                ..
+            } => Some(Self::Ban {
+                ip,
+                reason,
+                duration_secs,
+            }),
+            SecurityEvent::Unban { ip, .. } => Some(Self::Unban { ip }),
+            SecurityEvent::Lockdown { until, .. } => Some(Self::Lockdown { until }),
+            SecurityEvent::Blocked { .. } => None,
+        }
+    }
+
+    /// Applies an update received from another instance.
+    pub fn apply(self, state: &AppState) {
+        match self {
+            Self::Ban {
+                ip,
+                reason,
+                duration_secs,
+            } => {
+                info!("Ban of {} received through Redis [{}]", ip, reason);
+                state
+                    .dynamic_bans
+                    .ban(&ip, duration_secs.map(Duration::from_secs), &reason, SOURCE_REDIS);
+            }
+            Self::Unban { ip } => {
+                info!("Unban of {} received through Redis", ip);
+                state.dynamic_bans.unban(&ip);
+            }
+            Self::Lockdown { until: Some(until) } => {
+                warn!("🔒 Lockdown until {} received through Redis", until);
+                state.lockdown.set(Some(until));
+            }
+            Self::Lockdown { until: None } => {
+                info!("Lockdown lifted through Redis");
+                state.lockdown.set(None);
+            }
+        }
+    }
+}
+
+/// Publishes local ban changes and applies those of other instances,
+/// reconnecting as needed, until the event bus closes.
+pub async fn sync_task(state: AppState, config: RedisConfig) {
+    let mut origin = [0u8; 8];
+    getrandom::fill(&mut origin).expect("OS random number generator is available");
+    let origin = u64::from_le_bytes(origin);
+    // Subscribed once, so bans made while disconnected are still published
+    let mut events = state.events.subscribe();
+    loop {
+        match run(&state, &config, origin, &mut events).await {
+            Ok(()) => return,
+            Err(e) => warn!(
+                "Redis ban sync interrupted, reconnecting in {:?}: {}",
+                RECONNECT_DELAY, e
+            ),
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// One connection's worth of syncing; `Ok` once the event bus is closed.
+async fn run(
+    state: &AppState,
+    config: &RedisConfig,
+    origin: u64,
+    events: &mut Receiver<SecurityEvent>,
+) -> Result<(), String> {
+    let client = redis::Client::open(config.url.as_str()).map_err(|e| e.to_string())?;
+    let mut publisher = client
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut pubsub = client.get_async_pubsub().await.map_err(|e| e.to_string())?;
+    pubsub
+        .subscribe(&config.channel)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut messages = pubsub.into_on_message();
+    info!("Syncing dynamic bans through Redis channel {}", config.channel);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let update = match event {
+                    Ok(event) => BanUpdate::from_event(event),
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Redis ban sync fell behind, {} events not published", missed);
+                        None
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                let Some(update) = update else {
+                    continue;
+                };
+                let payload = serde_json::to_string(&Envelope { origin, update })
+                    .map_err(|e| e.to_string())?;
+                publisher
+                    .publish::<_, _, ()>(&config.channel, payload)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            message = messages.next() => {
+                let Some(message) = message else {
+                    return Err("subscription closed".to_string());
+                };
+                let payload = message.get_payload_bytes();
+                match serde_json::from_slice::<Envelope>(payload) {
+                    Ok(envelope) if envelope.origin == origin => {}
+                    Ok(envelope) => envelope.update.apply(state),
+                    Err(e) => warn!("Ignoring malformed message on {}: {}", config.channel, e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Query-string rules that ban exploit probes (`QUERY_RULES`).
+//!
+//! Scanners probe for command injection and SQL injection through query
+//! parameters (`?cmd=id`, `?id=1+union+select+...`) no legitimate client
+//! sends. A request whose query matches a rule bans the client like a
+//! honeypot path does. Rules are separated by `;`:
+//!
+//! - `cmd`: the parameter is present, with any value
+//! - `action=shell`: the parameter has exactly this value
+//! - `~(?i)union\s+select`: the regular expression matches the decoded query
+//!   (write a `;` in it as `\x3b`)
+//!
+//! Parameters are compared after percent- and `+`-decoding, so `union+select`
+//! and `union%20select` both read `union select`.
+
+use std::fmt;
+
+use crate::patterns::PatternSet;
+
+/// A `QUERY_RULES` entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryRule {
+    /// Any request carrying the parameter
+    Present(String),
+    /// The parameter with this value
+    Equals(String, String),
+    /// Matches anywhere in the decoded query (`key=value&...`)
+    Pattern(String),
+}
+
+impl QueryRule {
+    /// Parses one rule; `None` for an empty key. Expressions are checked
+    /// when compiled by [`QueryRules::parse`].
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(pattern) = s.strip_prefix('~') {
+            return Some(Self::Pattern(pattern.to_string()));
+        }
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            return None;
+        }
+        Some(match value {
+            Some(value) => Self::Equals(key.to_string(), value.to_string()),
+            None => Self::Present(key.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for QueryRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Present(key) => f.write_str(key),
+            Self::Equals(key, value) => write!(f, "{}={}", key, value),
+            Self::Pattern(pattern) => write!(f, "~{}", pattern),
+        }
+    }
+}
+
+/// `QUERY_RULES` with their expressions compiled.
+#[derive(Clone, Debug, Default)]
+pub struct QueryRules {
+    rules: Vec<QueryRule>,
+    /// The expressions of the `Pattern` rules, in order
+    patterns: PatternSet,
+}
+
+impl QueryRules {
+    /// Parses rules separated by `;` and compiles their expressions.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for rule in s.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+            rules.push(QueryRule::parse(rule).ok_or_else(|| format!("invalid rule '{}'", rule))?);
+        }
+        let patterns: Vec<String> = rules
+            .iter()
+            .filter_map(|rule| match rule {
+                QueryRule::Pattern(pattern) => Some(pattern.clone()),
+                _ => None,
+            })
+            .collect();
+        let patterns = PatternSet::new(&patterns)?;
+        Ok(Self { rules, patterns })
+    }
+
+    pub fn rules(&self) -> &[QueryRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first rule matching `query` (without the leading `?`).
+    pub fn matching(&self, query: &str) -> Option<&QueryRule> {
+        if self.rules.is_empty() || query.is_empty() {
+            return None;
+        }
+        let params: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        let decoded = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let matched = self.patterns.matches([decoded.as_str()]);
+        let mut patterns = matched.into_iter();
+        self.rules.iter().find(|rule| match rule {
+            QueryRule::Present(key) => params.iter().any(|(k, _)| k == key),
+            QueryRule::Equals(key, value) => params.iter().any(|(k, v)| k == key && v == value),
+            QueryRule::Pattern(_) => patterns.next().unwrap_or(false),
+        })
+    }
+}
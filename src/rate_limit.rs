@@ -0,0 +1,351 @@
+//! Per-IP request rate limits, optionally stricter on some paths.
+//!
+//! `RATE_LIMIT` caps how fast one client IP may send requests, e.g. `100/s`.
+//! `RATE_LIMIT_RULES` overrides it for a host or path, first match wins, so
+//! brute-force targets can be throttled much harder than the rest:
+//!
+//! ```text
+//! RATE_LIMIT=100/s
+//! RATE_LIMIT_RULES=/login=5/min;api.example.com/export=10/h;/health=off
+//! ```
+//!
+//! Each rule counts its own requests: the five `/login` attempts a minute
+//! don't use up any of the hundred requests a second allowed elsewhere.
+//! `off` exempts a target from the limits. Rates are a number of requests
+//! per `s`, `min`, `h` or `d`, or per duration (`20/10s`).
+//!
+//! Requests over the limit get `429 Too Many Requests` with a `Retry-After`
+//! telling when the next one will pass. Like the greylist, rate limits are
+//! skipped in observe-only mode.
+//!
+//! Requests are counted per instance, unless `RATE_LIMIT_REDIS` counts them
+//! in Redis across replicas, see [`crate::rate_limit_redis`]. The local
+//! counters are part of the state snapshot (`STATE_SNAPSHOT_FILE`), so a
+//! redeploy doesn't hand every client a fresh limit.
+//!
+//! # Algorithms
+//! `RATE_LIMIT_ALGORITHM` selects how requests are counted:
+//!
+//! - `token-bucket` (default): a bucket holds a full period's worth of
+//!   requests and refills steadily, so a client may burst up to the limit
+//!   and then continues at the average rate. A few bytes per client.
+//! - `fixed-window`: counts requests in consecutive periods starting with the
+//!   client's first request. As small and the cheapest to update, but a
+//!   client may send up to twice the limit around the end of a window.
+//! - `sliding-log`: remembers the time of each request in the last period,
+//!   so no period ever holds more than the limit. Exact, but memory grows
+//!   with the limit: a `1000/h` limit keeps up to a thousand timestamps per
+//!   busy client.
+
+use std::{collections::VecDeque, fmt, net::IpAddr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{config::RateLimitRule, shard::ShardedMap};
+
+/// Requests allowed per period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rate {
+    pub limit: u32,
+    pub period: Duration,
+}
+
+impl Rate {
+    /// Parses `limit/unit` (`s`, `min`, `h` or `d`) or `limit/duration`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (limit, period) = s.split_once('/')?;
+        let limit = limit.trim().parse().ok().filter(|&limit| limit > 0)?;
+        let period = match period.trim() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            "d" | "day" => Duration::from_secs(86400),
+            period => crate::config::parse_duration(period).filter(|d| !d.is_zero())?,
+        };
+        Some(Self { limit, period })
+    }
+
+    /// Time for one request's worth of the limit to come back.
+    fn interval(&self) -> Duration {
+        self.period / self.limit
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.period.as_secs() {
+            1 => write!(f, "{}/s", self.limit),
+            60 => write!(f, "{}/min", self.limit),
+            3600 => write!(f, "{}/h", self.limit),
+            86400 => write!(f, "{}/d", self.limit),
+            secs => write!(f, "{}/{}s", self.limit, secs),
+        }
+    }
+}
+
+/// How requests are counted against a [`Rate`] (`RATE_LIMIT_ALGORITHM`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    TokenBucket,
+    FixedWindow,
+    SlidingLog,
+}
+
+impl Algorithm {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "token-bucket" => Some(Self::TokenBucket),
+            "fixed-window" => Some(Self::FixedWindow),
+            "sliding-log" => Some(Self::SlidingLog),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TokenBucket => "token-bucket",
+            Self::FixedWindow => "fixed-window",
+            Self::SlidingLog => "sliding-log",
+        })
+    }
+}
+
+/// Which limit counts a request: a `RATE_LIMIT_RULES` rule by index, or
+/// `RATE_LIMIT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    Rule(usize),
+    Default,
+}
+
+/// Scope and rate limiting a request for `path` on `host`; `None` when it
+/// isn't limited.
+pub fn applicable(
+    rules: &[RateLimitRule],
+    default: Option<Rate>,
+    host: Option<&str>,
+    path: &str,
+) -> Option<(Scope, Rate)> {
+    match rules.iter().position(|rule| rule.matches(host, path)) {
+        Some(index) => rules[index].rate.map(|rate| (Scope::Rule(index), rate)),
+        None => default.map(|rate| (Scope::Default, rate)),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    counter: Counter,
+    /// Last request counted
+    updated: Instant,
+    /// Time after which an idle entry no longer limits anything
+    period: Duration,
+}
+
+#[derive(Clone, Debug)]
+enum Counter {
+    /// Requests that may still be sent right away
+    Bucket(f64),
+    /// Requests since the window started
+    Window { start: Instant, count: u32 },
+    /// Times of the requests of the last period, oldest first
+    Log(VecDeque<Instant>),
+}
+
+impl Counter {
+    fn new(algorithm: Algorithm, rate: Rate, now: Instant) -> Self {
+        match algorithm {
+            Algorithm::TokenBucket => Self::Bucket(f64::from(rate.limit)),
+            Algorithm::FixedWindow => Self::Window { start: now, count: 0 },
+            Algorithm::SlidingLog => Self::Log(VecDeque::new()),
+        }
+    }
+
+    /// Counts a request at `now` after one at `updated`; see
+    /// [`RateLimiter::check`].
+    fn count(&mut self, rate: Rate, updated: Instant, now: Instant) -> Option<Duration> {
+        match self {
+            Self::Bucket(tokens) => {
+                let limit = f64::from(rate.limit);
+                let elapsed = now.duration_since(updated).as_secs_f64();
+                *tokens = (*tokens + elapsed / rate.period.as_secs_f64() * limit).min(limit);
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return None;
+                }
+                Some(rate.interval().mul_f64(1.0 - *tokens))
+            }
+            Self::Window { start, count } => {
+                if now.duration_since(*start) >= rate.period {
+                    *start = now;
+                    *count = 0;
+                }
+                if *count < rate.limit {
+                    *count += 1;
+                    return None;
+                }
+                Some(rate.period - now.duration_since(*start))
+            }
+            Self::Log(times) => {
+                while times.front().is_some_and(|&t| now.duration_since(t) >= rate.period) {
+                    times.pop_front();
+                }
+                // A smaller limit than when the requests were logged
+                while times.len() > rate.limit as usize {
+                    times.pop_front();
+                }
+                if times.len() < rate.limit as usize {
+                    times.push_back(now);
+                    return None;
+                }
+                times.front().map(|&oldest| rate.period - now.duration_since(oldest))
+            }
+        }
+    }
+}
+
+/// Request counter of one client IP and scope, as saved in the runtime
+/// state snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitEntry {
+    pub ip: IpAddr,
+    pub scope: Scope,
+    /// Last request counted
+    pub updated: DateTime<Utc>,
+    /// Idle time after which the entry no longer limits anything
+    pub period: Duration,
+    pub counter: SavedCounter,
+}
+
+/// [`RateLimitEntry`] state for each algorithm, with wall-clock times.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "kebab-case")]
+pub enum SavedCounter {
+    TokenBucket { tokens: f64 },
+    FixedWindow { start: DateTime<Utc>, count: u32 },
+    SlidingLog { times: Vec<DateTime<Utc>> },
+}
+
+/// Request counters per client IP and scope.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    algorithm: Algorithm,
+    entries: ShardedMap<(IpAddr, Scope), Entry>,
+}
+
+impl RateLimiter {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            entries: ShardedMap::new(),
+        }
+    }
+
+    /// Counts a request from `ip` against `rate`, returning how long the
+    /// client should wait before retrying, or `None` when it may pass.
+    pub fn check(&self, ip: IpAddr, scope: Scope, rate: Rate) -> Option<Duration> {
+        let now = Instant::now();
+        let key = (ip, scope);
+        let mut entries = self.entries.write(&key);
+        let entry = entries.entry(key).or_insert_with(|| Entry {
+            counter: Counter::new(self.algorithm, rate, now),
+            updated: now,
+            period: rate.period,
+        });
+        let retry_after = entry.counter.count(rate, entry.updated, now);
+        entry.updated = now;
+        entry.period = rate.period;
+        retry_after
+    }
+
+    /// Drops entries idle for a whole period, which no longer limit
+    /// anything, returning how many.
+    pub fn prune(&self) -> usize {
+        self.entries.retain(|_, entry| entry.updated.elapsed() < entry.period)
+    }
+
+    /// Counters still limiting something, for the state snapshot.
+    pub fn export(&self) -> Vec<RateLimitEntry> {
+        let now = Instant::now();
+        let wall_now = Utc::now();
+        let wall = |t: Instant| {
+            chrono::Duration::from_std(now.duration_since(t))
+                .ok()
+                .map(|age| wall_now - age)
+        };
+        self.entries
+            .entries()
+            .into_iter()
+            .filter(|(_, entry)| now.duration_since(entry.updated) < entry.period)
+            .filter_map(|((ip, scope), entry)| {
+                let counter = match entry.counter {
+                    Counter::Bucket(tokens) => SavedCounter::TokenBucket { tokens },
+                    Counter::Window { start, count } => SavedCounter::FixedWindow {
+                        start: wall(start)?,
+                        count,
+                    },
+                    Counter::Log(times) => SavedCounter::SlidingLog {
+                        times: times.into_iter().filter_map(wall).collect(),
+                    },
+                };
+                Some(RateLimitEntry {
+                    ip,
+                    scope,
+                    updated: wall(entry.updated)?,
+                    period: entry.period,
+                    counter,
+                })
+            })
+            .collect()
+    }
+
+    /// Re-adds exported counters, dropping those idle for a whole period and
+    /// those of another `RATE_LIMIT_ALGORITHM`.
+    pub fn restore(&self, entries: Vec<RateLimitEntry>) {
+        let now = Instant::now();
+        let wall_now = Utc::now();
+        let instant = |t: DateTime<Utc>| {
+            now.checked_sub((wall_now - t).to_std().unwrap_or_default())
+        };
+        for entry in entries {
+            let Some(updated) = instant(entry.updated) else {
+                continue;
+            };
+            if now.duration_since(updated) >= entry.period {
+                continue;
+            }
+            let counter = match (self.algorithm, entry.counter) {
+                (Algorithm::TokenBucket, SavedCounter::TokenBucket { tokens }) => {
+                    Counter::Bucket(tokens)
+                }
+                (Algorithm::FixedWindow, SavedCounter::FixedWindow { start, count }) => {
+                    let Some(start) = instant(start) else { continue };
+                    Counter::Window { start, count }
+                }
+                (Algorithm::SlidingLog, SavedCounter::SlidingLog { times }) => {
+                    Counter::Log(times.into_iter().filter_map(instant).collect())
+                }
+                _ => continue,
+            };
+            let entry_state = Entry {
+                counter,
+                updated,
+                period: entry.period,
+            };
+            self.entries.insert((entry.ip, entry.scope), entry_state);
+        }
+    }
+
+    /// Number of client IP and scope pairs tracked.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
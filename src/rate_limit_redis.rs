@@ -0,0 +1,241 @@
+//! Rate limits counted in Redis, shared by all replicas.
+//!
+//! Each replica limiting on its own lets a client through N times as often
+//! behind N replicas. With `RATE_LIMIT_REDIS=true` (and `REDIS_URL`, `redis`
+//! feature) requests are counted in Redis instead, with the
+//! `RATE_LIMIT_ALGORITHM` run as a Lua script so each request is counted
+//! atomically in a single round trip. Time comes from the Redis server, so
+//! replicas don't need synchronized clocks.
+//!
+//! Keys are `tezcatlipoca:ratelimit:<algorithm>:<ip>:<scope>` and expire
+//! after a period without requests. The scope names the `RATE_LIMIT_RULES`
+//! rule by index, so all replicas must share the same rules.
+//!
+//! When Redis is unreachable or doesn't answer within
+//! `RATE_LIMIT_REDIS_TIMEOUT_MS`, requests are counted by the local
+//! [`RateLimiter`](crate::rate_limit::RateLimiter) meanwhile: limits stay
+//! enforced, if per replica. With `FAIL_MODE=closed` they are rate limited
+//! instead, retrying after [`RECONNECT_DELAY`], the interval the connection
+//! is retried at.
+
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use redis::aio::MultiplexedConnection;
+use tokio::{sync::Notify, time::sleep};
+use tracing::{info, warn};
+
+use crate::{
+    config::RedisConfig,
+    pubsub::RECONNECT_DELAY,
+    rate_limit::{Algorithm, Rate, Scope},
+};
+
+const KEY_PREFIX: &str = "tezcatlipoca:ratelimit:";
+
+/// Refills the bucket in a hash since its last update, then takes a token.
+const TOKEN_BUCKET: &str = r"
+redis.replicate_commands()
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = time[1] * 1000 + time[2] / 1000
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or limit
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(limit, tokens + math.max(now - updated, 0) / period * limit)
+local wait = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  wait = math.ceil((1 - tokens) * period / limit)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', tostring(now))
+redis.call('PEXPIRE', KEYS[1], period)
+return wait
+";
+
+/// Counts requests in a key expiring at the end of the window.
+const FIXED_WINDOW: &str = r"
+local count = redis.call('INCR', KEYS[1])
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then
+  ttl = tonumber(ARGV[2])
+  redis.call('PEXPIRE', KEYS[1], ttl)
+end
+if count <= tonumber(ARGV[1]) then
+  return 0
+end
+return math.max(ttl, 1)
+";
+
+/// Keeps the requests of the last period in a sorted set by time.
+const SLIDING_LOG: &str = r"
+redis.replicate_commands()
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - period)
+if redis.call('ZCARD', KEYS[1]) < limit then
+  redis.call('ZADD', KEYS[1], now, ARGV[3])
+  redis.call('PEXPIRE', KEYS[1], period)
+  return 0
+end
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+return math.max(tonumber(oldest[2]) + period - now, 1)
+";
+
+/// Request counters kept in Redis.
+pub struct RedisRateLimiter {
+    client: redis::Client,
+    algorithm: Algorithm,
+    timeout: Duration,
+    /// Current connection, `None` while (re)connecting
+    connection: RwLock<Option<MultiplexedConnection>>,
+    /// Wakes [`connection_task`] when the connection broke
+    broken: Notify,
+    /// Whether requests are currently counted locally, to log changes once
+    falling_back: AtomicBool,
+    /// Distinguishes this instance's sliding log entries from other replicas'
+    origin: u64,
+    sequence: AtomicU64,
+}
+
+impl RedisRateLimiter {
+    /// Sets up the client; the connection is made by [`connection_task`].
+    pub fn new(
+        config: &RedisConfig,
+        algorithm: Algorithm,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let client = redis::Client::open(config.url.as_str()).map_err(|e| e.to_string())?;
+        let mut origin = [0u8; 8];
+        getrandom::fill(&mut origin).expect("OS random number generator is available");
+        Ok(Self {
+            client,
+            algorithm,
+            timeout,
+            connection: RwLock::new(None),
+            broken: Notify::new(),
+            falling_back: AtomicBool::new(false),
+            origin: u64::from_le_bytes(origin),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Counts a request from `ip` against `rate` across all replicas,
+    /// returning how long the client should wait before retrying, or `None`
+    /// when it may pass.
+    ///
+    /// Fails when Redis can't be used right now, in which case the request
+    /// should be counted locally, or refused with `FAIL_MODE=closed`.
+    pub async fn check(
+        &self,
+        ip: IpAddr,
+        scope: Scope,
+        rate: Rate,
+    ) -> Result<Option<Duration>, String> {
+        let result = self.count(ip, scope, rate).await;
+        match &result {
+            Ok(_) => {
+                if self.falling_back.swap(false, Ordering::Relaxed) {
+                    info!("Rate limits counted in Redis again");
+                }
+            }
+            Err(e) => {
+                if !self.falling_back.swap(true, Ordering::Relaxed) {
+                    warn!("Rate limits can't be counted in Redis until it is back: {}", e);
+                }
+            }
+        }
+        result
+    }
+
+    async fn count(
+        &self,
+        ip: IpAddr,
+        scope: Scope,
+        rate: Rate,
+    ) -> Result<Option<Duration>, String> {
+        let Some(mut connection) = self
+            .connection
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+        else {
+            return Err("not connected".to_string());
+        };
+        let script = match self.algorithm {
+            Algorithm::TokenBucket => TOKEN_BUCKET,
+            Algorithm::FixedWindow => FIXED_WINDOW,
+            Algorithm::SlidingLog => SLIDING_LOG,
+        };
+        let scope = match scope {
+            Scope::Default => "default".to_string(),
+            Scope::Rule(index) => format!("rule{}", index),
+        };
+        let key = format!("{}{}:{}:{}", KEY_PREFIX, self.algorithm, ip, scope);
+        let member = format!(
+            "{:x}:{}",
+            self.origin,
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut eval = redis::cmd("EVAL");
+        eval.arg(script)
+            .arg(1)
+            .arg(key)
+            .arg(rate.limit)
+            .arg(rate.period.as_millis() as u64)
+            .arg(member);
+        let query = eval.query_async::<u64>(&mut connection);
+        match tokio::time::timeout(self.timeout, query).await {
+            Ok(Ok(0)) => Ok(None),
+            Ok(Ok(wait_ms)) => Ok(Some(Duration::from_millis(wait_ms))),
+            Ok(Err(e)) => {
+                if e.is_io_error() || e.is_connection_dropped() {
+                    self.broken.notify_one();
+                }
+                Err(e.to_string())
+            }
+            Err(_) => Err(format!("no answer within {:?}", self.timeout)),
+        }
+    }
+}
+
+/// Keeps a connection to Redis open for the rate limits, reconnecting
+/// after it breaks.
+pub async fn connection_task(limiter: Arc<RedisRateLimiter>) {
+    loop {
+        match limiter.client.get_multiplexed_async_connection().await {
+            Ok(connection) => {
+                info!("Counting rate limits in Redis");
+                *limiter
+                    .connection
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner()) = Some(connection);
+                limiter.broken.notified().await;
+                limiter
+                    .connection
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take();
+                warn!(
+                    "Redis connection for rate limits lost, reconnecting in {:?}",
+                    RECONNECT_DELAY
+                );
+            }
+            Err(e) => warn!(
+                "Failed to connect to Redis for rate limits, retrying in {:?}: {}",
+                RECONNECT_DELAY, e
+            ),
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,210 @@
+//! Reverse DNS names of blocked clients (`dns` feature).
+//!
+//! With `RDNS=true`, blocked IPs are resolved to a hostname in the
+//! background, and the name is added to block logs, decision events and
+//! webhook notifications. A name is only used when it is forward-confirmed:
+//! the PTR record of the address names a host that resolves back to it, so
+//! a client can't pose as e.g. `crawl-1.googlebot.com` by setting its own
+//! PTR record.
+//!
+//! Lookups never delay a response: the first block of an IP queues a lookup
+//! and is logged without a name; later ones carry it once it is cached for
+//! `RDNS_CACHE_TTL`. At most `RDNS_RATE` lookups start per second, so a
+//! flood of new addresses can't flood the resolver; queued IPs beyond that
+//! wait, and are dropped when the queue is full.
+
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::{ResolveError, TokioResolver};
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::{
+    abuseipdb::is_public, config::RdnsConfig, dnsbl::resolver, metrics::Metrics,
+    shard::ShardedMap, AppState,
+};
+
+/// Maximum lookups waiting for the background task; extra IPs are dropped
+/// and retried on their next block.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How long a failed lookup is remembered before the IP is retried.
+const FAILURE_TTL: Duration = Duration::from_secs(300);
+
+/// Time allowed for each PTR and forward query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+struct CachedName {
+    /// Confirmed hostname, `None` when the address has none (or the lookup
+    /// failed)
+    hostname: Option<String>,
+    expires: Instant,
+}
+
+/// Hostname cache plus the queue feeding the lookup task.
+#[derive(Debug)]
+pub struct Rdns {
+    config: RdnsConfig,
+    names: ShardedMap<String, CachedName>,
+    /// IPs queued or being looked up, to avoid duplicate queries
+    pending: Mutex<HashSet<String>>,
+    queue: mpsc::Sender<String>,
+    receiver: Mutex<Option<mpsc::Receiver<String>>>,
+}
+
+impl Rdns {
+    pub fn new(config: RdnsConfig) -> Self {
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            config,
+            names: ShardedMap::new(),
+            pending: Mutex::new(HashSet::new()),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Hostname of `ip`, queuing a lookup when it isn't known yet.
+    pub fn hostname(&self, ip: &str) -> Option<String> {
+        if let Some(cached) = self.cached_entry(ip) {
+            return cached.hostname;
+        }
+        if is_public(ip) {
+            self.enqueue(ip);
+        }
+        None
+    }
+
+    /// Hostname of `ip` if already known, without queuing a lookup.
+    pub fn cached(&self, ip: &str) -> Option<String> {
+        self.cached_entry(ip).and_then(|cached| cached.hostname)
+    }
+
+    fn cached_entry(&self, ip: &str) -> Option<CachedName> {
+        self.names
+            .get(ip)
+            .filter(|cached| cached.expires > Instant::now())
+    }
+
+    fn enqueue(&self, ip: &str) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.contains(ip) {
+            return;
+        }
+        if self.queue.try_send(ip.to_string()).is_ok() {
+            pending.insert(ip.to_string());
+        }
+    }
+
+    fn store(&self, ip: String, hostname: Option<String>, ttl: Duration) {
+        let now = Instant::now();
+        // The cache limit is split evenly across shards
+        let shard_limit = self.config.max_cached.div_ceil(self.names.shard_count());
+        let mut names = self.names.write(&ip);
+        if names.len() >= shard_limit {
+            names.retain(|_, cached| cached.expires > now);
+            if names.len() >= shard_limit
+                && let Some(victim) = names.keys().next().cloned()
+            {
+                names.remove(&victim);
+            }
+        }
+        names.insert(
+            ip.clone(),
+            CachedName {
+                hostname,
+                expires: now + ttl,
+            },
+        );
+        drop(names);
+
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&ip);
+    }
+
+    /// Number of cached results, including expired ones not yet evicted.
+    pub fn cached_count(&self) -> usize {
+        self.names.len()
+    }
+}
+
+/// Forward-confirmed name of `ip`: `Ok(None)` when it has no PTR record or
+/// no name it points to resolves back to the address.
+async fn confirmed_name(
+    resolver: &TokioResolver,
+    ip: IpAddr,
+) -> Result<Option<String>, ResolveError> {
+    let names = match resolver.reverse_lookup(ip).await {
+        Ok(names) => names,
+        Err(e) if e.is_nx_domain() || e.is_no_records_found() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let ip = ip.to_canonical();
+    for name in names.iter() {
+        let name = name.to_utf8();
+        match resolver.lookup_ip(name.as_str()).await {
+            Ok(addrs) if addrs.iter().any(|addr| addr.to_canonical() == ip) => {
+                return Ok(Some(name.trim_end_matches('.').to_string()));
+            }
+            Ok(_) => {}
+            Err(e) if e.is_nx_domain() || e.is_no_records_found() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Works through queued lookups, starting at most `RDNS_RATE` per second.
+///
+/// Runs until the state is dropped. Does nothing if reverse DNS is disabled
+/// or the task was already started.
+pub async fn lookup_task(state: AppState) {
+    let Some(rdns) = state.rdns.clone() else {
+        return;
+    };
+    let Some(mut receiver) = rdns
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    else {
+        return;
+    };
+    let resolver = match resolver(rdns.config.resolver, QUERY_TIMEOUT) {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            warn!("Reverse DNS disabled, no usable resolver: {}", e);
+            return;
+        }
+    };
+    let mut ticks = tokio::time::interval(Duration::from_secs(1) / rdns.config.rate);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    while let Some(ip) = receiver.recv().await {
+        let Ok(addr) = ip.parse::<IpAddr>() else {
+            rdns.store(ip, None, FAILURE_TTL);
+            continue;
+        };
+        ticks.tick().await;
+        Metrics::incr(&state.metrics.rdns_lookups);
+        match confirmed_name(&resolver, addr).await {
+            Ok(hostname) => {
+                debug!("Reverse DNS for {}: {:?}", ip, hostname);
+                rdns.store(ip, hostname, rdns.config.cache_ttl);
+            }
+            Err(e) => {
+                warn!("Reverse DNS lookup of {} failed: {}", ip, e);
+                rdns.store(ip, None, FAILURE_TTL);
+            }
+        }
+    }
+}
+
@@ -0,0 +1,240 @@
+//! Opening hours for hosts and paths (`SCHEDULE_RULES`).
+//!
+//! A rule names a host and/or path prefix, like `CATEGORY_RULES`, and when
+//! it is reachable; requests outside that time are blocked with the reason
+//! `SCHEDULE`:
+//!
+//! ```text
+//! SCHEDULE_RULES=staging.example.com=mon-fri 08:00-18:00;/reports=sat,sun 00:00-24:00
+//! ```
+//!
+//! A schedule is either day and time ranges separated by `|`
+//! (`mon-fri 08:00-12:00|mon-fri 13:00-17:00`), or a five-field cron
+//! expression (`* 8-17 * * 1-5`) whose every matching minute is open. A
+//! time range ending before it starts runs past midnight, still belonging
+//! to the day it starts on. Times are in `SCHEDULE_TIMEZONE` (an IANA name,
+//! UTC by default). The first rule matching a request decides.
+
+use std::fmt;
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+use crate::config::ScheduleRule;
+
+/// When a rule lets requests through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Any of these day and time ranges
+    Windows(Vec<Window>),
+    /// Minutes matching a cron expression
+    Cron(Cron),
+}
+
+/// Time of day range on some weekdays.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// Bit `n` set when open on day `n`, Monday being 0
+    days: u8,
+    /// Minutes since midnight; open from `start` until before `end`
+    start: u16,
+    end: u16,
+}
+
+/// Five-field cron expression: minute, hour, day of month, month and day of
+/// week, each a set of allowed values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    /// The expression as configured
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether the day of month and day of week fields are restricted (don't
+    /// start with `*`); when both are, a day matching either one is open
+    day_restricted: (bool, bool),
+}
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Schedule {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() == 5
+            && let Some(cron) = Cron::parse(&fields)
+        {
+            return Some(Self::Cron(cron));
+        }
+        s.split('|')
+            .map(Window::parse)
+            .collect::<Option<Vec<_>>>()
+            .map(Self::Windows)
+    }
+
+    /// Whether requests are let through at `time`.
+    pub fn is_open<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let day = time.weekday().num_days_from_monday() as u8;
+        let minute = (time.hour() * 60 + time.minute()) as u16;
+        match self {
+            Self::Windows(windows) => windows.iter().any(|w| w.contains(day, minute)),
+            Self::Cron(cron) => cron.matches(time),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Windows(windows) => {
+                for (i, window) in windows.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("|")?;
+                    }
+                    let days: Vec<&str> = (0..7)
+                        .filter(|day| window.days & (1 << day) != 0)
+                        .map(|day| DAY_NAMES[day])
+                        .collect();
+                    write!(
+                        f,
+                        "{} {:02}:{:02}-{:02}:{:02}",
+                        days.join(","),
+                        window.start / 60,
+                        window.start % 60,
+                        window.end / 60,
+                        window.end % 60
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Cron(cron) => f.write_str(&cron.expression),
+        }
+    }
+}
+
+/// Whether the first rule matching a request for `path` on `host` keeps it
+/// closed at `now`; requests no rule matches are never closed.
+pub fn closed(
+    rules: &[ScheduleRule],
+    timezone: Tz,
+    host: Option<&str>,
+    path: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    rules
+        .iter()
+        .find(|rule| rule.matches(host, path))
+        .is_some_and(|rule| !rule.schedule.is_open(&now.with_timezone(&timezone)))
+}
+
+impl Window {
+    /// Parses `days hh:mm-hh:mm`, days being a name (`mon`), a range
+    /// (`mon-fri`) or a list of either (`sat,sun`).
+    fn parse(s: &str) -> Option<Self> {
+        let (days, times) = s.trim().rsplit_once(char::is_whitespace)?;
+        let mut day_bits = 0;
+        for part in days.split(',') {
+            let part = part.trim().to_ascii_lowercase();
+            let (first, last) = part.split_once('-').unwrap_or((&part, &part));
+            let first = DAY_NAMES.iter().position(|d| *d == first.trim())?;
+            let last = DAY_NAMES.iter().position(|d| *d == last.trim())?;
+            // Ranges may wrap around the week, e.g. `fri-mon`
+            let mut day = first;
+            loop {
+                day_bits |= 1 << day;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+        let (start, end) = times.split_once('-')?;
+        let start = parse_time(start)?;
+        let end = parse_time(end)?;
+        (start != end && start < 24 * 60).then_some(Self {
+            days: day_bits,
+            start,
+            end,
+        })
+    }
+
+    fn contains(&self, day: u8, minute: u16) -> bool {
+        let opens_on = |day: u8| self.days & (1 << day) != 0;
+        if self.start < self.end {
+            opens_on(day) && (self.start..self.end).contains(&minute)
+        } else {
+            // Past midnight: the evening part today, or the morning part of a
+            // range opened yesterday
+            (opens_on(day) && minute >= self.start)
+                || (opens_on((day + 6) % 7) && minute < self.end)
+        }
+    }
+}
+
+/// `hh:mm`, with `24:00` for the end of the day.
+fn parse_time(s: &str) -> Option<u16> {
+    let (hours, minutes) = s.trim().split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    let valid = minutes < 60 && (hours < 24 || (hours == 24 && minutes == 0));
+    valid.then_some(hours * 60 + minutes)
+}
+
+impl Cron {
+    fn parse(fields: &[&str]) -> Option<Self> {
+        let day_of_week = cron_field(fields[4], 0, 7)?;
+        Some(Self {
+            expression: fields.join(" "),
+            minutes: cron_field(fields[0], 0, 59)?,
+            hours: cron_field(fields[1], 0, 23)?,
+            days_of_month: cron_field(fields[2], 1, 31)?,
+            months: cron_field(fields[3], 1, 12)?,
+            // Both 0 and 7 are Sunday
+            days_of_week: (day_of_week | (day_of_week >> 7)) & 0x7f,
+            day_restricted: (!fields[2].starts_with('*'), !fields[4].starts_with('*')),
+        })
+    }
+
+    fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let bit = |set: u64, value: u32| set & (1 << value) != 0;
+        let day_of_month = bit(self.days_of_month, time.day());
+        let day_of_week = bit(self.days_of_week, time.weekday().num_days_from_sunday());
+        let day = match self.day_restricted {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        day && bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+    }
+}
+
+/// Values allowed by one cron field: `*`, numbers, ranges `a-b` and steps
+/// `*/n` or `a-b/n`, separated by `,`.
+fn cron_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&s| s > 0)?),
+            None => (part, 1),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            (first.parse().ok()?, last.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            (value, value)
+        };
+        if first < min || last > max || first > last {
+            return None;
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Some(set)
+}
+
@@ -0,0 +1,127 @@
+//! Rhai decision hook.
+//!
+//! With `SCRIPT_FILE` set (and the `scripting` feature), a Rhai script sees
+//! every request once the ban, reputation and policy checks are done and may
+//! override their verdict. It runs with three constants:
+//!
+//! - `request`: map with `ip`, `method`, `path`, `user_agent` and `headers`
+//!   (lower-case names; `cookie` and `authorization` are left out)
+//! - `decision`: `"allow"` or `"block"`
+//! - `reason`: why the request is blocked, empty when it isn't
+//!
+//! The script's value decides: `"allow"` lets a blocked request through,
+//! `"block"` blocks an allowed one, anything else (including `()`) keeps the
+//! decision. `weekday()` (1 = Monday to 7 = Sunday) and `hour()` (0-23), both
+//! UTC, help with time-based rules:
+//!
+//! ```rhai
+//! if request.user_agent.contains("BadBot") && weekday() >= 6 { "block" }
+//! ```
+//!
+//! The script is compiled once at startup and runs with an operation limit,
+//! so a runaway loop fails (keeping the decision) instead of stalling
+//! requests. Requests it allows still go through authentication, the
+//! challenge and the greylist.
+
+use std::net::IpAddr;
+
+use axum::http::{
+    header::{AUTHORIZATION, COOKIE, USER_AGENT},
+    HeaderMap,
+};
+use chrono::{Datelike, Timelike, Utc};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use tracing::{debug, warn};
+
+/// Operations one evaluation may take before it's aborted.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Reason recorded for requests the script blocks.
+const BLOCK_REASON: &str = "SCRIPT";
+
+/// Compiled decision script.
+pub struct ScriptHook {
+    engine: Engine,
+    ast: AST,
+    path: String,
+}
+
+impl ScriptHook {
+    /// Compiles the script at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(64 * 1024);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        engine.register_fn("weekday", || {
+            i64::from(Utc::now().weekday().number_from_monday())
+        });
+        engine.register_fn("hour", || i64::from(Utc::now().hour()));
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            engine,
+            ast,
+            path: path.to_string(),
+        })
+    }
+
+    /// Block reason after the script had its say; `reason` is the current
+    /// one (`None` when the request is allowed so far).
+    pub fn decide(
+        &self,
+        ip: IpAddr,
+        method: &str,
+        path: &str,
+        headers: &HeaderMap,
+        reason: Option<String>,
+    ) -> Option<String> {
+        let mut header_values = Map::new();
+        for (name, value) in headers {
+            if name == COOKIE || name == AUTHORIZATION {
+                continue;
+            }
+            if let Ok(value) = value.to_str() {
+                header_values.insert(name.as_str().into(), value.into());
+            }
+        }
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        let mut request = Map::new();
+        request.insert("ip".into(), ip.to_string().into());
+        request.insert("method".into(), method.into());
+        request.insert("path".into(), path.into());
+        request.insert("user_agent".into(), user_agent.into());
+        request.insert("headers".into(), header_values.into());
+
+        let mut scope = Scope::new();
+        scope.push_constant("request", request);
+        scope.push_constant("decision", if reason.is_some() { "block" } else { "allow" });
+        scope.push_constant("reason", reason.clone().unwrap_or_default());
+
+        let verdict = match self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+        {
+            Ok(value) => value.into_string().ok(),
+            Err(e) => {
+                warn!("Decision script {} failed for IP {}: {}", self.path, ip, e);
+                None
+            }
+        };
+        match (verdict.as_deref(), reason) {
+            (Some("allow"), Some(reason)) => {
+                debug!("📜 SCRIPT ALLOWED: IP {} accessed {} despite [{}]", ip, path, reason);
+                None
+            }
+            (Some("block"), None) => Some(BLOCK_REASON.to_string()),
+            (_, reason) => reason,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! HTTP listener with tunable connection handling.
+//!
+//! At tens of thousands of ForwardAuth calls per second the connection
+//! settings start to matter more than the handlers. `serve` is
+//! `axum::serve` with the knobs exposed:
+//!
+//! - `HTTP_VERSION`: `auto` speaks HTTP/1.1 and cleartext HTTP/2 (h2c), told
+//!   apart by the connection preface; `http1` or `http2` only accept one.
+//! - `HTTP_KEEPALIVE_TIMEOUT`: how long an idle HTTP/1 connection waits for
+//!   its next request, and the ping interval that detects dead HTTP/2
+//!   connections. `0` closes HTTP/1 connections after every response.
+//! - `HTTP2_MAX_CONCURRENT_STREAMS`: requests multiplexed on one HTTP/2
+//!   connection at a time.
+//! - `TCP_BACKLOG`: connections the kernel queues before they are accepted.
+//! - `IPV6_MODE`: whether `[::]` also accepts IPv4 clients (`dual`, the
+//!   default), only IPv6 ones (`v6only`), or is paired with a `0.0.0.0`
+//!   listener on the same port (`separate`). The socket option is always set
+//!   explicitly, since the OS defaults differ (Linux is dual-stack, Windows
+//!   and the BSDs are not).
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{serve::Listener, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+use tower_service::Service;
+use tracing::trace;
+
+use crate::config::{Config, HttpVersion, Ipv6Mode};
+
+/// How long an HTTP/2 keep-alive ping may go unanswered.
+const HTTP2_PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Binds `addr` with a listen queue of `TCP_BACKLOG` connections.
+pub async fn bind(addr: &str, config: &Config) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host(addr).await? {
+        match listen(addr, config) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+/// Binds `addr`, plus `0.0.0.0` on the same port when `addr` is `[::]` and
+/// `IPV6_MODE=separate`.
+pub async fn bind_all(addr: &str, config: &Config) -> io::Result<Vec<TcpListener>> {
+    let listener = bind(addr, config).await?;
+    let local = listener.local_addr()?;
+    let mut listeners = vec![listener];
+    if config.ipv6_mode == Ipv6Mode::Separate && local.ip() == Ipv6Addr::UNSPECIFIED {
+        let v4 = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), local.port());
+        listeners.push(listen(v4, config)?);
+    }
+    Ok(listeners)
+}
+
+fn listen(addr: SocketAddr, config: &Config) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // Same as std and tokio: restarts shouldn't wait for TIME_WAIT sockets
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(config.ipv6_mode != Ipv6Mode::Dual)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(i32::try_from(config.tcp_backlog).unwrap_or(i32::MAX))?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves `router` on `listener` until the listener fails.
+///
+/// Handlers see the connection address as `ConnectInfo<SocketAddr>`, as with
+/// `into_make_service_with_connect_info`.
+pub async fn serve<L>(mut listener: L, router: Router, config: &Config) -> io::Result<()>
+where
+    L: Listener<Addr = SocketAddr>,
+{
+    let builder = builder(config);
+    let mut make_service = router.into_make_service_with_connect_info::<SocketAddr>();
+    loop {
+        let (io, addr) = listener.accept().await;
+        let service = make_service
+            .call(addr)
+            .await
+            .unwrap_or_else(|err| match err {});
+        let builder = builder.clone();
+        tokio::spawn(async move {
+            let io = TokioIo::new(io);
+            if let Err(e) = builder
+                .serve_connection(io, TowerToHyperService::new(service))
+                .await
+            {
+                trace!("Connection from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Connection settings from the configuration.
+fn builder(config: &Config) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive_timeout.is_some());
+    if let Some(timeout) = config.keep_alive_timeout {
+        // The header timer also runs while the connection waits for a request
+        builder.http1().header_read_timeout(timeout);
+    }
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .keep_alive_interval(config.keep_alive_timeout)
+        .keep_alive_timeout(HTTP2_PING_TIMEOUT);
+    match config.http_version {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_only(),
+    }
+}
@@ -0,0 +1,212 @@
+//! Signed tokens for cookies issued by the service.
+//!
+//! Tokens are `base64url(body).base64url(HMAC-SHA256(body))`, where the body
+//! is the payload itself or, with `COOKIE_ENCRYPT=true`, the payload sealed
+//! with AES-256-GCM under a key derived from the same secret. Plain tokens
+//! are tamper-proof but readable, so their payloads must not hold secrets.
+//!
+//! `COOKIE_SECRET` takes a comma-separated list of keys, newest first: new
+//! tokens are signed with the first key and tokens signed with any of them
+//! are accepted, so a key can be rotated by prepending its successor and
+//! dropping the old one once its tokens have expired. Without a secret a
+//! random key is generated at startup, which invalidates outstanding cookies
+//! on restart.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use axum::http::{header::COOKIE, HeaderMap};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// AES-GCM nonce length in bytes.
+const NONCE_LEN: usize = 12;
+
+/// One signing key and the encryption key derived from it.
+#[derive(Clone)]
+struct Key {
+    mac: Vec<u8>,
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    fn new(secret: &[u8]) -> Self {
+        let derived = Sha256::new()
+            .chain_update(b"tezcatlipoca cookie encryption\0")
+            .chain_update(secret)
+            .finalize();
+        Self {
+            mac: secret.to_vec(),
+            cipher: Aes256Gcm::new(&derived),
+        }
+    }
+
+    fn mac(&self, body: &[u8]) -> HmacSha256 {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&self.mac).expect("HMAC accepts any key length");
+        mac.update(body);
+        mac
+    }
+}
+
+/// HMAC-SHA256 token signer with key rotation and optional encryption.
+#[derive(Clone)]
+pub struct Signer {
+    /// Newest first; only the first one signs
+    keys: Vec<Key>,
+    encrypt: bool,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signer")
+            .field("keys", &self.keys.len())
+            .field("encrypt", &self.encrypt)
+            .finish()
+    }
+}
+
+impl Signer {
+    /// Signer with a single key and readable payloads.
+    pub fn new(key: &[u8]) -> Self {
+        Self::with_keys(&[key], false)
+    }
+
+    /// Signer accepting tokens from any of `keys` and signing with the first.
+    ///
+    /// # Panics
+    /// When `keys` is empty.
+    pub fn with_keys(keys: &[&[u8]], encrypt: bool) -> Self {
+        assert!(!keys.is_empty(), "a signer needs at least one key");
+        Self {
+            keys: keys.iter().map(|k| Key::new(k)).collect(),
+            encrypt,
+        }
+    }
+
+    /// Signer with a random 256-bit key.
+    pub fn random(encrypt: bool) -> Self {
+        let mut key = [0u8; 32];
+        getrandom::fill(&mut key).expect("OS random number generator is available");
+        Self::with_keys(&[&key], encrypt)
+    }
+
+    /// Signer from the configured secrets, or a random key when there are none.
+    pub fn from_secrets(secrets: &[String], encrypt: bool) -> Self {
+        if secrets.is_empty() {
+            return Self::random(encrypt);
+        }
+        let keys: Vec<&[u8]> = secrets.iter().map(|s| s.as_bytes()).collect();
+        Self::with_keys(&keys, encrypt)
+    }
+
+    /// Signs (and, if enabled, encrypts) `payload` into a token.
+    pub fn sign(&self, payload: &str) -> String {
+        let key = &self.keys[0];
+        let body = if self.encrypt {
+            let mut nonce = [0u8; NONCE_LEN];
+            getrandom::fill(&mut nonce).expect("OS random number generator is available");
+            let sealed = key
+                .cipher
+                .encrypt(Nonce::from_slice(&nonce), payload.as_bytes())
+                .expect("AES-GCM encryption of a short payload cannot fail");
+            [nonce.as_slice(), &sealed].concat()
+        } else {
+            payload.as_bytes().to_vec()
+        };
+        let mac = key.mac(&body).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&body),
+            URL_SAFE_NO_PAD.encode(mac)
+        )
+    }
+
+    /// Payload of `token` if its signature is valid under any key.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let (body, mac) = token.split_once('.')?;
+        let body = URL_SAFE_NO_PAD.decode(body).ok()?;
+        let mac = URL_SAFE_NO_PAD.decode(mac).ok()?;
+        // `verify_slice` compares in constant time
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.mac(&body).verify_slice(&mac).is_ok())?;
+        let payload = if self.encrypt {
+            if body.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, sealed) = body.split_at(NONCE_LEN);
+            key.cipher.decrypt(Nonce::from_slice(nonce), sealed).ok()?
+        } else {
+            body
+        };
+        String::from_utf8(payload).ok()
+    }
+
+    /// Token binding `subject` (e.g. a client IP) until `ttl` from now.
+    pub fn issue(&self, subject: &str, ttl: Duration) -> String {
+        let expires = unix_now() + ttl.as_secs();
+        self.sign(&format!("{}|{}", subject, expires))
+    }
+
+    /// Subject of `token` if it is valid and hasn't expired.
+    pub fn subject(&self, token: &str) -> Option<String> {
+        let payload = self.verify(token)?;
+        let (subject, expires) = payload.rsplit_once('|')?;
+        expires
+            .parse::<u64>()
+            .is_ok_and(|t| t > unix_now())
+            .then(|| subject.to_string())
+    }
+
+    /// Whether `token` was issued for `subject` and hasn't expired.
+    pub fn validate(&self, token: &str, subject: &str) -> bool {
+        self.subject(token).is_some_and(|s| s == subject)
+    }
+}
+
+/// Whether the original request came in over HTTPS, per `X-Forwarded-Proto`.
+pub fn is_https(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-forwarded-proto")
+        .is_some_and(|proto| proto.as_bytes().eq_ignore_ascii_case(b"https"))
+}
+
+/// Value of cookie `name` in the request headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// `Set-Cookie` value for a cookie valid on the whole site for `ttl`.
+///
+/// `Secure` is only set when the original request came in over HTTPS, so
+/// plain-HTTP test setups still work.
+pub fn set_cookie(name: &str, value: &str, ttl: Duration, secure: bool) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        name,
+        value,
+        ttl.as_secs(),
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
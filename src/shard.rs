@@ -0,0 +1,157 @@
+//! Sharded maps for per-IP state mutated on the request path.
+//!
+//! Dynamic bans, reputation scores and per-IP counters are written by many
+//! concurrent requests. Splitting each map into independently locked shards
+//! keyed by the hash of the IP means two requests only contend when their
+//! addresses land in the same shard, instead of every request serializing on
+//! one global write lock.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Shards per map. A power of two well above typical core counts.
+pub const SHARDS: usize = 16;
+
+/// Hashes keys to shard indexes.
+#[derive(Debug, Clone, Default)]
+pub struct ShardRouter {
+    hasher: RandomState,
+}
+
+impl ShardRouter {
+    /// Index of the shard responsible for `key`, in `0..shards`.
+    pub fn index<Q: Hash + ?Sized>(&self, key: &Q, shards: usize) -> usize {
+        (self.hasher.hash_one(key) as usize) % shards
+    }
+}
+
+/// `HashMap` split into [`SHARDS`] independently locked parts.
+#[derive(Debug)]
+pub struct ShardedMap<K, V> {
+    router: ShardRouter,
+    shards: Box<[RwLock<HashMap<K, V>>]>,
+}
+
+impl<K, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            router: ShardRouter::default(),
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read access to the shard holding `key`.
+    pub fn read<Q>(&self, key: &Q) -> RwLockReadGuard<'_, HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write access to the shard holding `key`, for compound updates.
+    pub fn write<Q>(&self, key: &Q) -> RwLockWriteGuard<'_, HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copy of the value stored for `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.read(key).get(key).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write(&key).insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(key).remove(key)
+    }
+
+    /// Keeps only the entries for which `keep` returns `true`, one shard at
+    /// a time. Returns how many were removed.
+    pub fn retain(&self, mut keep: impl FnMut(&K, &mut V) -> bool) -> usize {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().unwrap_or_else(|e| e.into_inner());
+            let before = shard.len();
+            shard.retain(&mut keep);
+            removed += before - shard.len();
+        }
+        removed
+    }
+
+    /// Copies of all entries, taken one shard at a time.
+    pub fn entries(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Number of entries matching `filter`. Shards are counted one after the
+    /// other, so the result is not an atomic snapshot.
+    pub fn count(&self, mut filter: impl FnMut(&V) -> bool) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .values()
+                    .filter(|v| filter(v))
+                    .count()
+            })
+            .sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count(|_| true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of shards, for callers splitting a global limit across them.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<HashMap<K, V>> {
+        &self.shards[self.router.index(key, self.shards.len())]
+    }
+}
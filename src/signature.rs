@@ -0,0 +1,107 @@
+//! HMAC request signatures for machine-to-machine traffic.
+//!
+//! With `SIGNATURE_SECRET` set, requests for paths under `SIGNATURE_PATHS`
+//! must carry two headers:
+//!
+//! - `X-Signature-Timestamp`: Unix time in seconds when the request was signed
+//! - `X-Signature`: hex HMAC-SHA256 over `"{timestamp}\n{METHOD}\n{path}"`
+//!   with the shared secret, where `path` excludes the query string
+//!
+//! Signatures older or newer than `SIGNATURE_WINDOW` are refused, so a
+//! captured request can only be replayed within that window. The check runs
+//! after IP screening; failures are answered with `401 Unauthorized`.
+//!
+//! ```sh
+//! ts=$(date +%s)
+//! sig=$(printf '%s\nGET\n/api/jobs' "$ts" | openssl dgst -sha256 -hmac "$SECRET" -hex | cut -d' ' -f2)
+//! curl -H "X-Signature-Timestamp: $ts" -H "X-Signature: $sig" https://internal.example/api/jobs
+//! ```
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{config::SignatureConfig, controllers};
+
+/// Header carrying the signing time.
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+/// Header carrying the hex signature.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Why a request signature was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum SignatureError {
+    /// One of the signature headers is absent
+    #[error("MISSING SIGNATURE")]
+    Missing,
+    /// The timestamp isn't a number or lies outside the replay window
+    #[error("EXPIRED SIGNATURE")]
+    Expired,
+    /// The signature doesn't match
+    #[error("INVALID SIGNATURE")]
+    Invalid,
+}
+
+/// Whether requests for `path` must be signed.
+///
+/// A configured path covers itself and everything below it.
+pub fn required(config: &SignatureConfig, path: &str) -> bool {
+    controllers::path_matches(&config.paths, path)
+}
+
+/// Checks the signature headers of a request.
+pub fn verify(
+    config: &SignatureConfig,
+    method: &str,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<(), SignatureError> {
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let (Some(timestamp), Some(signature)) = (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER))
+    else {
+        return Err(SignatureError::Missing);
+    };
+
+    let signed_at = timestamp.parse::<u64>().map_err(|_| SignatureError::Expired)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    if now.abs_diff(signed_at) > config.window.as_secs() {
+        return Err(SignatureError::Expired);
+    }
+
+    let signature = decode_hex(signature.trim()).ok_or(SignatureError::Invalid)?;
+    // `verify_slice` compares in constant time
+    mac(&config.secret, timestamp, method, path)
+        .verify_slice(&signature)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+/// Hex signature for a request, as clients compute it.
+pub fn sign(secret: &str, timestamp: u64, method: &str, path: &str) -> String {
+    mac(secret, &timestamp.to_string(), method, path)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn mac(secret: &str, timestamp: &str, method: &str, path: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}\n{}", timestamp, method.to_ascii_uppercase(), path).as_bytes());
+    mac
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
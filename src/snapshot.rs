@@ -0,0 +1,139 @@
+//! Snapshot and restore of runtime state.
+//!
+//! With `STATE_SNAPSHOT_FILE` set, everything the service learns while
+//! running is written to disk every `STATE_SNAPSHOT_INTERVAL` and loaded
+//! again at startup, so a redeploy in the middle of an attack doesn't let
+//! banned clients straight back in:
+//!
+//! - dynamic bans with their reason and expiry, including imported ones
+//!   (`BAN_HISTORY_FILE` only covers bans from offenses),
+//! - offense history driving ban escalation,
+//! - greylist state, so trusted clients aren't deferred again,
+//! - local rate limit counters, so clients don't get a fresh limit (those
+//!   counted in Redis with `RATE_LIMIT_REDIS` are kept there). Counters of
+//!   `RATE_LIMIT_RULES` rules are keyed by index, so they only carry over
+//!   to the same rules.
+//!
+//! Expiries are stored as wall-clock times; time spent down counts towards
+//! them.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, time::sleep};
+use tracing::{debug, info, warn};
+
+use crate::{
+    bans::{ActiveBan, OffenseRecord},
+    greylist::GreylistEntry,
+    rate_limit::RateLimitEntry,
+    AppState,
+};
+
+/// Contents of `STATE_SNAPSHOT_FILE`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub taken: DateTime<Utc>,
+    #[serde(default)]
+    pub bans: Vec<ActiveBan>,
+    #[serde(default)]
+    pub offenses: HashMap<String, OffenseRecord>,
+    #[serde(default)]
+    pub greylist: Vec<GreylistEntry>,
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimitEntry>,
+}
+
+impl Snapshot {
+    /// Captures the current runtime state.
+    pub fn take(state: &AppState) -> Self {
+        Self {
+            taken: Utc::now(),
+            bans: state.dynamic_bans.active(),
+            offenses: state.dynamic_bans.history(),
+            greylist: state
+                .greylist
+                .as_ref()
+                .map(|greylist| greylist.export())
+                .unwrap_or_default(),
+            rate_limits: state
+                .rate_limiter
+                .as_ref()
+                .map(|limiter| limiter.export())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Applies the snapshot on top of the current state; entries that have
+    /// expired in the meantime are skipped.
+    pub fn restore(self, state: &AppState) {
+        // Offense records re-apply their own bans; the explicit list then
+        // sets the exact reason and expiry of every ban
+        state.dynamic_bans.restore(self.offenses);
+        state.dynamic_bans.import(&self.bans);
+        if let Some(greylist) = &state.greylist {
+            greylist.restore(self.greylist);
+        }
+        if let Some(limiter) = &state.rate_limiter {
+            limiter.restore(self.rate_limits);
+        }
+    }
+}
+
+/// Loads `STATE_SNAPSHOT_FILE` into the state.
+///
+/// A missing file is normal on first start; an unreadable one is logged and
+/// ignored rather than preventing startup.
+pub async fn load(state: &AppState) {
+    let Some(path) = &state.config.state_snapshot_file else {
+        return;
+    };
+    let content = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Failed to read state snapshot {}: {}", path, e);
+            return;
+        }
+    };
+    match serde_json::from_str::<Snapshot>(&content) {
+        Ok(snapshot) => {
+            info!(
+                "Restoring {} bans, {} offense records, {} greylist entries and {} rate limit \
+                 counters from {}",
+                snapshot.bans.len(),
+                snapshot.offenses.len(),
+                snapshot.greylist.len(),
+                snapshot.rate_limits.len(),
+                path
+            );
+            snapshot.restore(state);
+        }
+        Err(e) => warn!("Ignoring unreadable state snapshot {}: {}", path, e),
+    }
+}
+
+/// Writes the snapshot every `STATE_SNAPSHOT_INTERVAL`.
+pub async fn snapshot_task(state: AppState) {
+    let Some(path) = state.config.state_snapshot_file.clone() else {
+        return;
+    };
+    loop {
+        sleep(state.config.state_snapshot_interval).await;
+        if let Err(e) = save(&path, &Snapshot::take(&state)).await {
+            warn!("Failed to save state snapshot to {}: {}", path, e);
+        }
+    }
+}
+
+/// Writes `snapshot` through a temporary file so a crash mid-write never
+/// leaves a truncated snapshot behind.
+pub async fn save(path: &str, snapshot: &Snapshot) -> std::io::Result<()> {
+    let json = serde_json::to_vec(snapshot)?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, json).await?;
+    fs::rename(&tmp, path).await?;
+    debug!("Saved state snapshot with {} bans", snapshot.bans.len());
+    Ok(())
+}
@@ -0,0 +1,277 @@
+//! Request statistics for the admin API.
+//!
+//! - [`IpStatsTable`] keeps hit, block and last-seen data for the most
+//!   recently active client IPs in a bounded LRU, so an operator can check
+//!   how busy an address has been before banning it. The least recently seen
+//!   IP is evicted once `IP_STATS_CAPACITY` addresses are tracked, which keeps
+//!   memory fixed even when a scan rotates through millions of addresses.
+//!   Large tables are split into independently locked LRU shards so
+//!   concurrent requests don't serialize on one lock.
+//! - [`TopCounters`] keeps rolling per-bucket counts of blocked IPs, paths
+//!   and user agents for "top offenders" reports over a recent time window.
+
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::{
+    events::Verdict,
+    shard::{ShardRouter, SHARDS},
+};
+
+/// Counters for a single client IP.
+#[derive(Clone, Debug, Serialize)]
+pub struct IpStats {
+    /// Requests seen, whatever the outcome
+    pub hits: u64,
+    pub blocked: u64,
+    pub would_block: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Tables smaller than this use a single shard, so small capacities are
+/// honoured exactly.
+const MIN_SHARDED_CAPACITY: usize = 1024;
+
+/// Bounded LRU of per-IP statistics.
+///
+/// Sharded tables evict per shard, so the least recently seen IP of the
+/// shard being written is dropped rather than the globally oldest one.
+#[derive(Debug)]
+pub struct IpStatsTable {
+    router: ShardRouter,
+    shards: Vec<Mutex<LruCache<String, IpStats>>>,
+}
+
+impl IpStatsTable {
+    /// Creates a table tracking up to `capacity` IPs; 0 disables tracking.
+    pub fn new(capacity: usize) -> Self {
+        let shard_count = if capacity < MIN_SHARDED_CAPACITY { 1 } else { SHARDS };
+        let shards = match NonZeroUsize::new(capacity.div_ceil(shard_count)) {
+            Some(cap) => (0..shard_count).map(|_| Mutex::new(LruCache::new(cap))).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            router: ShardRouter::default(),
+            shards,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.shards.is_empty()
+    }
+
+    /// Records one request from `ip` with the given outcome.
+    pub fn record(&self, ip: &str, verdict: Verdict) {
+        let Some(entries) = self.shard(ip) else {
+            return;
+        };
+        let now = Utc::now();
+        let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = match entries.get_mut(ip) {
+            Some(stats) => stats,
+            None => {
+                entries.put(
+                    ip.to_string(),
+                    IpStats {
+                        hits: 0,
+                        blocked: 0,
+                        would_block: 0,
+                        first_seen: now,
+                        last_seen: now,
+                    },
+                );
+                entries.get_mut(ip).expect("entry was just inserted")
+            }
+        };
+        stats.hits += 1;
+        stats.last_seen = now;
+        match verdict {
+            Verdict::Allowed
+            | Verdict::Greylisted
+            | Verdict::RateLimited
+            | Verdict::Challenged
+            | Verdict::Unauthorized => {}
+            Verdict::Blocked => stats.blocked += 1,
+            Verdict::WouldBlock => stats.would_block += 1,
+        }
+    }
+
+    /// Statistics for `ip`, without affecting its LRU position.
+    pub fn get(&self, ip: &str) -> Option<IpStats> {
+        self.shard(ip)?
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .peek(ip)
+            .cloned()
+    }
+
+    /// Number of IPs currently tracked.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, ip: &str) -> Option<&Mutex<LruCache<String, IpStats>>> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        Some(&self.shards[self.router.index(ip, self.shards.len())])
+    }
+}
+
+/// Number of buckets the retention period is split into.
+const BUCKETS: usize = 60;
+
+/// Distinct keys kept per counter and bucket. Further keys in the same
+/// bucket are folded into [`OTHER_KEY`] so a scan with random paths or user
+/// agents can't grow memory without bound.
+const MAX_KEYS_PER_BUCKET: usize = 1024;
+
+/// Key that absorbs counts once a bucket is full.
+pub const OTHER_KEY: &str = "(other)";
+
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Start of the bucket, in bucket units since the table was created
+    index: u64,
+    blocked_ips: HashMap<String, u64>,
+    paths: HashMap<String, u64>,
+    user_agents: HashMap<String, u64>,
+}
+
+/// Rolling counters of blocked IPs, requested paths and user agents.
+///
+/// The retention period is split into fixed-width buckets kept in a ring;
+/// queries sum the buckets overlapping the requested window, so results are
+/// accurate to one bucket width (one minute with the default one-hour
+/// retention).
+#[derive(Debug)]
+pub struct TopCounters {
+    started: Instant,
+    bucket_width: Duration,
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+/// One entry of a top-N list.
+#[derive(Clone, Debug, Serialize)]
+pub struct TopEntry {
+    pub key: String,
+    pub count: u64,
+}
+
+/// Top-N lists for a time window.
+#[derive(Clone, Debug, Serialize)]
+pub struct TopReport {
+    pub window_secs: u64,
+    pub blocked_ips: Vec<TopEntry>,
+    pub paths: Vec<TopEntry>,
+    pub user_agents: Vec<TopEntry>,
+}
+
+impl TopCounters {
+    /// Creates counters covering `retention` (at least one second per bucket).
+    pub fn new(retention: Duration) -> Self {
+        let bucket_width = (retention / BUCKETS as u32).max(Duration::from_secs(1));
+        Self {
+            started: Instant::now(),
+            bucket_width,
+            buckets: Mutex::new((0..BUCKETS).map(|_| Bucket::default()).collect()),
+        }
+    }
+
+    /// Longest window that can be queried.
+    pub fn retention(&self) -> Duration {
+        self.bucket_width * BUCKETS as u32
+    }
+
+    /// Counts one request.
+    pub fn record(&self, ip: &str, path: &str, user_agent: Option<&str>, verdict: Verdict) {
+        let index = self.current_index();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = &mut buckets[(index % BUCKETS as u64) as usize];
+        if bucket.index != index {
+            // The slot still holds data from a previous lap around the ring
+            *bucket = Bucket {
+                index,
+                ..Bucket::default()
+            };
+        }
+
+        if matches!(verdict, Verdict::Blocked | Verdict::WouldBlock) {
+            bump(&mut bucket.blocked_ips, ip);
+        }
+        bump(&mut bucket.paths, path);
+        bump(&mut bucket.user_agents, user_agent.unwrap_or("(none)"));
+    }
+
+    /// Top `limit` entries of each counter over the last `window`.
+    pub fn report(&self, window: Duration, limit: usize) -> TopReport {
+        let window = window.clamp(self.bucket_width, self.retention());
+        let span = window.as_secs_f64() / self.bucket_width.as_secs_f64();
+        let newest = self.current_index();
+        let oldest = newest.saturating_sub(span.ceil() as u64 - 1);
+
+        let mut blocked_ips = HashMap::new();
+        let mut paths = HashMap::new();
+        let mut user_agents = HashMap::new();
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        for bucket in buckets.iter().filter(|b| (oldest..=newest).contains(&b.index)) {
+            merge(&mut blocked_ips, &bucket.blocked_ips);
+            merge(&mut paths, &bucket.paths);
+            merge(&mut user_agents, &bucket.user_agents);
+        }
+        drop(buckets);
+
+        TopReport {
+            window_secs: window.as_secs(),
+            blocked_ips: top(blocked_ips, limit),
+            paths: top(paths, limit),
+            user_agents: top(user_agents, limit),
+        }
+    }
+
+    fn current_index(&self) -> u64 {
+        (self.started.elapsed().as_nanos() / self.bucket_width.as_nanos()) as u64
+    }
+}
+
+fn bump(counts: &mut HashMap<String, u64>, key: &str) {
+    if let Some(count) = counts.get_mut(key) {
+        *count += 1;
+    } else if counts.len() < MAX_KEYS_PER_BUCKET {
+        counts.insert(key.to_string(), 1);
+    } else {
+        *counts.entry(OTHER_KEY.to_string()).or_default() += 1;
+    }
+}
+
+fn merge(into: &mut HashMap<String, u64>, from: &HashMap<String, u64>) {
+    for (key, count) in from {
+        *into.entry(key.clone()).or_default() += count;
+    }
+}
+
+fn top(counts: HashMap<String, u64>, limit: usize) -> Vec<TopEntry> {
+    let mut entries: Vec<TopEntry> = counts
+        .into_iter()
+        .map(|(key, count)| TopEntry { key, count })
+        .collect();
+    entries.sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    entries.truncate(limit);
+    entries
+}
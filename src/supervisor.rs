@@ -0,0 +1,287 @@
+//! Supervision of the background tasks.
+//!
+//! Tasks started through [`Tasks::spawn`] are tracked by name: when they last
+//! ran, how often they failed and whether they are still running. A task
+//! that panics is logged, counted in the `task_restarts` metric and started
+//! again after a backoff doubling from [`RESTART_BACKOFF_MIN`] up to
+//! [`RESTART_BACKOFF_MAX`], so e.g. the cache refresh can't silently stop
+//! for good. A task
+//! reports each round of work with [`ran`] or [`failed`]; tasks waiting on
+//! events rather than a timer (e.g. the webhook sender) are spawned without
+//! an interval and only checked for still running.
+//!
+//! A task that hasn't reported for [`STALL_FACTOR`] times its interval (and
+//! at least [`MIN_STALL`]) is stalled, e.g. stuck on a lock or a request
+//! without timeout. Stalls are logged once by [`monitor_task`] and, like
+//! stopped tasks, make `/readyz` answer 503 with the status of every task.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::time::{sleep, Instant};
+use tracing::{error, info, warn};
+
+use crate::metrics::Metrics;
+
+/// A task is stalled after this many intervals without reporting.
+pub const STALL_FACTOR: u32 = 3;
+
+/// Shortest time without reports before a task counts as stalled, leaving
+/// room for slow requests of tasks with short intervals.
+pub const MIN_STALL: Duration = Duration::from_secs(60);
+
+/// Wait before restarting a task after its first panic.
+pub const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait between restarts. A task that ran this long before
+/// panicking is restarted after [`RESTART_BACKOFF_MIN`] again.
+pub const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// How often [`monitor_task`] looks for stalled tasks.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    static CURRENT: Arc<Task>;
+}
+
+/// Background tasks by name.
+pub struct Tasks {
+    tasks: RwLock<BTreeMap<String, Arc<Task>>>,
+    metrics: Arc<Metrics>,
+}
+
+struct Task {
+    interval: Option<Duration>,
+    progress: Mutex<Progress>,
+}
+
+struct Progress {
+    /// Last report, or the start of the task before the first one
+    last_seen: Instant,
+    last_run: Option<DateTime<Utc>>,
+    failures: u64,
+    /// Error of the last round, cleared when a round succeeds
+    last_error: Option<String>,
+    restarts: u64,
+    /// Whether the task panicked and waits to be restarted
+    restarting: bool,
+    /// Why the task ended, once it did
+    stopped: Option<String>,
+    /// Whether the stall was logged, to log it once
+    stall_logged: bool,
+}
+
+/// What a task is doing, as shown on `/readyz`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    Stalled,
+    /// Panicked, waiting for the restart backoff
+    Restarting,
+    Stopped,
+}
+
+/// Status of one task.
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    /// Expected time between rounds, for periodic tasks
+    pub interval_secs: Option<u64>,
+    pub last_run: Option<DateTime<Utc>>,
+    /// Failed rounds, panics included
+    pub failures: u64,
+    pub last_error: Option<String>,
+    /// Restarts after a panic
+    pub restarts: u64,
+    /// Why the task ended, once it did
+    pub stopped: Option<String>,
+}
+
+impl Tasks {
+    /// Tasks counting their restarts in `metrics`.
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            tasks: RwLock::default(),
+            metrics,
+        }
+    }
+
+    /// Spawns the future made by `task` under `name`, expecting a report
+    /// every `interval` if set, and makes a new one whenever it panics.
+    /// Spawning again under the same name replaces the tracked task.
+    pub fn spawn<F, Fut>(&self, name: impl Into<String>, interval: Option<Duration>, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.into();
+        let tracked = Arc::new(Task {
+            interval,
+            progress: Mutex::new(Progress {
+                last_seen: Instant::now(),
+                last_run: None,
+                failures: 0,
+                last_error: None,
+                restarts: 0,
+                restarting: false,
+                stopped: None,
+                stall_logged: false,
+            }),
+        });
+        self.tasks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.clone(), tracked.clone());
+
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut backoff = RESTART_BACKOFF_MIN;
+            let reason = loop {
+                let started = Instant::now();
+                let message = match tokio::spawn(CURRENT.scope(tracked.clone(), task())).await {
+                    Ok(()) => break "returned".to_string(),
+                    Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                    Err(e) => break e.to_string(),
+                };
+                if started.elapsed() >= RESTART_BACKOFF_MAX {
+                    backoff = RESTART_BACKOFF_MIN;
+                }
+                error!(
+                    "Background task '{}' panicked, restarting in {:?}: {}",
+                    name, backoff, message
+                );
+                Metrics::incr(&metrics.task_restarts);
+                tracked.report(Some(format!("panicked: {}", message)));
+                tracked.lock().restarting = true;
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+
+                let mut progress = tracked.lock();
+                progress.restarting = false;
+                progress.restarts += 1;
+                progress.last_seen = Instant::now();
+            };
+            warn!("Background task '{}' stopped: {}", name, reason);
+            tracked.lock().stopped = Some(reason);
+        });
+    }
+
+    /// Status of every task, by name.
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(name, task)| task.status(name))
+            .collect()
+    }
+
+    /// Logs tasks that stalled since the last check, or recovered.
+    pub fn check(&self) {
+        let tasks = self.tasks.read().unwrap_or_else(|e| e.into_inner());
+        for (name, task) in tasks.iter() {
+            let stalled = task.stalled();
+            let mut progress = task.lock();
+            match (stalled, progress.stall_logged) {
+                (Some(idle), false) => warn!(
+                    "Background task '{}' stalled, no progress for {:?}",
+                    name, idle
+                ),
+                (None, true) => info!("Background task '{}' is making progress again", name),
+                _ => continue,
+            }
+            progress.stall_logged = stalled.is_some();
+        }
+    }
+}
+
+impl Task {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Time without reports, if longer than allowed for the interval.
+    fn stalled(&self) -> Option<Duration> {
+        let interval = self.interval?;
+        let progress = self.lock();
+        let idle = progress.last_seen.elapsed();
+        let allowed = (interval * STALL_FACTOR).max(MIN_STALL);
+        let waiting = progress.stopped.is_some() || progress.restarting;
+        (!waiting && idle > allowed).then_some(idle)
+    }
+
+    fn status(&self, name: &str) -> TaskStatus {
+        let stalled = self.stalled().is_some();
+        let progress = self.lock();
+        let state = if progress.stopped.is_some() {
+            TaskState::Stopped
+        } else if progress.restarting {
+            TaskState::Restarting
+        } else if stalled {
+            TaskState::Stalled
+        } else {
+            TaskState::Running
+        };
+        TaskStatus {
+            name: name.to_string(),
+            state,
+            interval_secs: self.interval.map(|i| i.as_secs()),
+            last_run: progress.last_run,
+            failures: progress.failures,
+            last_error: progress.last_error.clone(),
+            restarts: progress.restarts,
+            stopped: progress.stopped.clone(),
+        }
+    }
+
+    fn report(&self, error: Option<String>) {
+        let mut progress = self.lock();
+        progress.last_seen = Instant::now();
+        progress.last_run = Some(Utc::now());
+        if error.is_some() {
+            progress.failures += 1;
+        }
+        progress.last_error = error;
+    }
+}
+
+/// Records a successful round of the current task.
+///
+/// Does nothing outside a task spawned by [`Tasks::spawn`].
+pub fn ran() {
+    let _ = CURRENT.try_with(|task| task.report(None));
+}
+
+/// Records a failed round of the current task.
+///
+/// Does nothing outside a task spawned by [`Tasks::spawn`].
+pub fn failed(error: impl Display) {
+    let _ = CURRENT.try_with(|task| task.report(Some(error.to_string())));
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Logs stalled tasks every [`MONITOR_INTERVAL`].
+pub async fn monitor_task(tasks: Arc<Tasks>) {
+    loop {
+        sleep(MONITOR_INTERVAL).await;
+        tasks.check();
+    }
+}
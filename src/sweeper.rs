@@ -0,0 +1,60 @@
+//! Periodic removal of expired per-IP state.
+//!
+//! Every `SWEEP_INTERVAL` one task drops the dynamic bans that have expired,
+//! offense records that no longer count towards escalation, greylist
+//! entries past `GREYLIST_TTL` and idle rate limit counters. Lookups already
+//! ignore expired entries; sweeping is what keeps memory bounded over weeks
+//! of uptime when many distinct IPs come and go.
+//!
+//! Sweeps are counted on `/health` (`sweeps`, `swept_bans`,
+//! `swept_counters`) along with their duration.
+
+use std::time::Instant;
+
+use tokio::time::sleep;
+use tracing::debug;
+
+use crate::{metrics::Metrics, supervisor, AppState};
+
+/// Entries removed by one sweep.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Swept {
+    /// Expired dynamic bans
+    pub bans: usize,
+    /// Offense records, greylist entries and rate limit counters
+    pub counters: usize,
+}
+
+/// Removes expired entries once and records the sweep in the metrics.
+pub fn sweep(state: &AppState) -> Swept {
+    let started = Instant::now();
+    let bans = state.dynamic_bans.sweep();
+    let mut counters = state.dynamic_bans.prune_offenses();
+    if let Some(greylist) = &state.greylist {
+        counters += greylist.prune();
+    }
+    if let Some(limiter) = &state.rate_limiter {
+        counters += limiter.prune();
+    }
+    let elapsed = started.elapsed();
+
+    let metrics = &state.metrics;
+    Metrics::incr(&metrics.sweeps);
+    Metrics::add(&metrics.swept_bans, bans as u64);
+    Metrics::add(&metrics.swept_counters, counters as u64);
+    metrics.latency_sweep.record(elapsed);
+    debug!(
+        "🧹 Swept {} expired bans and {} stale counters in {:?}",
+        bans, counters, elapsed
+    );
+    Swept { bans, counters }
+}
+
+/// Sweeps every `SWEEP_INTERVAL`.
+pub async fn sweep_task(state: AppState) {
+    loop {
+        sleep(state.config.sweep_interval).await;
+        sweep(&state);
+        supervisor::ran();
+    }
+}
@@ -0,0 +1,114 @@
+//! systemd integration: service notifications and socket activation (Unix
+//! only).
+//!
+//! Under a `Type=notify` unit, `READY=1` is sent once the listener is bound
+//! and the banned IPs files were loaded successfully, so units ordered after
+//! this one don't start while every request would still be allowed. With
+//! `WatchdogSec=` set, `WATCHDOG=1` is sent at half the interval as long as
+//! the banned IPs cache can still be locked; a hung instance stops pinging
+//! and systemd restarts it.
+//!
+//! ```ini
+//! [Service]
+//! Type=notify
+//! WatchdogSec=30
+//! Restart=on-failure
+//! ```
+//!
+//! Outside systemd (no `NOTIFY_SOCKET`) nothing is sent.
+//!
+//! The service can also be socket-activated: when systemd passes a
+//! listening socket (`LISTEN_FDS`), it is served instead of binding
+//! `APP_HOSTNAME:PORT`. systemd keeps the socket open across restarts and
+//! queues new connections meanwhile, so Traefik never sees a refused one:
+//!
+//! ```ini
+//! # tezcatlipoca-auth.socket
+//! [Socket]
+//! ListenStream=8199
+//!
+//! [Install]
+//! WantedBy=sockets.target
+//! ```
+
+use std::{io, os::fd::FromRawFd, time::Duration};
+
+use sd_notify::NotifyState;
+use tokio::{
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use tracing::{debug, warn};
+
+use crate::AppState;
+
+/// How often readiness is checked while the first load is pending
+const READY_POLL: Duration = Duration::from_millis(250);
+
+/// Starts the readiness and watchdog notifications, if running under systemd.
+///
+/// Call once the listener is bound.
+pub fn spawn(state: AppState) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    tokio::spawn(ready_task(state.clone()));
+
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        tokio::spawn(watchdog_task(state, Duration::from_micros(usec)));
+    }
+}
+
+/// Sends `READY=1` once the banned IPs files are loaded.
+pub async fn ready_task(state: AppState) {
+    let count = loop {
+        let cache = state.banned_ips.read().await;
+        if cache.loaded {
+            break cache.len();
+        }
+        drop(cache);
+        sleep(READY_POLL).await;
+    };
+    let status = format!("Serving with {} banned entries", count);
+    match sd_notify::notify(false, &[NotifyState::Ready, NotifyState::Status(&status)]) {
+        Ok(()) => debug!("Notified systemd of readiness"),
+        Err(e) => warn!("Failed to notify systemd of readiness: {}", e),
+    }
+}
+
+/// Pings the watchdog every half `interval` while the service is healthy.
+pub async fn watchdog_task(state: AppState, interval: Duration) {
+    let period = interval / 2;
+    loop {
+        sleep(period).await;
+        // Every request takes this lock; if it can't be had, nothing is served
+        if timeout(period, state.banned_ips.read()).await.is_err() {
+            warn!("Banned IPs cache is stuck, skipping the systemd watchdog ping");
+            continue;
+        }
+        if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+            warn!("Failed to ping the systemd watchdog: {}", e);
+        }
+    }
+}
+
+/// The listening socket passed by systemd socket activation, if any.
+///
+/// Only the first socket is used; extra ones are reported and left alone.
+pub fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    // Also clears LISTEN_FDS so child processes don't pick the sockets up
+    let mut fds = sd_notify::listen_fds()?;
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+    let extra = fds.count();
+    if extra > 0 {
+        warn!("Ignoring {} extra socket(s) passed by systemd", extra);
+    }
+    // SAFETY: systemd passed this descriptor to this process (LISTEN_PID is
+    // checked) and nothing else in the process owns it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener).map(Some)
+}
@@ -0,0 +1,67 @@
+//! Tarpit for banned clients.
+//!
+//! Instead of answering banned IPs immediately, the middleware can hold the
+//! connection open for a while before responding 403, which slows down
+//! scanners that wait for each response. Waiting is done with
+//! `tokio::time::sleep`, so a held connection costs a parked task rather than
+//! a thread. The number of simultaneously held connections is capped by a
+//! semaphore; once the cap is reached further banned requests are answered
+//! immediately, so a flood of banned traffic cannot pile up unbounded tasks.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use tokio::{sync::Semaphore, time::sleep};
+
+use crate::config::DelayRange;
+
+/// Delays responses to banned clients, bounded by a concurrency cap.
+#[derive(Debug)]
+pub struct Tarpit {
+    delay: Option<DelayRange>,
+    slots: Semaphore,
+}
+
+impl Tarpit {
+    /// Creates a tarpit; `None` disables it.
+    pub fn new(delay: Option<DelayRange>, max_concurrent: usize) -> Self {
+        Self {
+            delay,
+            slots: Semaphore::new(max_concurrent),
+        }
+    }
+
+    /// Whether a delay is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.delay.is_some()
+    }
+
+    /// Holds the caller for the configured delay.
+    ///
+    /// Returns `false` without waiting when the tarpit is disabled or all
+    /// slots are taken.
+    pub async fn hold(&self) -> bool {
+        let Some(delay) = self.delay else {
+            return false;
+        };
+        let Ok(_permit) = self.slots.try_acquire() else {
+            return false;
+        };
+        sleep(pick_delay(delay)).await;
+        true
+    }
+}
+
+/// Picks a delay uniformly within the range so responses don't arrive on a
+/// fixed, easily fingerprinted schedule.
+fn pick_delay(range: DelayRange) -> Duration {
+    let spread = range.max.saturating_sub(range.min).as_millis() as u64;
+    if spread == 0 {
+        return range.min;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    range.min + Duration::from_millis(random % (spread + 1))
+}
@@ -0,0 +1,128 @@
+//! In-process test harness for end-to-end middleware tests.
+//!
+//! Available with the `test-support` feature. [`TestApp`] builds the real
+//! router on top of a temporary banned IPs file and sends requests through it
+//! without binding a socket, filling in the peer address the middleware
+//! expects from `into_make_service_with_connect_info`.
+//!
+//! ```no_run
+//! use axum::http::StatusCode;
+//! use tezcatlipoca_auth::testing::TestApp;
+//!
+//! # async fn example() {
+//! let app = TestApp::new(&["203.0.113.7"]).await;
+//! let res = app.get_from("203.0.113.7", "/admin").await;
+//! assert_eq!(res.status(), StatusCode::FORBIDDEN);
+//! # }
+//! ```
+
+use std::{io::Write, net::SocketAddr};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header::AUTHORIZATION, Request},
+    response::Response,
+    Router,
+};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+use crate::{build_router, cache::reload_banned_ips, config::Config, AppState};
+
+/// Peer address used for requests that don't set one explicitly.
+pub const DEFAULT_PEER: &str = "127.0.0.1:40000";
+
+/// `ADMIN_TOKEN` to configure for the admin API; [`TestApp::admin`] sends it.
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Router plus the temporary banned IPs file backing it.
+pub struct TestApp {
+    router: Router,
+    state: AppState,
+    banned_ips: NamedTempFile,
+}
+
+impl TestApp {
+    /// Builds an app with default configuration and the given banned entries.
+    pub async fn new(banned: &[&str]) -> Self {
+        Self::with_config(Config::default(), banned).await
+    }
+
+    /// Builds an app from `config`, pointing it at a temporary file holding `banned`.
+    ///
+    /// The cache is loaded before returning, so the first request sees the list.
+    pub async fn with_config(mut config: Config, banned: &[&str]) -> Self {
+        let banned_ips = NamedTempFile::new().expect("create temp banned IPs file");
+        config.banned_ips_files = vec![banned_ips.path().to_string_lossy().into_owned()];
+
+        let state = AppState::new(config);
+        let app = Self {
+            router: build_router(state.clone()),
+            state,
+            banned_ips,
+        };
+        app.set_banned(banned).await;
+        app
+    }
+
+    /// Shared state of the app under test.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Replaces the banned IPs file contents and reloads the cache.
+    pub async fn set_banned(&self, banned: &[&str]) {
+        let mut file = self.banned_ips.reopen().expect("reopen temp banned IPs file");
+        file.set_len(0).expect("truncate temp banned IPs file");
+        for entry in banned {
+            writeln!(file, "{}", entry).expect("write temp banned IPs file");
+        }
+
+        reload_banned_ips(&self.state)
+            .await
+            .expect("load temp banned IPs file");
+    }
+
+    /// Sends a GET request from the default peer without proxy headers.
+    pub async fn get(&self, path: &str) -> Response {
+        self.send(Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    /// Sends a GET request that claims to come from `ip` via `x-forwarded-for`.
+    pub async fn get_from(&self, ip: &str, path: &str) -> Response {
+        let req = Request::get(path)
+            .header("x-forwarded-for", ip)
+            .body(Body::empty())
+            .unwrap();
+        self.send(req).await
+    }
+
+    /// Sends an arbitrary request, using [`DEFAULT_PEER`] as the socket address
+    /// unless the request already carries a `ConnectInfo` extension.
+    pub async fn send(&self, mut req: Request<Body>) -> Response {
+        if req.extensions().get::<ConnectInfo<SocketAddr>>().is_none() {
+            let peer: SocketAddr = DEFAULT_PEER.parse().unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+        }
+        self.router.clone().oneshot(req).await.unwrap()
+    }
+
+    /// Sends an admin API request carrying [`ADMIN_TOKEN`].
+    pub async fn admin(&self, mut req: Request<Body>) -> Response {
+        let bearer = format!("Bearer {}", ADMIN_TOKEN).parse().unwrap();
+        req.headers_mut().insert(AUTHORIZATION, bearer);
+        self.send(req).await
+    }
+
+    /// Sends an admin API GET request carrying [`ADMIN_TOKEN`].
+    pub async fn admin_get(&self, path: &str) -> Response {
+        self.admin(Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    /// Sends a request with an explicit socket peer address.
+    pub async fn send_from_peer(&self, peer: SocketAddr, mut req: Request<Body>) -> Response {
+        req.extensions_mut().insert(ConnectInfo(peer));
+        self.send(req).await
+    }
+}
@@ -0,0 +1,210 @@
+//! TOTP second factor for sensitive paths.
+//!
+//! With `TOTP_PATHS` set (and `AUTH_MODE` enabled), requests for those paths
+//! additionally need a one-time code from an authenticator app. A user who
+//! passed the primary login but not the second factor gets a page asking for
+//! the code; it is submitted to `TOTP_PATH`, and a valid code upgrades the
+//! session cookie so the code isn't asked for again until it expires. The
+//! session cookie is only issued once the code has been checked.
+//!
+//! Users without a secret are enrolled on first use: a secret is generated
+//! and the page shows its `otpauth://` provisioning URI until the first code
+//! is confirmed. Secrets are kept in `TOTP_STORE_FILE` (JSON, written with
+//! owner-only permissions); without one they only live in memory and every
+//! user has to enroll again after a restart. Each code is accepted once.
+//!
+//! The file is rewritten on a blocking thread after every change, so the
+//! request that enrolled or verified doesn't wait for the disk.
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, TOTP};
+use tracing::{info, warn};
+
+use crate::config::TotpConfig;
+
+/// Code length and validity, the defaults of every authenticator app.
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+
+/// Stored secret of one user.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Raw secret bytes, base32 encoded
+    secret: String,
+    /// Whether a code has been verified since enrollment
+    confirmed: bool,
+    /// Time step of the last accepted code, to refuse replays
+    #[serde(default)]
+    last_step: u64,
+}
+
+/// What the code page shows for a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Enrollment {
+    /// The user already set up their authenticator
+    Enrolled,
+    /// Not confirmed yet: the provisioning URI to scan
+    Pending { uri: String },
+}
+
+/// TOTP secrets by user name.
+#[derive(Debug)]
+pub struct TotpStore {
+    config: TotpConfig,
+    /// Shown as the account issuer in authenticator apps
+    issuer: String,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    /// Held while writing `TOTP_STORE_FILE`, so writes don't interleave
+    writing: Arc<Mutex<()>>,
+}
+
+impl TotpStore {
+    /// Opens the store, loading `TOTP_STORE_FILE` when configured.
+    pub fn new(config: TotpConfig, issuer: &str) -> Self {
+        let entries = match &config.store_file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) => match serde_json::from_str::<HashMap<String, Entry>>(&text) {
+                    Ok(entries) => {
+                        info!("Loaded TOTP secrets for {} user(s) from {}", entries.len(), path);
+                        entries
+                    }
+                    Err(e) => {
+                        warn!("Ignoring unreadable TOTP store {}: {}", path, e);
+                        HashMap::new()
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+                Err(e) => {
+                    warn!("Failed to read TOTP store {}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            None => HashMap::new(),
+        };
+        Self {
+            config,
+            issuer: issuer.replace(':', ""),
+            entries: Arc::new(Mutex::new(entries)),
+            writing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn config(&self) -> &TotpConfig {
+        &self.config
+    }
+
+    /// Whether requests for `path` need the second factor.
+    ///
+    /// The code submission path itself never does.
+    pub fn required(&self, path: &str) -> bool {
+        path != self.config.path && crate::controllers::path_matches(&self.config.paths, path)
+    }
+
+    /// Enrollment state of `user`, generating a secret on first use.
+    pub fn enrollment(&self, user: &str) -> Enrollment {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.entry(user.to_string()).or_insert_with(|| {
+            let mut secret = [0u8; 20];
+            getrandom::fill(&mut secret).expect("OS random number generator is available");
+            Entry {
+                secret: totp_rs::Secret::Raw(secret.to_vec()).to_encoded().to_string(),
+                confirmed: false,
+                last_step: 0,
+            }
+        });
+        if entry.confirmed {
+            return Enrollment::Enrolled;
+        }
+        let uri = self
+            .totp(user, &entry.secret)
+            .map(|totp| totp.get_url())
+            .unwrap_or_default();
+        drop(entries);
+        // Persist right away so the secret that was shown stays valid
+        self.persist();
+        Enrollment::Pending { uri }
+    }
+
+    /// Checks `code` for `user`, confirming a pending enrollment.
+    pub fn verify(&self, user: &str, code: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let current = now / STEP_SECS;
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = entries.get_mut(user) else {
+            return false;
+        };
+        let Some(totp) = self.totp(user, &entry.secret) else {
+            return false;
+        };
+        // One step of clock drift either way, like most servers allow
+        let matched = [current.saturating_sub(1), current, current + 1]
+            .into_iter()
+            .filter(|&step| step > entry.last_step)
+            .find(|&step| totp.check(code.trim(), step * STEP_SECS));
+        let Some(step) = matched else {
+            return false;
+        };
+        entry.last_step = step;
+        entry.confirmed = true;
+        drop(entries);
+        self.persist();
+        true
+    }
+
+    fn totp(&self, user: &str, secret: &str) -> Option<TOTP> {
+        let secret = totp_rs::Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+        TOTP::new(
+            Algorithm::SHA1,
+            DIGITS,
+            0,
+            STEP_SECS,
+            secret,
+            Some(self.issuer.clone()),
+            user.to_string(),
+        )
+        .ok()
+    }
+
+    /// Writes `TOTP_STORE_FILE` in the background.
+    ///
+    /// The entries are copied once the write lock is held, so whichever
+    /// write runs last saves the latest state.
+    fn persist(&self) {
+        let Some(path) = self.config.store_file.clone() else {
+            return;
+        };
+        let (entries, writing) = (self.entries.clone(), self.writing.clone());
+        tokio::task::spawn_blocking(move || {
+            let _writing = writing.lock().unwrap_or_else(|e| e.into_inner());
+            let snapshot = entries.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            if let Err(e) = save(&path, &snapshot) {
+                warn!("Failed to write TOTP store {}: {}", path, e);
+            }
+        });
+    }
+}
+
+/// Writes the store atomically, readable by the owner only.
+fn save(path: &str, entries: &HashMap<String, Entry>) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(entries)?;
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        io::Write::write_all(&mut file, &json)?;
+    }
+    std::fs::rename(tmp, path)
+}
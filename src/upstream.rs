@@ -0,0 +1,135 @@
+//! Chained authentication through a second ForwardAuth service.
+//!
+//! With `UPSTREAM_AUTH_URL` set, requests that pass every local check are
+//! handed on to another auth service (Authelia, Authentik, oauth2-proxy, ...),
+//! so banned and suspicious clients are turned away cheaply before the
+//! heavier SSO ever sees them.
+//!
+//! The auth request is built the way Traefik builds its own: a `GET` with the
+//! original request headers plus `X-Forwarded-Method`, `X-Forwarded-Uri` and
+//! `X-Forwarded-For`. A `2xx` answer allows the request, and the upstream's
+//! headers (e.g. `Remote-User`) are returned next to ours, where Traefik's
+//! `authResponseHeaders` picks them up. Any other answer, typically a
+//! redirect to the login page or a `401`, is passed to the client unchanged;
+//! redirects are never followed. When the upstream can't be reached the
+//! request is refused with `503`.
+
+use std::net::IpAddr;
+
+use axum::http::{
+    header::{self, HeaderName},
+    HeaderMap, HeaderValue,
+};
+use tracing::{debug, warn};
+
+use crate::config::{BlockResponse, UpstreamAuthConfig, UpstreamResponse};
+
+/// Largest upstream body passed on to the client.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Headers describing a single connection or message, never copied between
+/// the client, this service and the upstream.
+const SKIPPED_HEADERS: [HeaderName; 11] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    header::TE,
+    header::TRAILER,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::HOST,
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+    header::DATE,
+];
+
+/// Client for the upstream auth service.
+#[derive(Debug)]
+pub struct UpstreamAuth {
+    config: UpstreamAuthConfig,
+    client: reqwest::Client,
+}
+
+impl UpstreamAuth {
+    pub fn new(config: UpstreamAuthConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("tezcatlipoca-auth/", env!("CARGO_PKG_VERSION")))
+            .timeout(config.timeout)
+            // Login redirects are meant for the browser
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("HTTP client configuration is valid");
+        Self { config, client }
+    }
+
+    pub fn config(&self) -> &UpstreamAuthConfig {
+        &self.config
+    }
+
+    /// Asks the upstream about a request that passed the local checks.
+    ///
+    /// Returns the headers to add to the allowed response, or the upstream's
+    /// answer when it denies the request.
+    pub async fn forward(
+        &self,
+        ip: IpAddr,
+        method: &str,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Result<Vec<(String, String)>, BlockResponse> {
+        let mut request_headers = headers.clone();
+        for name in &SKIPPED_HEADERS {
+            request_headers.remove(name);
+        }
+        // Envoy doesn't send Traefik's headers; describe the request ourselves
+        let defaults = [
+            ("x-forwarded-method", method.to_string()),
+            ("x-forwarded-uri", path.to_string()),
+            ("x-forwarded-for", ip.to_string()),
+        ];
+        for (name, value) in defaults {
+            if !request_headers.contains_key(name)
+                && let Ok(value) = HeaderValue::from_str(&value)
+            {
+                request_headers.insert(name, value);
+            }
+        }
+
+        let response = match self
+            .client
+            .get(&self.config.url)
+            .headers(request_headers)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Upstream auth request to {} failed: {}", self.config.url, e);
+                return Err(BlockResponse::Status(503));
+            }
+        };
+
+        let status = response.status();
+        let response_headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !SKIPPED_HEADERS.contains(name))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        if status.is_success() {
+            return Ok(response_headers
+                .into_iter()
+                .filter(|(name, _)| name != header::CONTENT_TYPE.as_str())
+                .collect());
+        }
+
+        debug!("🔗 UPSTREAM AUTH DENIED: IP {} accessed {} [{}]", ip, path, status);
+        let mut body = response.bytes().await.unwrap_or_default().to_vec();
+        body.truncate(MAX_BODY);
+        Err(BlockResponse::Upstream(Box::new(UpstreamResponse {
+            status: status.as_u16(),
+            headers: response_headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })))
+    }
+}
@@ -0,0 +1,326 @@
+//! Secrets fetched from HashiCorp Vault at startup.
+//!
+//! With `VAULT_ADDR` set, the service logs in (with `VAULT_TOKEN` or the
+//! Kubernetes auth method) before loading its configuration, reads every
+//! `VAR=path#field` of `VAULT_SECRETS` and uses the values as if they were
+//! set in the environment. Any variable can come from Vault: `ADMIN_TOKEN`,
+//! `COOKIE_SECRET`, `GOSSIP_SECRET`, `REDIS_URL`, API keys...
+//!
+//! Both KV versions are supported: a field is looked up in `data.data`
+//! (version 2) and then in `data` (version 1 and other engines). Startup
+//! fails if any secret can't be read, rather than running without it.
+//!
+//! The login token and the leases of dynamic secrets (e.g. database
+//! credentials) are renewed in the background at half their TTL, so they
+//! stay valid for as long as the service runs. Values are read once: a
+//! rotated secret takes effect on the next restart.
+
+use std::{collections::HashMap, time::Duration};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::{VaultAuth, VaultConfig};
+
+/// Shortest pause between two renewals
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Why secrets couldn't be loaded
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("Vault request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The Kubernetes service account token couldn't be read
+    #[error("failed to read {0}: {1}")]
+    Jwt(String, #[source] std::io::Error),
+    /// The secret has no such field
+    #[error("Vault secret {path} has no field '{field}'")]
+    MissingField { path: String, field: String },
+}
+
+/// Token obtained by logging in
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub token: String,
+    /// `None` for tokens that never expire
+    pub ttl: Option<Duration>,
+    pub renewable: bool,
+}
+
+/// Lease of a dynamic secret
+#[derive(Clone, Debug)]
+pub struct Lease {
+    pub id: String,
+    pub ttl: Duration,
+}
+
+/// Result of [`load`]
+#[derive(Debug)]
+pub struct Loaded {
+    /// Variable name to value
+    pub secrets: HashMap<String, String>,
+    pub session: Session,
+    /// Renewable leases of the secrets read
+    pub leases: Vec<Lease>,
+}
+
+#[derive(Deserialize)]
+struct AuthResponse {
+    auth: Auth,
+}
+
+#[derive(Deserialize)]
+struct Auth {
+    client_token: String,
+    #[serde(default)]
+    lease_duration: u64,
+    #[serde(default)]
+    renewable: bool,
+}
+
+#[derive(Deserialize)]
+struct SecretResponse {
+    #[serde(default)]
+    lease_id: String,
+    #[serde(default)]
+    lease_duration: u64,
+    #[serde(default)]
+    renewable: bool,
+    #[serde(default)]
+    data: Value,
+}
+
+#[derive(Deserialize)]
+struct TokenLookup {
+    data: TokenData,
+}
+
+#[derive(Deserialize)]
+struct TokenData {
+    #[serde(default)]
+    ttl: u64,
+    #[serde(default)]
+    renewable: bool,
+}
+
+/// Logs in and reads every secret of `VAULT_SECRETS`.
+pub async fn load(http: &reqwest::Client, config: &VaultConfig) -> Result<Loaded, VaultError> {
+    let session = login(http, config).await?;
+
+    let mut responses: HashMap<&str, SecretResponse> = HashMap::new();
+    let mut secrets = HashMap::new();
+    for secret in &config.secrets {
+        if !responses.contains_key(secret.path.as_str()) {
+            let response = request(http, config, reqwest::Method::GET, &secret.path, &session.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            responses.insert(&secret.path, response);
+        }
+        let data = &responses[secret.path.as_str()].data;
+        let value = data
+            .get("data")
+            .and_then(|d| d.get(&secret.field))
+            .or_else(|| data.get(&secret.field))
+            .ok_or_else(|| VaultError::MissingField {
+                path: secret.path.clone(),
+                field: secret.field.clone(),
+            })?;
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        secrets.insert(secret.var.clone(), value);
+    }
+
+    let leases = responses
+        .into_values()
+        .filter(|r| r.renewable && !r.lease_id.is_empty() && r.lease_duration > 0)
+        .map(|r| Lease {
+            id: r.lease_id,
+            ttl: Duration::from_secs(r.lease_duration),
+        })
+        .collect();
+    Ok(Loaded {
+        secrets,
+        session,
+        leases,
+    })
+}
+
+async fn login(http: &reqwest::Client, config: &VaultConfig) -> Result<Session, VaultError> {
+    match &config.auth {
+        VaultAuth::Token(token) => {
+            // A given token may be short-lived too: look up its TTL to renew it
+            let lookup: TokenLookup = request(
+                http,
+                config,
+                reqwest::Method::GET,
+                "auth/token/lookup-self",
+                token,
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+            Ok(Session {
+                token: token.clone(),
+                ttl: (lookup.data.ttl > 0).then(|| Duration::from_secs(lookup.data.ttl)),
+                renewable: lookup.data.renewable,
+            })
+        }
+        VaultAuth::Kubernetes {
+            role,
+            mount,
+            jwt_file,
+        } => {
+            let jwt = tokio::fs::read_to_string(jwt_file)
+                .await
+                .map_err(|e| VaultError::Jwt(jwt_file.clone(), e))?;
+            let path = format!("auth/{}/login", mount);
+            let response: AuthResponse = request(http, config, reqwest::Method::POST, &path, "")
+                .json(&json!({"role": role, "jwt": jwt.trim()}))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(session(response.auth))
+        }
+    }
+}
+
+fn session(auth: Auth) -> Session {
+    Session {
+        token: auth.client_token,
+        ttl: (auth.lease_duration > 0).then(|| Duration::from_secs(auth.lease_duration)),
+        renewable: auth.renewable,
+    }
+}
+
+fn request(
+    http: &reqwest::Client,
+    config: &VaultConfig,
+    method: reqwest::Method,
+    path: &str,
+    token: &str,
+) -> reqwest::RequestBuilder {
+    let mut req = http.request(method, format!("{}/v1/{}", config.addr, path));
+    if !token.is_empty() {
+        req = req.header("X-Vault-Token", token);
+    }
+    if let Some(namespace) = &config.namespace {
+        req = req.header("X-Vault-Namespace", namespace);
+    }
+    req
+}
+
+/// Renews the login token, and the secret leases, at half their TTL.
+///
+/// A token that can no longer be renewed is replaced by logging in again
+/// with the Kubernetes auth method; a static `VAULT_TOKEN` is only renewed
+/// while Vault allows it.
+pub async fn renew_task(http: reqwest::Client, config: VaultConfig, loaded: Loaded) {
+    for lease in loaded.leases {
+        tokio::spawn(renew_lease(http.clone(), config.clone(), loaded.session.clone(), lease));
+    }
+
+    let mut session = loaded.session;
+    while let Some(ttl) = session.ttl {
+        sleep((ttl / 2).max(MIN_RENEW_INTERVAL)).await;
+        let renewed = if session.renewable {
+            renew_token(&http, &config, &session.token).await
+        } else {
+            Err(None)
+        };
+        session = match renewed {
+            Ok(renewed) => renewed,
+            Err(e) => {
+                if let Some(e) = e {
+                    warn!("Failed to renew the Vault token: {}", e);
+                }
+                if !matches!(config.auth, VaultAuth::Kubernetes { .. }) {
+                    warn!("Vault token is no longer renewed and will expire");
+                    return;
+                }
+                match login(&http, &config).await {
+                    Ok(session) => {
+                        info!("Logged in to Vault again");
+                        session
+                    }
+                    Err(e) => {
+                        warn!("Failed to log in to Vault again: {}", e);
+                        Session {
+                            ttl: Some(MIN_RENEW_INTERVAL * 2),
+                            renewable: false,
+                            ..session
+                        }
+                    }
+                }
+            }
+        };
+    }
+}
+
+async fn renew_token(
+    http: &reqwest::Client,
+    config: &VaultConfig,
+    token: &str,
+) -> Result<Session, Option<VaultError>> {
+    let response: AuthResponse = request(
+        http,
+        config,
+        reqwest::Method::POST,
+        "auth/token/renew-self",
+        token,
+    )
+    .send()
+    .await
+    .and_then(reqwest::Response::error_for_status)
+    .map_err(|e| Some(e.into()))?
+    .json()
+    .await
+    .map_err(|e| Some(e.into()))?;
+    Ok(session(response.auth))
+}
+
+async fn renew_lease(http: reqwest::Client, config: VaultConfig, session: Session, lease: Lease) {
+    let mut ttl = lease.ttl;
+    loop {
+        sleep((ttl / 2).max(MIN_RENEW_INTERVAL)).await;
+        let renewed = request(
+            &http,
+            &config,
+            reqwest::Method::PUT,
+            "sys/leases/renew",
+            &session.token,
+        )
+        .json(&json!({"lease_id": lease.id}))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+        let response: SecretResponse = match renewed {
+            Ok(res) => match res.json().await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Failed to renew Vault lease {}: {}", lease.id, e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                warn!("Failed to renew Vault lease {}: {}", lease.id, e);
+                continue;
+            }
+        };
+        if response.lease_duration == 0 {
+            warn!("Vault lease {} reached its maximum TTL and will expire", lease.id);
+            return;
+        }
+        ttl = Duration::from_secs(response.lease_duration);
+    }
+}
@@ -0,0 +1,217 @@
+//! Webhook notifications for block events.
+//!
+//! When `WEBHOOK_URL` is set, blocked requests, automatic bans and unbans are POSTed
+//! to it as JSON. Events are batched (up to `WEBHOOK_BATCH_SIZE`, or whatever
+//! arrived within `WEBHOOK_BATCH_SECS` of the first one) and each batch is
+//! retried with exponential backoff before it is dropped. Besides the raw
+//! JSON format, `WEBHOOK_FORMAT=discord` and `WEBHOOK_FORMAT=slack` render the
+//! batch as a chat message.
+
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::{sleep, timeout_at, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{WebhookConfig, WebhookFormat},
+    events::{describe_block, SecurityEvent},
+    supervisor,
+    AppState,
+};
+
+/// Discord rejects messages longer than this.
+const DISCORD_MAX_CHARS: usize = 2000;
+
+/// Forwards security events to the webhook until the event bus closes.
+pub async fn webhook_task(state: AppState, config: WebhookConfig) {
+    let mut events = state.events.subscribe();
+    while let Some(batch) = next_batch(&mut events, &config).await {
+        #[cfg(feature = "dns")]
+        let batch = add_hostnames(&state, batch);
+        let payload = render(&batch, config.format);
+        match send_with_retry(&state.http, &config, &payload).await {
+            Ok(()) => {
+                debug!("Delivered {} events to webhook", batch.len());
+                supervisor::ran();
+            }
+            Err(e) => {
+                warn!(
+                    "Dropping {} webhook events after {} attempts: {}",
+                    batch.len(),
+                    config.max_retries + 1,
+                    e
+                );
+                supervisor::failed(&e);
+            }
+        }
+    }
+}
+
+/// Waits for the first event, then collects more until the batch is full or
+/// the batch window has passed. Returns `None` once the bus is closed.
+async fn next_batch(
+    events: &mut Receiver<SecurityEvent>,
+    config: &WebhookConfig,
+) -> Option<Vec<SecurityEvent>> {
+    let first = recv(events).await?;
+    let deadline = Instant::now() + config.batch_window;
+    let mut batch = vec![first];
+
+    while batch.len() < config.batch_size {
+        match timeout_at(deadline, recv(events)).await {
+            Ok(Some(event)) => batch.push(event),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+/// Fills in hostnames resolved since the events were published, e.g. for
+/// the first block of an IP.
+#[cfg(feature = "dns")]
+fn add_hostnames(state: &AppState, mut batch: Vec<SecurityEvent>) -> Vec<SecurityEvent> {
+    let Some(rdns) = &state.rdns else {
+        return batch;
+    };
+    for event in &mut batch {
+        if let SecurityEvent::Blocked { ip, hostname, .. } = event
+            && hostname.is_none()
+        {
+            *hostname = rdns.cached(ip);
+        }
+    }
+    batch
+}
+
+async fn recv(events: &mut Receiver<SecurityEvent>) -> Option<SecurityEvent> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Webhook sender fell behind, {} events dropped", missed)
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn render(batch: &[SecurityEvent], format: WebhookFormat) -> serde_json::Value {
+    match format {
+        WebhookFormat::Json => json!({ "events": batch }),
+        WebhookFormat::Discord => {
+            let mut content = summary(batch);
+            if content.len() > DISCORD_MAX_CHARS {
+                let cut = content
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .take_while(|&i| i <= DISCORD_MAX_CHARS - 3)
+                    .last()
+                    .unwrap_or(0);
+                content.truncate(cut);
+                content.push_str("...");
+            }
+            json!({ "content": content })
+        }
+        WebhookFormat::Slack => json!({ "text": summary(batch) }),
+    }
+}
+
+/// One line per event, for chat formats.
+fn summary(batch: &[SecurityEvent]) -> String {
+    batch
+        .iter()
+        .map(|event| match event {
+            SecurityEvent::Blocked {
+                ip,
+                path,
+                reason,
+                ban,
+                hostname,
+                enforced,
+                timestamp,
+            } => format!(
+                "{} {} {}{} → {} [{}]",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                if *enforced { "🚫 blocked" } else { "👀 would block" },
+                ip,
+                hostname.as_ref().map_or(String::new(), |name| format!(" ({})", name)),
+                path,
+                describe_block(reason, ban.as_ref())
+            ),
+            SecurityEvent::AutoBan {
+                ip,
+                path,
+                reason,
+                duration_secs,
+                timestamp,
+            } => format!(
+                "{} 🔨 auto-banned {} after {} [{}] {}",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                ip,
+                path,
+                reason,
+                duration_secs.map_or("permanently".to_string(), |s| format!("for {}s", s))
+            ),
+            SecurityEvent::Unban { ip, timestamp } => format!(
+                "{} ✅ unbanned {}",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                ip
+            ),
+            SecurityEvent::BanUpdated {
+                ip,
+                duration_secs,
+                timestamp,
+                ..
+            } => format!(
+                "{} ⏱️ ban of {} now lasts {}",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                ip,
+                duration_secs.map_or("permanently".to_string(), |s| format!("{}s", s))
+            ),
+            SecurityEvent::Lockdown {
+                until: Some(until),
+                timestamp,
+            } => format!(
+                "{} 🔒 lockdown until {}",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                until.format("%Y-%m-%d %H:%M:%S")
+            ),
+            SecurityEvent::Lockdown {
+                until: None,
+                timestamp,
+            } => format!("{} 🔓 lockdown lifted", timestamp.format("%Y-%m-%d %H:%M:%S")),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn send_with_retry(
+    http: &reqwest::Client,
+    config: &WebhookConfig,
+    payload: &serde_json::Value,
+) -> Result<(), reqwest::Error> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        let result = http
+            .post(&config.url)
+            .json(payload)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < config.max_retries => {
+                debug!("Webhook delivery failed (attempt {}): {}", attempt + 1, e);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
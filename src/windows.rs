@@ -0,0 +1,321 @@
+//! Windows service support (Windows only).
+//!
+//! `tezcatlipoca-auth service install` registers the executable with the
+//! Service Control Manager, started automatically at boot, and
+//! `tezcatlipoca-auth service uninstall` stops and removes it. The SCM runs
+//! `tezcatlipoca-auth service run`, which reports the service as running,
+//! serves until a Stop or Shutdown control and reports it stopped once the
+//! server is down. Started from a console, the binary behaves as on any
+//! other platform.
+//!
+//! ```powershell
+//! tezcatlipoca-auth.exe service install
+//! Start-Service tezcatlipoca-auth
+//! ```
+//!
+//! Services start in `C:\Windows\System32`, so the working directory is
+//! changed to the executable's: a `.env` file and a relative `LOG_DIR` are
+//! found next to it.
+//!
+//! A service has no console to read: `LOG_TARGET=eventlog` sends events to
+//! the Application event log under the `tezcatlipoca-auth` source instead.
+//! Errors that stop the service, including log files that can't be opened,
+//! are always written there.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, Write},
+    iter,
+    os::windows::ffi::OsStrExt,
+    path::Path,
+    ptr,
+    sync::{mpsc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::oneshot;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl,
+        ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+        EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+    },
+};
+
+/// Service name, also the event log source
+pub const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+const DISPLAY_NAME: &str = "Tezcatlipoca Auth";
+
+const DESCRIPTION: &str = "IP-based access control for Traefik ForwardAuth";
+
+/// How long the SCM waits for in-flight work after a Stop control
+const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// Serves until the receiver fires. An error stops the service with a
+/// failure exit code, so recovery actions apply.
+pub type Entry = fn(oneshot::Receiver<()>) -> Result<(), String>;
+
+static ENTRY: OnceLock<Entry> = OnceLock::new();
+
+/// What the service thread waits for
+enum Event {
+    /// Stop or Shutdown control from the SCM
+    Stop,
+    /// `Entry` returned
+    Exited(Result<(), String>),
+}
+
+/// Hands the current thread to the SCM, which runs `entry` as the service.
+///
+/// Returns once the service stopped. Fails when the process wasn't started
+/// by the SCM.
+pub fn run(entry: Entry) -> windows_service::Result<()> {
+    let _ = ENTRY.set(entry);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        report_error(&format!("Service failed: {}", e));
+    }
+}
+
+fn run_service() -> windows_service::Result<()> {
+    let (events, received) = mpsc::channel();
+    let controls = events.clone();
+    let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = controls.send(Event::Stop);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    if let Some(dir) = std::env::current_exe().ok().as_deref().and_then(Path::parent) {
+        let _ = std::env::set_current_dir(dir);
+    }
+
+    let entry = *ENTRY.get().expect("entry point is set before dispatching");
+    let (stop, stopped) = oneshot::channel();
+    thread::Builder::new()
+        .name("service".to_string())
+        .spawn(move || {
+            let _ = events.send(Event::Exited(entry(stopped)));
+        })
+        .map_err(windows_service::Error::Winapi)?;
+    set_status(status, ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+
+    let mut stop = Some(stop);
+    let result = loop {
+        match received.recv() {
+            Ok(Event::Stop) => {
+                if let Some(stop) = stop.take() {
+                    set_status(status, ServiceState::StopPending, ServiceExitCode::NO_ERROR)?;
+                    let _ = stop.send(());
+                }
+            }
+            Ok(Event::Exited(result)) => break result,
+            Err(_) => break Ok(()),
+        }
+    };
+    let exit_code = match result {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(e) => {
+            report_error(&e);
+            ServiceExitCode::ServiceSpecific(1)
+        }
+    };
+    set_status(status, ServiceState::Stopped, exit_code)
+}
+
+fn set_status(
+    handle: ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> windows_service::Result<()> {
+    let running = state == ServiceState::Running;
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: if running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        },
+        exit_code,
+        checkpoint: 0,
+        wait_hint: if state == ServiceState::StopPending {
+            STOP_WAIT_HINT
+        } else {
+            Duration::ZERO
+        },
+        process_id: None,
+    })
+}
+
+/// Registers the running executable as a service started at boot.
+pub fn install() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: DISPLAY_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().map_err(windows_service::Error::Winapi)?,
+        launch_arguments: vec!["service".into(), "run".into()],
+        dependencies: Vec::new(),
+        // LocalSystem
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description(DESCRIPTION)
+}
+
+/// Stops the service if it is running and removes it.
+pub fn uninstall() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    // The SCM removes it once stopped and every handle is closed
+    service.delete()
+}
+
+/// Writes `text` to the event log as an error, e.g. before logging is set up.
+pub fn report_error(text: &str) {
+    if let Ok(log) = EventLog::open() {
+        log.report(EVENTLOG_ERROR_TYPE, text);
+    }
+}
+
+/// Writer factory for the Application event log; each event becomes one
+/// entry, typed after its level.
+pub struct EventLog(HANDLE);
+
+// SAFETY: event source handles can be used from any thread
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    /// Opens the `tezcatlipoca-auth` event source.
+    pub fn open() -> io::Result<Self> {
+        let source = wide(SERVICE_NAME);
+        // SAFETY: `source` is NUL-terminated and outlives the call
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+
+    /// Writes one entry. Failures are ignored: there is nowhere left to
+    /// report them.
+    fn report(&self, kind: REPORT_EVENT_TYPE, text: &str) {
+        let text = wide(text);
+        let strings = [text.as_ptr()];
+        // SAFETY: the handle is open until dropped, and `strings` holds one
+        // NUL-terminated string outliving the call
+        unsafe {
+            ReportEventW(
+                self.0,
+                kind,
+                0,
+                0,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            );
+        }
+    }
+
+    fn entry(&self, level: &Level) -> EventLogEntry<'_> {
+        EventLogEntry {
+            log: self,
+            kind: match *level {
+                Level::ERROR => EVENTLOG_ERROR_TYPE,
+                Level::WARN => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            },
+            text: Vec::new(),
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        // SAFETY: the handle was opened by `open` and isn't used afterwards
+        unsafe { DeregisterEventSource(self.0) };
+    }
+}
+
+impl<'a> MakeWriter<'a> for EventLog {
+    type Writer = EventLogEntry<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.entry(&Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.entry(meta.level())
+    }
+}
+
+/// One formatted event, reported when dropped.
+pub struct EventLogEntry<'a> {
+    log: &'a EventLog,
+    kind: REPORT_EVENT_TYPE,
+    text: Vec<u8>,
+}
+
+impl Write for EventLogEntry<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventLogEntry<'_> {
+    fn drop(&mut self) {
+        let text = self.text.trim_ascii_end();
+        if !text.is_empty() {
+            self.log.report(self.kind, &String::from_utf8_lossy(text));
+        }
+    }
+}
+
+/// NUL-terminated UTF-16 for the wide Win32 functions.
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(iter::once(0)).collect()
+}
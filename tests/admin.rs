@@ -0,0 +1,345 @@
+use std::time::Duration;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use futures_util::StreamExt;
+use tezcatlipoca_auth::{
+    config::{Config, FeedConfig},
+    testing::{TestApp, ADMIN_TOKEN},
+};
+use tokio::net::TcpListener;
+
+fn admin_config() -> Config {
+    Config {
+        admin_api: true,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn admin_routes_are_absent_unless_enabled() {
+    let app = TestApp::new(&[]).await;
+
+    // Falls through to the ForwardAuth catch-all, which returns an empty 200
+    let res = app.get("/admin/events").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("content-type").is_none());
+}
+
+#[tokio::test]
+async fn event_stream_reports_live_decisions() {
+    let app = TestApp::with_config(admin_config(), &["203.0.113.7"]).await;
+
+    let res = app.admin_get("/admin/events?filter=blocked").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/event-stream");
+    let mut body = res.into_body().into_data_stream();
+
+    app.get_from("198.51.100.1", "/ignored-by-filter").await;
+    app.get_from("203.0.113.7", "/wp-admin").await;
+
+    let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+        .await
+        .expect("event received")
+        .unwrap()
+        .unwrap();
+    let text = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(text.starts_with("event: decision\n"), "{text}");
+    assert!(text.contains(r#""verdict":"blocked""#), "{text}");
+    assert!(text.contains(r#""ip":"203.0.113.7""#), "{text}");
+}
+
+#[tokio::test]
+async fn ip_stats_count_hits_and_blocks() {
+    let app = TestApp::with_config(admin_config(), &["203.0.113.7"]).await;
+    app.get_from("203.0.113.7", "/").await;
+    app.get_from("203.0.113.7", "/again").await;
+    app.get_from("198.51.100.1", "/").await;
+
+    let res = app.admin_get("/admin/stats/ip/203.0.113.7").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["hits"], 2);
+    assert_eq!(json["blocked"], 2);
+    assert_eq!(json["currently_banned"], true);
+
+    let res = app.admin_get("/admin/stats/ip/192.0.2.99").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn top_report_ranks_offenders() {
+    let app = TestApp::with_config(admin_config(), &["203.0.113.7", "203.0.113.8"]).await;
+    for _ in 0..3 {
+        app.get_from("203.0.113.7", "/").await;
+    }
+    app.get_from("203.0.113.8", "/").await;
+    app.get_from("198.51.100.1", "/").await;
+
+    let res = app.admin_get("/admin/stats/top?window=15m&limit=1").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["window_secs"], 900);
+    assert_eq!(json["blocked_ips"][0]["key"], "203.0.113.7");
+    assert_eq!(json["blocked_ips"][0]["count"], 3);
+    assert_eq!(json["blocked_ips"].as_array().unwrap().len(), 1);
+    assert_eq!(json["paths"][0]["count"], 5);
+
+    let res = app.admin_get("/admin/stats/top?window=soon").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn refresh_reloads_files_and_feeds_at_once() {
+    let feed = Router::new().route(
+        "/list.txt",
+        get(|| async { "198.51.100.0/24\n192.0.2.1\n" }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, feed).await.unwrap() });
+
+    let config = Config {
+        feeds: vec![
+            FeedConfig {
+                name: "tor".to_string(),
+                url: format!("http://{}/list.txt", addr),
+                refresh: Duration::from_secs(3600),
+            },
+            FeedConfig {
+                name: "gone".to_string(),
+                url: format!("http://{}/missing.txt", addr),
+                refresh: Duration::from_secs(3600),
+            },
+        ],
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    // Changed behind the cache's back, as an operator editing the file would
+    let file = &app.state().config.banned_ips_files[0];
+    std::fs::write(file, "203.0.113.7\n203.0.113.8\n").unwrap();
+
+    let req = Request::post("/admin/refresh").body(Body::empty()).unwrap();
+    let res = app.admin(req).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["entries"], 4);
+    assert_eq!(json["sources"][0]["source"], "files");
+    assert_eq!(json["sources"][0]["entries"], 2);
+    assert_eq!(json["sources"][1]["source"], "tor");
+    assert_eq!(json["sources"][1]["entries"], 2);
+    assert!(json["sources"][1].get("error").is_none());
+    assert_eq!(json["sources"][2]["entries"], 0);
+    assert!(json["sources"][2]["error"].as_str().unwrap().contains("404"));
+
+    let res = app.get_from("198.51.100.20", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn admin_requests_need_the_bearer_token() {
+    let config = Config {
+        admin_token: Some("s3cret-admin-token".to_string()),
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    let stats = |authorization: Option<&str>| {
+        let mut req = Request::get("/admin/stats/top");
+        if let Some(value) = authorization {
+            req = req.header("authorization", value);
+        }
+        req.header("x-forwarded-for", "198.51.100.1")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let res = app.send(stats(None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers()["www-authenticate"], "Bearer");
+    let res = app.send(stats(Some("Bearer s3cret-admin-tok"))).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = app.send(stats(Some("Basic s3cret-admin-token"))).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = app.send(stats(Some("Bearer s3cret-admin-token"))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Independent of the IP rules: a banned IP with the token gets in
+    let req = Request::get("/admin/stats/top")
+        .header("authorization", "Bearer s3cret-admin-token")
+        .header("x-forwarded-for", "203.0.113.7")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(req).await.status(), StatusCode::OK);
+
+    let res = app.get("/health").await;
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["admin_auth_failures"], 3);
+}
+
+#[tokio::test]
+async fn admin_api_without_a_token_refuses_every_request() {
+    let config = Config {
+        admin_token: None,
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.get("/admin/stats/top").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let res = app.admin_get("/admin/bans").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn ban_listing_filters_and_paginates() {
+    let banned: Vec<String> = (1..=25).map(|i| format!("203.0.113.{i}")).collect();
+    let mut banned: Vec<&str> = banned.iter().map(String::as_str).collect();
+    banned.push("198.51.100.0/24");
+    let config = Config {
+        honeypot_paths: vec!["/wp-login.php".to_string()],
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &banned).await;
+    app.get_from("203.0.113.200", "/wp-login.php").await;
+    let get_json = |path: &'static str| async {
+        let res = app.admin_get(path).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+    };
+
+    let json = get_json("/admin/bans?prefix=203.0.113.&per_page=10&page=3").await;
+    assert_eq!(json["total"], 26);
+    let entries = json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 6);
+    // Sorted by address, not as text
+    assert_eq!(entries[0]["entry"], "203.0.113.21");
+    assert_eq!(entries[5]["entry"], "203.0.113.200");
+    assert_eq!(entries[5]["source"], "dynamic");
+    assert_eq!(entries[5]["reason"], "honeypot");
+    assert!(entries[5]["expires"].is_string());
+
+    let json = get_json("/admin/bans?source=files&prefix=198.").await;
+    assert_eq!(json["total"], 1);
+    assert_eq!(json["entries"][0]["entry"], "198.51.100.0/24");
+    assert_eq!(json["entries"][0]["source"], "files");
+    assert!(json["entries"][0]["reason"].is_null());
+
+    let json = get_json("/admin/bans?source=crowdsec").await;
+    assert_eq!(json["total"], 0);
+}
+
+#[tokio::test]
+async fn bans_import_and_export_in_bulk() {
+    let app = TestApp::with_config(admin_config(), &[]).await;
+    let req = Request::post("/admin/bans/import")
+        .header("content-type", "text/csv")
+        .body(Body::from(
+            "ip,reason,expires\n203.0.113.7,fail2ban sshd,\n203.0.113.8,old,2001-01-01T00:00:00Z\nbogus\n",
+        ))
+        .unwrap();
+    let res = app.admin(req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["imported"], 1);
+    assert_eq!(json["expired"], 1);
+    assert_eq!(json["errors"][0]["record"], 4);
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = app.admin_get("/admin/bans/export?format=csv").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/csv; charset=utf-8");
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, "ip,reason,expires,source\n203.0.113.7,fail2ban sshd,,import\n");
+
+    let req = Request::post("/admin/bans/import")
+        .body(Body::from("not json"))
+        .unwrap();
+    assert_eq!(app.admin(req).await.status(), StatusCode::BAD_REQUEST);
+    let res = app.admin_get("/admin/bans/export?format=xml").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unban_lifts_a_dynamic_ban_and_announces_it() {
+    let config = Config {
+        honeypot_paths: vec!["/.env".to_string()],
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    app.get_from("203.0.113.7", "/.env").await;
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+    let mut events = app.state().events.subscribe();
+
+    let req = Request::delete("/admin/bans/203.0.113.7").body(Body::empty()).unwrap();
+    assert_eq!(app.admin(req).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+    assert!(matches!(
+        events.try_recv(),
+        Ok(tezcatlipoca_auth::events::SecurityEvent::Unban { .. })
+    ));
+
+    let req = Request::delete("/admin/bans/203.0.113.7").body(Body::empty()).unwrap();
+    assert_eq!(app.admin(req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn ban_length_can_be_checked_and_changed() {
+    let config = Config {
+        honeypot_paths: vec!["/.env".to_string()],
+        honeypot_ban_duration: Some(Duration::from_secs(3600)),
+        ..admin_config()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    app.get_from("203.0.113.7", "/.env").await;
+
+    let body = to_bytes(app.admin_get("/admin/bans/203.0.113.7").await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let ban: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ban["source"], "local");
+    assert!((3590..=3600).contains(&ban["remaining_secs"].as_u64().unwrap()));
+
+    let patch = |duration: &str| {
+        Request::patch("/admin/bans/203.0.113.7")
+            .header("content-type", "application/json")
+            .body(Body::from(format!(r#"{{"duration": "{}"}}"#, duration)))
+            .unwrap()
+    };
+    let mut events = app.state().events.subscribe();
+    let res = app.admin(patch("7d")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let ban: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(ban["remaining_secs"].as_u64().unwrap() > 6 * 86400);
+    assert!(matches!(
+        events.try_recv(),
+        Ok(tezcatlipoca_auth::events::SecurityEvent::BanUpdated {
+            duration_secs: Some(604800),
+            ..
+        })
+    ));
+
+    let body = to_bytes(app.admin(patch("permanent")).await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let ban: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(ban["expires"].is_null() && ban["remaining_secs"].is_null());
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+
+    assert_eq!(app.admin(patch("soon")).await.status(), StatusCode::BAD_REQUEST);
+    let res = app.admin_get("/admin/bans/198.51.100.1").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
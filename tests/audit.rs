@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::{config::Config, testing::TestApp};
+
+/// Lines of the audit file once it holds `count` records.
+async fn wait_for_records(path: &std::path::Path, count: usize) -> Vec<serde_json::Value> {
+    for _ in 0..100 {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        let records: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if records.len() >= count {
+            return records;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("audit trail never reached {} records", count);
+}
+
+#[tokio::test]
+async fn every_decision_is_recorded_with_a_sequence_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let config = Config {
+        audit_log: Some(path.to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+
+    let app = TestApp::with_config(config.clone(), &["203.0.113.7"]).await;
+    app.get_from("203.0.113.7", "/admin").await;
+    app.get_from("198.51.100.1", "/").await;
+
+    let records = wait_for_records(&path, 2).await;
+    assert_eq!(records[0]["seq"], 1);
+    assert_eq!(records[0]["ip"], "203.0.113.7");
+    assert_eq!(records[0]["path"], "/admin");
+    assert_eq!(records[0]["verdict"], "blocked");
+    assert_eq!(records[0]["reason"], "BANNED");
+    assert_eq!(records[0]["ban"]["source"], "files");
+    assert_eq!(records[0]["enforce"], true);
+    assert_eq!(records[1]["seq"], 2);
+    assert_eq!(records[1]["verdict"], "allowed");
+    assert!(records[1].get("ban").is_none());
+
+    // A restarted service continues the sequence
+    drop(app);
+    let app = TestApp::with_config(config, &[]).await;
+    app.get_from("198.51.100.1", "/").await;
+    let records = wait_for_records(&path, 3).await;
+    assert_eq!(records[2]["seq"], 3);
+}
@@ -0,0 +1,175 @@
+use std::{io::Write, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tempfile::NamedTempFile;
+use tezcatlipoca_auth::{
+    config::{AuthConfig, AuthMode, Config, LdapConfig},
+    ldap::in_groups,
+    session::Signer,
+    testing::TestApp,
+};
+
+async fn basic_auth_app() -> (TestApp, NamedTempFile) {
+    basic_auth_app_with(Config::default(), &[]).await
+}
+
+/// `config` with Basic authentication on `/admin` for alice.
+async fn basic_auth_app_with(config: Config, banned: &[&str]) -> (TestApp, NamedTempFile) {
+    let mut users = NamedTempFile::new().unwrap();
+    let hash = bcrypt::hash("hunter2", 4).unwrap();
+    writeln!(users, "# admins\nalice:{}", hash).unwrap();
+
+    let config = Config {
+        auth: Some(AuthConfig {
+            mode: AuthMode::Basic {
+                users_file: users.path().to_string_lossy().into_owned(),
+            },
+            paths: vec!["/admin".to_string()],
+            realm: "internal".to_string(),
+            session_ttl: Duration::from_secs(3600),
+            totp: None,
+        }),
+        ..config
+    };
+    (TestApp::with_config(config, banned).await, users)
+}
+
+fn request(path: &str, credentials: Option<&str>, cookie: Option<&str>) -> Request<Body> {
+    let mut req = Request::get("/").header("x-forwarded-uri", path);
+    if let Some(credentials) = credentials {
+        let value = format!("Basic {}", STANDARD.encode(credentials));
+        req = req.header(header::AUTHORIZATION, value);
+    }
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn basic_login_issues_a_session_cookie() {
+    let (app, _users) = basic_auth_app().await;
+
+    let res = app.send(request("/admin", None, None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers()[header::WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .starts_with("Basic realm=\"internal\""));
+
+    let res = app.send(request("/admin", Some("alice:wrong"), None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = app.send(request("/admin", Some("mallory:hunter2"), None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = app.send(request("/admin/users", Some("alice:hunter2"), None)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-auth-user"], "alice");
+    let set_cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.starts_with("tez_session="));
+    let cookie = set_cookie.split(';').next().unwrap();
+
+    let res = app.send(request("/admin", None, Some(cookie))).await;
+    assert_eq!(res.status(), StatusCode::OK, "the session cookie replaces credentials");
+    assert_eq!(res.headers()["x-auth-user"], "alice");
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+
+    let res = app.send(request("/blog", None, None)).await;
+    assert_eq!(res.status(), StatusCode::OK, "paths outside AUTH_PATHS are open");
+}
+
+#[tokio::test]
+async fn observed_blocks_still_require_a_login() {
+    let config = Config {
+        enforce: false,
+        ..Config::default()
+    };
+    let (app, _users) = basic_auth_app_with(config, &["203.0.113.7"]).await;
+    let request = |credentials| {
+        let mut req = request("/admin", credentials, None);
+        req.headers_mut().insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        req
+    };
+
+    // Banned, which ENFORCE=false only records, but not logged in
+    let res = app.send(request(None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = app.send(request(Some("alice:hunter2"))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-auth-user"], "alice");
+}
+
+#[test]
+fn rotated_keys_keep_older_tokens_valid() {
+    let old = Signer::with_keys(&[b"old"], false);
+    let token = old.issue("user:alice", Duration::from_secs(60));
+
+    let rotated = Signer::with_keys(&[b"new", b"old"], false);
+    assert_eq!(rotated.subject(&token).as_deref(), Some("user:alice"));
+    let fresh = rotated.issue("user:alice", Duration::from_secs(60));
+    assert!(old.subject(&fresh).is_none(), "new tokens use the newest key");
+
+    let retired = Signer::with_keys(&[b"new"], false);
+    assert!(retired.subject(&token).is_none());
+}
+
+#[test]
+fn encrypted_tokens_hide_their_payload() {
+    let signer = Signer::with_keys(&[b"key"], true);
+    let token = signer.issue("user:alice", Duration::from_secs(60));
+    let (body, _) = token.split_once('.').unwrap();
+    let body = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(body).unwrap();
+    assert!(!String::from_utf8_lossy(&body).contains("alice"));
+    assert_eq!(signer.subject(&token).as_deref(), Some("user:alice"));
+    assert!(Signer::with_keys(&[b"key"], false).subject(&token).is_none());
+}
+
+#[tokio::test]
+async fn ldap_logins_fail_closed_when_the_server_is_unreachable() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let config = Config {
+        auth: Some(AuthConfig {
+            mode: AuthMode::Ldap(LdapConfig {
+                url,
+                starttls: false,
+                bind_dn: "uid={user},ou=people,dc=example,dc=org".to_string(),
+                base_dn: None,
+                user_filter: "(uid={user})".to_string(),
+                groups: vec![],
+                timeout: Duration::from_secs(2),
+            }),
+            paths: vec!["/".to_string()],
+            realm: "internal".to_string(),
+            session_ttl: Duration::from_secs(3600),
+            totp: None,
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.send(request("/", Some("alice:hunter2"), None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = app.send(request("/", Some("alice:"), None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "empty passwords are never sent");
+}
+
+#[test]
+fn ldap_groups_match_by_common_name() {
+    let member_of = || {
+        vec![
+            "CN=Domain Users,CN=Users,DC=corp,DC=example".to_string(),
+            "cn=ops,ou=groups,dc=example,dc=org".to_string(),
+        ]
+    };
+    assert!(in_groups(member_of(), &["OPS".to_string()]));
+    assert!(in_groups(member_of(), &["admins".to_string(), "domain users".to_string()]));
+    assert!(!in_groups(member_of(), &["groups".to_string()]));
+    assert!(!in_groups(member_of(), &[]));
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::bans::{export_bans, parse_bans, save_history, BanFormat, DynamicBans};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[test]
+fn repeat_offenses_escalate_and_stay_on_the_last_step() {
+    let bans = DynamicBans::with_escalation(
+        vec![Some(HOUR), Some(6 * HOUR), None],
+        Duration::from_secs(30 * 86400),
+    );
+
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), Some(HOUR));
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), Some(6 * HOUR));
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), None);
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), None);
+    assert_eq!(bans.offenses("203.0.113.7"), 4);
+    assert!(bans.contains("203.0.113.7"));
+    let detail = bans.detail("203.0.113.7").unwrap();
+    assert_eq!((detail.source.as_str(), detail.reason.as_deref()), ("local", Some("honeypot")));
+
+    assert_eq!(
+        bans.offend("198.51.100.1", Some(HOUR), "honeypot"),
+        Some(HOUR),
+        "counts are per IP"
+    );
+}
+
+#[test]
+fn without_escalation_the_default_duration_applies() {
+    let bans = DynamicBans::new();
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), Some(HOUR));
+    assert_eq!(bans.offend("203.0.113.7", Some(HOUR), "honeypot"), Some(HOUR));
+}
+
+#[tokio::test]
+async fn history_survives_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bans.json");
+    let path = path.to_str().unwrap();
+
+    let steps = vec![Some(HOUR), None];
+    let before = DynamicBans::with_escalation(steps.clone(), Duration::from_secs(86400));
+    before.offend("203.0.113.7", None, "honeypot");
+    before.offend("203.0.113.7", None, "honeypot");
+    save_history(path, &before.history()).await.unwrap();
+
+    let content = std::fs::read_to_string(path).unwrap();
+    let after = DynamicBans::with_escalation(steps, Duration::from_secs(86400));
+    after.restore(serde_json::from_str(&content).unwrap());
+    assert!(after.contains("203.0.113.7"), "permanent ban is restored");
+    assert_eq!(after.offenses("203.0.113.7"), 2);
+}
+
+#[tokio::test]
+async fn lifted_bans_stay_lifted_after_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bans.json");
+    let path = path.to_str().unwrap();
+
+    let steps = vec![Some(HOUR), None];
+    let before = DynamicBans::with_escalation(steps.clone(), Duration::from_secs(86400));
+    before.offend("203.0.113.7", None, "honeypot");
+    before.offend("203.0.113.7", None, "honeypot");
+    before.offend("198.51.100.1", None, "honeypot");
+    assert!(before.unban("203.0.113.7"), "permanent ban");
+    assert!(before.unban("198.51.100.1"), "temporary ban");
+    save_history(path, &before.history()).await.unwrap();
+
+    let content = std::fs::read_to_string(path).unwrap();
+    let after = DynamicBans::with_escalation(steps, Duration::from_secs(86400));
+    after.restore(serde_json::from_str(&content).unwrap());
+    assert!(!after.contains("203.0.113.7"));
+    assert!(!after.contains("198.51.100.1"));
+    assert_eq!(after.offenses("203.0.113.7"), 2, "offenses still count");
+}
+
+#[test]
+fn exports_round_trip_through_import() {
+    let bans = DynamicBans::new();
+    bans.ban("203.0.113.7", Some(HOUR), "honeypot, repeated", "local");
+    bans.ban("198.51.100.1", None, "manual", "import");
+
+    for format in [BanFormat::Csv, BanFormat::Json] {
+        let exported = export_bans(&bans.active(), format);
+        let (parsed, errors) = parse_bans(&exported, format);
+        assert!(errors.is_empty(), "{format}: {errors:?}");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].ip, "198.51.100.1");
+        assert_eq!(parsed[0].expires, None);
+        assert_eq!(parsed[1].reason, "honeypot, repeated");
+        assert_eq!(parsed[1].source, "local", "{format}");
+        assert!(parsed[1].expires.is_some());
+
+        let restored = DynamicBans::new();
+        assert_eq!(restored.import(&parsed), 2);
+        assert!(restored.contains("203.0.113.7"));
+        assert!(restored.contains("198.51.100.1"));
+    }
+}
+
+#[test]
+fn imports_report_bad_records_and_skip_expired_bans() {
+    let csv = "ip,reason,expires\n\
+               203.0.113.7,ssh brute force,2099-01-01T00:00:00Z,fail2ban\n\
+               203.0.113.8,,1\n\
+               203.0.113.0/24,spam,\n\
+               not-an-ip\n\
+               ::ffff:192.0.2.1\n";
+    let (parsed, errors) = parse_bans(csv, BanFormat::Csv);
+    assert_eq!(parsed.len(), 3);
+    assert_eq!(parsed[0].source, "fail2ban");
+    assert_eq!(parsed[1].reason, "imported");
+    assert_eq!(parsed[1].source, "import");
+    assert_eq!(parsed[2].ip, "192.0.2.1", "mapped addresses are normalized");
+    let lines: Vec<usize> = errors.iter().map(|e| e.record).collect();
+    assert_eq!(lines, [4, 5]);
+
+    let bans = DynamicBans::new();
+    assert_eq!(bans.import(&parsed), 2, "the 1970 ban has expired");
+    assert!(!bans.contains("203.0.113.8"));
+
+    let (_, errors) = parse_bans(r#"{"ip": "203.0.113.7"}"#, BanFormat::Json);
+    assert_eq!(errors[0].record, 0);
+    let json = r#"[{"ip": "203.0.113.7", "expires": 4102444800}, {}]"#;
+    let (parsed, errors) = parse_bans(json, BanFormat::Json);
+    assert_eq!(parsed.len(), 1);
+    assert_eq!(errors[0].record, 2);
+}
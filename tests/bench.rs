@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::{bench, build_router, server, testing::TestApp};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn reports_every_answer_from_random_client_ips() {
+    // Half of the address space, so random clients land on both sides
+    let app = TestApp::new(&["0.0.0.0/1"]).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = app.state().clone();
+    tokio::spawn(async move {
+        server::serve(listener, build_router(state.clone()), &state.config).await
+    });
+
+    let options = bench::Options {
+        target: format!("http://{}", addr),
+        rps: 200,
+        duration: Duration::from_millis(500),
+        ips: 100,
+        ..bench::Options::default()
+    };
+    let report = bench::run(&options).await.unwrap();
+
+    assert!(report.sent >= 50, "{}", report);
+    assert_eq!(report.errors, 0, "{}", report);
+    assert_eq!(report.statuses.values().sum::<u64>(), report.sent);
+    assert!(report.statuses.contains_key(&200), "{}", report);
+    assert!(report.statuses.contains_key(&403), "{}", report);
+    assert_eq!(report.latency.count, report.sent);
+    assert!(report.latency.p50_us > 0);
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+};
+use tezcatlipoca_auth::{
+    bot_score::{self, DEFAULT_USER_AGENTS},
+    config::{BotScoreConfig, ChallengeConfig, ChallengeMode, Config, CHALLENGE_PATH},
+    patterns::PatternSet,
+    testing::TestApp,
+};
+
+const BROWSER: &[(&str, &str)] = &[
+    ("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"),
+    ("accept", "text/html"),
+    ("accept-language", "en-US"),
+];
+
+fn bot_config() -> BotScoreConfig {
+    BotScoreConfig {
+        user_agents: PatternSet::parse(DEFAULT_USER_AGENTS).unwrap(),
+        protocol_header: Some("x-client-protocol".parse().unwrap()),
+        header_order_header: Some("x-client-header-order".parse().unwrap()),
+        header_orders: PatternSet::parse("^user-agent,accept-encoding,accept,").unwrap(),
+        ..BotScoreConfig::default()
+    }
+}
+
+fn request(ip: &str, pairs: &[(&str, &str)]) -> Request<Body> {
+    let mut req = Request::get("/").header("x-forwarded-for", ip);
+    for &(name, value) in pairs {
+        req = req.header(name, value);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[test]
+fn signals_add_up() {
+    let config = bot_config();
+    let score = |pairs: &[(&str, &str)]| {
+        let headers: HeaderMap = request("192.0.2.1", pairs).headers().clone();
+        bot_score::score(&config, &headers)
+    };
+
+    assert_eq!(score(BROWSER).points, 0);
+    let curl = score(&[("user-agent", "curl/8.5.0"), ("accept", "*/*")]);
+    assert_eq!(curl.points, 60);
+    assert_eq!(curl.signals, ["suspicious user agent", "no accept-language"]);
+    assert_eq!(score(&[]).points, 70);
+
+    let hinted = [
+        BROWSER,
+        &[
+            ("x-client-protocol", "HTTP/1.0"),
+            ("x-client-header-order", "User-Agent,Accept-Encoding,Accept,Connection"),
+        ],
+    ]
+    .concat();
+    assert_eq!(score(&hinted).points, 60);
+}
+
+#[tokio::test]
+async fn bot_scores_challenge_and_ban() {
+    let config = Config {
+        challenge: Some(ChallengeConfig {
+            mode: ChallengeMode::ProofOfWork { difficulty: 4 },
+            threshold: None,
+            cookie_ttl: Duration::from_secs(3600),
+            path: CHALLENGE_PATH.to_string(),
+        }),
+        bot_score: Some(BotScoreConfig {
+            challenge_threshold: Some(40),
+            ban_threshold: Some(70),
+            ..bot_config()
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    // Browsers pass without a challenge, scripts get one
+    assert_eq!(app.send(request("192.0.2.1", BROWSER)).await.status(), StatusCode::OK);
+    let curl = [("user-agent", "curl/8.5.0"), ("accept", "*/*")];
+    let res = app.send(request("192.0.2.2", &curl)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(app.state().dynamic_bans.is_empty());
+
+    // Bare requests score enough for a ban
+    assert_eq!(app.send(request("192.0.2.3", &[])).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.send(request("192.0.2.3", BROWSER)).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.state().dynamic_bans.len(), 1);
+}
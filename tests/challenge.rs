@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    routing::post,
+    Form, Json, Router,
+};
+use serde_json::json;
+use tezcatlipoca_auth::{
+    challenge::pow_solves,
+    config::{CaptchaProvider, ChallengeConfig, ChallengeMode, Config, CHALLENGE_PATH},
+    session::Signer,
+    testing::TestApp,
+};
+use tokio::net::TcpListener;
+
+/// Fake `siteverify` endpoint accepting only the response `"good"`.
+async fn spawn_siteverify() -> String {
+    let app = Router::new().route(
+        "/siteverify",
+        post(|Form(form): Form<Vec<(String, String)>>| async move {
+            let ok = form.iter().any(|(k, v)| k == "secret" && v == "secret-key")
+                && form.iter().any(|(k, v)| k == "response" && v == "good");
+            Json(json!({ "success": ok }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/siteverify", addr)
+}
+
+async fn challenge_app(verify_url: String) -> TestApp {
+    challenge_app_with(ChallengeMode::Captcha {
+        provider: CaptchaProvider::Turnstile,
+        site_key: "site-key".to_string(),
+        secret_key: "secret-key".to_string(),
+        verify_url,
+    })
+    .await
+}
+
+async fn challenge_app_with(mode: ChallengeMode) -> TestApp {
+    let config = Config {
+        challenge: Some(ChallengeConfig {
+            mode,
+            threshold: None,
+            cookie_ttl: Duration::from_secs(3600),
+            path: CHALLENGE_PATH.to_string(),
+        }),
+        ..Config::default()
+    };
+    TestApp::with_config(config, &["203.0.113.7"]).await
+}
+
+async fn body_text(res: axum::response::Response) -> String {
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+fn request(ip: &str, uri: &str, cookie: Option<&str>) -> Request<Body> {
+    let mut req = Request::get("/").header("x-forwarded-for", ip).header("x-forwarded-uri", uri);
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[test]
+fn signed_tokens_are_bound_to_subject_and_key() {
+    let signer = Signer::new(b"key");
+    let token = signer.issue("198.51.100.1", Duration::from_secs(60));
+    assert!(signer.validate(&token, "198.51.100.1"));
+    assert!(!signer.validate(&token, "198.51.100.2"));
+    assert!(!Signer::new(b"other").validate(&token, "198.51.100.1"));
+
+    let (payload, mac) = token.split_once('.').unwrap();
+    let tampered = format!("{}A.{}", payload, mac);
+    assert!(!signer.validate(&tampered, "198.51.100.1"));
+
+    let expired = signer.issue("198.51.100.1", Duration::ZERO);
+    assert!(!signer.validate(&expired, "198.51.100.1"));
+}
+
+#[tokio::test]
+async fn suspicious_clients_get_the_challenge_page() {
+    let app = challenge_app(spawn_siteverify().await).await;
+
+    let res = app.send(request("198.51.100.1", "/app?x=1", None)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(res.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    let body = body_text(res).await;
+    assert!(body.contains(r#"data-sitekey="site-key""#));
+    assert!(body.contains(r#"name="return" value="/app""#));
+
+    assert_eq!(
+        app.get_from("203.0.113.7", "/").await.status(),
+        StatusCode::FORBIDDEN,
+        "banned IPs are blocked, not challenged"
+    );
+}
+
+#[tokio::test]
+async fn solving_the_challenge_sets_a_bypass_cookie() {
+    let app = challenge_app(spawn_siteverify().await).await;
+    let ip = "198.51.100.1";
+
+    let uri = format!("{}?return=%2Fapp&cf-turnstile-response=good", CHALLENGE_PATH);
+    let res = app.send(request(ip, &uri, None)).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()[header::LOCATION], "/app");
+    let set_cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    let cookie = set_cookie.split(';').next().unwrap();
+
+    assert_eq!(app.send(request(ip, "/app", Some(cookie))).await.status(), StatusCode::OK);
+    assert_eq!(
+        app.send(request("198.51.100.2", "/app", Some(cookie))).await.status(),
+        StatusCode::FORBIDDEN,
+        "the cookie is bound to the IP that solved the challenge"
+    );
+    assert_eq!(
+        app.send(request("203.0.113.7", "/app", Some(cookie))).await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn invalid_responses_and_foreign_redirects_are_refused() {
+    let app = challenge_app(spawn_siteverify().await).await;
+
+    let uri = format!("{}?return=%2Fapp&cf-turnstile-response=bad", CHALLENGE_PATH);
+    let res = app.send(request("198.51.100.1", &uri, None)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+
+    let uri = format!("{}?return=%2F%2Fevil.example&cf-turnstile-response=good", CHALLENGE_PATH);
+    let res = app.send(request("198.51.100.1", &uri, None)).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()[header::LOCATION], "/");
+}
+
+#[tokio::test]
+async fn proof_of_work_solutions_are_verified_locally() {
+    let app = challenge_app_with(ChallengeMode::ProofOfWork { difficulty: 8 }).await;
+    let ip = "198.51.100.1";
+
+    let page = body_text(app.send(request(ip, "/app", None)).await).await;
+    let token = page
+        .split(r#"name="challenge" value=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("challenge token in page");
+    let nonce = (0u64..)
+        .map(|n| n.to_string())
+        .find(|n| pow_solves(token, n, 8))
+        .unwrap();
+    let wrong = (0u64..)
+        .map(|n| n.to_string())
+        .find(|n| !pow_solves(token, n, 8))
+        .unwrap();
+
+    let submit = |ip: &str, nonce: &str| {
+        let uri = format!("{}?return=%2Fapp&challenge={}&nonce={}", CHALLENGE_PATH, token, nonce);
+        request(ip, &uri, None)
+    };
+    assert_eq!(app.send(submit(ip, &wrong)).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        app.send(submit("198.51.100.2", &nonce)).await.status(),
+        StatusCode::FORBIDDEN,
+        "challenges are bound to the IP they were issued to"
+    );
+
+    let res = app.send(submit(ip, &nonce)).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    let set_cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = set_cookie.split(';').next().unwrap();
+    assert_eq!(app.send(request(ip, "/app", Some(cookie))).await.status(), StatusCode::OK);
+
+    let challenge_as_cookie = format!("tez_challenge={}", token);
+    assert_eq!(
+        app.send(request(ip, "/app", Some(&challenge_as_cookie))).await.status(),
+        StatusCode::FORBIDDEN,
+        "a challenge token is not a bypass cookie"
+    );
+}
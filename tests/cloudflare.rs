@@ -0,0 +1,99 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use tezcatlipoca_auth::{
+    cloudflare,
+    config::{Config, IpSource},
+    testing::TestApp,
+};
+use tokio::net::TcpListener;
+
+/// Fake lists: a new IPv4 range, an IPv6 one, and one with no range.
+async fn spawn_lists() -> String {
+    let app = Router::new()
+        .route("/ips-v4", get(|| async { "203.0.113.0/24\nnot a range\n" }))
+        .route("/ips-v6", get(|| async { "2001:db8::/32\n" }))
+        .route("/empty", get(|| async { "<html>maintenance</html>" }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn through_edge(edge: &str) -> Request<Body> {
+    Request::get("/")
+        .header("cf-connecting-ip", "192.0.2.1")
+        .header("x-forwarded-for", format!("198.51.100.1, {}", edge))
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn refreshed_ranges_replace_the_configured_ones() {
+    let base = spawn_lists().await;
+    let config = Config {
+        cloudflare_ips_urls: vec![format!("{}/ips-v4", base), format!("{}/ips-v6", base)],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["192.0.2.1"]).await;
+    let state = app.state();
+
+    assert_eq!(app.send(through_edge("173.245.48.1")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.send(through_edge("203.0.113.9")).await.status(), StatusCode::OK);
+
+    assert_eq!(cloudflare::parse_ranges("203.0.113.0/24\nnot a range\n").len(), 1);
+    assert_eq!(cloudflare::refresh(state).await.unwrap(), 2);
+    assert_eq!(app.send(through_edge("173.245.48.1")).await.status(), StatusCode::OK);
+    assert_eq!(app.send(through_edge("203.0.113.9")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.send(through_edge("2001:db8::1")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn failed_refreshes_keep_the_current_ranges() {
+    let base = spawn_lists().await;
+    let config = Config {
+        cloudflare_ips_urls: vec![format!("{}/ips-v4", base), format!("{}/empty", base)],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["192.0.2.1"]).await;
+    let state = app.state();
+    let before = state.cloudflare_ips.read().unwrap().len();
+
+    assert!(cloudflare::refresh(state).await.is_err());
+    let mut config = state.config.clone();
+    config.cloudflare_ips_urls = vec![format!("{}/missing", base)];
+    let other = TestApp::with_config(config, &[]).await;
+    assert!(cloudflare::refresh(other.state()).await.is_err());
+
+    assert_eq!(state.cloudflare_ips.read().unwrap().len(), before);
+    assert_eq!(app.send(through_edge("173.245.48.1")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn forwarded_edges_only_count_from_trusted_proxies() {
+    let config = Config {
+        client_ip_sources: vec![IpSource::Header("cf-connecting-ip".to_string()), IpSource::Socket],
+        trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["198.51.100.66"]).await;
+    let forged = || {
+        Request::get("/")
+            .header("cf-connecting-ip", "192.0.2.50")
+            .header("x-forwarded-for", "173.245.48.1")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // A client connecting directly can't claim to be behind Cloudflare
+    let direct = "198.51.100.66:40000".parse().unwrap();
+    assert_eq!(app.send_from_peer(direct, forged()).await.status(), StatusCode::FORBIDDEN);
+
+    let proxy = "10.0.0.5:40000".parse().unwrap();
+    assert_eq!(app.send_from_peer(proxy, forged()).await.status(), StatusCode::OK);
+    let local = "127.0.0.1:40000".parse().unwrap();
+    assert_eq!(app.send_from_peer(local, forged()).await.status(), StatusCode::OK);
+}
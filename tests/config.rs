@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use tezcatlipoca_auth::config::{suggest, Categories, CategoryRule, Config, Listen, Routes};
+
+#[test]
+fn variables_come_from_prefixed_names_and_files() {
+    let mut token = tempfile::NamedTempFile::new().unwrap();
+    writeln!(token, "s3cret-token").unwrap();
+    let mut password = tempfile::NamedTempFile::new().unwrap();
+    write!(password, "p@ss").unwrap();
+
+    // SAFETY: the only test in this binary touching the environment
+    unsafe {
+        std::env::set_var("CONFIG_VALIDATION", "warn");
+        std::env::set_var("TEZ_CACHE_TTL_SECS", "30");
+        std::env::set_var("CACHE_TTL_SECS", "10");
+        std::env::set_var("TEZ_HONEYPOT_PATHS", "/.env");
+        std::env::set_var("TEZ_CACHE_TTL_SEC", "60");
+        std::env::set_var("TEZ_NOT_A_SETTING", "1");
+        std::env::set_var("TEZ_ADMIN_TOKEN_FILE", token.path());
+        std::env::set_var("REDIS_URL", "redis://redis:6379/0");
+        std::env::set_var("REDIS_PASSWORD_FILE", password.path());
+        std::env::set_var("COOKIE_SECRET", "0123456789abcdef0123456789abcdef");
+        std::env::set_var("COOKIE_SECRET_FILE", "/run/secrets/cookie");
+        std::env::set_var("OPA_URL", "http://opa:8181/v1/data/tezcatlipoca/allow");
+    }
+
+    let config = Config::from_env().unwrap();
+    assert_eq!(config.cache_ttl.as_secs(), 30);
+    assert_eq!(config.honeypot_paths, ["/.env"]);
+    assert_eq!(config.unknown_vars, ["TEZ_CACHE_TTL_SEC", "TEZ_NOT_A_SETTING"]);
+    assert_eq!(config.admin_token.as_deref(), Some("s3cret-token"));
+    assert_eq!(config.redis.unwrap().url, "redis://:p%40ss@redis:6379/0");
+    assert!(config.opa.unwrap().fail_open, "follows FAIL_MODE=open");
+    let issues: Vec<_> = config.validation_warnings.iter().map(|i| i.var.as_str()).collect();
+    assert_eq!(issues, ["COOKIE_SECRET_FILE"], "setting a variable and its file is ambiguous");
+}
+
+#[test]
+fn misspelled_variables_get_a_suggestion() {
+    assert_eq!(suggest("TEZ_CACHE_TTL_SEC").as_deref(), Some("TEZ_CACHE_TTL_SECS"));
+    assert_eq!(suggest("TEZ_ENFORCED").as_deref(), Some("TEZ_ENFORCE"));
+    assert_eq!(suggest("TEZ_ADMIN_TOKN_FILE").as_deref(), Some("TEZ_ADMIN_TOKEN_FILE"));
+    assert_eq!(suggest("TEZ_NOT_A_SETTING"), None);
+}
+
+#[test]
+fn listeners_are_parsed() {
+    assert_eq!(
+        Listen::parse("127.0.0.1:8200=admin"),
+        Some(Listen {
+            addr: "127.0.0.1:8200".to_string(),
+            routes: Routes::Admin,
+        })
+    );
+    assert_eq!(Listen::parse(" [::]:8199 ").map(|l| l.routes), Some(Routes::All));
+    assert!(Listen::parse("0.0.0.0:8199=metrics").is_none());
+    assert!(Listen::parse("0.0.0.0").is_none());
+    assert!(Listen::parse(":8199").is_none());
+}
+
+#[test]
+fn category_rules_are_parsed() {
+    let rule = CategoryRule::parse("Blog.Example.com/admin = scanner, Tor").unwrap();
+    assert_eq!(rule.host.as_deref(), Some("blog.example.com"));
+    assert_eq!(rule.path, "/admin");
+    assert_eq!(rule.categories, Categories::Only(vec!["scanner".into(), "tor".into()]));
+    assert!(rule.matches(Some("blog.example.com"), "/admin/users"));
+    assert!(!rule.matches(Some("blog.example.com"), "/administrator"));
+    assert!(!rule.matches(None, "/admin"));
+
+    let rule = CategoryRule::parse("/=-tor,-geo").unwrap();
+    assert_eq!(rule.host, None);
+    assert!(rule.categories.enforces("scanner"));
+    assert!(!rule.categories.enforces("tor"));
+    assert_eq!(rule.categories.to_string(), "-tor,-geo");
+    assert_eq!(CategoryRule::parse("example.com=*").unwrap().categories, Categories::All);
+
+    assert!(CategoryRule::parse("example.com").is_none());
+    assert!(CategoryRule::parse("/admin=tor,-geo").is_none(), "mixed forms");
+    assert!(CategoryRule::parse("/admin=not a name").is_none());
+}
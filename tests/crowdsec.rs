@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use axum::{http::HeaderMap, routing::get, Json, Router};
+use serde_json::json;
+use tezcatlipoca_auth::{config::CrowdSecConfig, crowdsec, testing::TestApp};
+use tokio::net::TcpListener;
+
+/// Minimal Local API that serves one ban decision to the right bouncer key.
+async fn spawn_fake_lapi() -> String {
+    let app = Router::new().route(
+        "/v1/decisions/stream",
+        get(|headers: HeaderMap| async move {
+            assert_eq!(headers["x-api-key"], "secret");
+            Json(json!({
+                "new": [
+                    { "scope": "Ip", "value": "203.0.113.50", "type": "ban" },
+                    { "scope": "Ip", "value": "203.0.113.51", "type": "captcha" },
+                ],
+                "deleted": null,
+            }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+#[tokio::test]
+async fn ban_decisions_are_merged_into_the_cache() {
+    let app = TestApp::new(&[]).await;
+    let config = CrowdSecConfig {
+        lapi_url: spawn_fake_lapi().await,
+        api_key: "secret".to_string(),
+        poll_interval: Duration::from_secs(60),
+        machine_id: None,
+        machine_password: None,
+    };
+    tokio::spawn(crowdsec::decision_stream_task(app.state().clone(), config));
+
+    for _ in 0..100 {
+        if !app.state().banned_ips.read().await.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let cache = app.state().banned_ips.read().await;
+    assert!(cache.contains("203.0.113.50"));
+    assert!(!cache.contains("203.0.113.51"), "captcha decisions are not bans");
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use axum::{body::to_bytes, http::StatusCode};
+use tezcatlipoca_auth::{
+    config::Config,
+    decision_cache::{Decision, DecisionCache},
+    testing::TestApp,
+};
+
+#[tokio::test]
+async fn decisions_expire_and_follow_list_versions() {
+    assert!(DecisionCache::new(0, Duration::from_secs(1)).is_none());
+
+    let cache = DecisionCache::new(2, Duration::from_millis(50)).unwrap();
+    let ip = "203.0.113.7".parse().unwrap();
+    let blocked = Decision {
+        reason: Some("BANNED".to_string()),
+        ban: None,
+    };
+    cache.insert(ip, "GET /", (1, 1), blocked.clone());
+    assert_eq!(cache.get(ip, "GET /", (1, 1)), Some(blocked.clone()));
+    assert_eq!(cache.get(ip, "GET /other", (1, 1)), None);
+    assert_eq!(cache.get(ip, "GET /", (1, 2)), None);
+    assert!(cache.is_empty());
+
+    cache.insert(ip, "GET /", (1, 1), blocked);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(cache.get(ip, "GET /", (1, 1)), None);
+}
+
+#[tokio::test]
+async fn cached_decisions_are_dropped_when_bans_change() {
+    let config = Config {
+        decision_cache_size: 100,
+        decision_cache_ttl: Duration::from_secs(60),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    let state = app.state();
+
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+
+    app.set_banned(&["203.0.113.7"]).await;
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+    app.set_banned(&[]).await;
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+
+    state.dynamic_bans.ban("203.0.113.7", None, "test", "local");
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+    state.dynamic_bans.unban("203.0.113.7");
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+
+    let body = to_bytes(app.get("/health").await.into_body(), usize::MAX).await.unwrap();
+    let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(health["decision_cache_hits"], 2);
+}
@@ -0,0 +1,120 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::http::StatusCode;
+use tezcatlipoca_auth::{
+    config::{Config, DnsblConfig, FailMode},
+    dnsbl::{lookup_task, query_name},
+    metrics::Metrics,
+    testing::TestApp,
+};
+use tokio::net::UdpSocket;
+
+/// Name server answering `127.0.0.2` for names under `listed` and NXDOMAIN
+/// for everything else.
+async fn spawn_zone(listed: &'static str) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            let query = &buf[..len];
+            // Question: labels up to the root, then type and class
+            let mut end = 12;
+            let mut name = Vec::new();
+            while query[end] != 0 {
+                let label = usize::from(query[end]);
+                name.push(String::from_utf8_lossy(&query[end + 1..end + 1 + label]).into_owned());
+                end += 1 + label;
+            }
+            end += 5;
+            let hit = format!("{}.", name.join(".")).starts_with(listed);
+
+            let mut answer = query[..2].to_vec();
+            answer.extend_from_slice(if hit { &[0x81, 0x80] } else { &[0x81, 0x83] });
+            answer.extend_from_slice(&[0, 1, 0, u8::from(hit), 0, 0, 0, 0]);
+            answer.extend_from_slice(&query[12..end]);
+            if hit {
+                answer.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 2]);
+            }
+            let _ = socket.send_to(&answer, peer).await;
+        }
+    });
+    addr
+}
+
+#[test]
+fn query_names_reverse_the_address() {
+    let v4 = query_name("203.0.113.7".parse().unwrap(), "zen.spamhaus.org");
+    assert_eq!(v4, "7.113.0.203.zen.spamhaus.org.");
+    let mapped = query_name("::ffff:203.0.113.7".parse().unwrap(), "bl.example");
+    assert_eq!(mapped, "7.113.0.203.bl.example.");
+    let v6 = query_name("2001:db8::1".parse().unwrap(), "bl.example");
+    assert_eq!(
+        v6,
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.bl.example."
+    );
+}
+
+#[tokio::test]
+async fn listed_ips_are_blocked_once_looked_up() {
+    let resolver = spawn_zone("4.3.2.1.bl.test.").await;
+    let config = Config {
+        dnsbl: Some(DnsblConfig {
+            zones: vec!["bl.test".to_string()],
+            resolver: Some(resolver),
+            timeout: Duration::from_secs(2),
+            cache_ttl: Duration::from_secs(3600),
+            max_cached: 100,
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    tokio::spawn(lookup_task(app.state().clone()));
+
+    // Unknown IPs pass until their lookup is done
+    assert_eq!(app.get_from("1.2.3.4", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("5.6.7.8", "/").await.status(), StatusCode::OK);
+    let dnsbl = app.state().dnsbl.clone().unwrap();
+    for _ in 0..100 {
+        if dnsbl.cached_count() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(app.get_from("1.2.3.4", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("5.6.7.8", "/").await.status(), StatusCode::OK);
+    assert_eq!(Metrics::get(&app.state().metrics.dnsbl_lookups), 2);
+    assert_eq!(dnsbl.listing("1.2.3.4").as_deref(), Some("bl.test"));
+}
+
+#[tokio::test]
+async fn failed_lookups_block_when_failing_closed() {
+    // A name server that never answers
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = Config {
+        dnsbl: Some(DnsblConfig {
+            zones: vec!["bl.test".to_string()],
+            resolver: Some(silent.local_addr().unwrap()),
+            timeout: Duration::from_millis(100),
+            cache_ttl: Duration::from_secs(3600),
+            max_cached: 100,
+        }),
+        fail_mode: FailMode::Closed,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    tokio::spawn(lookup_task(app.state().clone()));
+
+    assert_eq!(app.get_from("1.2.3.4", "/").await.status(), StatusCode::OK);
+    let dnsbl = app.state().dnsbl.clone().unwrap();
+    for _ in 0..100 {
+        if dnsbl.failed("1.2.3.4") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert!(dnsbl.failed("1.2.3.4"));
+    assert_eq!(app.get_from("1.2.3.4", "/").await.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,71 @@
+use tezcatlipoca_auth::{
+    config::{Config, FeedConfig},
+    doctor::{self, Status},
+};
+
+#[tokio::test]
+async fn reports_what_would_keep_the_service_from_working() {
+    let dir = tempfile::tempdir().unwrap();
+    let banned = dir.path().join("banned.txt");
+    std::fs::write(&banned, "203.0.113.7\n198.51.100.0/24\nnot-an-ip\n").unwrap();
+    let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+    let config = Config {
+        banned_ips_files: vec![path("banned.txt")],
+        log_dir: path("logs"),
+        geoip_country_db: Some(path("banned.txt")),
+        ban_history_file: Some(path("missing/history.json")),
+        feeds: vec![FeedConfig {
+            name: "tor".to_string(),
+            url: "ftp://example.com/tor.txt".to_string(),
+            refresh: std::time::Duration::from_secs(3600),
+        }],
+        ..Config::default()
+    };
+
+    let report = doctor::check(&config).await;
+    let status = |subject: &str| {
+        report
+            .checks
+            .iter()
+            .find(|c| c.subject == subject)
+            .map(|c| c.status)
+    };
+    assert_eq!(status("BANNED_IPS_FILE"), Some(Status::Warning));
+    assert!(
+        report.checks[0]
+            .detail
+            .ends_with("2 entries, 1 malformed line(s) skipped")
+    );
+    assert_eq!(status("LOG_DIR"), Some(Status::Ok));
+    assert_eq!(status("TRUSTED_PROXIES"), Some(Status::Warning));
+    assert_eq!(status("GEOIP_COUNTRY_DB"), Some(Status::Failed));
+    assert_eq!(status("BAN_HISTORY_FILE"), Some(Status::Failed));
+    assert_eq!(status("feed 'tor'"), Some(Status::Failed));
+    assert!(!report.passed());
+    assert!(
+        report
+            .to_string()
+            .ends_with("3 failed, 2 warnings: not ready\n")
+    );
+}
+
+#[tokio::test]
+async fn passes_a_working_configuration() {
+    let dir = tempfile::tempdir().unwrap();
+    let banned = dir.path().join("banned.txt");
+    std::fs::write(&banned, "203.0.113.7\n").unwrap();
+    let config = Config {
+        banned_ips_files: vec![banned.to_string_lossy().into_owned()],
+        log_dir: dir.path().to_string_lossy().into_owned(),
+        trusted_proxies: vec!["172.16.0.0/12".parse().unwrap()],
+        ..Config::default()
+    };
+
+    let report = doctor::check(&config).await;
+    assert!(report.passed(), "{}", report);
+    assert!(
+        report.checks.iter().all(|c| c.status == Status::Ok),
+        "{}",
+        report
+    );
+}
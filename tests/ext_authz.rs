@@ -0,0 +1,65 @@
+#![cfg(feature = "ext-authz")]
+
+use std::collections::HashMap;
+
+use tezcatlipoca_auth::{
+    ext_authz::{
+        authorize,
+        proto::{
+            Address, AttributeContext, CheckRequest, HttpRequest, HttpResponse, Peer, Request,
+            SocketAddress,
+        },
+    },
+    testing::TestApp,
+};
+
+fn check_request(source: &str, headers: &[(&str, &str)], path: &str) -> CheckRequest {
+    CheckRequest {
+        attributes: Some(AttributeContext {
+            source: Some(Peer {
+                address: Some(Address {
+                    socket_address: Some(SocketAddress {
+                        address: source.to_string(),
+                        port_value: 50000,
+                    }),
+                }),
+            }),
+            request: Some(Request {
+                http: Some(HttpRequest {
+                    method: "GET".to_string(),
+                    headers: headers
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect::<HashMap<_, _>>(),
+                    path: path.to_string(),
+                    host: "example.com".to_string(),
+                }),
+            }),
+        }),
+    }
+}
+
+#[tokio::test]
+async fn banned_source_is_denied_with_403() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    let res = authorize(app.state(), check_request("203.0.113.7", &[], "/login?x=1")).await;
+    assert_eq!(res.status.unwrap().code, 7);
+    let Some(HttpResponse::Denied(denied)) = res.http_response else {
+        panic!("expected a denied response");
+    };
+    assert_eq!(denied.status.unwrap().code, 403);
+}
+
+#[tokio::test]
+async fn forwarded_header_takes_priority_over_the_source() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    let req = check_request("10.0.0.5", &[("x-forwarded-for", "198.51.100.1")], "/");
+    let res = authorize(app.state(), req).await;
+    assert_eq!(res.status.unwrap().code, 0);
+    assert!(matches!(res.http_response, Some(HttpResponse::Ok(_))));
+
+    let req = check_request("10.0.0.5", &[("x-forwarded-for", "203.0.113.7")], "/");
+    assert_eq!(authorize(app.state(), req).await.status.unwrap().code, 7);
+}
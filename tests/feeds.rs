@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use object_store::{memory::InMemory, path::Path, ObjectStore, PutPayload};
+use tezcatlipoca_auth::{
+    feeds::{fetch, Fetched, Validators},
+    storage::ObjectList,
+};
+use tokio::net::TcpListener;
+
+/// Serves a small list with an ETag and honours `If-None-Match`.
+async fn spawn_list_server() -> String {
+    let app = Router::new().route(
+        "/list.txt",
+        get(|headers: HeaderMap| async move {
+            if headers.get(header::IF_NONE_MATCH).is_some_and(|v| v == "\"v1\"") {
+                return StatusCode::NOT_MODIFIED.into_response();
+            }
+            (
+                [
+                    (header::ETAG, "\"v1\""),
+                    (header::LAST_MODIFIED, "Mon, 01 Jan 2024 00:00:00 GMT"),
+                ],
+                "203.0.113.0/24\n198.51.100.7\n",
+            )
+                .into_response()
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/list.txt", addr)
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_downloaded_again() {
+    let url = spawn_list_server().await;
+    let http = reqwest::Client::new();
+
+    let Fetched::Updated {
+        entries,
+        validators,
+        ..
+    } = fetch(&http, &url, &Validators::default()).await.unwrap()
+    else {
+        panic!("first fetch must download the list");
+    };
+    assert_eq!(entries.len(), 2);
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+    assert!(validators.last_modified.is_some());
+
+    let again = fetch(&http, &url, &validators).await.unwrap();
+    assert!(matches!(again, Fetched::NotModified));
+}
+
+#[tokio::test]
+async fn bucket_lists_are_downloaded_again_only_when_changed() {
+    let store = Arc::new(InMemory::new());
+    let path = Path::from("lists/banned.txt");
+    store
+        .put(&path, PutPayload::from_static(b"203.0.113.0/24\n198.51.100.7\n"))
+        .await
+        .unwrap();
+    let list = ObjectList::new(store.clone(), path.clone());
+
+    let Fetched::Updated {
+        entries,
+        validators,
+        ..
+    } = list.fetch(&Validators::default()).await.unwrap()
+    else {
+        panic!("first fetch must download the object");
+    };
+    assert_eq!(entries.len(), 2);
+    assert!(validators.etag.is_some());
+    assert!(matches!(list.fetch(&validators).await.unwrap(), Fetched::NotModified));
+
+    store.put(&path, PutPayload::from_static(b"192.0.2.1\n")).await.unwrap();
+    let Fetched::Updated { entries, .. } = list.fetch(&validators).await.unwrap() else {
+        panic!("a replaced object must be downloaded again");
+    };
+    assert_eq!(entries.len(), 1);
+}
@@ -0,0 +1,45 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tezcatlipoca_auth::{config::Config, fingerprint, testing::TestApp};
+
+const BOTNET_JA3: &str = "e7d705a3286e19ea42f587b344ee6865";
+
+fn request(ip: &str, header: Option<(&str, &str)>) -> Request<Body> {
+    let mut req = Request::get("/").header("x-forwarded-for", ip);
+    if let Some((name, value)) = header {
+        req = req.header(name, value);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[test]
+fn lists_take_commas_lines_and_comments() {
+    let list = fingerprint::parse_list(
+        "# botnet\nE7D705A3286E19EA42F587B344EE6865, t13d1516h2_8daaf6152771_02713d6af862\n\n",
+    );
+    assert_eq!(list.len(), 2);
+    assert!(list.contains(BOTNET_JA3));
+    assert!(list.contains("t13d1516h2_8daaf6152771_02713d6af862"));
+}
+
+#[tokio::test]
+async fn listed_fingerprints_are_blocked_from_any_ip() {
+    let config = Config {
+        blocked_tls_fingerprints: fingerprint::parse_list(BOTNET_JA3),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    for ip in ["192.0.2.1", "198.51.100.1"] {
+        let res = app.send(request(ip, Some(("x-ja3-hash", BOTNET_JA3)))).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+    let other = Some(("x-ja3-hash", "cd08e31494f9531f560d64c695473da9"));
+    assert_eq!(app.send(request("192.0.2.1", other)).await.status(), StatusCode::OK);
+    assert_eq!(app.send(request("192.0.2.1", None)).await.status(), StatusCode::OK);
+    // Only the configured headers carry fingerprints
+    let elsewhere = Some(("x-fingerprint", BOTNET_JA3));
+    assert_eq!(app.send(request("192.0.2.1", elsewhere)).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,165 @@
+use axum::body::to_bytes;
+use tezcatlipoca_auth::{
+    config::Config,
+    geoip::{Geo, GeoIp},
+    testing::TestApp,
+};
+
+fn string(s: &str) -> Vec<u8> {
+    // Sizes from 29 on take an extra byte
+    let mut out = match s.len() {
+        len @ 0..29 => vec![0x40 | len as u8],
+        len => vec![0x40 | 29, (len - 29) as u8],
+    };
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+fn uint16(n: u16) -> Vec<u8> {
+    let mut out = vec![0xa2];
+    out.extend_from_slice(&n.to_be_bytes());
+    out
+}
+
+fn uint32(n: u32) -> Vec<u8> {
+    let mut out = vec![0xc4];
+    out.extend_from_slice(&n.to_be_bytes());
+    out
+}
+
+fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![0xe0 | entries.len() as u8];
+    for (key, value) in entries {
+        out.extend(string(key));
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+/// IPv4 MaxMind DB with 24-bit records mapping each `a.0.0.0/8` to its record.
+fn mmdb(networks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    // Children of each tree node: another node, or a data section offset
+    #[derive(Clone, Copy)]
+    enum Child {
+        Empty,
+        Node(usize),
+        Data(usize),
+    }
+    let mut nodes = vec![[Child::Empty; 2]];
+    let mut data = Vec::new();
+    for (octet, record) in networks {
+        let mut node = 0;
+        for bit in 0..8 {
+            let side = usize::from((octet >> (7 - bit)) & 1);
+            if bit == 7 {
+                nodes[node][side] = Child::Data(data.len());
+            } else if let Child::Node(next) = nodes[node][side] {
+                node = next;
+            } else {
+                nodes.push([Child::Empty; 2]);
+                nodes[node][side] = Child::Node(nodes.len() - 1);
+                node = nodes.len() - 1;
+            }
+        }
+        data.extend_from_slice(record);
+    }
+
+    let count = nodes.len();
+    let mut db = Vec::new();
+    for children in &nodes {
+        for child in children {
+            let value = match *child {
+                Child::Empty => count,
+                Child::Node(node) => node,
+                Child::Data(offset) => count + 16 + offset,
+            } as u32;
+            db.extend_from_slice(&value.to_be_bytes()[1..]);
+        }
+    }
+    db.extend_from_slice(&[0; 16]);
+    db.extend(data);
+    db.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+    db.extend(map(&[
+        ("binary_format_major_version", uint16(2)),
+        ("binary_format_minor_version", uint16(0)),
+        ("build_epoch", vec![0x08, 0x02, 0, 0, 0, 0, 0, 0, 0, 0]),
+        ("database_type", string("Test")),
+        ("description", map(&[])),
+        ("ip_version", uint16(4)),
+        ("languages", vec![0x00, 0x04]),
+        ("node_count", uint32(count as u32)),
+        ("record_size", uint16(24)),
+    ]));
+    db
+}
+
+fn country(code: &str) -> Vec<u8> {
+    map(&[("country", map(&[("iso_code", string(code))]))])
+}
+
+/// Country database placing 1/8 in DE and 2/8 in FR, and an ASN database
+/// covering 1/8.
+fn databases(dir: &tempfile::TempDir) -> (String, String) {
+    let country_db = dir.path().join("country.mmdb");
+    std::fs::write(&country_db, mmdb(&[(1, country("DE")), (2, country("FR"))])).unwrap();
+    let asn_db = dir.path().join("asn.mmdb");
+    let asn = map(&[
+        ("autonomous_system_number", uint32(3320)),
+        ("autonomous_system_organization", string("Deutsche Telekom AG")),
+    ]);
+    std::fs::write(&asn_db, mmdb(&[(1, asn)])).unwrap();
+    (
+        country_db.to_string_lossy().into_owned(),
+        asn_db.to_string_lossy().into_owned(),
+    )
+}
+
+#[test]
+fn lookups_combine_both_databases() {
+    let dir = tempfile::tempdir().unwrap();
+    let (country_db, asn_db) = databases(&dir);
+    let geoip = GeoIp::open(Some(&country_db), Some(&asn_db)).unwrap();
+
+    let geo = geoip.lookup("1.2.3.4");
+    assert_eq!(geo.country.as_deref(), Some("DE"));
+    assert_eq!(geo.asn, Some(3320));
+    assert_eq!(geo.as_org.as_deref(), Some("Deutsche Telekom AG"));
+    assert_eq!(geo.describe().as_deref(), Some("DE, AS3320"));
+    assert_eq!(geoip.lookup("::ffff:2.0.0.1").country.as_deref(), Some("FR"));
+    assert_eq!(geoip.lookup("2.0.0.1").asn, None);
+    assert_eq!(geoip.lookup("3.0.0.1"), Geo::default());
+
+    assert!(GeoIp::open(Some("/nonexistent.mmdb"), None).is_none());
+}
+
+#[tokio::test]
+async fn decisions_are_annotated_and_blocks_counted_per_country() {
+    let dir = tempfile::tempdir().unwrap();
+    let (country_db, asn_db) = databases(&dir);
+    let config = Config {
+        geoip_country_db: Some(country_db),
+        geoip_asn_db: Some(asn_db),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["1.2.3.4", "2.3.4.5"]).await;
+    let mut decisions = app.state().events.subscribe_decisions();
+
+    app.get_from("1.2.3.4", "/").await;
+    app.get_from("1.2.3.4", "/").await;
+    app.get_from("2.3.4.5", "/").await;
+    app.get_from("3.4.5.6", "/").await;
+
+    let blocked = serde_json::to_value(decisions.try_recv().unwrap()).unwrap();
+    assert_eq!(blocked["country"], "DE");
+    assert_eq!(blocked["asn"], 3320);
+    assert_eq!(blocked["as_org"], "Deutsche Telekom AG");
+    decisions.try_recv().unwrap();
+    decisions.try_recv().unwrap();
+    let allowed = serde_json::to_value(decisions.try_recv().unwrap()).unwrap();
+    assert_eq!(allowed["verdict"], "allowed");
+    assert!(allowed.get("country").is_none());
+
+    let body = to_bytes(app.get("/health").await.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["blocked_by_country"], serde_json::json!({"DE": 2, "FR": 1}));
+}
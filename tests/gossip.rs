@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tezcatlipoca_auth::{
+    config::{Config, GossipConfig},
+    events::SecurityEvent,
+    bans::SOURCE_GOSSIP,
+    gossip::{self, open, seal, BanMessage, GossipError},
+    metrics::Metrics,
+    AppState,
+};
+
+const SECRET: &str = "replica-shared-secret";
+
+fn message() -> BanMessage {
+    BanMessage {
+        origin: 7,
+        id: 1,
+        sent: Utc::now(),
+        ip: "203.0.113.7".to_string(),
+        reason: "honeypot".to_string(),
+        duration_secs: Some(3600),
+    }
+}
+
+#[test]
+fn only_fresh_messages_signed_with_the_secret_are_accepted() {
+    let sent = message();
+    let datagram = seal(&sent, SECRET);
+    assert_eq!(open(&datagram, SECRET), Ok(sent));
+    assert_eq!(open(&datagram, "another-secret-entirely"), Err(GossipError::BadSignature));
+
+    let mut tampered = datagram.clone();
+    let last = tampered.len() - 3;
+    tampered[last] ^= 1;
+    assert_eq!(open(&tampered, SECRET), Err(GossipError::BadSignature));
+    assert_eq!(open(&datagram[..10], SECRET), Err(GossipError::Malformed));
+
+    let old = BanMessage {
+        sent: Utc::now() - chrono::Duration::minutes(10),
+        ..message()
+    };
+    assert_eq!(open(&seal(&old, SECRET), SECRET), Err(GossipError::Stale));
+}
+
+#[tokio::test]
+async fn auto_bans_reach_the_peer_within_moments() {
+    let a_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let gossip_config = |peer: &tokio::net::UdpSocket| GossipConfig {
+        bind: "127.0.0.1:0".parse().unwrap(),
+        peers: vec![peer.local_addr().unwrap().to_string()],
+        secret: SECRET.to_string(),
+    };
+    let a_config = gossip_config(&b_socket);
+    let b_config = gossip_config(&a_socket);
+
+    let a = AppState::new(Config::default());
+    let b = AppState::new(Config::default());
+    tokio::spawn(gossip::gossip_task(a.clone(), a_config, a_socket));
+    tokio::spawn(gossip::gossip_task(b.clone(), b_config, b_socket));
+    tokio::task::yield_now().await;
+
+    a.events.publish(SecurityEvent::AutoBan {
+        ip: "203.0.113.7".to_string(),
+        path: "/wp-login.php".to_string(),
+        reason: "honeypot".to_string(),
+        duration_secs: Some(3600),
+        timestamp: Utc::now(),
+    });
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !b.dynamic_bans.contains("203.0.113.7") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("ban propagated to the peer");
+    assert_eq!(b.dynamic_bans.active()[0].reason, "honeypot");
+    assert_eq!(b.dynamic_bans.active()[0].source, SOURCE_GOSSIP);
+    // Not echoed back
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!a.dynamic_bans.contains("203.0.113.7"));
+}
+
+#[tokio::test]
+async fn bad_datagrams_are_counted_as_dropped() {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let config = GossipConfig {
+        bind: addr,
+        peers: Vec::new(),
+        secret: SECRET.to_string(),
+    };
+    let state = AppState::new(Config::default());
+    tokio::spawn(gossip::gossip_task(state.clone(), config, socket));
+
+    let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let forged = seal(&message(), "not-the-secret");
+    sender.send_to(&forged, addr).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while Metrics::get(&state.metrics.gossip_dropped) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("datagram counted as dropped");
+    assert!(!state.dynamic_bans.contains("203.0.113.7"));
+}
@@ -0,0 +1,70 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+};
+use tezcatlipoca_auth::{
+    config::Config,
+    header_rules::HeaderRules,
+    testing::TestApp,
+};
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    pairs.iter().map(|&(name, value)| (name.parse().unwrap(), value.parse().unwrap())).collect()
+}
+
+#[test]
+fn rules_check_presence_values_and_patterns() {
+    let rules = HeaderRules::parse(
+        "X-Debug; !x-internal-token; x-requested-with=BotNet; user-agent~(?i)masscan; \
+         user-agent~zgrab",
+    )
+    .unwrap();
+    let matching = |pairs: &[(&'static str, &str)]| {
+        rules.matching(&headers(pairs)).map(ToString::to_string)
+    };
+
+    let token = ("x-internal-token", "1");
+    assert_eq!(matching(&[token]), None);
+    assert_eq!(matching(&[]), Some("!x-internal-token".to_string()));
+    assert_eq!(matching(&[token, ("x-debug", "")]), Some("x-debug".to_string()));
+    assert_eq!(
+        matching(&[token, ("x-requested-with", "BotNet")]),
+        Some("x-requested-with=BotNet".to_string())
+    );
+    assert_eq!(matching(&[token, ("x-requested-with", "XMLHttpRequest")]), None);
+    assert_eq!(
+        matching(&[token, ("user-agent", "MASSCAN/1.3")]),
+        Some("user-agent~(?i)masscan".to_string())
+    );
+
+    assert_eq!(
+        matching(&[token, ("user-agent", "Mozilla/5.0"), ("user-agent", "zgrab/0.x")]),
+        Some("user-agent~zgrab".to_string())
+    );
+
+    for invalid in ["bad header", "user-agent~(unclosed", "=value"] {
+        assert!(HeaderRules::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn matching_requests_are_blocked_without_a_ban() {
+    let config = Config {
+        header_rules: HeaderRules::parse("x-requested-with=BotNet").unwrap(),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    let request = |with: Option<&str>| {
+        let mut req = Request::get("/").header("x-forwarded-for", "192.0.2.1");
+        if let Some(with) = with {
+            req = req.header("x-requested-with", with);
+        }
+        req.body(Body::empty()).unwrap()
+    };
+
+    let res = app.send(request(Some("BotNet"))).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.send(request(Some("XMLHttpRequest"))).await.status(), StatusCode::OK);
+    assert_eq!(app.send(request(None)).await.status(), StatusCode::OK);
+    assert!(app.state().dynamic_bans.is_empty());
+}
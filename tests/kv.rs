@@ -0,0 +1,126 @@
+use axum::{
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde_json::{json, Value};
+use tezcatlipoca_auth::{
+    config::{KvBackend, KvConfig},
+    kv::{self, Entries},
+    testing::TestApp,
+};
+use tokio::net::TcpListener;
+
+async fn spawn(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn kv_config(backend: KvBackend, url: String) -> KvConfig {
+    KvConfig {
+        backend,
+        url,
+        prefix: "tezcatlipoca/".to_string(),
+        token: None,
+    }
+}
+
+fn entries(pairs: &[(&str, &str)]) -> Entries {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn kv_bans_and_overrides_apply_until_removed() {
+    let app = TestApp::new(&[]).await;
+    let state = app.state();
+
+    let count = kv::apply(
+        state,
+        &entries(&[
+            ("bans/scanners", "203.0.113.0/24\n198.51.100.7"),
+            ("config/HONEYPOT_PATHS", "/wp-login.php, /.env"),
+        ]),
+    )
+    .await;
+    assert_eq!(count, 2);
+    assert_eq!(app.get_from("203.0.113.9", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("192.0.2.1", "/.env").await.status(), StatusCode::FORBIDDEN);
+    assert!(state.dynamic_bans.contains("192.0.2.1"));
+
+    // Observe-only from the store overrides ENFORCE=true
+    kv::apply(state, &entries(&[("config/ENFORCE", "false")])).await;
+    assert!(!state.enforce());
+    assert_eq!(app.get_from("203.0.113.9", "/").await.status(), StatusCode::OK);
+
+    // Deleting the keys restores the environment values
+    kv::apply(state, &Entries::new()).await;
+    assert!(state.enforce());
+    assert_eq!(app.get_from("203.0.113.9", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("192.0.2.2", "/.env").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn consul_keys_are_read_under_the_prefix() {
+    let url = spawn(Router::new().route(
+        "/v1/kv/tezcatlipoca/",
+        get(|| async {
+            (
+                [("X-Consul-Index", "42")],
+                Json(json!([
+                    {"Key": "tezcatlipoca/", "Value": null},
+                    {"Key": "tezcatlipoca/bans/one", "Value": STANDARD.encode("203.0.113.7")},
+                    {"Key": "tezcatlipoca/config/ENFORCE", "Value": STANDARD.encode("false")},
+                ])),
+            )
+        }),
+    ))
+    .await;
+
+    let (index, read) = kv::read_consul(
+        &reqwest::Client::new(),
+        &kv_config(KvBackend::Consul, url),
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(index, 42);
+    assert_eq!(
+        read,
+        entries(&[("bans/one", "203.0.113.7"), ("config/ENFORCE", "false")])
+    );
+}
+
+#[tokio::test]
+async fn etcd_keys_are_read_under_the_prefix() {
+    let url = spawn(Router::new().route(
+        "/v3/kv/range",
+        post(|Json(body): Json<Value>| async move {
+            assert_eq!(body["key"], STANDARD.encode("tezcatlipoca/"));
+            assert_eq!(body["range_end"], STANDARD.encode("tezcatlipoca0"));
+            Json(json!({
+                "header": {"revision": "7"},
+                "kvs": [{
+                    "key": STANDARD.encode("tezcatlipoca/bans/one"),
+                    "value": STANDARD.encode("198.51.100.0/24"),
+                }],
+            }))
+        }),
+    ))
+    .await;
+
+    let (revision, read) = kv::read_etcd(
+        &reqwest::Client::new(),
+        &kv_config(KvBackend::Etcd, url),
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(revision, 7);
+    assert_eq!(read, entries(&[("bans/one", "198.51.100.0/24")]));
+}
@@ -0,0 +1,102 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tezcatlipoca_auth::{
+    config::{Config, DelayRange},
+    limits::{ConcurrencyLimits, Limited},
+    metrics::Metrics,
+    testing::TestApp,
+};
+use tokio::time::Instant;
+
+#[test]
+fn slots_are_limited_globally_and_per_ip() {
+    let limits = Arc::new(ConcurrencyLimits::new(Some(2), Some(1)));
+    let a: IpAddr = "198.51.100.1".parse().unwrap();
+    let b: IpAddr = "198.51.100.2".parse().unwrap();
+    let c: IpAddr = "198.51.100.3".parse().unwrap();
+
+    let first = limits.acquire(a).unwrap();
+    assert_eq!(limits.acquire(a).unwrap_err(), Limited::PerIp);
+    assert_eq!(limits.inflight(a), 1);
+
+    let _second = limits.acquire(b).unwrap();
+    assert_eq!(limits.acquire(c).unwrap_err(), Limited::Global);
+
+    drop(first);
+    assert_eq!(limits.inflight(a), 0);
+    assert!(limits.acquire(c).is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_over_the_per_ip_limit_are_shed() {
+    let config = Config {
+        max_inflight_per_ip: Some(1),
+        tarpit_delay: Some(DelayRange {
+            min: Duration::from_secs(10),
+            max: Duration::from_secs(10),
+        }),
+        ..Config::default()
+    };
+    let app = Arc::new(TestApp::with_config(config, &["203.0.113.7"]).await);
+
+    // The first request is held in the tarpit, keeping its slot
+    let held = tokio::spawn({
+        let app = app.clone();
+        async move { app.get_from("203.0.113.7", "/").await.status() }
+    });
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+    assert_eq!(Metrics::get(&app.state().metrics.shed), 1);
+
+    assert_eq!(held.await.unwrap(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_past_the_deadline_time_out() {
+    let config = Config {
+        request_timeout: Some(Duration::from_secs(2)),
+        tarpit_delay: Some(DelayRange {
+            min: Duration::from_secs(10),
+            max: Duration::from_secs(10),
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let started = Instant::now();
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(Metrics::get(&app.state().metrics.timed_out), 1);
+
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected() {
+    let config = Config {
+        max_body_bytes: Some(16),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let req = |length: usize| {
+        Request::post("/")
+            .header("content-length", length)
+            .body(Body::from(vec![b'x'; length]))
+            .unwrap()
+    };
+    let res = app.send(req(17)).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(res.headers()["connection"], "close");
+
+    assert_eq!(app.send(req(16)).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,276 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use ipnet::IpNet;
+use tezcatlipoca_auth::{
+    cache::{reload_banned_ips, reload_banned_ips_if_changed, watch_banned_ips, IpSet},
+    config::{CategoryRule, Config},
+    error::Error,
+    lists::{parse_list, parse_sections},
+    testing::TestApp,
+    AppState,
+};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn net(s: &str) -> IpNet {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_spamhaus_drop_format() {
+    let drop = "; Spamhaus DROP List 2024/01/01\n\
+                ; Last-Modified: Mon, 01 Jan 2024 00:00:00 GMT\n\
+                1.10.16.0/20 ; SBL256894\n\
+                1.19.0.0/16 ; SBL434604\n";
+    let (entries, rejected) = parse_list(drop);
+    assert_eq!(entries.len(), 2);
+    assert!(rejected.is_empty());
+}
+
+#[test]
+fn parses_firehol_netset_format() {
+    let netset = "#\n# firehol_level1\n#\n0.0.0.0/8\n1.2.3.4\n2001:db8::/32\nnot-an-ip\n";
+    let (entries, rejected) = parse_list(netset);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].number, 7);
+    assert_eq!(rejected[0].text, "not-an-ip");
+}
+
+#[test]
+fn ip_set_matches_addresses_and_networks() {
+    let (entries, _) = parse_list("198.51.100.7\n203.0.113.0/24\n10.1.2.3/8\n2001:db8:bad::/48\n");
+    let set: IpSet = entries.into_iter().collect();
+
+    assert!(set.contains(ip("198.51.100.7")));
+    assert!(!set.contains(ip("198.51.100.8")));
+    assert!(set.contains(ip("203.0.113.255")));
+    assert!(!set.contains(ip("203.0.114.0")));
+    assert!(set.contains(ip("10.200.0.1")), "host bits are cleared");
+    assert!(set.contains(ip("2001:db8:bad:1::1")));
+    assert!(!set.contains(ip("2001:db8:bae::1")));
+    assert!(set.contains(ip("::ffff:203.0.113.9")), "IPv4-mapped IPv6 matches");
+}
+
+#[test]
+fn ip_set_removes_networks() {
+    let (entries, _) = parse_list("203.0.113.0/24\n203.0.113.128/25\n");
+    let mut set: IpSet = entries.into_iter().collect();
+    assert_eq!(set.len(), 2);
+
+    set.update([], ["203.0.113.0/24".parse().unwrap()]);
+    assert!(!set.contains(ip("203.0.113.1")));
+    assert!(set.contains(ip("203.0.113.200")));
+}
+
+#[test]
+fn ip_set_reports_the_most_specific_match() {
+    let (entries, _) = parse_list("10.0.0.0/8\n10.1.0.0/16\n10.1.2.3\n2001:db8::/32\n");
+    let set: IpSet = entries.into_iter().collect();
+
+    assert_eq!(set.longest_match(ip("10.9.9.9")), Some("10.0.0.0/8".parse().unwrap()));
+    assert_eq!(set.longest_match(ip("10.1.9.9")), Some("10.1.0.0/16".parse().unwrap()));
+    assert_eq!(set.longest_match(ip("10.1.2.3")), Some("10.1.2.3/32".parse().unwrap()));
+    assert_eq!(
+        set.longest_match(ip("2001:db8:1::1")),
+        Some("2001:db8::/32".parse().unwrap())
+    );
+    assert_eq!(set.longest_match(ip("11.0.0.1")), None);
+}
+
+#[test]
+fn ip_set_stays_exact_as_hosts_grow_and_shrink() {
+    let hosts: Vec<IpNet> = (0..5000u32)
+        .map(|i| IpNet::from(IpAddr::from(Ipv4Addr::from(0x0a00_0000 + i))))
+        .collect();
+    let mut set = IpSet::new();
+    set.update(hosts.iter().copied(), []);
+    assert!(hosts.iter().all(|h| set.contains(h.addr())));
+
+    set.update([], hosts[..2500].iter().copied());
+    assert!(hosts[..2500].iter().all(|h| !set.contains(h.addr())));
+    assert!(hosts[2500..].iter().all(|h| set.contains(h.addr())));
+}
+
+#[tokio::test]
+async fn cidr_entries_in_the_banned_file_are_enforced() {
+    let app = TestApp::new(&["# scanners", "203.0.113.0/24 ; range"]).await;
+
+    assert_eq!(
+        app.get_from("203.0.113.77", "/").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(app.get_from("203.0.114.1", "/").await.status(), StatusCode::OK);
+}
+
+#[test]
+fn sections_sort_entries_into_categories() {
+    let list = "203.0.113.1\n\
+                [Tor] # exit nodes\n\
+                185.220.101.0/24\n\
+                [scanner]\n\
+                198.51.100.7\n\
+                [not a name]\n\
+                198.51.100.8\n";
+    let (sections, rejected) = parse_sections(list);
+    assert_eq!(sections.uncategorized, [net("203.0.113.1/32")]);
+    assert_eq!(sections.categories["tor"], [net("185.220.101.0/24")]);
+    assert_eq!(
+        sections.categories["scanner"],
+        [net("198.51.100.7/32"), net("198.51.100.8/32")],
+        "an invalid header keeps the current category"
+    );
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].number, 6);
+}
+
+#[tokio::test]
+async fn categories_are_enforced_per_host_and_path() {
+    let config = Config {
+        category_rules: vec![
+            CategoryRule::parse("blog.example.com/admin=*").unwrap(),
+            CategoryRule::parse("blog.example.com=-tor").unwrap(),
+            CategoryRule::parse("/api=scanner").unwrap(),
+        ],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.1", "[tor]", "185.220.101.1"]).await;
+    let get = |ip: &str, host: &str, path: &str| {
+        let req = Request::get(path)
+            .header("x-forwarded-for", ip)
+            .header("x-forwarded-host", host)
+            .body(Body::empty())
+            .unwrap();
+        app.send(req)
+    };
+
+    let tor = "185.220.101.1";
+    assert_eq!(get(tor, "blog.example.com", "/").await.status(), StatusCode::OK);
+    assert_eq!(get(tor, "Blog.Example.com:443", "/post").await.status(), StatusCode::OK);
+    assert_eq!(get(tor, "blog.example.com", "/admin").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get(tor, "shop.example.com", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get(tor, "shop.example.com", "/api/v1").await.status(), StatusCode::OK);
+
+    // Uncategorized entries are blocked everywhere
+    let manual = "203.0.113.1";
+    assert_eq!(get(manual, "blog.example.com", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get(manual, "shop.example.com", "/api").await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn banned_files_and_directories_are_merged() {
+    let root = tempfile::tempdir().unwrap();
+    let manual = root.path().join("manual.txt");
+    std::fs::write(&manual, "203.0.113.1\n").unwrap();
+    let dir = root.path().join("lists.d");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "198.51.100.0/24\n198.51.101.1\n").unwrap();
+    std::fs::write(dir.join("b.txt"), "192.0.2.1\n").unwrap();
+    std::fs::write(dir.join(".hidden"), "10.0.0.1\n").unwrap();
+
+    let state = AppState::new(Config {
+        banned_ips_files: vec![
+            manual.to_string_lossy().into_owned(),
+            dir.to_string_lossy().into_owned(),
+        ],
+        ..Config::default()
+    });
+    reload_banned_ips(&state).await.unwrap();
+
+    let cache = state.banned_ips.read().await;
+    assert!(cache.contains("203.0.113.1"));
+    assert!(cache.contains("198.51.100.42"));
+    assert!(cache.contains("192.0.2.1"));
+    assert!(!cache.contains("10.0.0.1"), "hidden files are skipped");
+    let counts: Vec<usize> = cache.files.iter().map(|f| f.entries).collect();
+    assert_eq!(counts, [1, 2, 1]);
+}
+
+#[tokio::test]
+async fn unchanged_files_are_not_reloaded() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+    let state = app.state();
+    assert!(!reload_banned_ips_if_changed(state).await.unwrap());
+
+    app.set_banned(&["203.0.113.7", "203.0.113.8"]).await;
+    assert!(!reload_banned_ips_if_changed(state).await.unwrap());
+
+    let path = &state.config.banned_ips_files[0];
+    std::fs::write(path, "198.51.100.1\n").unwrap();
+    assert!(reload_banned_ips_if_changed(state).await.unwrap());
+    assert!(state.banned_ips.read().await.contains("198.51.100.1"));
+}
+
+#[tokio::test]
+async fn unreadable_files_fail_the_reload_with_their_path() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+    let file = &app.state().config.banned_ips_files[0];
+    // A path below a regular file can't be read, unlike a missing one
+    let path = format!("{}/banned.txt", file);
+    let state = AppState::new(Config {
+        banned_ips_files: vec![path.clone()],
+        ..Config::default()
+    });
+
+    let error = reload_banned_ips(&state).await.unwrap_err();
+    assert!(matches!(&error, Error::Cache { path: failed, .. } if *failed == path));
+    assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!state.banned_ips.read().await.loaded);
+}
+
+/// Lays out a ConfigMap volume the way the kubelet does: versioned data
+/// directories, a `..data` symlink to the current one and per-key symlinks
+/// through it. Updates swap `..data` with a rename.
+#[cfg(unix)]
+fn write_configmap(root: &std::path::Path, version: &str, contents: &str) {
+    use std::os::unix::fs::symlink;
+
+    let data = root.join(version);
+    std::fs::create_dir(&data).unwrap();
+    let path = data.join("banned-ips.txt");
+    std::fs::write(&path, contents).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    // Same size and timestamp for every version, so only the swap differs
+    file.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+
+    symlink(version, root.join("..data_tmp")).unwrap();
+    std::fs::rename(root.join("..data_tmp"), root.join("..data")).unwrap();
+    let key = root.join("banned-ips.txt");
+    if key.symlink_metadata().is_err() {
+        symlink("..data/banned-ips.txt", key).unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn configmap_updates_are_reloaded_right_away() {
+    let root = tempfile::tempdir().unwrap();
+    write_configmap(root.path(), "..2024_01_01_00_00_00.1", "203.0.113.1\n");
+
+    let state = AppState::new(Config {
+        banned_ips_files: vec![root.path().to_string_lossy().into_owned()],
+        cache_ttl: std::time::Duration::from_secs(3600),
+        banned_ips_watch: true,
+        ..Config::default()
+    });
+    reload_banned_ips(&state).await.unwrap();
+    assert!(state.banned_ips.read().await.contains("203.0.113.1"));
+    watch_banned_ips(state.clone()).unwrap();
+
+    write_configmap(root.path(), "..2024_01_01_00_05_00.2", "203.0.113.2\n");
+    for _ in 0..100 {
+        if state.banned_ips.read().await.contains("203.0.113.2") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    let cache = state.banned_ips.read().await;
+    assert!(cache.contains("203.0.113.2"), "reloaded after the ..data swap");
+    assert!(!cache.contains("203.0.113.1"));
+}
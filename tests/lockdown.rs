@@ -0,0 +1,87 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    response::Response,
+};
+use chrono::{Duration, Utc};
+use tezcatlipoca_auth::{
+    config::Config,
+    events::SecurityEvent,
+    kv::{self, Entries},
+    pubsub::BanUpdate,
+    testing::{TestApp, ADMIN_TOKEN},
+};
+
+async fn json(res: Response) -> serde_json::Value {
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn request(method: &str, uri: &str) -> Request<Body> {
+    Request::builder().method(method).uri(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn lockdown_blocks_all_but_the_allowlist_until_lifted() {
+    let config = Config {
+        admin_api: true,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        enforce: false,
+        lockdown_allowlist: vec!["10.0.0.0/8".parse().unwrap()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    let mut events = app.state().events.subscribe();
+
+    let res = app.admin(request("POST", "/admin/lockdown?duration=48h")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let status = json(app.admin(request("POST", "/admin/lockdown?duration=30m")).await).await;
+    assert_eq!(status["active"], true);
+    assert_eq!(status["origin"], "admin");
+    let SecurityEvent::Lockdown { until: Some(until), .. } = events.recv().await.unwrap() else {
+        panic!("expected a lockdown event");
+    };
+    assert!(until > Utc::now() + Duration::minutes(29));
+
+    // Enforced even in observe-only mode
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("10.1.2.3", "/").await.status(), StatusCode::OK);
+    let health = json(app.get("/health").await).await;
+    assert_eq!(health["requests_locked_out"], 1);
+    assert!(health["lockdown_until"].is_string());
+
+    let status = json(app.admin(request("DELETE", "/admin/lockdown")).await).await;
+    assert_eq!(status, serde_json::json!({"active": false}));
+    // After the block of 198.51.100.1
+    assert!(matches!(events.recv().await.unwrap(), SecurityEvent::Blocked { .. }));
+    assert!(matches!(
+        events.recv().await.unwrap(),
+        SecurityEvent::Lockdown { until: None, .. }
+    ));
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn lockdowns_from_other_replicas_expire_on_their_own() {
+    let app = TestApp::new(&[]).await;
+    let state = app.state();
+
+    let until = Utc::now() + Duration::minutes(5);
+    BanUpdate::Lockdown { until: Some(until) }.apply(state);
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let expires = until.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    assert_eq!(res.headers()["x-ban-expires"], expires.as_str());
+    assert!(res.headers().contains_key("retry-after"));
+    BanUpdate::Lockdown { until: None }.apply(state);
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+
+    let entries = |until: chrono::DateTime<Utc>| -> Entries {
+        [("config/LOCKDOWN_UNTIL".to_string(), until.to_rfc3339())].into()
+    };
+    kv::apply(state, &entries(Utc::now() + Duration::minutes(5))).await;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::FORBIDDEN);
+    kv::apply(state, &entries(Utc::now() - Duration::minutes(5))).await;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+}
@@ -0,0 +1,85 @@
+use std::{net::UdpSocket, time::Duration};
+
+use tezcatlipoca_auth::{
+    config::{Config, LogTarget, SyslogConfig, SyslogTransport},
+    logger::{format_rfc5424, journal_field_name, setup_logging, JournaldLayer},
+};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[test]
+fn syslog_settings_are_parsed() {
+    assert_eq!(
+        SyslogConfig::parse_address("udp://10.0.0.1:514"),
+        Some((SyslogTransport::Udp, "10.0.0.1:514".to_string()))
+    );
+    assert_eq!(
+        SyslogConfig::parse_address("/dev/log"),
+        Some((SyslogTransport::Unix, "/dev/log".to_string()))
+    );
+    assert_eq!(SyslogConfig::parse_address("tcp://collector"), None);
+    assert_eq!(SyslogConfig::parse_address("http://collector:514"), None);
+
+    assert_eq!(SyslogConfig::parse_facility("daemon"), Some(3));
+    assert_eq!(SyslogConfig::parse_facility("LOCAL7"), Some(23));
+    assert_eq!(SyslogConfig::parse_facility("local8"), None);
+
+    let message = format_rfc5424(28, "2026-01-02T03:04:05.000000Z", "host", 42, b"hello");
+    assert_eq!(
+        String::from_utf8(message).unwrap(),
+        "<28>1 2026-01-02T03:04:05.000000Z host tezcatlipoca-auth 42 - - hello"
+    );
+}
+
+#[test]
+fn events_are_sent_to_syslog() {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let config = Config {
+        log_target: LogTarget::Syslog(SyslogConfig {
+            transport: SyslogTransport::Udp,
+            address: collector.local_addr().unwrap().to_string(),
+            facility: 3,
+        }),
+        ..Config::default()
+    };
+    setup_logging(&config).unwrap();
+
+    tracing::warn!("syslog test event");
+
+    let mut buf = [0; 2048];
+    let n = collector.recv(&mut buf).unwrap();
+    let message = String::from_utf8_lossy(&buf[..n]);
+    // daemon (3) * 8 + warning (4)
+    assert!(message.starts_with("<28>1 "), "{}", message);
+    assert!(message.ends_with("syslog test event"), "{}", message);
+}
+
+#[test]
+fn events_are_sent_to_journald() {
+    assert_eq!(journal_field_name("message"), "MESSAGE");
+    assert_eq!(journal_field_name("client.ip"), "CLIENT_IP");
+    assert_eq!(journal_field_name("_hidden"), "F_HIDDEN");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.socket");
+    let journal = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+    journal.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let layer = JournaldLayer::new(path.to_str().unwrap()).unwrap();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+        tracing::error!(ip = "203.0.113.7", "first line\nsecond line");
+    });
+
+    let mut buf = [0; 4096];
+    let n = journal.recv(&mut buf).unwrap();
+    let entry = &buf[..n];
+    let contains = |needle: &[u8]| entry.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"PRIORITY=3\n"));
+    assert!(contains(b"SYSLOG_IDENTIFIER=tezcatlipoca-auth\n"));
+    assert!(contains(b"IP=203.0.113.7\n"));
+    // Multi-line values are length-prefixed
+    let mut message = b"MESSAGE\n".to_vec();
+    message.extend_from_slice(&22u64.to_le_bytes());
+    message.extend_from_slice(b"first line\nsecond line\n");
+    assert!(contains(&message));
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+use tezcatlipoca_auth::{config::LokiConfig, loki, testing::TestApp};
+use tokio::{net::TcpListener, sync::mpsc};
+
+type Pushed = (HeaderMap, serde_json::Value);
+
+/// Fake Loki forwarding every push to the returned channel.
+async fn spawn_loki() -> (String, mpsc::UnboundedReceiver<Pushed>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let app = Router::new()
+        .route(
+            "/loki/api/v1/push",
+            post(
+                |State(tx): State<mpsc::UnboundedSender<Pushed>>,
+                 headers: HeaderMap,
+                 Json(body): Json<serde_json::Value>| async move {
+                    tx.send((headers, body)).unwrap();
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        )
+        .with_state(tx);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/loki/api/v1/push", addr), rx)
+}
+
+#[test]
+fn labels_are_parsed() {
+    assert_eq!(
+        LokiConfig::parse_labels("env=prod, cluster = eu1"),
+        Some(vec![
+            ("env".to_string(), "prod".to_string()),
+            ("cluster".to_string(), "eu1".to_string()),
+        ])
+    );
+    assert_eq!(LokiConfig::parse_labels("no-dashes=x"), None);
+    assert_eq!(LokiConfig::parse_labels("missing"), None);
+}
+
+#[tokio::test]
+async fn decisions_are_pushed_in_streams_per_verdict() {
+    let (url, mut pushed) = spawn_loki().await;
+    let app = TestApp::new(&["203.0.113.7"]).await;
+    let config = LokiConfig {
+        url,
+        labels: vec![("env".to_string(), "test".to_string())],
+        tenant: Some("team-a".to_string()),
+        batch_size: 3,
+        batch_window: Duration::from_secs(5),
+    };
+    tokio::spawn(loki::push_task(app.state().clone(), config));
+    tokio::task::yield_now().await;
+
+    app.get_from("203.0.113.7", "/a").await;
+    app.get_from("198.51.100.1", "/b").await;
+    app.get_from("198.51.100.2", "/c").await;
+
+    let (headers, body) = tokio::time::timeout(Duration::from_secs(5), pushed.recv())
+        .await
+        .expect("decisions pushed")
+        .unwrap();
+    assert_eq!(headers["x-scope-orgid"], "team-a");
+
+    let streams = body["streams"].as_array().unwrap();
+    assert_eq!(streams.len(), 2);
+    let stream = |decision: &str| {
+        streams
+            .iter()
+            .find(|s| s["stream"]["decision"] == decision)
+            .unwrap()
+    };
+    let allowed = stream("allowed");
+    assert_eq!(allowed["stream"]["env"], "test");
+    assert_eq!(allowed["stream"]["service"], "tezcatlipoca-auth");
+    assert_eq!(allowed["values"].as_array().unwrap().len(), 2);
+
+    let blocked = &stream("blocked")["values"][0];
+    assert!(blocked[0].as_str().unwrap().parse::<i64>().is_ok());
+    let line: serde_json::Value = serde_json::from_str(blocked[1].as_str().unwrap()).unwrap();
+    assert_eq!(line["ip"], "203.0.113.7");
+    assert_eq!(line["path"], "/a");
+}
@@ -0,0 +1,61 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    response::Response,
+};
+use tezcatlipoca_auth::{config::Config, maintenance::DEFAULT_PAGE, testing::{TestApp, ADMIN_TOKEN}};
+
+async fn json(res: Response) -> serde_json::Value {
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn maintenance_answers_503_except_to_the_allowlist() {
+    let config = Config {
+        maintenance: true,
+        maintenance_allowlist: vec!["10.0.0.0/8".parse().unwrap()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()["retry-after"], "300");
+    assert_eq!(res.headers()["content-type"], "text/html; charset=utf-8");
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, DEFAULT_PAGE.as_bytes());
+
+    assert_eq!(app.get_from("10.1.2.3", "/").await.status(), StatusCode::OK);
+    let health = app.get("/health").await;
+    assert_eq!(health.status(), StatusCode::OK);
+    assert_eq!(json(health).await["requests_maintenance"], 1);
+}
+
+#[tokio::test]
+async fn maintenance_is_switched_through_the_admin_api() {
+    let config = Config {
+        admin_api: true,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        maintenance_page: Some("<p>Back soon</p>".to_string()),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+
+    let req = Request::put("/admin/maintenance")
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"enabled": true}"#))
+        .unwrap();
+    let status = json(app.admin(req).await).await;
+    assert_eq!(status, serde_json::json!({"enabled": true, "origin": "admin"}));
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, "<p>Back soon</p>");
+
+    let req = Request::delete("/admin/maintenance").body(Body::empty()).unwrap();
+    let status = json(app.admin(req).await).await;
+    assert_eq!(status, serde_json::json!({"enabled": false, "origin": "config"}));
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+}
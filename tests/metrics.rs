@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::metrics::{LatencyHistogram, LatencySummary};
+
+#[test]
+fn latency_percentiles_are_within_a_bucket() {
+    let histogram = LatencyHistogram::default();
+    assert_eq!(histogram.summary(), LatencySummary::default());
+
+    // 1..=1000 µs: exact percentiles are 500, 950 and 990
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+    let summary = histogram.summary();
+    assert_eq!(summary.count, 1000);
+    for (value, exact) in [(summary.p50_us, 500), (summary.p95_us, 950), (summary.p99_us, 990)] {
+        assert!(value >= exact, "{} < {}", value, exact);
+        assert!(value <= exact + exact / 8, "{} too far above {}", value, exact);
+    }
+
+    // Small and huge values don't panic and land at the ends
+    histogram.record(Duration::ZERO);
+    histogram.record(Duration::from_secs(u64::MAX));
+}
@@ -0,0 +1,408 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use std::time::Duration;
+
+use tezcatlipoca_auth::{
+    config::{BlockResponse, Config, DelayRange, FailMode, IpSource},
+    metrics::Metrics,
+    testing::TestApp,
+};
+use tokio::time::Instant;
+
+#[tokio::test]
+async fn banned_forwarded_ip_is_blocked() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn unknown_ip_is_allowed() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    let res = app.get_from("198.51.100.1", "/some/path").await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn first_forwarded_for_entry_is_used() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7, 10.0.0.1", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn trusted_proxies_and_hops_pick_the_client_entry() {
+    // The client forged 192.0.2.1, the edge saw 203.0.113.7, an internal
+    // load balancer appended the edge's address
+    let chain = "192.0.2.1, 203.0.113.7, 10.0.0.5";
+
+    let config = Config {
+        trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    assert_eq!(app.get_from(chain, "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("10.0.0.9, 10.0.0.5", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("203.0.113.7, 10.0.0.5", "/").await.status(), StatusCode::FORBIDDEN);
+
+    let config = Config {
+        trusted_hops: 2,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    assert_eq!(app.get_from(chain, "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::FORBIDDEN);
+    let response = app.get_from("203.0.113.7, 192.0.2.1, 10.0.0.5", "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cloudflare_header_takes_priority() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+
+    // Traefik appends the Cloudflare edge it got the request from
+    let req = Request::get("/")
+        .header("cf-connecting-ip", "203.0.113.7")
+        .header("x-forwarded-for", "198.51.100.1, 173.245.48.1")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(req).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn cloudflare_header_is_ignored_unless_sent_by_cloudflare() {
+    let app = TestApp::new(&["198.51.100.1"]).await;
+    let forged = |forwarded_for: &str| {
+        Request::get("/")
+            .header("cf-connecting-ip", "192.0.2.1")
+            .header("x-forwarded-for", forwarded_for)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let res = app.send(forged("198.51.100.1")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN, "forged header skipped");
+    let res = app.send(forged("198.51.100.1, 2606:4700::1")).await;
+    assert_eq!(res.status(), StatusCode::OK, "header from a Cloudflare edge used");
+
+    let req = Request::get("/")
+        .header("cf-connecting-ip", "198.51.100.1")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.send_from_peer("162.158.1.1:443".parse().unwrap(), req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN, "direct connection from Cloudflare");
+
+    let config = Config {
+        cloudflare_verify: false,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["192.0.2.1"]).await;
+    assert_eq!(app.send(forged("198.51.100.1")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn socket_address_is_used_without_proxy_headers() {
+    let app = TestApp::new(&["192.0.2.10"]).await;
+
+    let req = Request::get("/").body(Body::empty()).unwrap();
+    let res = app.send_from_peer("192.0.2.10:5555".parse().unwrap(), req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn list_updates_are_picked_up() {
+    let app = TestApp::new(&[]).await;
+    assert_eq!(app.get_from("203.0.113.7", "/").await.status(), StatusCode::OK);
+
+    app.set_banned(&["203.0.113.7"]).await;
+    assert_eq!(
+        app.get_from("203.0.113.7", "/").await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn health_reports_banned_count() {
+    let app = TestApp::new(&["203.0.113.7", "203.0.113.8"]).await;
+
+    let res = app.get("/health").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["banned_ip_count"], 2);
+}
+
+#[tokio::test]
+async fn health_reports_rejected_list_lines() {
+    let app = TestApp::new(&["# manual bans", "", "203.0.113.7", "203.0.113.300", "garbage"]).await;
+
+    let res = app.get("/health").await;
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["banned_ip_count"], 1);
+    assert_eq!(json["rejected_lines"], 2);
+}
+
+#[tokio::test]
+async fn health_reports_decision_latency() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+    app.get_from("198.51.100.1", "/").await;
+    app.get_from("198.51.100.2", "/").await;
+    app.get_from("203.0.113.7", "/").await;
+
+    let res = app.get("/health").await;
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let latency = &json["decision_latency"];
+    // The health request itself passes the middleware too
+    assert_eq!(latency["allowed"]["count"], 3);
+    assert_eq!(latency["blocked"]["count"], 1);
+    let percentile = |name: &str| latency["allowed"][name].as_u64().unwrap();
+    assert!(percentile("p99_us") >= percentile("p50_us"));
+}
+
+#[tokio::test]
+async fn observe_only_mode_lets_banned_ips_through() {
+    let config = Config {
+        enforce: false,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(Metrics::get(&app.state().metrics.would_block), 1);
+    assert_eq!(Metrics::get(&app.state().metrics.blocked), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn tarpit_delays_blocked_responses() {
+    let config = Config {
+        tarpit_delay: Some(DelayRange {
+            min: Duration::from_secs(10),
+            max: Duration::from_secs(10),
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let started = Instant::now();
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(started.elapsed() >= Duration::from_secs(10));
+    assert_eq!(Metrics::get(&app.state().metrics.tarpitted), 1);
+
+    let started = Instant::now();
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn honeypot_path_bans_the_client() {
+    let config = Config {
+        honeypot_paths: vec!["/wp-login.php".to_string(), "/phpmyadmin".to_string()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let probe = Request::get("/")
+        .header("x-forwarded-for", "203.0.113.9")
+        .header("x-forwarded-uri", "/phpMyAdmin/index.php?lang=en")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(probe).await.status(), StatusCode::FORBIDDEN);
+
+    // Every later request from the same IP is blocked, whatever the path
+    assert_eq!(
+        app.get_from("203.0.113.9", "/").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.state().dynamic_bans.len(), 1);
+    assert_eq!(Metrics::get(&app.state().metrics.honeypot_hits), 1);
+}
+
+#[tokio::test]
+async fn temporary_bans_tell_clients_when_they_end() {
+    let config = Config {
+        honeypot_paths: vec!["/wp-login.php".to_string()],
+        honeypot_ban_duration: Some(Duration::from_secs(3600)),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    app.get_from("203.0.113.9", "/wp-login.php").await;
+    let res = app.get_from("203.0.113.9", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let retry_after: u64 = res.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((3590..=3600).contains(&retry_after));
+    let expires = res.headers()["x-ban-expires"].to_str().unwrap();
+    assert!(chrono::DateTime::parse_from_rfc3339(expires).is_ok());
+
+    // Listed IPs are banned for as long as the list says so
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(!res.headers().contains_key("retry-after"));
+    assert!(!res.headers().contains_key("x-ban-expires"));
+}
+
+#[tokio::test]
+async fn malformed_proxy_header_falls_back_to_the_next_source() {
+    let app = TestApp::new(&["192.0.2.10"]).await;
+
+    let req = Request::get("/")
+        .header("cf-connecting-ip", "not-an-ip")
+        .header("x-forwarded-for", "unknown")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.send_from_peer("192.0.2.10:5555".parse().unwrap(), req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn real_ip_headers_are_understood() {
+    let app = TestApp::new(&["203.0.113.7", "203.0.113.8"]).await;
+
+    for (header, ip) in [("x-real-ip", "203.0.113.7"), ("true-client-ip", "203.0.113.8")] {
+        let req = Request::get("/").header(header, ip).body(Body::empty()).unwrap();
+        let res = app.send_from_peer("192.0.2.1:5555".parse().unwrap(), req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", header);
+    }
+
+    let req = Request::get("/")
+        .header("x-forwarded-for", "203.0.113.7")
+        .header("x-real-ip", "198.51.100.1")
+        .header("true-client-ip", "198.51.100.1")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        app.send(req).await.status(),
+        StatusCode::FORBIDDEN,
+        "x-forwarded-for set by the proxy wins over client-supplied headers"
+    );
+}
+
+#[tokio::test]
+async fn client_ip_sources_are_configurable() {
+    let config = Config {
+        client_ip_sources: vec![IpSource::Socket],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["192.0.2.10"]).await;
+    let req = Request::get("/")
+        .header("x-forwarded-for", "198.51.100.1")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.send_from_peer("192.0.2.10:5555".parse().unwrap(), req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN, "headers are ignored without a proxy");
+
+    let config = Config {
+        client_ip_sources: vec![
+            IpSource::Header("x-forwarded-for".to_string()),
+            IpSource::Header("cf-connecting-ip".to_string()),
+        ],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    let req = Request::get("/")
+        .header("cf-connecting-ip", "198.51.100.1")
+        .header("x-forwarded-for", "203.0.113.7")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(req).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn block_status_is_configurable() {
+    let config = Config {
+        block_response: BlockResponse::Status(404),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn blocked_requests_can_be_redirected() {
+    let config = Config {
+        block_response: BlockResponse::Redirect("https://example.com/appeal".to_string()),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()["location"], "https://example.com/appeal");
+}
+
+#[tokio::test]
+async fn unloaded_ban_list_blocks_only_when_failing_closed() {
+    let app = TestApp::new(&["203.0.113.7"]).await;
+    app.state().banned_ips.write().await.loaded = false;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+
+    let config = Config {
+        fail_mode: FailMode::Closed,
+        block_reason_header: true,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+
+    app.state().banned_ips.write().await.loaded = false;
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.headers()["x-block-reason"], "BAN LIST UNAVAILABLE");
+}
+
+#[tokio::test]
+async fn block_reasons_can_be_sent_to_the_client() {
+    let config = Config {
+        block_response: BlockResponse::Status(404),
+        block_reason_header: true,
+        honeypot_paths: vec!["/wp-login.php".to_string()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+
+    let res = app.get_from("203.0.113.7", "/").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers()["x-block-reason"], "BANNED (files)");
+
+    app.get_from("203.0.113.9", "/wp-login.php").await;
+    let res = app.get_from("203.0.113.9", "/").await;
+    assert_eq!(res.headers()["x-block-reason"], "DYNAMIC BAN (local: honeypot)");
+
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert!(!res.headers().contains_key("x-block-reason"));
+}
+
+#[tokio::test]
+async fn greylist_defers_first_requests_until_the_delay_has_passed() {
+    let config = Config {
+        greylist: true,
+        greylist_delay: Duration::from_millis(200),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "1");
+    assert_eq!(
+        app.get_from("198.51.100.1", "/").await.status(),
+        StatusCode::TOO_MANY_REQUESTS,
+        "retrying too early is deferred again"
+    );
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("198.51.100.1", "/").await.status(), StatusCode::OK);
+}
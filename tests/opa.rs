@@ -0,0 +1,85 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use tezcatlipoca_auth::{
+    config::{Config, OpaConfig},
+    testing::TestApp,
+};
+use tokio::net::TcpListener;
+
+/// Fake OPA data API denying `/admin` to everyone but 198.51.100.1; keeps
+/// the last input document.
+async fn spawn_opa(last_input: Arc<Mutex<Value>>) -> String {
+    let app = Router::new().route(
+        "/v1/data/tezcatlipoca/allow",
+        post(move |Json(body): Json<Value>| async move {
+            let input = body["input"].clone();
+            let allow = input["path"] != "/admin" || input["ip"] == "198.51.100.1";
+            *last_input.lock().unwrap() = input;
+            Json(json!({ "result": { "allow": allow } }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/v1/data/tezcatlipoca/allow", addr)
+}
+
+async fn opa_app(url: String, fail_open: bool) -> TestApp {
+    let config = Config {
+        opa: Some(OpaConfig {
+            url,
+            timeout: Duration::from_secs(5),
+            fail_open,
+        }),
+        ..Config::default()
+    };
+    TestApp::with_config(config, &[]).await
+}
+
+fn request(ip: &str, path: &str) -> Request<Body> {
+    Request::get("/")
+        .header("x-forwarded-for", ip)
+        .header("x-forwarded-uri", path)
+        .header("x-forwarded-method", "POST")
+        .header("cookie", "session=secret")
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn policy_decisions_are_enforced() {
+    let last_input = Arc::new(Mutex::new(Value::Null));
+    let app = opa_app(spawn_opa(last_input.clone()).await, false).await;
+
+    assert_eq!(app.send(request("198.51.100.2", "/docs")).await.status(), StatusCode::OK);
+    assert_eq!(app.send(request("198.51.100.1", "/admin")).await.status(), StatusCode::OK);
+    assert_eq!(app.send(request("198.51.100.2", "/admin")).await.status(), StatusCode::FORBIDDEN);
+
+    let input = last_input.lock().unwrap().clone();
+    assert_eq!(input["ip"], "198.51.100.2");
+    assert_eq!(input["method"], "POST");
+    assert_eq!(input["headers"]["x-forwarded-uri"], "/admin");
+    assert!(input["headers"].get("cookie").is_none(), "credentials stay out of the input");
+}
+
+#[tokio::test]
+async fn unreachable_opa_fails_closed_unless_configured_open() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/v1/data/tezcatlipoca/allow", listener.local_addr().unwrap());
+    drop(listener);
+
+    let app = opa_app(url.clone(), false).await;
+    assert_eq!(app.send(request("198.51.100.2", "/")).await.status(), StatusCode::FORBIDDEN);
+    let app = opa_app(url, true).await;
+    assert_eq!(app.send(request("198.51.100.2", "/")).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,92 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tempfile::TempDir;
+use tezcatlipoca_auth::{config::Config, testing::TestApp};
+
+/// Bump allocator shared by the test plugins.
+const ALLOC: &str = r#"
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+"#;
+
+/// Blocks requests whose input document contains "evil".
+const BLOCK_EVIL: &str = r#"
+  (func (export "decide") (param $ptr i32) (param $len i32) (result i32)
+    (local $i i32)
+    (local $end i32)
+    (local.set $i (local.get $ptr))
+    (local.set $end (i32.add (local.get $ptr) (i32.sub (local.get $len) (i32.const 4))))
+    (block $done
+      (loop $scan
+        (br_if $done (i32.gt_s (local.get $i) (local.get $end)))
+        (if (i32.eq (i32.load (local.get $i)) (i32.const 0x6c697665))
+          (then (return (i32.const 2))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $scan)))
+    (i32.const 0))
+"#;
+
+/// Never returns; stopped by the fuel limit.
+const SPIN: &str = r#"
+  (func (export "decide") (param i32 i32) (result i32)
+    (loop $forever (br $forever))
+    (i32.const 2))
+"#;
+
+/// Allows everything.
+const ALLOW: &str = r#"
+  (func (export "decide") (param i32 i32) (result i32)
+    (i32.const 1))
+"#;
+
+async fn plugin_app(plugins: &[(&str, &str)]) -> (TestApp, TempDir) {
+    let dir = TempDir::new().unwrap();
+    for (name, body) in plugins {
+        let module = format!("(module {} {})", ALLOC, body);
+        std::fs::write(dir.path().join(name), module).unwrap();
+    }
+    std::fs::write(dir.path().join("broken.wasm"), b"not a module").unwrap();
+    std::fs::write(dir.path().join("README.txt"), b"ignored").unwrap();
+
+    let config = Config {
+        plugin_dir: Some(dir.path().to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+    (TestApp::with_config(config, &["203.0.113.7"]).await, dir)
+}
+
+fn request(ip: &str, path: &str) -> Request<Body> {
+    Request::get("/")
+        .header("x-forwarded-for", ip)
+        .header("x-forwarded-uri", path)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn plugins_block_in_a_sandbox() {
+    let (app, _dir) = plugin_app(&[("10-evil.wat", BLOCK_EVIL), ("20-spin.wat", SPIN)]).await;
+    assert_eq!(app.state().plugins.as_ref().unwrap().len(), 2, "broken modules are skipped");
+
+    let res = app.send(request("198.51.100.1", "/docs")).await;
+    assert_eq!(res.status(), StatusCode::OK, "a plugin running out of fuel keeps the decision");
+    let res = app.send(request("198.51.100.1", "/evil")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = app.send(request("203.0.113.7", "/docs")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn plugins_can_allow_blocked_requests() {
+    let (app, _dir) = plugin_app(&[("allow.wat", ALLOW)]).await;
+
+    let res = app.send(request("203.0.113.7", "/docs")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
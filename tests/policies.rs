@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tezcatlipoca_auth::{
+    config::{AuthConfig, AuthMode, Config},
+    policies::{self, PolicyAuth},
+    testing::TestApp,
+};
+
+const POLICIES: &str = r#"{
+    "Internal": {"auth": "required"},
+    "public": {"auth": "none", "category_rules": "/=-dynamic", "honeypot_paths": ["/trap"]},
+    "observe": {"enforce": false}
+}"#;
+
+fn request(policy: Option<&str>, ip: &str, path: &str) -> Request<Body> {
+    let mut req = Request::get(path).header("x-forwarded-for", ip);
+    if let Some(policy) = policy {
+        req = req.header("x-auth-policy", policy);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[test]
+fn policies_parse_with_the_rule_syntax_of_their_settings() {
+    let parsed = policies::parse(POLICIES).unwrap();
+    assert_eq!(parsed.keys().collect::<Vec<_>>(), ["internal", "observe", "public"]);
+    assert_eq!(parsed["internal"].auth, PolicyAuth::Required);
+    assert_eq!(parsed["observe"].enforce, Some(false));
+    assert_eq!(parsed["observe"].category_rules, None);
+    assert_eq!(parsed["public"].category_rules.as_ref().unwrap().len(), 1);
+
+    let invalid = [
+        r#"{"a": {"category_rules": "/=-a,b"}}"#,
+        r#"{"a": {"schedule_rules": "/=someday"}}"#,
+        r#"{"a": {"auth": "maybe"}}"#,
+        r#"{"a": {"enfroce": true}}"#,
+        "[]",
+    ];
+    for invalid in invalid {
+        assert!(policies::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn the_named_policy_replaces_the_global_settings() {
+    let users = tempfile::NamedTempFile::new().unwrap();
+    let config = Config {
+        auth: Some(AuthConfig {
+            mode: AuthMode::Basic {
+                users_file: users.path().to_string_lossy().into_owned(),
+            },
+            paths: vec!["/admin".to_string()],
+            realm: "internal".to_string(),
+            session_ttl: Duration::from_secs(3600),
+            totp: None,
+        }),
+        honeypot_paths: vec!["/.env".to_string()],
+        policies: policies::parse(POLICIES).unwrap(),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["203.0.113.7"]).await;
+    let status = |policy, ip, path| {
+        let req = request(policy, ip, path);
+        async { app.send(req).await.status() }
+    };
+
+    // Login on every path, or on none
+    assert_eq!(status(None, "192.0.2.1", "/").await, StatusCode::OK);
+    assert_eq!(status(Some("internal"), "192.0.2.1", "/").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(None, "192.0.2.1", "/admin").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(Some("public"), "192.0.2.1", "/admin").await, StatusCode::OK);
+
+    // Own trap paths and ban categories
+    assert_eq!(status(Some("public"), "192.0.2.2", "/.env").await, StatusCode::OK);
+    // The trap bans dynamically, which this policy doesn't enforce
+    assert_eq!(status(Some("public"), "192.0.2.2", "/trap").await, StatusCode::OK);
+    assert_eq!(status(None, "192.0.2.2", "/").await, StatusCode::FORBIDDEN);
+    assert_eq!(status(Some("public"), "192.0.2.2", "/").await, StatusCode::OK);
+    assert_eq!(status(Some("public"), "203.0.113.7", "/").await, StatusCode::FORBIDDEN);
+
+    // Observe-only, and unknown names blocked regardless
+    assert_eq!(status(Some("observe"), "203.0.113.7", "/").await, StatusCode::OK);
+    assert_eq!(status(Some("other"), "192.0.2.1", "/").await, StatusCode::FORBIDDEN);
+}
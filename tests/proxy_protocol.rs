@@ -0,0 +1,80 @@
+use tezcatlipoca_auth::{
+    build_router,
+    config::{Config, IpSource},
+    proxy_protocol::ProxyProtocolListener,
+    server,
+    testing::TestApp,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Serves the router behind a PROXY protocol listener accepting connections
+/// from `trusted`; returns its address.
+async fn spawn_server(banned: &[&str], trusted: &str) -> (TestApp, std::net::SocketAddr) {
+    let config = Config {
+        client_ip_sources: vec![IpSource::Socket],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, banned).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = app.state().clone();
+    let trusted = vec![trusted.parse().unwrap()];
+    tokio::spawn(async move {
+        let listener = ProxyProtocolListener::new(listener, trusted);
+        server::serve(listener, build_router(state.clone()), &state.config).await
+    });
+    (app, addr)
+}
+
+/// Sends `header` followed by a request and returns the response status line.
+async fn status_line(addr: std::net::SocketAddr, header: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(header).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: auth\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await;
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+fn v2_header(ip: [u8; 4]) -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    header.extend_from_slice(&ip);
+    header.extend_from_slice(&[127, 0, 0, 1, 0x30, 0x39, 0x1f, 0x90]);
+    header
+}
+
+#[tokio::test]
+async fn proxy_headers_provide_the_client_address() {
+    let (_app, addr) = spawn_server(&["203.0.113.7"], "127.0.0.1/32").await;
+
+    let v1 = |ip: &str| format!("PROXY TCP4 {} 127.0.0.1 12345 8199\r\n", ip);
+    assert!(status_line(addr, v1("203.0.113.7").as_bytes()).await.contains("403"));
+    assert!(status_line(addr, v1("198.51.100.1").as_bytes()).await.contains("200"));
+
+    assert!(status_line(addr, &v2_header([203, 0, 113, 7])).await.contains("403"));
+    assert!(status_line(addr, &v2_header([198, 51, 100, 1])).await.contains("200"));
+}
+
+#[tokio::test]
+async fn connections_without_a_header_are_dropped() {
+    let (_app, addr) = spawn_server(&[], "127.0.0.1/32").await;
+
+    assert_eq!(status_line(addr, b"").await, "");
+    assert_eq!(status_line(addr, b"PROXY TCP4 garbage\r\n").await, "");
+}
+
+#[tokio::test]
+async fn headers_from_untrusted_peers_are_dropped() {
+    let (_app, addr) = spawn_server(&[], "10.0.0.0/8").await;
+
+    let header = b"PROXY TCP4 198.51.100.1 127.0.0.1 12345 8199\r\n";
+    assert_eq!(status_line(addr, header).await, "");
+    assert_eq!(status_line(addr, &v2_header([198, 51, 100, 1])).await, "");
+}
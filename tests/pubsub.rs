@@ -0,0 +1,57 @@
+use chrono::Utc;
+use tezcatlipoca_auth::{
+    config::Config,
+    events::SecurityEvent,
+    pubsub::{BanUpdate, Envelope},
+    AppState,
+};
+
+#[test]
+fn auto_bans_and_unbans_are_published() {
+    let ban = BanUpdate::from_event(SecurityEvent::AutoBan {
+        ip: "203.0.113.7".to_string(),
+        path: "/.env".to_string(),
+        reason: "honeypot".to_string(),
+        duration_secs: Some(600),
+        timestamp: Utc::now(),
+    })
+    .unwrap();
+    let json = serde_json::to_value(Envelope { origin: 1, update: ban.clone() }).unwrap();
+    assert_eq!(json["op"], "ban");
+    assert_eq!(json["ip"], "203.0.113.7");
+    assert_eq!(json["duration_secs"], 600);
+    let parsed: Envelope = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.update, ban);
+
+    let unban = BanUpdate::from_event(SecurityEvent::Unban {
+        ip: "203.0.113.7".to_string(),
+        timestamp: Utc::now(),
+    });
+    assert_eq!(unban, Some(BanUpdate::Unban { ip: "203.0.113.7".to_string() }));
+
+    let blocked = BanUpdate::from_event(SecurityEvent::Blocked {
+        ip: "203.0.113.7".to_string(),
+        path: "/".to_string(),
+        reason: "banned".to_string(),
+        ban: None,
+        hostname: None,
+        enforced: true,
+        timestamp: Utc::now(),
+    });
+    assert_eq!(blocked, None);
+}
+
+#[test]
+fn received_updates_change_the_dynamic_bans() {
+    let state = AppState::new(Config::default());
+    BanUpdate::Ban {
+        ip: "203.0.113.7".to_string(),
+        reason: "honeypot".to_string(),
+        duration_secs: None,
+    }
+    .apply(&state);
+    assert!(state.dynamic_bans.contains("203.0.113.7"));
+
+    BanUpdate::Unban { ip: "203.0.113.7".to_string() }.apply(&state);
+    assert!(!state.dynamic_bans.contains("203.0.113.7"));
+}
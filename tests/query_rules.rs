@@ -0,0 +1,40 @@
+use axum::{body::to_bytes, http::StatusCode};
+use tezcatlipoca_auth::{config::Config, query_rules::QueryRules, testing::TestApp};
+
+#[test]
+fn rules_match_decoded_parameters() {
+    let rules = QueryRules::parse(r"cmd; action=shell; ~(?i)union\s+select; ~/etc/passwd").unwrap();
+    let matching = |query| rules.matching(query).map(ToString::to_string);
+
+    assert_eq!(matching("page=2&cmd="), Some("cmd".to_string()));
+    assert_eq!(matching("action=shell"), Some("action=shell".to_string()));
+    assert_eq!(matching("action=shellfish"), None);
+    assert_eq!(matching("id=1+UNION+SELECT+1"), Some(r"~(?i)union\s+select".to_string()));
+    assert_eq!(matching("id=1%20union%20select%201"), Some(r"~(?i)union\s+select".to_string()));
+    assert_eq!(matching("f=..%2F..%2Fetc%2Fpasswd"), Some("~/etc/passwd".to_string()));
+    assert_eq!(matching("q=reunion&select=1"), None);
+    assert_eq!(matching(""), None);
+
+    assert!(QueryRules::parse("=value").is_err());
+    let error = QueryRules::parse("cmd;~(unclosed").unwrap_err();
+    assert!(error.contains("'(unclosed'"), "{}", error);
+}
+
+#[tokio::test]
+async fn matching_queries_ban_the_client() {
+    let config = Config {
+        query_rules: QueryRules::parse("cmd").unwrap(),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    assert_eq!(app.get_from("192.0.2.1", "/?page=2").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("192.0.2.1", "/?cmd=id").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("192.0.2.1", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("192.0.2.2", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.state().dynamic_bans.len(), 1);
+
+    let body = to_bytes(app.get("/health").await.into_body(), usize::MAX).await.unwrap();
+    let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(health["requests_query_rule"], 1);
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use axum::{body::to_bytes, http::StatusCode};
+use tezcatlipoca_auth::{
+    config::{Config, FailMode, RateLimitRule, RedisConfig},
+    metrics::Metrics,
+    rate_limit::{self, Algorithm, Rate, RateLimiter, Scope},
+    testing::TestApp,
+};
+
+#[test]
+fn rates_and_rules_are_parsed() {
+    let rate = |limit, secs| Some(Rate { limit, period: Duration::from_secs(secs) });
+    assert_eq!(Rate::parse("100/s"), rate(100, 1));
+    assert_eq!(Rate::parse(" 5 / min "), rate(5, 60));
+    assert_eq!(Rate::parse("10/h"), rate(10, 3600));
+    assert_eq!(Rate::parse("20/10s"), rate(20, 10));
+    assert_eq!(Rate::parse("0/s"), None);
+    assert_eq!(Rate::parse("5/0s"), None);
+    assert_eq!(Rate::parse("5"), None);
+    assert_eq!(Rate::parse("5/min").unwrap().to_string(), "5/min");
+    assert_eq!(Rate::parse("20/10s").unwrap().to_string(), "20/10s");
+
+    let rules: Vec<RateLimitRule> = ["/login=5/min", "API.example.com/export=10/h", "/health=off"]
+        .into_iter()
+        .map(|rule| RateLimitRule::parse(rule).unwrap())
+        .collect();
+    assert_eq!(rules[1].host.as_deref(), Some("api.example.com"));
+    assert_eq!(rules[2].rate, None);
+    assert!(RateLimitRule::parse("/login=fast").is_none());
+
+    let default = Rate::parse("100/s");
+    let applicable = |host, path| rate_limit::applicable(&rules, default, host, path);
+    let scope = |host, path| applicable(host, path).map(|(scope, _)| scope);
+    assert_eq!(applicable(None, "/login/"), Some((Scope::Rule(0), rate(5, 60).unwrap())));
+    assert_eq!(scope(Some("api.example.com"), "/export"), Some(Scope::Rule(1)));
+    assert_eq!(scope(Some("www.example.com"), "/export"), Some(Scope::Default));
+    assert_eq!(applicable(None, "/health"), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn buckets_refill_at_the_average_rate() {
+    let limiter = RateLimiter::new(Algorithm::TokenBucket);
+    let ip = "192.0.2.1".parse().unwrap();
+    let rate = Rate::parse("3/min").unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    }
+    assert_eq!(limiter.check(ip, Scope::Default, rate), Some(Duration::from_secs(20)));
+    // Other scopes have their own bucket
+    assert_eq!(limiter.check(ip, Scope::Rule(0), rate), None);
+
+    tokio::time::advance(Duration::from_secs(15)).await;
+    assert_eq!(limiter.check(ip, Scope::Default, rate), Some(Duration::from_secs(5)));
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    assert!(limiter.check(ip, Scope::Default, rate).is_some());
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    limiter.prune();
+    assert!(limiter.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn fixed_windows_start_over_each_period() {
+    let limiter = RateLimiter::new(Algorithm::FixedWindow);
+    let ip = "192.0.2.1".parse().unwrap();
+    let rate = Rate::parse("3/min").unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    }
+    tokio::time::advance(Duration::from_secs(50)).await;
+    assert_eq!(limiter.check(ip, Scope::Default, rate), Some(Duration::from_secs(10)));
+
+    // A full window again right after the boundary
+    tokio::time::advance(Duration::from_secs(10)).await;
+    for _ in 0..3 {
+        assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    }
+    assert!(limiter.check(ip, Scope::Default, rate).is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn sliding_logs_never_exceed_the_limit_in_any_period() {
+    let limiter = RateLimiter::new(Algorithm::SlidingLog);
+    let ip = "192.0.2.1".parse().unwrap();
+    let rate = Rate::parse("3/min").unwrap();
+
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    tokio::time::advance(Duration::from_secs(50)).await;
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    assert_eq!(limiter.check(ip, Scope::Default, rate), Some(Duration::from_secs(10)));
+
+    // Only the oldest request has left the last minute
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    assert_eq!(limiter.check(ip, Scope::Default, rate), Some(Duration::from_secs(50)));
+
+    assert_eq!(Algorithm::parse("Sliding-Log"), Some(Algorithm::SlidingLog));
+    assert_eq!(Algorithm::parse("leaky-bucket"), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn paths_get_their_own_limits() {
+    let config = Config {
+        rate_limit: Rate::parse("5/s"),
+        rate_limit_rules: vec![
+            RateLimitRule::parse("/login=2/min").unwrap(),
+            RateLimitRule::parse("/health=off").unwrap(),
+        ],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    assert_eq!(app.get_from("192.0.2.1", "/login").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("192.0.2.1", "/login").await.status(), StatusCode::OK);
+    let res = app.get_from("192.0.2.1", "/login").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "30");
+
+    // The rest of the site, and other clients, are unaffected
+    for _ in 0..5 {
+        assert_eq!(app.get_from("192.0.2.1", "/").await.status(), StatusCode::OK);
+    }
+    let res = app.get_from("192.0.2.1", "/").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(app.get_from("192.0.2.2", "/login").await.status(), StatusCode::OK);
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    assert_eq!(app.get_from("192.0.2.1", "/login").await.status(), StatusCode::OK);
+
+    let body = to_bytes(app.get("/health").await.into_body(), usize::MAX).await.unwrap();
+    let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(health["requests_rate_limited"], 2);
+}
+
+#[tokio::test]
+async fn limits_are_counted_locally_while_redis_is_unreachable() {
+    let config = Config {
+        rate_limit: Rate::parse("2/min"),
+        redis: Some(RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            channel: "tezcatlipoca:bans".to_string(),
+        }),
+        rate_limit_redis: Some(Duration::from_millis(50)),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    assert!(app.state().redis_rate_limiter.is_some());
+
+    assert_eq!(app.get_from("192.0.2.1", "/").await.status(), StatusCode::OK);
+    assert_eq!(app.get_from("192.0.2.1", "/").await.status(), StatusCode::OK);
+    let res = app.get_from("192.0.2.1", "/").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(Metrics::get(&app.state().metrics.rate_limit_fallbacks), 3);
+}
+
+#[tokio::test]
+async fn requests_redis_cant_count_are_refused_when_failing_closed() {
+    let config = Config {
+        rate_limit: Rate::parse("2/min"),
+        redis: Some(RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            channel: "tezcatlipoca:bans".to_string(),
+        }),
+        rate_limit_redis: Some(Duration::from_millis(50)),
+        fail_mode: FailMode::Closed,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.get_from("192.0.2.1", "/").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "5");
+    assert_eq!(Metrics::get(&app.state().metrics.rate_limit_fallbacks), 1);
+}
+
+#[tokio::test]
+async fn limits_are_skipped_in_observe_only_mode() {
+    let config = Config {
+        rate_limit: Rate::parse("1/min"),
+        enforce: false,
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    for _ in 0..3 {
+        assert_eq!(app.get_from("192.0.2.1", "/").await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::http::StatusCode;
+use tezcatlipoca_auth::{
+    config::{Config, RdnsConfig},
+    events::SecurityEvent,
+    metrics::Metrics,
+    rdns::lookup_task,
+    testing::TestApp,
+};
+use tokio::net::UdpSocket;
+
+const PTR: u16 = 12;
+const A: u16 = 1;
+
+/// Name server answering from `records`, as (name, type, rdata) triples.
+/// Unknown names get NXDOMAIN, known ones without a record of the asked type
+/// an empty answer.
+async fn spawn_server(records: &'static [(&'static str, u16, &'static [u8])]) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            let query = &buf[..len];
+            let mut end = 12;
+            let mut labels = Vec::new();
+            while query[end] != 0 {
+                let label = usize::from(query[end]);
+                labels.push(String::from_utf8_lossy(&query[end + 1..end + 1 + label]).into_owned());
+                end += 1 + label;
+            }
+            let qtype = u16::from_be_bytes([query[end + 1], query[end + 2]]);
+            end += 5;
+            let name = format!("{}.", labels.join(".")).to_lowercase();
+
+            let known = records.iter().any(|(n, _, _)| *n == name);
+            let answers: Vec<_> = records
+                .iter()
+                .filter(|(n, t, _)| *n == name && *t == qtype)
+                .collect();
+            let mut answer = query[..2].to_vec();
+            answer.extend_from_slice(if known { &[0x81, 0x80] } else { &[0x81, 0x83] });
+            answer.extend_from_slice(&[0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+            answer.extend_from_slice(&query[12..end]);
+            for (_, rtype, rdata) in answers {
+                answer.extend_from_slice(&[0xc0, 12]);
+                answer.extend_from_slice(&rtype.to_be_bytes());
+                answer.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+                answer.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                answer.extend_from_slice(rdata);
+            }
+            let _ = socket.send_to(&answer, peer).await;
+        }
+    });
+    addr
+}
+
+fn hostname_of(event: SecurityEvent) -> Option<String> {
+    match event {
+        SecurityEvent::Blocked { hostname, .. } => hostname,
+        other => panic!("expected a block, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn blocked_ips_are_named_once_confirmed() {
+    let resolver = spawn_server(&[
+        ("4.3.2.1.in-addr.arpa.", PTR, b"\x04host\x07example\x00"),
+        ("host.example.", A, &[1, 2, 3, 4]),
+        // Points back to another address, so it must not be trusted
+        ("8.7.6.5.in-addr.arpa.", PTR, b"\x05spoof\x07example\x00"),
+        ("spoof.example.", A, &[9, 9, 9, 9]),
+    ])
+    .await;
+    let config = Config {
+        rdns: Some(RdnsConfig {
+            resolver: Some(resolver),
+            rate: 1000,
+            cache_ttl: Duration::from_secs(3600),
+            max_cached: 100,
+        }),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &["1.2.3.4", "5.6.7.8"]).await;
+    tokio::spawn(lookup_task(app.state().clone()));
+    let mut events = app.state().events.subscribe();
+
+    // The first block queues the lookup and goes out without a name
+    assert_eq!(app.get_from("1.2.3.4", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(app.get_from("5.6.7.8", "/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(hostname_of(events.try_recv().unwrap()), None);
+    assert_eq!(hostname_of(events.try_recv().unwrap()), None);
+    let rdns = app.state().rdns.clone().unwrap();
+    for _ in 0..100 {
+        if rdns.cached_count() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    app.get_from("1.2.3.4", "/").await;
+    app.get_from("5.6.7.8", "/").await;
+    assert_eq!(hostname_of(events.try_recv().unwrap()).as_deref(), Some("host.example"));
+    assert_eq!(hostname_of(events.try_recv().unwrap()), None);
+    assert_eq!(Metrics::get(&app.state().metrics.rdns_lookups), 2);
+}
@@ -0,0 +1,103 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use tezcatlipoca_auth::{
+    config::{Config, ScheduleRule},
+    schedule::{closed, Schedule},
+    testing::TestApp,
+};
+
+/// 2025-06-02 was a Monday.
+fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 6, day, hour, minute, 0).unwrap()
+}
+
+#[test]
+fn day_and_time_ranges_are_open_inside_only() {
+    let schedule = Schedule::parse("mon-fri 08:00-18:00|sat 10:00-12:00").unwrap();
+    assert!(schedule.is_open(&at(2, 8, 0)));
+    assert!(schedule.is_open(&at(6, 17, 59)));
+    assert!(!schedule.is_open(&at(6, 18, 0)));
+    assert!(!schedule.is_open(&at(2, 7, 59)));
+    assert!(schedule.is_open(&at(7, 11, 0)));
+    assert!(!schedule.is_open(&at(8, 11, 0)));
+    assert_eq!(
+        schedule.to_string(),
+        "mon,tue,wed,thu,fri 08:00-18:00|sat 10:00-12:00"
+    );
+
+    // Past midnight belongs to the day the range starts on
+    let night = Schedule::parse("fri 22:00-06:00").unwrap();
+    assert!(night.is_open(&at(6, 23, 0)));
+    assert!(night.is_open(&at(7, 5, 59)));
+    assert!(!night.is_open(&at(2, 5, 0)));
+
+    let weekend = Schedule::parse("sat,sun 00:00-24:00").unwrap();
+    assert!(weekend.is_open(&at(8, 23, 59)));
+    assert!(!weekend.is_open(&at(9, 0, 0)));
+}
+
+#[test]
+fn cron_expressions_open_matching_minutes() {
+    let schedule = Schedule::parse("*/15 8-17 * * 1-5").unwrap();
+    assert!(schedule.is_open(&at(2, 8, 45)));
+    assert!(!schedule.is_open(&at(2, 8, 46)));
+    assert!(!schedule.is_open(&at(8, 9, 0)));
+    assert_eq!(schedule.to_string(), "*/15 8-17 * * 1-5");
+
+    // Sunday is 0 or 7; with both day fields set either one opens the day
+    assert!(Schedule::parse("* * * * 7").unwrap().is_open(&at(8, 3, 0)));
+    let either = Schedule::parse("* * 3 * 0").unwrap();
+    assert!(either.is_open(&at(3, 12, 0)));
+    assert!(either.is_open(&at(8, 12, 0)));
+    assert!(!either.is_open(&at(4, 12, 0)));
+}
+
+#[test]
+fn invalid_schedules_are_rejected() {
+    let invalid = [
+        "",
+        "mon",
+        "mon 08:00",
+        "xyz 08:00-09:00",
+        "mon 25:00-26:00",
+        "mon 08:00-08:00",
+    ];
+    for invalid in invalid {
+        assert_eq!(Schedule::parse(invalid), None, "{}", invalid);
+    }
+    assert_eq!(Schedule::parse("60 * * * *"), None);
+    assert_eq!(Schedule::parse("* * * 0 *"), None);
+    assert_eq!(ScheduleRule::parse("staging.example.com"), None);
+}
+
+#[test]
+fn the_first_matching_rule_decides_in_the_configured_timezone() {
+    let rules = vec![
+        ScheduleRule::parse("staging.example.com/public=* * * * *").unwrap(),
+        ScheduleRule::parse("staging.example.com=mon-fri 09:00-17:00").unwrap(),
+    ];
+    let berlin: Tz = "Europe/Berlin".parse().unwrap();
+    let host = Some("staging.example.com");
+
+    // 07:30 UTC is 09:30 in Berlin (summer time)
+    assert!(closed(&rules, Tz::UTC, host, "/", at(2, 7, 30)));
+    assert!(!closed(&rules, berlin, host, "/", at(2, 7, 30)));
+    assert!(!closed(&rules, Tz::UTC, host, "/public/docs", at(8, 7, 30)));
+    assert!(!closed(&rules, Tz::UTC, Some("www.example.com"), "/", at(8, 7, 30)));
+}
+
+#[tokio::test]
+async fn requests_outside_the_schedule_are_blocked() {
+    let config = Config {
+        // February 31st never comes
+        schedule_rules: vec![ScheduleRule::parse("/staging=* * 31 2 *").unwrap()],
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+
+    let res = app.get_from("198.51.100.1", "/staging/app").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = app.get_from("198.51.100.1", "/").await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
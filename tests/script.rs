@@ -0,0 +1,60 @@
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use tempfile::NamedTempFile;
+use tezcatlipoca_auth::{config::Config, testing::TestApp};
+
+async fn scripted_app(source: &str) -> (TestApp, NamedTempFile) {
+    let mut script = NamedTempFile::new().unwrap();
+    script.write_all(source.as_bytes()).unwrap();
+    let config = Config {
+        script_file: Some(script.path().to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+    (TestApp::with_config(config, &["203.0.113.7"]).await, script)
+}
+
+fn request(ip: &str, path: &str, user_agent: &str) -> Request<Body> {
+    Request::get("/")
+        .header("x-forwarded-for", ip)
+        .header("x-forwarded-uri", path)
+        .header(header::USER_AGENT, user_agent)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn scripts_override_decisions() {
+    let (app, _script) = scripted_app(
+        r#"
+        if request.user_agent.contains("BadBot") && weekday() >= 1 {
+            "block"
+        } else if decision == "block" && reason == "BANNED" && request.path == "/status" {
+            "allow"
+        }
+        "#,
+    )
+    .await;
+
+    let res = app.send(request("198.51.100.1", "/", "curl/8.5.0")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = app.send(request("198.51.100.1", "/", "BadBot/1.0")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = app.send(request("203.0.113.7", "/", "curl/8.5.0")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = app.send(request("203.0.113.7", "/status", "curl/8.5.0")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn failing_scripts_keep_the_decision() {
+    let (app, _script) = scripted_app("loop {}").await;
+
+    let res = app.send(request("198.51.100.1", "/", "curl/8.5.0")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = app.send(request("203.0.113.7", "/", "curl/8.5.0")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,165 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tezcatlipoca_auth::{
+    build_router, build_router_for,
+    config::{Config, HttpVersion, Ipv6Mode, Routes},
+    server,
+    testing::{TestApp, ADMIN_TOKEN},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Serves the router with `config` on a local port; returns its address.
+async fn spawn_server(config: Config) -> SocketAddr {
+    let app = TestApp::with_config(config, &[]).await;
+    let state = app.state().clone();
+    let listener = server::bind("127.0.0.1:0", &state.config).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let _app = app;
+        server::serve(listener, build_router(state.clone()), &state.config).await
+    });
+    addr
+}
+
+/// Writes `request` and reads until the server closes the connection.
+async fn exchange(addr: SocketAddr, request: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .expect("server closed the connection")
+        .ok();
+    response
+}
+
+/// Writes `request` and returns the first bytes the server answers with.
+async fn first_read(addr: SocketAddr, request: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("server answered")
+        .unwrap_or(0);
+    buf.truncate(n);
+    buf
+}
+
+const HTTP1_REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: auth\r\n\r\n";
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+#[tokio::test]
+async fn keep_alive_is_configurable() {
+    let addr = spawn_server(Config {
+        keep_alive_timeout: None,
+        ..Config::default()
+    })
+    .await;
+    let response = String::from_utf8(exchange(addr, HTTP1_REQUEST).await).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.to_lowercase().contains("connection: close"));
+
+    // Idle connections are closed once the timeout passes
+    let addr = spawn_server(Config {
+        keep_alive_timeout: Some(Duration::from_secs(1)),
+        ..Config::default()
+    })
+    .await;
+    let response = String::from_utf8(exchange(addr, HTTP1_REQUEST).await).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+}
+
+#[tokio::test]
+async fn http_versions_are_configurable() {
+    // HTTP/2 with prior knowledge gets the server's SETTINGS frame
+    let is_settings = |response: &[u8]| response.len() >= 9 && response[3] == 0x4;
+
+    let auto = spawn_server(Config::default()).await;
+    assert!(is_settings(&first_read(auto, HTTP2_PREFACE).await));
+
+    let http1 = spawn_server(Config {
+        http_version: HttpVersion::Http1,
+        ..Config::default()
+    })
+    .await;
+    assert!(!is_settings(&first_read(http1, HTTP2_PREFACE).await));
+
+    let http2 = spawn_server(Config {
+        http_version: HttpVersion::Http2,
+        ..Config::default()
+    })
+    .await;
+    assert!(!first_read(http2, HTTP1_REQUEST).await.starts_with(b"HTTP/1.1"));
+}
+
+#[tokio::test]
+async fn listeners_serve_their_own_routes() {
+    let app = TestApp::with_config(
+        Config {
+            admin_api: true,
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..Config::default()
+        },
+        &[],
+    )
+    .await;
+    let mut addrs = Vec::new();
+    for routes in [Routes::Auth, Routes::Admin] {
+        let state = app.state().clone();
+        let listener = server::bind("127.0.0.1:0", &state.config).await.unwrap();
+        addrs.push(listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            server::serve(listener, build_router_for(state.clone(), routes), &state.config).await
+        });
+    }
+    let status = |addr: SocketAddr, path: &str| {
+        let url = format!("http://{}{}", addr, path);
+        let req = reqwest::Client::new().get(url).bearer_auth(ADMIN_TOKEN);
+        async move { req.send().await.unwrap().status().as_u16() }
+    };
+
+    let (auth, admin) = (addrs[0], addrs[1]);
+    assert_eq!(status(auth, "/").await, 200);
+    assert_eq!(status(auth, "/health").await, 200);
+    assert_eq!(status(auth, "/admin/bans").await, 200, "answered by ForwardAuth");
+    assert_eq!(status(admin, "/health").await, 200);
+    assert_eq!(status(admin, "/admin/stats/top").await, 200);
+    assert_eq!(status(admin, "/").await, 404);
+}
+
+#[tokio::test]
+async fn ipv6_listeners_follow_the_mode() {
+    let config = |ipv6_mode| Config {
+        ipv6_mode,
+        ..Config::default()
+    };
+
+    // IPv4 clients of a dual-stack listener are seen with their IPv4 address
+    let app = TestApp::with_config(config(Ipv6Mode::Dual), &["127.0.0.1"]).await;
+    let state = app.state().clone();
+    let mut listeners = server::bind_all("[::]:0", &state.config).await.unwrap();
+    assert_eq!(listeners.len(), 1);
+    let listener = listeners.remove(0);
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        server::serve(listener, build_router(state.clone()), &state.config).await
+    });
+    let response = first_read(SocketAddr::from(([127, 0, 0, 1], port)), HTTP1_REQUEST).await;
+    assert!(String::from_utf8(response).unwrap().starts_with("HTTP/1.1 403"));
+
+    let listeners = server::bind_all("[::]:0", &config(Ipv6Mode::V6Only)).await.unwrap();
+    let port = listeners[0].local_addr().unwrap().port();
+    assert!(TcpStream::connect(("::1", port)).await.is_ok());
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+
+    let listeners = server::bind_all("[::]:0", &config(Ipv6Mode::Separate)).await.unwrap();
+    assert_eq!(listeners.len(), 2);
+    let port = listeners[0].local_addr().unwrap().port();
+    assert_eq!(listeners[1].local_addr().unwrap(), SocketAddr::from(([0, 0, 0, 0], port)));
+    assert!(TcpStream::connect(("::1", port)).await.is_ok());
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_ok());
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tezcatlipoca_auth::{
+    config::{Config, SignatureConfig},
+    signature::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    testing::TestApp,
+};
+
+const SECRET: &str = "shared-secret";
+
+fn signature() -> Option<SignatureConfig> {
+    Some(SignatureConfig {
+        secret: SECRET.to_string(),
+        paths: vec!["/api".to_string()],
+        window: Duration::from_secs(300),
+    })
+}
+
+async fn signed_app() -> TestApp {
+    let config = Config {
+        signature: signature(),
+        ..Config::default()
+    };
+    TestApp::with_config(config, &["203.0.113.7"]).await
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn request(ip: &str, method: &str, path: &str, signature: Option<(u64, String)>) -> Request<Body> {
+    let mut req = Request::get("/")
+        .header("x-forwarded-for", ip)
+        .header("x-forwarded-method", method)
+        .header("x-forwarded-uri", path);
+    if let Some((timestamp, signature)) = signature {
+        req = req
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, signature);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn signed_requests_pass_and_unsigned_ones_are_refused() {
+    let app = signed_app().await;
+    let ip = "198.51.100.1";
+    let ts = now();
+
+    let signed = Some((ts, sign(SECRET, ts, "POST", "/api/jobs")));
+    let res = app.send(request(ip, "POST", "/api/jobs?page=2", signed)).await;
+    assert_eq!(res.status(), StatusCode::OK, "the query string isn't signed");
+
+    let res = app.send(request(ip, "POST", "/api/jobs", None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let wrong_method = Some((ts, sign(SECRET, ts, "GET", "/api/jobs")));
+    let res = app.send(request(ip, "POST", "/api/jobs", wrong_method)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let wrong_key = Some((ts, sign("other", ts, "POST", "/api/jobs")));
+    let res = app.send(request(ip, "POST", "/api/jobs", wrong_key)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = app.send(request(ip, "GET", "/apiary", None)).await;
+    assert_eq!(res.status(), StatusCode::OK, "paths outside SIGNATURE_PATHS are open");
+}
+
+#[tokio::test]
+async fn signatures_outside_the_replay_window_are_refused() {
+    let app = signed_app().await;
+    let old = now() - 600;
+
+    let replayed = Some((old, sign(SECRET, old, "GET", "/api")));
+    let res = app.send(request("198.51.100.1", "GET", "/api", replayed)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let ts = now();
+    let banned = Some((ts, sign(SECRET, ts, "GET", "/api")));
+    let res = app.send(request("203.0.113.7", "GET", "/api", banned)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN, "IP screening runs first");
+}
+
+#[tokio::test]
+async fn observed_blocks_still_require_a_signature() {
+    let config = Config {
+        enforce: false,
+        honeypot_paths: vec!["/.env".to_string()],
+        signature: signature(),
+        ..Config::default()
+    };
+    let app = TestApp::with_config(config, &[]).await;
+    let ip = "198.51.100.1";
+
+    // Banned by the trap, which ENFORCE=false only records
+    let res = app.send(request(ip, "GET", "/.env", None)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(app.state().dynamic_bans.len(), 1);
+
+    let res = app.send(request(ip, "POST", "/api/jobs", None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let ts = now();
+    let signed = Some((ts, sign(SECRET, ts, "POST", "/api/jobs")));
+    let res = app.send(request(ip, "POST", "/api/jobs", signed)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::{
+    config::Config,
+    rate_limit::{Rate, Scope},
+    snapshot::{self, Snapshot},
+    AppState,
+};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn config(path: &str) -> Config {
+    Config {
+        state_snapshot_file: Some(path.to_string()),
+        greylist: true,
+        greylist_delay: Duration::ZERO,
+        ban_escalation: vec![Some(HOUR), None],
+        rate_limit: Rate::parse("2/h"),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn runtime_state_survives_a_redeploy() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.json");
+    let path = path.to_str().unwrap();
+
+    let before = AppState::new(config(path));
+    before.dynamic_bans.offend("203.0.113.7", None, "honeypot");
+    before.dynamic_bans.ban("198.51.100.1", Some(HOUR), "imported", "import");
+    let greylist = before.greylist.as_ref().unwrap();
+    greylist.check("192.0.2.10");
+    assert_eq!(greylist.check("192.0.2.10"), None, "passes after the delay");
+    let limiter = before.rate_limiter.as_ref().unwrap();
+    let (ip, rate) = ("192.0.2.20".parse().unwrap(), Rate::parse("2/h").unwrap());
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    assert_eq!(limiter.check(ip, Scope::Default, rate), None);
+    snapshot::save(path, &Snapshot::take(&before)).await.unwrap();
+
+    let after = AppState::new(config(path));
+    snapshot::load(&after).await;
+    assert!(after.dynamic_bans.contains("203.0.113.7"));
+    assert_eq!(after.dynamic_bans.offenses("203.0.113.7"), 1);
+    let bans = after.dynamic_bans.active();
+    let imported = bans.iter().find(|b| b.ip == "198.51.100.1").unwrap();
+    assert_eq!(imported.reason, "imported");
+    assert!(imported.expires.is_some());
+    // Trusted before the restart, so not deferred again
+    assert_eq!(after.greylist.as_ref().unwrap().check("192.0.2.10"), None);
+    // The limit was used up before the restart
+    let limiter = after.rate_limiter.as_ref().unwrap();
+    assert!(limiter.check(ip, Scope::Default, rate).is_some());
+}
+
+#[tokio::test]
+async fn a_missing_or_corrupt_snapshot_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.json");
+    let path = path.to_str().unwrap();
+
+    let state = AppState::new(config(path));
+    snapshot::load(&state).await;
+    std::fs::write(path, "{not json").unwrap();
+    snapshot::load(&state).await;
+    assert!(state.dynamic_bans.is_empty());
+}
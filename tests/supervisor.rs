@@ -0,0 +1,94 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{body::to_bytes, http::StatusCode, response::Response};
+use tezcatlipoca_auth::{metrics::Metrics, supervisor, testing::TestApp};
+
+async fn json(res: Response) -> serde_json::Value {
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn readiness_reports_stalled_tasks() {
+    let app = TestApp::new(&[]).await;
+    let tasks = &app.state().tasks;
+    tasks.spawn("refresh", Some(Duration::from_secs(30)), || async {
+        supervisor::failed("unreachable");
+        supervisor::ran();
+        // Stuck from here on
+        std::future::pending::<()>().await;
+    });
+    tokio::task::yield_now().await;
+
+    let res = app.get("/readyz").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let ready = json(res).await;
+    assert_eq!(ready["ready"], true);
+    assert_eq!(ready["tasks"][0]["name"], "refresh");
+    assert_eq!(ready["tasks"][0]["state"], "running");
+    assert_eq!(ready["tasks"][0]["failures"], 1);
+    assert!(ready["tasks"][0]["last_error"].is_null());
+
+    tokio::time::advance(Duration::from_secs(91)).await;
+    let res = app.get("/readyz").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json(res).await["tasks"][0]["state"], "stalled");
+}
+
+#[tokio::test(start_paused = true)]
+async fn panicked_tasks_are_restarted_with_backoff() {
+    let app = TestApp::new(&[]).await;
+    let runs = Arc::new(AtomicUsize::new(0));
+    let task_runs = runs.clone();
+    app.state().tasks.spawn("webhook", None, move || {
+        let runs = task_runs.clone();
+        async move {
+            if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                panic!("boom");
+            }
+            std::future::pending::<()>().await;
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let res = app.get("/readyz").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let ready = json(res).await;
+    assert_eq!(ready["tasks"][0]["state"], "restarting");
+    assert_eq!(ready["tasks"][0]["last_error"], "panicked: boom");
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+    // Restarted after 1s, panics again and waits 2s more
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+    let res = app.get("/readyz").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let ready = json(res).await;
+    assert_eq!(ready["tasks"][0]["state"], "running");
+    assert_eq!(ready["tasks"][0]["restarts"], 2);
+    assert_eq!(ready["tasks"][0]["failures"], 2);
+    assert_eq!(Metrics::get(&app.state().metrics.task_restarts), 2);
+}
+
+#[tokio::test]
+async fn tasks_that_return_are_reported_as_stopped() {
+    let app = TestApp::new(&[]).await;
+    app.state().tasks.spawn("feed:tor", None, || async {});
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let res = app.get("/readyz").await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let ready = json(res).await;
+    assert_eq!(ready["banned_ips_loaded"], true);
+    assert_eq!(ready["tasks"][0]["state"], "stopped");
+    assert_eq!(ready["tasks"][0]["stopped"], "returned");
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use tezcatlipoca_auth::{
+    config::Config,
+    metrics::Metrics,
+    rate_limit::{Rate, Scope},
+    sweeper::{self, Swept},
+    AppState,
+};
+
+#[tokio::test]
+async fn sweeps_drop_expired_bans_and_stale_counters() {
+    let config = Config {
+        greylist: true,
+        greylist_ttl: Duration::from_millis(1),
+        rate_limit: Rate::parse("10/s"),
+        ..Config::default()
+    };
+    let state = AppState::new(config);
+    let bans = &state.dynamic_bans;
+    bans.ban("203.0.113.7", Some(Duration::from_millis(1)), "honeypot", "local");
+    bans.ban("203.0.113.8", None, "honeypot", "local");
+    state.greylist.as_ref().unwrap().check("198.51.100.1");
+    let short = Rate {
+        limit: 1,
+        period: Duration::from_millis(1),
+    };
+    let limiter = state.rate_limiter.as_ref().unwrap();
+    limiter.check("198.51.100.1".parse().unwrap(), Scope::Default, short);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(sweeper::sweep(&state), Swept { bans: 1, counters: 2 });
+    assert!(bans.contains("203.0.113.8"));
+    assert!(state.greylist.as_ref().unwrap().is_empty());
+    assert!(limiter.is_empty());
+
+    assert_eq!(sweeper::sweep(&state), Swept::default());
+    assert_eq!(Metrics::get(&state.metrics.sweeps), 2);
+    assert_eq!(Metrics::get(&state.metrics.swept_bans), 1);
+    assert_eq!(Metrics::get(&state.metrics.swept_counters), 2);
+    assert_eq!(state.metrics.latency_sweep.summary().count, 2);
+}
@@ -0,0 +1,40 @@
+#![cfg(unix)]
+
+use std::{os::unix::net::UnixDatagram, time::Duration};
+
+use tezcatlipoca_auth::{cache::reload_banned_ips, config::Config, systemd, AppState};
+
+#[tokio::test]
+async fn readiness_waits_for_the_first_list_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("notify.sock");
+    let socket = UnixDatagram::bind(&socket_path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    // SAFETY: the only test in this binary touching the environment
+    unsafe { std::env::set_var("NOTIFY_SOCKET", &socket_path) };
+
+    let list = dir.path().join("banned-ips.txt");
+    std::fs::write(&list, "203.0.113.7\n198.51.100.0/24\n").unwrap();
+    let state = AppState::new(Config {
+        banned_ips_files: vec![list.to_string_lossy().into_owned()],
+        ..Config::default()
+    });
+    systemd::spawn(state.clone());
+
+    let mut buf = [0u8; 256];
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(socket.recv(&mut buf).is_err(), "not ready before the list is loaded");
+
+    reload_banned_ips(&state).await.unwrap();
+    let mut received = None;
+    for _ in 0..40 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if let Ok(len) = socket.recv(&mut buf) {
+            received = Some(String::from_utf8_lossy(&buf[..len]).into_owned());
+            break;
+        }
+    }
+    let message = received.expect("READY=1 sent after the load");
+    assert!(message.contains("READY=1"));
+    assert!(message.contains("STATUS=Serving with 2 banned entries"));
+}
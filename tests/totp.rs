@@ -0,0 +1,110 @@
+use std::{io::Write, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tempfile::NamedTempFile;
+use tezcatlipoca_auth::{
+    config::{AuthConfig, AuthMode, Config, TotpConfig, TOTP_PATH},
+    testing::TestApp,
+};
+use totp_rs::TOTP;
+
+async fn totp_app(store_file: Option<String>) -> (TestApp, NamedTempFile) {
+    let mut users = NamedTempFile::new().unwrap();
+    writeln!(users, "alice:{}", bcrypt::hash("hunter2", 4).unwrap()).unwrap();
+
+    let config = Config {
+        auth: Some(AuthConfig {
+            mode: AuthMode::Basic {
+                users_file: users.path().to_string_lossy().into_owned(),
+            },
+            paths: vec!["/".to_string()],
+            realm: "internal".to_string(),
+            session_ttl: Duration::from_secs(3600),
+            totp: Some(TotpConfig {
+                paths: vec!["/admin".to_string()],
+                path: TOTP_PATH.to_string(),
+                store_file,
+            }),
+        }),
+        ..Config::default()
+    };
+    (TestApp::with_config(config, &[]).await, users)
+}
+
+fn request(uri: &str, cookie: Option<&str>) -> Request<Body> {
+    let credentials = format!("Basic {}", STANDARD.encode("alice:hunter2"));
+    let mut req = Request::get("/")
+        .header("x-forwarded-uri", uri)
+        .header(header::AUTHORIZATION, credentials);
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+async fn body_text(res: axum::response::Response) -> String {
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn sensitive_paths_need_a_code_before_the_session_starts() {
+    let (app, _users) = totp_app(None).await;
+
+    let res = app.send(request("/docs", None)).await;
+    assert_eq!(res.status(), StatusCode::OK, "other paths only need the login");
+
+    let res = app.send(request("/admin/users", None)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+    let page = body_text(res).await;
+    let uri = page
+        .split(r#"<code>"#)
+        .nth(1)
+        .and_then(|rest| rest.split('<').next())
+        .expect("provisioning URI on the enrollment page")
+        .replace("&amp;", "&");
+    let totp = TOTP::from_url(&uri).unwrap();
+
+    let submit = |code: &str| request(&format!("{}?return=%2Fadmin&code={}", TOTP_PATH, code), None);
+    let res = app.send(submit("000000x")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let code = totp.generate_current().unwrap();
+    let res = app.send(submit(&code)).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()[header::LOCATION], "/admin");
+    let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let res = app.send(request("/admin/users", Some(&cookie))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-auth-user"], "alice");
+
+    let res = app.send(submit(&code)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN, "codes are accepted once");
+    let page = body_text(res).await;
+    assert!(!page.contains("otpauth://"), "enrolled users aren't shown the secret again");
+}
+
+#[tokio::test]
+async fn enrollments_are_written_to_the_store_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = dir.path().join("totp.json");
+    let (app, _users) = totp_app(Some(store.to_string_lossy().into_owned())).await;
+
+    let res = app.send(request("/admin/users", None)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !std::fs::read_to_string(&store).is_ok_and(|text| text.contains("\"alice\"")) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("secret saved in the background");
+}
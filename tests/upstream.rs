@@ -0,0 +1,92 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use tezcatlipoca_auth::{
+    config::{Config, UpstreamAuthConfig},
+    testing::TestApp,
+};
+use tokio::net::TcpListener;
+
+/// Fake SSO: lets requests with the `sso=alice` cookie through and sends
+/// everyone else to its login page. Counts the auth requests it receives.
+async fn spawn_sso(hits: Arc<AtomicUsize>) -> String {
+    let app = Router::new().route(
+        "/verify",
+        get(move |headers: HeaderMap| async move {
+            hits.fetch_add(1, Ordering::SeqCst);
+            let header = |name| headers.get(name).and_then(|h| h.to_str().ok()).unwrap_or_default();
+            if header("cookie") == "sso=alice" {
+                return [("remote-user", "alice")].into_response();
+            }
+            let login = format!("https://sso.example/login?rd={}", header("x-forwarded-uri"));
+            (StatusCode::FOUND, [(header::LOCATION, login)], "login required").into_response()
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/verify", addr)
+}
+
+async fn chained_app(url: String) -> TestApp {
+    let config = Config {
+        upstream_auth: Some(UpstreamAuthConfig {
+            url,
+            timeout: Duration::from_secs(5),
+        }),
+        ..Config::default()
+    };
+    TestApp::with_config(config, &["203.0.113.7"]).await
+}
+
+fn request(ip: &str, cookie: Option<&str>) -> Request<Body> {
+    let mut req = Request::get("/").header("x-forwarded-for", ip).header("x-forwarded-uri", "/app");
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn upstream_answers_are_propagated_after_ip_screening() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let app = chained_app(spawn_sso(hits.clone()).await).await;
+
+    let res = app.send(request("203.0.113.7", Some("sso=alice"))).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(hits.load(Ordering::SeqCst), 0, "banned IPs never reach the upstream");
+
+    let res = app.send(request("198.51.100.1", None)).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()[header::LOCATION], "https://sso.example/login?rd=/app");
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"login required");
+
+    let res = app.send(request("198.51.100.1", Some("sso=alice"))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["remote-user"], "alice");
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn unreachable_upstream_fails_closed() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/verify", listener.local_addr().unwrap());
+    drop(listener);
+    let app = chained_app(url).await;
+
+    let res = app.send(request("198.51.100.1", None)).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
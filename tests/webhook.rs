@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use tezcatlipoca_auth::{
+    config::{WebhookConfig, WebhookFormat},
+    testing::TestApp,
+    webhook,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+/// Receiver that forwards every posted body to the returned channel.
+async fn spawn_receiver() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(tx): State<mpsc::UnboundedSender<serde_json::Value>>,
+                 Json(body): Json<serde_json::Value>| async move {
+                    tx.send(body).unwrap();
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        )
+        .with_state(tx);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/hook", addr), rx)
+}
+
+#[tokio::test]
+async fn blocked_requests_are_posted_in_a_batch() {
+    let (url, mut received) = spawn_receiver().await;
+    let app = TestApp::new(&["203.0.113.7", "203.0.113.8"]).await;
+    let config = WebhookConfig {
+        url,
+        format: WebhookFormat::Json,
+        batch_size: 2,
+        batch_window: Duration::from_secs(5),
+        max_retries: 0,
+    };
+    tokio::spawn(webhook::webhook_task(app.state().clone(), config));
+    tokio::task::yield_now().await;
+
+    app.get_from("203.0.113.7", "/a").await;
+    app.get_from("198.51.100.1", "/allowed").await;
+    app.get_from("203.0.113.8", "/b").await;
+
+    let body = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .expect("webhook delivered")
+        .unwrap();
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["event"], "blocked");
+    assert_eq!(events[0]["ip"], "203.0.113.7");
+    assert_eq!(events[0]["path"], "/a");
+    assert_eq!(events[1]["ip"], "203.0.113.8");
+    assert!(events[0]["timestamp"].is_string());
+}